@@ -0,0 +1,84 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module exports the anomalies as inline review annotations.
+
+use anyhow::{Context, Result};
+use clap::ValueEnum;
+use logreduce_model::{AnomalyContext, Report};
+use serde_json::{json, Value};
+use std::path::Path;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum Format {
+    /// The `CheckResult` list of the Gerrit checks API.
+    Gerrit,
+    /// The `output.annotations` list of the GitHub Checks API.
+    Github,
+}
+
+/// The maximum length of the excerpt in a message.
+const EXCERPT_SIZE: usize = 256;
+
+pub fn save(format: Format, report: &Report, path: &Path) -> Result<()> {
+    let anomalies = report.log_reports.iter().flat_map(|lr| {
+        let path = lr.source.get_relative().to_string();
+        lr.anomalies
+            .iter()
+            .map(move |anomaly| (path.clone(), anomaly))
+    });
+    save_anomalies(format, anomalies, path)
+}
+
+/// Save the annotations of the anomalies, given with the path of their source.
+pub fn save_anomalies<'a>(
+    format: Format,
+    anomalies: impl Iterator<Item = (String, &'a AnomalyContext)>,
+    path: &Path,
+) -> Result<()> {
+    let annotations: Vec<Value> = anomalies
+        .map(|(path, anomaly)| annotation(format, &path, anomaly))
+        .collect();
+    let file = std::fs::File::create(path).context("Can't create annotations file")?;
+    serde_json::to_writer_pretty(file, &annotations).context("Can't write annotations")
+}
+
+fn excerpt(line: &str) -> String {
+    match line.char_indices().nth(EXCERPT_SIZE) {
+        Some((idx, _)) => format!("{}...", &line[..idx]),
+        None => line.to_string(),
+    }
+}
+
+fn annotation(format: Format, path: &str, anomaly: &AnomalyContext) -> Value {
+    let line = anomaly.anomaly.pos;
+    let message = excerpt(&anomaly.anomaly.line);
+    let title = format!("Anomaly (distance {:.2})", anomaly.anomaly.distance);
+    match format {
+        Format::Gerrit => json!({
+            "category": "WARNING",
+            "externalId": anomaly.anomaly.id,
+            "summary": title,
+            "message": message,
+            "codePointers": [{
+                "path": path,
+                "range": {"start_line": line, "end_line": line},
+            }],
+        }),
+        Format::Github => json!({
+            "path": path,
+            "start_line": line,
+            "end_line": line,
+            "annotation_level": "warning",
+            "title": title,
+            "message": message,
+            "raw_details": format!("Anomaly id: {}", anomaly.anomaly.id),
+        }),
+    }
+}
+
+#[test]
+fn test_excerpt() {
+    assert_eq!(excerpt("short"), "short");
+    assert_eq!(excerpt(&"x".repeat(300)).len(), EXCERPT_SIZE + 3);
+}
@@ -0,0 +1,160 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the benchmark command, to compute the precision and recall on a
+//! directory of labeled cases.
+//!
+//! Each case is a directory containing a `.good` baseline, a `.fail` target and an
+//! `expected.yaml` file listing the anomalous line ranges (inclusive) of the target:
+//!
+//! ```yaml
+//! anomalies:
+//!   - start: 42
+//!     end: 44
+//! ```
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::path::Path;
+
+use logreduce_model::{Content, Model, OutputMode};
+
+#[derive(Deserialize, Debug)]
+struct Range {
+    start: usize,
+    end: usize,
+}
+
+impl Range {
+    fn contains(&self, pos: usize) -> bool {
+        self.start <= pos && pos <= self.end
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct Expected {
+    anomalies: Vec<Range>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Score {
+    /// The anomalies found in an expected range.
+    true_positives: usize,
+    /// The anomalies found outside of the expected ranges.
+    false_positives: usize,
+    /// The expected ranges that contain at least one anomaly.
+    found_ranges: usize,
+    ranges: usize,
+}
+
+impl Score {
+    fn new(expected: &Expected, positions: &[usize]) -> Score {
+        let true_positives = positions
+            .iter()
+            .filter(|pos| expected.anomalies.iter().any(|r| r.contains(**pos)))
+            .count();
+        let found_ranges = expected
+            .anomalies
+            .iter()
+            .filter(|r| positions.iter().any(|pos| r.contains(*pos)))
+            .count();
+        Score {
+            true_positives,
+            false_positives: positions.len() - true_positives,
+            found_ranges,
+            ranges: expected.anomalies.len(),
+        }
+    }
+
+    fn add(&mut self, other: &Score) {
+        self.true_positives += other.true_positives;
+        self.false_positives += other.false_positives;
+        self.found_ranges += other.found_ranges;
+        self.ranges += other.ranges;
+    }
+
+    fn precision(&self) -> f32 {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    fn recall(&self) -> f32 {
+        ratio(self.found_ranges, self.ranges)
+    }
+}
+
+fn ratio(x: usize, total: usize) -> f32 {
+    if total == 0 {
+        1.0
+    } else {
+        x as f32 / total as f32
+    }
+}
+
+impl std::fmt::Display for Score {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "precision {:.3} recall {:.3} ({} true positives, {} false positives, {}/{} ranges)",
+            self.precision(),
+            self.recall(),
+            self.true_positives,
+            self.false_positives,
+            self.found_ranges,
+            self.ranges
+        )
+    }
+}
+
+fn run_case(path: &Path) -> Result<Score> {
+    let expected: Expected = serde_yaml::from_reader(
+        std::fs::File::open(path.join("expected.yaml")).context("Can't open expected.yaml")?,
+    )
+    .context("Invalid expected.yaml")?;
+    let (good, fail) = crate::dataset::case_files(path)?;
+    let model = Model::train(
+        &OutputMode::Quiet,
+        vec![Content::from_pathbuf(good)],
+        logreduce_model::hashing_index::new,
+    )?;
+    let report = model.report(&OutputMode::Quiet, Content::from_pathbuf(fail))?;
+    let positions = report
+        .log_reports
+        .iter()
+        .flat_map(|lr| lr.anomalies.iter().map(|a| a.anomaly.pos))
+        .collect::<Vec<_>>();
+    Ok(Score::new(&expected, &positions))
+}
+
+/// Run every case of the dataset directory and print the scores.
+pub fn run(dataset: &Path) -> Result<()> {
+    let mut cases = std::fs::read_dir(dataset)
+        .context("Can't read the dataset")?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    cases.retain(|path| path.join("expected.yaml").exists());
+    cases.sort();
+
+    let mut total = Score::default();
+    for case in cases {
+        let score = run_case(&case).with_context(|| format!("Case {:?} failed", case))?;
+        println!("{:?}: {}", case.file_name().unwrap_or_default(), score);
+        total.add(&score);
+    }
+    println!("Total: {}", total);
+    Ok(())
+}
+
+#[test]
+fn test_score() {
+    let expected = Expected {
+        anomalies: vec![Range { start: 10, end: 12 }, Range { start: 42, end: 42 }],
+    };
+    let score = Score::new(&expected, &[3, 10, 11]);
+    assert_eq!(score.true_positives, 2);
+    assert_eq!(score.false_positives, 1);
+    assert_eq!(score.found_ranges, 1);
+    assert_eq!(score.recall(), 0.5);
+}
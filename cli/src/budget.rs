@@ -0,0 +1,97 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the anomaly budget, a lightweight regression detection between builds.
+//!
+//! The anomaly count per index of the previous run is stored in a state file, and the
+//! indexes whose count exceed the previous one by more than the margin are reported.
+//! The state file is only updated when there is no regression, so that a regression is
+//! reported until it is fixed.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The anomaly count per index name.
+pub type Counts = BTreeMap<String, usize>;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Regression {
+    pub index: String,
+    pub previous: usize,
+    pub current: usize,
+}
+
+impl std::fmt::Display for Regression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} anomalies, previously {}",
+            self.index, self.current, self.previous
+        )
+    }
+}
+
+/// Compare the counts with the state file, then update it with the current counts when
+/// there is no regression.
+pub fn check(path: &Path, margin: f32, counts: &Counts) -> Result<Vec<Regression>> {
+    let previous: Counts = match std::fs::File::open(path) {
+        Ok(file) => serde_json::from_reader(file).context("Invalid budget state file")?,
+        Err(_) => Counts::new(),
+    };
+    let regressions = regressions(&previous, margin, counts);
+    if regressions.is_empty() {
+        let file = std::fs::File::create(path).context("Can't create budget state file")?;
+        serde_json::to_writer_pretty(file, counts).context("Can't write budget state file")?;
+    }
+    Ok(regressions)
+}
+
+fn regressions(previous: &Counts, margin: f32, counts: &Counts) -> Vec<Regression> {
+    counts
+        .iter()
+        .filter_map(|(index, current)| {
+            let previous = *previous.get(index)?;
+            let allowed = previous + (previous as f32 * margin / 100.0).ceil() as usize;
+            if *current > allowed {
+                Some(Regression {
+                    index: index.clone(),
+                    previous,
+                    current: *current,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
+}
+
+#[test]
+fn test_regressions() {
+    let counts =
+        |xs: &[(&str, usize)]| -> Counts { xs.iter().map(|(k, v)| (k.to_string(), *v)).collect() };
+    let previous = counts(&[("a", 10), ("b", 0), ("c", 5)]);
+    let current = counts(&[("a", 11), ("b", 1), ("c", 5), ("new", 42)]);
+    assert_eq!(
+        regressions(&previous, 10.0, &current),
+        vec![Regression {
+            index: "b".to_string(),
+            previous: 0,
+            current: 1
+        }]
+    );
+    assert_eq!(regressions(&previous, 0.0, &current).len(), 2);
+}
+
+#[test]
+fn test_check() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("budget.json");
+    let counts = |v: usize| -> Counts { std::iter::once(("a".to_string(), v)).collect() };
+    assert_eq!(check(&path, 0.0, &counts(1)).unwrap(), vec![]);
+    assert_eq!(check(&path, 0.0, &counts(2)).unwrap().len(), 1);
+    // The regression is still reported by the next run.
+    assert_eq!(check(&path, 0.0, &counts(2)).unwrap().len(), 1);
+    assert_eq!(check(&path, 0.0, &counts(1)).unwrap(), vec![]);
+}
@@ -0,0 +1,98 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the terminal colors of the live output.
+
+use clap::ValueEnum;
+use std::collections::HashSet;
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum ColorChoice {
+    /// Use colors when the output is a terminal and NO_COLOR is not set.
+    /// On Windows, only the Windows Terminal is known to support the colors.
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn enabled(&self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            ColorChoice::Auto => {
+                std::env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+                    && atty::is(atty::Stream::Stdout)
+                    && (!cfg!(windows) || std::env::var_os("WT_SESSION").is_some())
+            }
+        }
+    }
+}
+
+const RESET: &str = "\x1b[0m";
+const DIM: &str = "\x1b[2m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+
+#[derive(Clone, Copy)]
+pub struct Style {
+    enabled: bool,
+}
+
+impl Style {
+    pub fn new(choice: ColorChoice) -> Style {
+        Style {
+            enabled: choice.enabled(),
+        }
+    }
+
+    /// Dim a context line.
+    pub fn context(&self, line: &str) -> String {
+        if self.enabled {
+            format!("{}{}{}", DIM, line, RESET)
+        } else {
+            line.to_string()
+        }
+    }
+
+    /// Color an anomaly by its distance, and highlight the words that are not in its context.
+    pub fn anomaly(&self, distance: f32, line: &str, context: &[String]) -> String {
+        if !self.enabled {
+            return line.to_string();
+        }
+        let color = if distance > 0.7 {
+            RED
+        } else if distance > 0.5 {
+            YELLOW
+        } else {
+            ""
+        };
+        let known = context
+            .iter()
+            .flat_map(|line| line.split_whitespace())
+            .collect::<HashSet<_>>();
+        let words = line
+            .split(' ')
+            .map(|word| {
+                if word.is_empty() || known.contains(word) {
+                    word.to_string()
+                } else {
+                    format!("{}{}{}{}", BOLD, word, RESET, color)
+                }
+            })
+            .collect::<Vec<_>>();
+        format!("{}{}{}", color, words.join(" "), RESET)
+    }
+}
+
+#[test]
+fn test_style() {
+    let plain = Style { enabled: false };
+    assert_eq!(plain.anomaly(0.9, "new error", &[]), "new error");
+    let style = Style { enabled: true };
+    assert_eq!(
+        style.anomaly(0.4, "task failed", &["task started".to_string()]),
+        format!("task {}failed{}{}", BOLD, RESET, RESET)
+    );
+}
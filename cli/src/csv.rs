@@ -0,0 +1,65 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module exports the anomalies as csv, to triage them in a spreadsheet.
+//!
+//! The fields that start like a formula, e.g. `=cmd` or `+ make`, are prefixed with a quote so
+//! that the spreadsheets show them as text instead of evaluating them.
+
+use anyhow::{Context, Result};
+use logreduce_model::Report;
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::Path;
+
+const HEADER: &str = "source,line,score,level,message";
+
+fn field(value: &str) -> Cow<'_, str> {
+    let value = if value.starts_with(['=', '+', '-', '@'].as_ref()) {
+        Cow::Owned(format!("'{}", value))
+    } else {
+        Cow::Borrowed(value)
+    };
+    if value.contains([',', '"', '\n', '\r'].as_ref()) {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        value
+    }
+}
+
+fn write(writer: &mut impl Write, report: &Report) -> std::io::Result<()> {
+    writeln!(writer, "{}", HEADER)?;
+    for log_report in &report.log_reports {
+        let source = log_report.source.get_relative();
+        for anomaly in &log_report.anomalies {
+            let level = anomaly.anomaly.level.map(|level| level.to_string());
+            writeln!(
+                writer,
+                "{},{},{:.2},{},{}",
+                field(&source),
+                anomaly.anomaly.pos,
+                anomaly.anomaly.distance,
+                level.unwrap_or_default(),
+                field(&anomaly.anomaly.line)
+            )?;
+        }
+    }
+    Ok(())
+}
+
+pub fn save(report: &Report, path: &Path) -> Result<()> {
+    let mut file =
+        std::io::BufWriter::new(std::fs::File::create(path).context("Can't create csv file")?);
+    write(&mut file, report)
+        .and_then(|_| file.flush())
+        .context("Can't write csv")
+}
+
+#[test]
+fn test_field() {
+    assert_eq!(field("Service started"), "Service started");
+    assert_eq!(field("a, b"), "\"a, b\"");
+    assert_eq!(field("say \"hi\""), "\"say \"\"hi\"\"\"");
+    assert_eq!(field("=1+1"), "'=1+1");
+    assert_eq!(field("+ make, check"), "\"'+ make, check\"");
+}
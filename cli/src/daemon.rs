@@ -0,0 +1,223 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the daemon mode, to follow the journal under systemd.
+//!
+//! The daemon is meant to run with a unit like this:
+//!
+//! ```ini
+//! [Service]
+//! Type=notify
+//! ExecStart=logreduce-cli --model /var/lib/logreduce/journal.bin journald --daemon
+//! ExecReload=kill -HUP $MAINPID
+//! StateDirectory=logreduce
+//! ```
+//!
+//! The anomalies are written to the journal with the `logreduce-anomaly` identifier,
+//! or as json files in the spool directory, with their secrets masked, see [Redactor]. The entries with this identifier are not inspected.
+//! On SIGHUP, the model is loaded again, and the previous model is kept when the new one is
+//! invalid. This module is only available on linux.
+
+use anyhow::{Context, Result};
+use libsystemd::daemon::{notify, NotifyState};
+use logreduce_model::redact::Redactor;
+use logreduce_model::{streaming, IndexName, Model};
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+const CONTEXT_SIZE: usize = 3;
+
+#[derive(Serialize, Debug)]
+struct SpoolAnomaly<'a> {
+    id: &'a str,
+    created_at: u64,
+    distance: f32,
+    line: &'a str,
+    before: &'a VecDeque<String>,
+}
+
+/// Where the anomalies are written.
+enum Output {
+    Journal,
+    Spool(PathBuf, usize),
+}
+
+impl Output {
+    fn write(
+        &mut self,
+        id: &str,
+        distance: f32,
+        line: &str,
+        before: &VecDeque<String>,
+    ) -> Result<()> {
+        match self {
+            Output::Journal => libsystemd::logging::journal_send(
+                libsystemd::logging::Priority::Warning,
+                line,
+                vec![
+                    (
+                        "SYSLOG_IDENTIFIER",
+                        streaming::ANOMALY_IDENTIFIER.to_string(),
+                    ),
+                    ("LOGREDUCE_ANOMALY_ID", id.to_string()),
+                    ("LOGREDUCE_DISTANCE", format!("{:.2}", distance)),
+                ]
+                .into_iter(),
+            )
+            .map_err(|e| anyhow::anyhow!("Can't write to the journal: {}", e)),
+            Output::Spool(dir, count) => {
+                let created_at = SystemTime::now()
+                    .duration_since(SystemTime::UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0);
+                *count += 1;
+                let path = dir.join(format!("{}-{}.json", created_at, count));
+                // Write to a temporary file first so that the consumers never see partial file.
+                let tmp = path.with_extension("tmp");
+                let anomaly = SpoolAnomaly {
+                    id,
+                    created_at,
+                    distance,
+                    line,
+                    before,
+                };
+                let file = std::fs::File::create(&tmp).context("Can't create spool file")?;
+                serde_json::to_writer(file, &anomaly).context("Can't write spool file")?;
+                std::fs::rename(&tmp, &path).context("Can't rename spool file")
+            }
+        }
+    }
+}
+
+/// Load the model and pick the index used for the journal lines.
+fn load(model_path: &Path, index_name: Option<&str>) -> Result<(Model, IndexName)> {
+    let model = Model::load(model_path)?;
+    let index_name = crate::exec::select_index(&model, index_name)?;
+    Ok((model, index_name))
+}
+
+fn notify_state(state: NotifyState) {
+    if let Err(e) = notify(false, &[state]) {
+        tracing::warn!("Can't notify systemd: {}", e);
+    }
+}
+
+/// Inspect the journal entries until journalctl stops.
+fn follow(
+    model: &Model,
+    index_name: &IndexName,
+    cursor_file: &Path,
+    output: &mut Output,
+    redactor: &Redactor,
+    signals: &[Arc<AtomicBool>; 2],
+) -> Result<()> {
+    let index = model.get_index(index_name).expect("Checked index");
+    tracing::info!("Following the journal with the {} index", index_name);
+
+    let (reader, journal) = streaming::follow_journal("", Some(cursor_file))?;
+
+    // Stop journalctl on signal, so that the reader below reaches the end.
+    let watcher = {
+        let (journal, signals) = (journal.clone(), signals.clone());
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_millis(200));
+            if signals.iter().any(|signal| signal.load(Ordering::Relaxed)) {
+                journal.stop();
+                break;
+            }
+            if !journal.is_running() {
+                break;
+            }
+        })
+    };
+    notify_state(NotifyState::Ready);
+
+    let mut scorer = index.line_scorer(Some(index_name));
+    let mut before = VecDeque::with_capacity(CONTEXT_SIZE);
+    let mut write = |scored: logreduce_model::process::ScoredLine| -> Result<()> {
+        let line = redactor.redact(&scored.line).into_owned();
+        if scored.distance > logreduce_model::process::THRESHOLD {
+            output.write(&scored.id, scored.distance, &line, &before)?;
+        }
+        if before.len() == CONTEXT_SIZE {
+            before.pop_front();
+        }
+        before.push_back(line);
+        Ok(())
+    };
+    // The messages are read line by line, a message may have multiple lines.
+    for line in std::io::BufReader::new(reader).lines() {
+        for scored in scorer.push(line?) {
+            write(scored)?;
+        }
+    }
+    for scored in scorer.finish() {
+        write(scored)?;
+    }
+    watcher.join().expect("Watcher thread");
+    journal.wait()?;
+    Ok(())
+}
+
+pub fn run(
+    model_path: &Path,
+    index: Option<&str>,
+    spool: Option<PathBuf>,
+    redactor: &Redactor,
+) -> Result<()> {
+    let reload = Arc::new(AtomicBool::new(false));
+    let terminate = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload))?;
+    signal_hook::flag::register(signal_hook::consts::SIGTERM, Arc::clone(&terminate))?;
+    signal_hook::flag::register(signal_hook::consts::SIGINT, Arc::clone(&terminate))?;
+
+    let cursor_file = std::env::var_os("STATE_DIRECTORY")
+        .map(PathBuf::from)
+        .unwrap_or_else(std::env::temp_dir)
+        .join("logreduce-journal.cursor");
+    let mut output = match spool {
+        Some(dir) => {
+            std::fs::create_dir_all(&dir).context("Can't create spool directory")?;
+            Output::Spool(dir, 0)
+        }
+        None => Output::Journal,
+    };
+
+    let (mut model, mut index_name) = load(model_path, index)?;
+    let signals = [reload.clone(), terminate.clone()];
+    loop {
+        follow(
+            &model,
+            &index_name,
+            &cursor_file,
+            &mut output,
+            redactor,
+            &signals,
+        )?;
+
+        if terminate.load(Ordering::Relaxed) {
+            notify_state(NotifyState::Stopping);
+            return Ok(());
+        } else if reload.swap(false, Ordering::Relaxed) {
+            tracing::info!("Reloading the model");
+            notify_state(NotifyState::Reloading);
+            match load(model_path, index) {
+                Ok(loaded) => {
+                    model = loaded.0;
+                    index_name = loaded.1;
+                }
+                // A bad model must not stop the daemon, the previous one is kept.
+                Err(e) => {
+                    tracing::error!("Can't reload the model, keeping the previous one: {:#}", e)
+                }
+            }
+        } else {
+            return Err(anyhow::anyhow!("journalctl stopped unexpectedly"));
+        }
+    }
+}
@@ -5,7 +5,7 @@ use anyhow::Result;
 use serde::{Deserialize, Serialize};
 use std::ffi::OsStr;
 use std::iter::zip;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use logreduce_model::{AnomalyContext, Content, IndexName, Model, OutputMode, Source};
 
@@ -35,55 +35,58 @@ pub fn test_datasets(paths: &[String]) -> Result<()> {
     Ok(())
 }
 
-fn process(path: &Path, dataset: Dataset) -> Result<()> {
-    let expected_count = dataset.anomalies.len();
+/// Find the `.good` baseline and the `.fail` target of a case directory.
+pub fn case_files(path: &Path) -> Result<(PathBuf, PathBuf)> {
     let paths = std::fs::read_dir(path)?
-        .map(|d| d.unwrap().path())
-        .collect::<Vec<std::path::PathBuf>>();
-    match (
-        paths
-            .iter()
-            .find(|p| p.extension() == Some(OsStr::new("good"))),
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    let find = |ext| {
         paths
             .iter()
-            .find(|p| p.extension() == Some(OsStr::new("fail"))),
-    ) {
-        (Some(good), Some(fail)) => {
-            let om = OutputMode::Debug;
-            let model = Model::train(
-                om,
-                [Content::from_pathbuf(good.to_path_buf())].to_vec(),
-                logreduce_model::hashing_index::new,
-            )?;
-            let index = model.get_index(&IndexName("".to_string())).unwrap();
-            let anomalies = index
-                .inspect(
-                    om,
-                    &Source::from_pathbuf(fail.to_path_buf()),
-                    &mut std::collections::HashSet::new(),
-                )
-                .collect::<Result<Vec<AnomalyContext>>>()?;
-            let anomalies_count = anomalies.len();
-            for (expected, anomaly) in zip(dataset.anomalies, anomalies) {
-                assert_anomaly_includes(expected.line, anomaly)?
-            }
-            if anomalies_count != expected_count {
-                Err(anyhow::anyhow!(
-                    "Expect miss-match: expected {}, got {}",
-                    expected_count,
-                    anomalies_count,
-                ))
-            } else {
-                Ok(())
-            }
-        }
-        _err => Err(anyhow::anyhow!(
+            .find(|p| p.extension() == Some(OsStr::new(ext)))
+            .cloned()
+    };
+    match (find("good"), find("fail")) {
+        (Some(good), Some(fail)) => Ok((good, fail)),
+        _ => Err(anyhow::anyhow!(
             "Can't find .good and .fail files in {:?}",
             paths
         )),
     }
 }
 
+fn process(path: &Path, dataset: Dataset) -> Result<()> {
+    let expected_count = dataset.anomalies.len();
+    let (good, fail) = case_files(path)?;
+    let om = OutputMode::Debug;
+    let model = Model::train(
+        &om,
+        [Content::from_pathbuf(good)].to_vec(),
+        logreduce_model::hashing_index::new,
+    )?;
+    let index = model.get_index(&IndexName("".to_string())).unwrap();
+    let anomalies = index
+        .inspect(
+            &om,
+            &Source::from_pathbuf(fail),
+            &mut std::collections::HashSet::new(),
+        )
+        .collect::<Result<Vec<AnomalyContext>>>()?;
+    let anomalies_count = anomalies.len();
+    for (expected, anomaly) in zip(dataset.anomalies, anomalies) {
+        assert_anomaly_includes(expected.line, anomaly)?
+    }
+    if anomalies_count != expected_count {
+        Err(anyhow::anyhow!(
+            "Expect miss-match: expected {}, got {}",
+            expected_count,
+            anomalies_count,
+        ))
+    } else {
+        Ok(())
+    }
+}
+
 fn assert_anomaly_includes(line: String, anomaly: AnomalyContext) -> Result<()> {
     if anomaly.anomaly.line.contains(line.trim()) {
         Ok(())
@@ -0,0 +1,108 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module prints what an analysis would do, without downloading or training anything.
+//!
+//! The plan only needs the directory listings, and the sizes of the remote files.
+
+use anyhow::Result;
+use itertools::Itertools;
+use logreduce_model::{IndexName, Model, Source};
+use std::collections::HashMap;
+
+/// The work to be done for a list of sources.
+struct Work {
+    count: usize,
+    size: u64,
+    unknown: usize,
+}
+
+impl Work {
+    fn new(sources: &[Source]) -> Work {
+        let sizes = sources
+            .iter()
+            .map(|source| source.size())
+            .collect::<Vec<_>>();
+        Work {
+            count: sources.len(),
+            size: sizes.iter().flatten().sum(),
+            unknown: sizes.iter().filter(|size| size.is_none()).count(),
+        }
+    }
+}
+
+impl std::fmt::Display for Work {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} sources, {}", self.count, human_size(self.size))?;
+        if self.unknown > 0 {
+            write!(f, " (+{} of unknown size)", self.unknown)?;
+        }
+        Ok(())
+    }
+}
+
+fn human_size(bytes: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, units[unit])
+}
+
+/// Show the training and the inspection plan.
+pub fn with_baselines(
+    train_groups: &HashMap<IndexName, Vec<Source>>,
+    target_sources: &[Source],
+) -> Result<()> {
+    println!("Training:");
+    let mut train_total = Vec::new();
+    for (index_name, sources) in train_groups.iter().sorted_by(|x, y| Ord::cmp(&x.0, &y.0)) {
+        println!("  {}: {}", index_name, Work::new(sources));
+        train_total.extend(sources.iter().cloned());
+    }
+    println!("  total: {}", Work::new(&train_total));
+    inspect_plan(target_sources, |index_name| {
+        train_groups.contains_key(index_name)
+            || train_groups.contains_key(&IndexName::global())
+            || train_groups.len() == 1
+    })
+}
+
+/// Show the inspection plan using an existing model.
+pub fn with_model(model: &Model, target_sources: &[Source]) -> Result<()> {
+    inspect_plan(target_sources, |index_name| {
+        model.get_index(index_name).is_some()
+    })
+}
+
+fn inspect_plan(sources: &[Source], has_index: impl Fn(&IndexName) -> bool) -> Result<()> {
+    let (known, unknown): (Vec<_>, Vec<_>) = Source::group_by_index(sources.to_vec())
+        .drain()
+        .sorted_by(|x, y| Ord::cmp(&x.0, &y.0))
+        .partition(|(index_name, _)| has_index(index_name));
+    println!("Inspecting:");
+    let mut inspect_total = Vec::new();
+    for (index_name, sources) in known {
+        println!("  {}: {}", index_name, Work::new(&sources));
+        inspect_total.extend(sources);
+    }
+    println!("  total: {}", Work::new(&inspect_total));
+    if !unknown.is_empty() {
+        println!("No baselines:");
+        for (_, sources) in unknown {
+            for source in sources {
+                println!("  {}", source);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_human_size() {
+    assert_eq!(human_size(42), "42.0 B");
+    assert_eq!(human_size(3 * 1024 * 1024), "3.0 MB");
+}
@@ -0,0 +1,93 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the eval command, to measure the false positives of a model.
+//!
+//! The model is trained on some passing runs and every anomaly found in another passing
+//! run, the holdout, is a false positive.
+
+use anyhow::Result;
+use logreduce_model::{ChunkIndex, Content, Input, Model, OutputMode};
+
+/// The thresholds used to show the scores distribution.
+const THRESHOLDS: [f32; 7] = [0.3, 0.4, 0.5, 0.6, 0.7, 0.8, 0.9];
+
+struct Evaluation {
+    line_count: usize,
+    /// The number of anomalies above each of the THRESHOLDS.
+    anomaly_counts: Vec<usize>,
+}
+
+impl Evaluation {
+    fn new(line_count: usize, distances: &[f32]) -> Evaluation {
+        let anomaly_counts = THRESHOLDS
+            .iter()
+            .map(|threshold| distances.iter().filter(|d| *d > threshold).count())
+            .collect();
+        Evaluation {
+            line_count,
+            anomaly_counts,
+        }
+    }
+
+    fn rate(&self, count: usize) -> f32 {
+        if self.line_count == 0 {
+            0.0
+        } else {
+            count as f32 * 100.0 / self.line_count as f32
+        }
+    }
+}
+
+impl std::fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "  {} lines", self.line_count)?;
+        writeln!(f, "  threshold | anomalies | false-positive rate")?;
+        for (threshold, count) in THRESHOLDS.iter().zip(&self.anomaly_counts) {
+            writeln!(
+                f,
+                "  {:>9.1} | {:>9} | {:.3}%",
+                threshold,
+                count,
+                self.rate(*count)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub fn eval(
+    output_mode: OutputMode,
+    mk_index: impl Fn() -> ChunkIndex,
+    baselines: Vec<String>,
+    holdouts: Vec<String>,
+) -> Result<()> {
+    let baselines = baselines
+        .into_iter()
+        .map(Input::from_string)
+        .map(Content::from_input)
+        .collect::<Result<Vec<_>>>()?;
+    let model = Model::train(&output_mode, baselines, mk_index)?;
+    for holdout in holdouts {
+        let content = Content::from_input(Input::from_string(holdout))?;
+        let report = model.report(&output_mode, content)?;
+        let distances = report
+            .log_reports
+            .iter()
+            .flat_map(|lr| lr.anomalies.iter().map(|a| a.anomaly.distance))
+            .collect::<Vec<_>>();
+        if output_mode.inlined() {
+            println!();
+        }
+        println!("{}:", report.target);
+        print!("{}", Evaluation::new(report.total_line_count, &distances));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_evaluation() {
+    let evaluation = Evaluation::new(200, &[0.35, 0.55, 0.95]);
+    assert_eq!(evaluation.anomaly_counts, vec![3, 2, 1, 1, 1, 1, 1]);
+    assert_eq!(evaluation.rate(2), 1.0);
+}
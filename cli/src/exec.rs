@@ -0,0 +1,205 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the exec mode, to run a command and inspect its output as it runs:
+//!
+//! ```shell
+//! logreduce-cli --model tests.bin exec -- tox -e py3
+//! ```
+//!
+//! The command output is written as it runs, and it is inspected like a source, with the
+//! context, the level filter and the rules of the other commands. The anomalies are printed with
+//! their context once their chunk of lines is scored. The run finishes with the command status,
+//! see [Failed], so that a flaky test run can be analyzed without saving its output first. The
+//! command is run with [streaming::spawn], which reads the stdout and stderr lines concurrently,
+//! so their relative order is only approximate.
+
+use anyhow::Result;
+use logreduce_model::{streaming, AnomalyContext, IndexName, Model};
+use std::collections::HashSet;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use crate::{annotations, color, Options};
+
+/// The error of a command that failed, to exit with its status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Failed(pub i32);
+
+impl std::fmt::Display for Failed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "The command exited with status {}", self.0)
+    }
+}
+
+impl std::error::Error for Failed {}
+
+/// The exit code of the status, which is 128 plus the signal number when the command was killed,
+/// like in a shell.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(1)
+}
+
+/// Pick the index used for the lines: the given one, or the only index of the model.
+pub fn select_index(model: &Model, index_name: Option<&str>) -> Result<IndexName> {
+    let index_name = match index_name {
+        Some(name) => IndexName(name.to_string()),
+        None => match model.index_names().collect::<Vec<_>>().as_slice() {
+            [name] => (*name).clone(),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "The model has multiple indexes, use --index"
+                ))
+            }
+        },
+    };
+    if model.get_index(&index_name).is_none() {
+        return Err(anyhow::anyhow!("Unknown index: {}", index_name));
+    }
+    Ok(index_name)
+}
+
+/// Print an anomaly with its context, after the anomalies already printed up to `last_pos`.
+fn print_anomaly(
+    options: &Options,
+    style: &color::Style,
+    anomaly: &mut AnomalyContext,
+    last_pos: Option<usize>,
+) {
+    if let Some(last_pos) = last_pos {
+        anomaly.trim_before(last_pos);
+    }
+    let starting_pos = anomaly.anomaly.pos.saturating_sub(1 + anomaly.before.len());
+    if last_pos.map_or(false, |last_pos| last_pos != starting_pos) {
+        println!("--");
+    }
+    for (idx, line) in anomaly.before.iter().enumerate() {
+        println!("   {} | {}", starting_pos + 1 + idx, style.context(line));
+    }
+    let context = [&anomaly.before[..], &anomaly.after[..]].concat();
+    for (pos, line) in anomaly.anomaly.lines() {
+        println!(
+            "{:02.0} {} | {}",
+            anomaly.anomaly.distance * 99.0,
+            pos,
+            style.anomaly(anomaly.anomaly.distance, line, &context)
+        );
+    }
+    if let Some(hint) = &anomaly.anomaly.hint {
+        match &hint.link {
+            Some(link) => println!(" -> Known error: {} ({})", hint.category, link),
+            None => println!(" -> Known error: {}", hint.category),
+        }
+    }
+    if anomaly.anomaly.run > 0 {
+        println!(
+            " -> Repeated {}× until line {}",
+            anomaly.anomaly.run,
+            anomaly.after_pos()
+        );
+    }
+    if options.show_ids {
+        println!(" -> Id: {}", anomaly.anomaly.id);
+    }
+    for (idx, line) in anomaly.after.iter().enumerate() {
+        println!(
+            "   {} | {}",
+            anomaly.after_pos() + 1 + idx,
+            style.context(line)
+        );
+    }
+}
+
+pub fn run(
+    options: &Options,
+    model_path: &Path,
+    index_name: Option<&str>,
+    command: &[String],
+) -> Result<()> {
+    let model = options.load_model(model_path)?;
+    let index_name = select_index(&model, index_name)?;
+    let index = model.get_index(&index_name).expect("Checked index");
+    let rules = options.rules()?;
+    let redactor = options.redactor();
+    let level_filter = options.level_filter();
+    let style = color::Style::new(options.color);
+    // The command line may contain a secret, like its output.
+    let name = redactor.redact(&command.join(" ")).into_owned();
+
+    // The command output is written as it runs, and its anomalies are printed once scored.
+    let (reader, handle) = streaming::spawn(command, true)?;
+    let mut skip_lines = HashSet::new();
+    let mut processor = index
+        .get_reader_processor(reader, false, &mut skip_lines)
+        .with_cancellation(Some(options.cancellation().clone()))
+        .with_index_name(index_name.clone());
+    let mut anomalies = Vec::new();
+    let mut last_pos = None;
+    for anomaly in processor.by_ref() {
+        let mut anomaly = anomaly?;
+        if !level_filter.keep(&mut anomaly.anomaly) || rules.is_suppressed(&anomaly.anomaly) {
+            continue;
+        }
+        rules.annotate(&mut anomaly.anomaly);
+        redactor.redact_context(&mut anomaly);
+        print_anomaly(options, &style, &mut anomaly, last_pos);
+        last_pos = Some(anomaly.last_pos());
+        anomalies.push(anomaly);
+    }
+    if processor.cancelled {
+        handle.stop();
+    }
+    let status = handle.wait()?.map_or(1, exit_code);
+    println!(
+        "{}: exited with status {}, {} anomalies in {} lines",
+        name,
+        status,
+        anomalies.len(),
+        processor.line_count
+    );
+    if let Some(path) = &options.annotations {
+        let anomalies = anomalies.iter().map(|anomaly| (name.clone(), anomaly));
+        annotations::save_anomalies(options.annotations_format, anomalies, path)?;
+    }
+    if status == 0 {
+        Ok(())
+    } else {
+        Err(Failed(status).into())
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn test_exec() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let baseline = dir.join("output.txt");
+    std::fs::write(&baseline, "Running the tests\nRan 42 tests\nOK\n").unwrap();
+    let sources = logreduce_model::Content::from_pathbuf(baseline)
+        .get_sources()
+        .unwrap();
+    let index =
+        logreduce_model::Index::train(&sources, logreduce_model::hashing_index::new()).unwrap();
+
+    let script = "echo Running the tests; echo Traceback: KeyError >&2; echo Ran 12 tests; exit 3";
+    let command = ["sh", "-c", script].map(String::from);
+    let (reader, handle) = streaming::spawn(&command, false).unwrap();
+    let mut skip_lines = HashSet::new();
+    let mut processor = index
+        .get_reader_processor(reader, false, &mut skip_lines)
+        .with_index_name(IndexName("output.txt".to_string()));
+    let anomalies = processor
+        .by_ref()
+        .map(|anomaly| anomaly.unwrap().anomaly.line)
+        .collect::<Vec<_>>();
+    assert_eq!(handle.wait().unwrap().map(exit_code), Some(3));
+    assert_eq!(anomalies, vec!["Traceback: KeyError"]);
+    assert_eq!(processor.line_count, 3);
+}
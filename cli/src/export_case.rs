@@ -0,0 +1,175 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module packages a minimized reproduction of a run, to attach to a bug report.
+//!
+//! The lines are tokenized, deduplicated and redacted, so that the raw logs are not shared. Only
+//! the baselines of the target indexes are kept, and the `baselines.jsonl` file can be trained
+//! with `logreduce train --from-tokens`.
+
+use anyhow::{Context, Result};
+use logreduce_model::ngram::Vectorizer;
+use logreduce_model::prefix::SAMPLE_LINES;
+use logreduce_model::profile::Framing;
+use logreduce_model::redact::Redactor;
+use logreduce_model::{ChunkIndex, Content, Input, Metric, Precision, Source};
+use serde::Serialize;
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::Options;
+
+/// The settings of the run, to reproduce it.
+#[derive(Serialize)]
+struct Config {
+    version: String,
+    tokenizer_version: String,
+    metric: Metric,
+    vectorizer: Vectorizer,
+    precision: Precision,
+    features: Option<usize>,
+    damp_common_tokens: bool,
+    strip_prefix: bool,
+    source_profiles: bool,
+    json_blocks: bool,
+    context: String,
+    baseline_lines: usize,
+    target_lines: usize,
+}
+
+/// The unique redacted tokens of the lines, in the first seen order.
+fn minimize(
+    index: &ChunkIndex,
+    redactor: &Redactor,
+    framing: &Framing,
+    lines: impl IntoIterator<Item = String>,
+) -> Vec<String> {
+    let mut seen = HashSet::new();
+    lines
+        .into_iter()
+        .map(|line| {
+            redactor
+                .redact(&index.tokenize(&framing.apply(&line)))
+                .into_owned()
+        })
+        .filter(|tokens| !tokens.is_empty() && seen.insert(tokens.clone()))
+        .collect()
+}
+
+fn read_lines(source: &Source, json_blocks: bool) -> Result<Vec<String>> {
+    let reader = source.open()?;
+    let mut lines = Vec::new();
+    for line in
+        logreduce_iterator::BytesLines::new(reader, source.is_json()).with_json_blocks(json_blocks)
+    {
+        let (bytes, _) = line?;
+        lines.push(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    Ok(lines)
+}
+
+/// Write the minimized tokens of each source to the corpus, one source at a time, and return
+/// the number of lines and sources written.
+fn minimize_sources(
+    options: &Options,
+    index: &ChunkIndex,
+    redactor: &Redactor,
+    sources: &[Source],
+    path: &Path,
+) -> Result<(usize, usize)> {
+    let mut writer = logreduce_model::tokens::Writer::create(path)?;
+    let (mut line_count, mut source_count) = (0, 0);
+    for source in sources {
+        match read_lines(source, options.json_blocks) {
+            Ok(lines) => {
+                let sample = lines.iter().take(SAMPLE_LINES).map(|line| line.as_str());
+                let framing = Framing::detect(index, source.is_json(), sample);
+                let tokens = minimize(index, redactor, &framing, lines);
+                writer.write_source(&source.get_relative(), &tokens)?;
+                line_count += tokens.len();
+                source_count += 1;
+            }
+            Err(e) => tracing::error!("{}: failed to load: {}", source, e),
+        }
+    }
+    writer.finish()?;
+    Ok((line_count, source_count))
+}
+
+/// Write the reproduction of the target with the baselines in the output directory.
+pub fn export(
+    options: &Options,
+    output: &Path,
+    baselines: Vec<Input>,
+    target: Input,
+) -> Result<()> {
+    let filter = options.source_filter();
+    let index = options.new_index();
+    let redactor = options.redactor();
+
+    let target_sources = filter.apply(Content::from_input(target)?.get_sources()?);
+    let target_groups = Source::group_by_index(target_sources.iter().cloned());
+    let baselines = baselines
+        .into_iter()
+        .map(Content::from_input)
+        .collect::<Result<Vec<_>>>()?;
+    let mut baseline_sources = Content::group_sources_with(&baselines, &filter)?
+        .into_iter()
+        .filter(|(index_name, _)| target_groups.contains_key(index_name))
+        .flat_map(|(_, sources)| sources)
+        .collect::<Vec<_>>();
+    baseline_sources.sort_by(|x, y| x.as_str().cmp(y.as_str()));
+
+    std::fs::create_dir_all(output)?;
+    let (baseline_lines, baseline_count) = minimize_sources(
+        options,
+        &index,
+        &redactor,
+        &baseline_sources,
+        &output.join("baselines.jsonl"),
+    )?;
+    let (target_lines, target_count) = minimize_sources(
+        options,
+        &index,
+        &redactor,
+        &target_sources,
+        &output.join("target.jsonl"),
+    )?;
+    let config = Config {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        tokenizer_version: logreduce_tokenizer::VERSION.to_string(),
+        metric: options.metric.unwrap_or_default(),
+        vectorizer: options.vectorizer.unwrap_or_default(),
+        precision: options.precision.unwrap_or_default(),
+        features: options.features,
+        damp_common_tokens: options.damp_common_tokens,
+        strip_prefix: options.strip_prefix,
+        source_profiles: options.source_profiles,
+        json_blocks: options.json_blocks,
+        context: format!("{:?}", options.context),
+        baseline_lines,
+        target_lines,
+    };
+
+    let file =
+        std::fs::File::create(output.join("config.json")).context("Can't create config file")?;
+    serde_json::to_writer_pretty(file, &config).context("Can't write config")?;
+    println!(
+        "{:?}: {} baseline lines from {} sources, {} target lines from {} sources",
+        output, config.baseline_lines, baseline_count, config.target_lines, target_count
+    );
+    Ok(())
+}
+
+#[test]
+fn test_minimize() {
+    let index = logreduce_model::hashing_index::new();
+    let redactor = Redactor::new(Vec::new());
+    let lines = ["Starting job 42", "Starting job 43", "", "password=hunter2"]
+        .iter()
+        .map(|line| line.to_string());
+    let tokens = minimize(&index, &redactor, &Framing::default(), lines);
+    assert_eq!(tokens.len(), 2);
+    assert!(tokens[0].contains("Starting"));
+    assert!(!tokens[1].contains("hunter2"));
+}
@@ -0,0 +1,134 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module mirrors a remote artifact tree to a local directory, separately from the analysis.
+//!
+//! The files are stored by content in the `.objects` directory and hard linked at their
+//! relative path, so that the mirrors of many builds share their identical files.
+
+use anyhow::{Context, Result};
+use logreduce_model::cancel::CancellationToken;
+use logreduce_model::{Content, Input, OutputMode, Source, SourceFilter};
+use sha2::{Digest, Sha256};
+use std::io::{Read, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Copy the reader into the objects directory, and return the object path.
+fn store(objects: &Path, mut reader: impl Read) -> Result<(PathBuf, bool)> {
+    let tmp_path = objects.join(format!(".tmp-{}", std::process::id()));
+    let mut tmp = std::fs::File::create(&tmp_path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let count = reader.read(&mut buf)?;
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buf[..count]);
+        tmp.write_all(&buf[..count])?;
+    }
+    let digest = format!("{:x}", hasher.finalize());
+    let object = objects.join(&digest[..2]).join(&digest[2..]);
+    if object.exists() {
+        std::fs::remove_file(&tmp_path)?;
+        Ok((object, false))
+    } else {
+        std::fs::create_dir_all(object.parent().unwrap())?;
+        std::fs::rename(&tmp_path, &object)?;
+        Ok((object, true))
+    }
+}
+
+/// The local path of a source, refusing the paths that escape the mirror directory.
+fn local_path(into: &Path, relative: &str) -> Result<PathBuf> {
+    let relative = Path::new(relative.trim_start_matches('/'));
+    if relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        Ok(into.join(relative))
+    } else {
+        Err(anyhow::anyhow!("Invalid relative path: {:?}", relative))
+    }
+}
+
+/// Link the object at the given path, falling back to a copy across filesystems.
+fn link(object: &Path, path: &Path) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+    std::fs::hard_link(object, path)
+        .or_else(|_| std::fs::copy(object, path).map(|_| ()))
+        .with_context(|| format!("Failed to write {:?}", path))
+}
+
+pub fn run(
+    output_mode: OutputMode,
+    input: Input,
+    into: &Path,
+    filter: &SourceFilter,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    let content = Content::from_input(input)?;
+    let objects = into.join(".objects");
+    std::fs::create_dir_all(&objects)?;
+    let (mut file_count, mut object_count) = (0, 0);
+    for source in filter.apply(content.get_sources()?) {
+        // The files already mirrored are kept.
+        cancel.check()?;
+        let (prefix, url) = match &source {
+            Source::Remote(prefix, url) => (*prefix, url),
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "Only remote sources can be fetched: {}",
+                    source
+                ))
+            }
+        };
+        let path = local_path(into, &source.get_relative())?;
+        logreduce_model::debug_or_progress(output_mode, &format!("Fetching {}", source));
+        let (object, created) = store(&objects, Source::url_open_raw(prefix, url)?)?;
+        link(&object, &path)?;
+        file_count += 1;
+        if created {
+            object_count += 1;
+        }
+    }
+    if output_mode.inlined() {
+        println!();
+    }
+    println!(
+        "{:?}: {} files mirrored, {} new objects",
+        into, file_count, object_count
+    );
+    Ok(())
+}
+
+#[test]
+fn test_local_path() {
+    let into = Path::new("/srv/mirror");
+    assert_eq!(
+        local_path(into, "/logs/job-output.txt").unwrap(),
+        PathBuf::from("/srv/mirror/logs/job-output.txt")
+    );
+    assert!(local_path(into, "logs/../../etc/passwd").is_err());
+}
+
+#[test]
+fn test_store() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let (first, created) = store(dir, "hello".as_bytes()).unwrap();
+    assert!(created);
+    let (second, created) = store(dir, "hello".as_bytes()).unwrap();
+    assert!(!created);
+    assert_eq!(first, second);
+    link(&first, &dir.join("logs/hello.txt")).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(dir.join("logs/hello.txt")).unwrap(),
+        "hello"
+    );
+}
@@ -0,0 +1,178 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the run history, to spot the gradual log health degradation of a job.
+//!
+//! With `--record-history`, each run appends a summary record to a json lines file, by default
+//! `$XDG_DATA_HOME/logreduce/history.jsonl`, and the `history` command shows the trend per job.
+//! The oldest records are removed when the file grows over [MAX_SIZE].
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// The maximum size of the history file, the oldest half of the records are removed after it.
+pub const MAX_SIZE: u64 = 8 << 20;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Record {
+    pub created_at: u64,
+    /// The job name, or the target when it is not a zuul build.
+    pub job: String,
+    pub target: String,
+    pub line_count: usize,
+    pub anomaly_count: usize,
+    /// The sum of the anomaly distances, which also grows when the anomalies get stranger.
+    pub total_distance: f32,
+    pub run_time: f32,
+}
+
+impl Record {
+    pub fn new(
+        job: String,
+        target: String,
+        line_count: usize,
+        anomaly_count: usize,
+        total_distance: f32,
+        run_time: Duration,
+    ) -> Record {
+        Record {
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            job,
+            target,
+            line_count,
+            anomaly_count,
+            total_distance,
+            run_time: run_time.as_secs_f32(),
+        }
+    }
+}
+
+pub fn default_path() -> Result<PathBuf> {
+    xdg::BaseDirectories::with_prefix("logreduce")
+        .context("Failed to get xdg data directory")?
+        .place_data_file("history.jsonl")
+        .context("Can't create history directory")
+}
+
+pub fn append(path: &Path, record: &Record) -> Result<()> {
+    if std::fs::metadata(path).map_or(false, |metadata| metadata.len() > MAX_SIZE) {
+        truncate(path)?;
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .context("Can't open history file")?;
+    writeln!(file, "{}", serde_json::to_string(record)?).context("Can't write history")
+}
+
+/// Remove the oldest half of the records, the file is replaced so that it is never incomplete.
+fn truncate(path: &Path) -> Result<()> {
+    let content = std::fs::read_to_string(path).context("Can't read history file")?;
+    let lines = content.lines().collect::<Vec<_>>();
+    let tmp = path.with_extension("tmp");
+    let mut kept = lines[lines.len() / 2..].join("\n");
+    kept.push('\n');
+    std::fs::write(&tmp, kept).context("Can't write history file")?;
+    std::fs::rename(&tmp, path).context("Can't replace history file")
+}
+
+pub fn load(path: &Path) -> Result<Vec<Record>> {
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(err) => return Err(err).context("Can't open history file"),
+    };
+    let mut records = Vec::new();
+    for line in std::io::BufReader::new(file).lines() {
+        let line = line.context("Can't read history file")?;
+        // A record may be truncated when the disk is full, it is skipped.
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(err) => tracing::warn!("Skipping invalid history record: {}", err),
+        }
+    }
+    Ok(records)
+}
+
+fn delta(current: usize, previous: Option<usize>) -> String {
+    match previous {
+        Some(previous) if current > previous => format!("(+{})", current - previous),
+        Some(previous) if current < previous => format!("(-{})", previous - current),
+        Some(_) => "(=)".to_string(),
+        None => String::new(),
+    }
+}
+
+/// Print the records of each job, with the anomaly count change since the previous run.
+pub fn show(records: Vec<Record>, job: Option<&str>, last: usize) {
+    let jobs = records
+        .into_iter()
+        .filter(|record| job.map_or(true, |job| record.job == job))
+        .into_group_map_by(|record| record.job.clone());
+    for (job, mut records) in jobs.into_iter().sorted_by(|x, y| x.0.cmp(&y.0)) {
+        records.sort_by_key(|record| record.created_at);
+        println!("{}:", job);
+        let skip = records.len().saturating_sub(last);
+        let mut previous = skip.checked_sub(1).map(|pos| records[pos].anomaly_count);
+        for record in &records[skip..] {
+            let date = chrono::NaiveDateTime::from_timestamp_opt(record.created_at as i64, 0)
+                .map(|date| date.format("%Y-%m-%d %H:%M").to_string())
+                .unwrap_or_default();
+            println!(
+                "  {} {:>6} anomalies {:<7} distance {:>8.2} in {:.1}s, {}",
+                date,
+                record.anomaly_count,
+                delta(record.anomaly_count, previous),
+                record.total_distance,
+                record.run_time,
+                record.target
+            );
+            previous = Some(record.anomaly_count);
+        }
+    }
+}
+
+#[test]
+fn test_history() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("history.jsonl");
+    let record = |anomaly_count| {
+        Record::new(
+            "tox-py39".to_string(),
+            "https://zuul/build/42".to_string(),
+            1000,
+            anomaly_count,
+            anomaly_count as f32 * 0.5,
+            Duration::from_secs(3),
+        )
+    };
+    append(&path, &record(12)).unwrap();
+    append(&path, &record(15)).unwrap();
+    std::fs::OpenOptions::new()
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(b"{\"created_at\":"))
+        .unwrap();
+    let records = load(&path).unwrap();
+    let counts = records
+        .iter()
+        .map(|record| record.anomaly_count)
+        .collect::<Vec<_>>();
+    assert_eq!(counts, vec![12, 15]);
+    assert_eq!(records[0].job, "tox-py39");
+
+    // The oldest records are removed when the file is too large.
+    std::fs::write(&path, "1\n2\n3\n4\n").unwrap();
+    truncate(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "3\n4\n");
+    assert_eq!(delta(15, Some(12)), "(+3)");
+    assert_eq!(delta(12, None), "");
+}
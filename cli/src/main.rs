@@ -2,12 +2,42 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use anyhow::{Context, Result};
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 use itertools::Itertools;
-use logreduce_model::{Content, Input, Model, OutputMode, Source};
+use logreduce_model::cancel::{Cancellable, CancellationToken};
+use logreduce_model::level::{Level, LevelFilter};
+use logreduce_model::model_cache::ModelCache;
+use logreduce_model::ngram::Vectorizer;
+use logreduce_model::redact::Redactor;
+use logreduce_model::rules::Rules;
+use logreduce_model::scores::ScoresDb;
+use logreduce_model::{
+    Aggregation, Content, Input, Metric, Model, OutputMode, Precision, ProgressObserver, Source,
+    SourceFilter,
+};
 use std::path::PathBuf;
+use std::sync::Arc;
 
+mod annotations;
+mod benchmark;
+mod budget;
+mod color;
+mod csv;
+#[cfg(all(target_os = "linux", feature = "daemon"))]
+mod daemon;
 mod dataset;
+mod dry_run;
+mod eval;
+mod exec;
+mod export_case;
+mod fetch;
+mod history;
+mod pinning;
+mod provenance;
+mod update;
+#[cfg(any(feature = "amqp", feature = "nats"))]
+mod worker;
+mod zuul_artifact;
 
 #[derive(Parser)]
 #[clap(version, about, long_about = None)]
@@ -19,19 +49,614 @@ struct Cli {
     #[clap(
         long,
         parse(from_os_str),
-        help = "Load or save the model",
+        help = "Load or save the model, multiple models are inspected as an ensemble. \
+                A directory path stores each index in a separate file that is loaded on demand",
         value_name = "FILE"
     )]
-    model: Option<PathBuf>,
+    model: Vec<PathBuf>,
+
+    #[clap(flatten)]
+    options: Options,
 
     #[clap(subcommand)]
     command: Commands,
 }
 
+/// The options to tune the analysis.
+#[derive(Args, Debug)]
+struct Options {
+    #[clap(
+        long,
+        help = "The distance metric used when training a model: cosine (default), jaccard or \
+                weighted-hamming"
+    )]
+    metric: Option<Metric>,
+
+    #[clap(
+        long,
+        help = "The features extracted when training a model: words (default), trigrams or auto"
+    )]
+    vectorizer: Option<Vectorizer>,
+
+    #[clap(
+        long,
+        help = "The features storage when training a model: f32 (default), or int8 for a \
+                smaller model"
+    )]
+    precision: Option<Precision>,
+
+    #[clap(
+        long,
+        value_name = "2^N",
+        value_parser = logreduce_model::hashing_index::parse_features,
+        help = "The hashed features count when training a model, more features reduce the \
+                collisions of diverse logs but use more memory. Defaults to 260000"
+    )]
+    features: Option<usize>,
+
+    #[clap(
+        long,
+        help = "When training a model, down-weight the tokens that are in most of the lines of \
+                an index, e.g. a constant prefix, so that the distances are driven by the \
+                informative tokens"
+    )]
+    damp_common_tokens: bool,
+
+    #[clap(
+        long,
+        help = "When training a model, strip the constant prefix of the lines of each source, \
+                e.g. the timestamp, host and program of the syslog lines"
+    )]
+    strip_prefix: bool,
+
+    #[clap(
+        long,
+        help = "When training a model, classify each source as a console output, a service log \
+                or structured data, and read it accordingly, e.g. the ANSI escape codes and the \
+                shell trace markers are removed from the console outputs"
+    )]
+    source_profiles: bool,
+
+    #[clap(
+        long,
+        help = "When training a model, read the pretty-printed json objects as single lines"
+    )]
+    json_blocks: bool,
+
+    #[clap(
+        long,
+        value_name = "[INDEX=]MODE",
+        help = "When training a model, score the chunks of lines instead of each line: line, \
+                paragraph for the lines between the blank lines, or a line count. The INDEX glob \
+                selects the indexes, and the first matching value is used"
+    )]
+    granularity: Vec<logreduce_model::process::GranularityRule>,
+
+    #[cfg(feature = "embedding")]
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "DIR",
+        help = "Train with the embedding model.onnx and tokenizer.json of this directory"
+    )]
+    embedding_model: Option<PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "min",
+        help = "How the distances of multiple models are combined: min, mean or max"
+    )]
+    aggregation: Aggregation,
+
+    #[clap(
+        long,
+        help = "Use the model whose tags match the target, instead of an ensemble of the models"
+    )]
+    select_model: bool,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "Demote the anomalies that occur more than COUNT times in a source"
+    )]
+    self_consistency: Option<usize>,
+
+    #[clap(
+        long,
+        help = "Only use the provided baseline that is the most similar to the target"
+    )]
+    select_baseline: bool,
+
+    #[clap(
+        long,
+        help = "Show what would be downloaded, trained and inspected, without doing it"
+    )]
+    dry_run: bool,
+
+    #[clap(
+        long,
+        help = "Show what will be downloaded, trained and inspected, before doing it"
+    )]
+    show_plan: bool,
+
+    #[clap(
+        long,
+        value_name = "RATE",
+        value_parser = logreduce_model::net::parse_rate,
+        help = "The maximum download rate shared by all the downloads, e.g. 200K or 1M"
+    )]
+    limit_rate: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "The number of connections kept open per host, and the concurrent listings"
+    )]
+    connections_per_host: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "SECONDS",
+        help = "Stop inspecting a source after this time, keeping the anomalies already found. \
+                This is also the timeout of the downloads"
+    )]
+    source_timeout: Option<u64>,
+
+    #[clap(
+        long,
+        default_value = "8192",
+        value_name = "MB",
+        help = "The maximum size of a decompressed source, to stop the decompression bombs, \
+                0 for no limit"
+    )]
+    max_decompressed_size: u64,
+
+    #[clap(
+        long,
+        default_value = "500",
+        value_name = "RATIO",
+        help = "The maximum decompression ratio of a large compressed source, local or remote, \
+                0 for no limit"
+    )]
+    max_decompression_ratio: u64,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "The run history file, default to $XDG_DATA_HOME/logreduce/history.jsonl"
+    )]
+    history: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Add the run summary to the history, see the history command"
+    )]
+    record_history: bool,
+
+    #[clap(long, help = "Follow the symlinks of the local directories")]
+    follow_symlinks: bool,
+
+    #[clap(
+        long,
+        help = "Read only once the local files reachable through multiple paths, e.g. hardlinks"
+    )]
+    dedup_links: bool,
+
+    #[clap(
+        long,
+        help = "Split the zuul job-output.txt in one source per playbook, with their own indexes"
+    )]
+    split_job_output: bool,
+
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Only process the sources whose relative path matches this regex"
+    )]
+    include: Vec<regex::Regex>,
+
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Skip the sources whose relative path matches this regex"
+    )]
+    exclude: Vec<regex::Regex>,
+
+    #[clap(
+        long,
+        value_name = "PATH_GLOB",
+        parse(from_str = logreduce_model::glob_regex),
+        help = "Only analyze the sources matching this glob, the other files are not downloaded"
+    )]
+    only: Vec<regex::Regex>,
+
+    #[clap(
+        long,
+        help = "Print the anomalies of local uncompressed files as file:line:col locations, before their score"
+    )]
+    locations: bool,
+
+    #[clap(
+        long,
+        help = "Print the anomaly ids, to reference them in the rules or to acknowledge them with the grpc service"
+    )]
+    show_ids: bool,
+
+    #[clap(
+        long,
+        value_name = "N",
+        help = "Only print the first N anomalies of each source, and count the others"
+    )]
+    max_print: Option<usize>,
+
+    #[clap(
+        long,
+        default_value = "lines",
+        help = "The anomaly context: lines, or block to start at the enclosing traceback, task or test"
+    )]
+    context: logreduce_model::process::ContextMode,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Extend the built-in known error rules"
+    )]
+    rules: Option<PathBuf>,
+
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Mask the matches of this regex in the reported lines, in addition to the secrets"
+    )]
+    redact: Vec<regex::Regex>,
+
+    #[clap(
+        long,
+        help = "Do not mask the built-in secret patterns, e.g. passwords and tokens"
+    )]
+    no_redact: bool,
+
+    #[clap(
+        long,
+        value_name = "LEVEL",
+        help = "Ignore the anomalies below a log level: trace, debug, info, warn, error or fatal"
+    )]
+    min_level: Option<Level>,
+
+    #[clap(long, help = "Weight the anomaly distance by its log level")]
+    level_weight: bool,
+
+    #[clap(
+        long,
+        help = "Lower the distance of the lines close to a baseline line seen in many sources"
+    )]
+    frequency_weight: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "auto",
+        help = "Color the live output"
+    )]
+    color: color::ColorChoice,
+
+    #[clap(long, help = "Page the live output through $PAGER, or less -R")]
+    pager: bool,
+
+    #[clap(
+        long,
+        help = "Print a notice when a new version is available, checked once a day"
+    )]
+    check_version: bool,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Write the run provenance record as json"
+    )]
+    provenance: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Add the report to the zuul-manifest.json and write the zuul-return.json artifact"
+    )]
+    zuul_artifact: bool,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "DIR",
+        help = "Brand the html report with the theme.yaml, style.css and template.html of DIR"
+    )]
+    report_theme: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Write the report anomalies as review annotations"
+    )]
+    annotations: Option<PathBuf>,
+
+    #[clap(long, value_enum, default_value = "github")]
+    annotations_format: annotations::Format,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Write the report anomalies as csv: source, line, score, level and message"
+    )]
+    csv: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Store the score of every line in this sqlite database, to tune the threshold"
+    )]
+    scores_db: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Fail when the anomaly count of an index exceeds the one recorded in FILE"
+    )]
+    budget: Option<PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "10",
+        value_name = "PERCENT",
+        help = "The anomaly count increase allowed by the budget"
+    )]
+    budget_margin: f32,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Write the sources without baselines and the actions to cover them as json"
+    )]
+    coverage_gaps: Option<PathBuf>,
+
+    #[clap(
+        long,
+        help = "Write the report with the anomalies already found when the run is interrupted"
+    )]
+    save_partial: bool,
+
+    #[clap(
+        long,
+        value_enum,
+        value_name = "INDEX",
+        help = "Inspect the sources without baselines with a global index of every baseline"
+    )]
+    fallback_index: Option<FallbackIndex>,
+
+    #[clap(
+        long,
+        value_enum,
+        default_value = "source",
+        help = "Show the anomalies of each index together, e.g. of the rotated files of every host"
+    )]
+    group_by: GroupBy,
+
+    /// Only inspect the targets that changed since this tree, set by `diff --only-changed`.
+    #[clap(skip)]
+    changed_since: Option<PathBuf>,
+
+    /// The index of the stream lines, set by `stream --index`.
+    #[clap(skip)]
+    stream_index: Option<logreduce_model::IndexName>,
+
+    /// Cancelled by the first Ctrl-C, see [Options::cancellation].
+    #[clap(skip)]
+    cancellation: CancellationToken,
+
+    /// The models kept in memory by the worker, see [ModelCache].
+    #[clap(skip)]
+    model_cache: Option<ModelCache>,
+}
+
+impl Options {
+    fn level_filter(&self) -> LevelFilter {
+        LevelFilter {
+            min_level: self.min_level,
+            weighted: self.level_weight,
+        }
+    }
+
+    /// The token cancelled by the first Ctrl-C, the second one exits immediately.
+    /// The handler is only installed by the commands that check the token.
+    fn cancellation(&self) -> &CancellationToken {
+        static INTERRUPT: std::sync::Once = std::sync::Once::new();
+        INTERRUPT.call_once(|| {
+            use signal_hook::consts::SIGINT;
+            let flag = self.cancellation.flag();
+            if let Err(e) = signal_hook::flag::register_conditional_shutdown(SIGINT, 130, flag)
+                .and_then(|_| signal_hook::flag::register(SIGINT, self.cancellation.flag()))
+            {
+                tracing::warn!("Can't handle Ctrl-C: {}", e);
+            }
+        });
+        &self.cancellation
+    }
+
+    fn source_filter(&self) -> SourceFilter {
+        SourceFilter::new(self.include.clone(), self.exclude.clone()).with_only(self.only.clone())
+    }
+
+    /// The index of the sources without baselines. A stream has no baselines, as its index name
+    /// is its address, so the `stream --index` is used for it.
+    fn fallback_index(&self) -> Option<logreduce_model::IndexName> {
+        self.stream_index.clone().or_else(|| {
+            self.fallback_index
+                .map(|fallback_index| match fallback_index {
+                    FallbackIndex::Global => logreduce_model::IndexName::global(),
+                })
+        })
+    }
+
+    /// Inspect the sources without baselines with the fallback index, when it is requested.
+    fn set_fallback_index(&self, model: &mut Model) {
+        let fallback_index = self.fallback_index();
+        if let Some(index_name) = &fallback_index {
+            if model.index_names().all(|name| name != index_name) {
+                if self.stream_index.is_some() {
+                    tracing::warn!("Unknown index: {}", index_name);
+                } else {
+                    tracing::warn!(
+                        "The model does not have the {} index, retrain it",
+                        index_name
+                    );
+                }
+            }
+        }
+        model.set_fallback_index(fallback_index);
+    }
+
+    /// Set the runtime settings of a loaded or trained model.
+    fn configure_model(&self, model: &mut Model) {
+        self.set_fallback_index(model);
+        model.set_context_mode(self.context);
+        model.set_frequency_weight(self.frequency_weight);
+        model.set_source_timeout(self.source_timeout());
+    }
+
+    /// The training options that are given.
+    fn train_flags(&self) -> Vec<&'static str> {
+        [
+            ("--metric", self.metric.is_some()),
+            ("--vectorizer", self.vectorizer.is_some()),
+            ("--precision", self.precision.is_some()),
+            ("--features", self.features.is_some()),
+            ("--damp-common-tokens", self.damp_common_tokens),
+            ("--strip-prefix", self.strip_prefix),
+            ("--source-profiles", self.source_profiles),
+            ("--json-blocks", self.json_blocks),
+            ("--granularity", !self.granularity.is_empty()),
+            ("--embedding-model", self.embedding_model.is_some()),
+        ]
+        .iter()
+        .filter(|(_, given)| *given)
+        .map(|(flag, _)| *flag)
+        .collect()
+    }
+
+    /// The training options can't change a loaded model.
+    fn check_loaded_model(&self) {
+        let flags = self.train_flags();
+        if !flags.is_empty() {
+            tracing::warn!(
+                "{} ignored, the loaded model keeps the settings it was trained with",
+                flags.join(", ")
+            );
+        }
+    }
+
+    /// Load and configure the model, using the worker cache when it is enabled.
+    fn load_model(&self, path: &std::path::Path) -> Result<Arc<Model>> {
+        self.check_loaded_model();
+        match &self.model_cache {
+            Some(cache) => cache.get(path, |model| self.configure_model(model)),
+            None => {
+                let mut model = Model::load(path)?;
+                self.configure_model(&mut model);
+                Ok(Arc::new(model))
+            }
+        }
+    }
+
+    fn source_timeout(&self) -> Option<std::time::Duration> {
+        self.source_timeout.map(std::time::Duration::from_secs)
+    }
+
+    fn history_path(&self) -> Result<PathBuf> {
+        match &self.history {
+            Some(path) => Ok(path.clone()),
+            None => history::default_path(),
+        }
+    }
+
+    fn redactor(&self) -> Redactor {
+        let redactor = Redactor::new(self.redact.clone());
+        if self.no_redact {
+            redactor.without_builtin()
+        } else {
+            redactor
+        }
+    }
+
+    /// The html report theme, see [logreduce_report::Theme].
+    fn report_theme(&self) -> Result<logreduce_report::Theme> {
+        match &self.report_theme {
+            Some(dir) => logreduce_report::Theme::load(dir),
+            None => Ok(logreduce_report::Theme::default()),
+        }
+    }
+
+    /// The known error rules, the built-in ones extended with the `--rules` file.
+    fn rules(&self) -> Result<Rules> {
+        match &self.rules {
+            Some(path) => Rules::load(path),
+            None => Ok(Rules::builtin()),
+        }
+    }
+
+    fn new_index(&self) -> logreduce_model::ChunkIndex {
+        #[cfg(feature = "embedding")]
+        if let Some(model_dir) = &self.embedding_model {
+            return logreduce_model::embedding_index::new(model_dir.clone());
+        }
+        match self.features {
+            Some(features) => logreduce_model::hashing_index::new_with_features(
+                self.metric.unwrap_or_default(),
+                self.vectorizer.unwrap_or_default(),
+                self.precision.unwrap_or_default(),
+                features,
+            ),
+            None => logreduce_model::hashing_index::new_with_precision(
+                self.metric.unwrap_or_default(),
+                self.vectorizer.unwrap_or_default(),
+                self.precision.unwrap_or_default(),
+            ),
+        }
+        .with_json_blocks(self.json_blocks)
+        .with_damping(self.damp_common_tokens)
+        .with_strip_prefix(self.strip_prefix)
+        .with_source_profiles(self.source_profiles)
+        .with_granularity(self.granularity.clone())
+    }
+}
+
 #[derive(Subcommand)]
 enum Commands {
     #[clap(about = "Compare targets", allow_missing_positional = true)]
-    Diff { src: Vec<String>, dst: String },
+    Diff {
+        src: Vec<String>,
+        dst: String,
+
+        #[clap(long, help = "Also report the lines unique to the src")]
+        bidirectional: bool,
+
+        #[clap(
+            long,
+            help = "Compare two snapshots of an artifact tree, the src and dst dirs"
+        )]
+        tree: bool,
+
+        #[clap(
+            long,
+            requires = "tree",
+            help = "Only inspect the dst files that are new, or whose size or mtime changed"
+        )]
+        only_changed: bool,
+    },
 
     #[clap(about = "Analyze a path")]
     Path { path: String },
@@ -39,30 +664,261 @@ enum Commands {
     #[clap(about = "Analyze a url")]
     Url { url: String },
 
+    #[clap(
+        about = "Analyze a stream: - for the stdin, tcp://HOST:PORT, journald://[UNIT] or \
+                 k8s://NAMESPACE/POD[/CONTAINER]"
+    )]
+    Stream {
+        address: String,
+
+        #[clap(long, help = "The model index used for the stream lines")]
+        index: Option<String>,
+    },
+
+    #[clap(about = "Run a command and analyze its output as it runs, with its exit status")]
+    Exec {
+        #[clap(long, help = "The model index used for the command output")]
+        index: Option<String>,
+
+        #[clap(
+            last = true,
+            required = true,
+            help = "The command and its arguments, after --"
+        )]
+        command: Vec<String>,
+    },
+
+    #[clap(about = "Mirror a remote artifact tree to a local directory")]
+    Fetch {
+        url: String,
+
+        #[clap(long, parse(from_os_str), value_name = "DIR")]
+        into: PathBuf,
+    },
+
     #[clap(about = "Analyze systemd-journal", allow_missing_positional = true)]
     Journald {
         start: Option<String>,
-        range: String,
+        #[clap(required_unless_present = "daemon")]
+        range: Option<String>,
+
+        #[clap(
+            long,
+            help = "Follow the journal continuously, to run as a systemd service"
+        )]
+        daemon: bool,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            requires = "daemon",
+            help = "Write the anomalies to this directory instead of the journal"
+        )]
+        spool: Option<PathBuf>,
+
+        #[clap(
+            long,
+            requires = "daemon",
+            help = "The model index used for the journal"
+        )]
+        index: Option<String>,
     },
 
     #[clap(about = "When running in CI, analyze the current build")]
-    CurrentBuild,
+    CurrentBuild {
+        #[clap(default_value = ".", help = "The build logs")]
+        logs: String,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            value_name = "DIR",
+            default_value = ".",
+            help = "The project checkout, whose .logreduce.baselines file pins the baselines"
+        )]
+        project: PathBuf,
+    },
+
+    #[clap(about = "Process analysis requests from a queue")]
+    Worker {
+        #[clap(long, value_name = "URL", required_unless_present = "nats")]
+        amqp: Option<String>,
+
+        #[clap(long, value_name = "URL", conflicts_with = "amqp")]
+        nats: Option<String>,
+
+        #[clap(long, default_value = "logreduce", help = "The queue or subject name")]
+        queue: String,
+
+        #[clap(
+            long,
+            value_name = "MB",
+            default_value = "1024",
+            help = "Keep the recently used models in memory, up to this size"
+        )]
+        model_cache_size: u64,
+    },
+
+    #[clap(about = "Train a model")]
+    Train {
+        #[clap(required_unless_present = "from-tokens")]
+        baselines: Vec<String>,
+
+        #[clap(
+            long,
+            parse(from_os_str),
+            value_name = "FILE",
+            conflicts_with_all = &["baselines", "update"],
+            help = "Train from the json lines of already tokenized baselines"
+        )]
+        from_tokens: Option<PathBuf>,
+
+        #[clap(
+            long = "tag",
+            value_name = "KEY=VALUE",
+            value_parser = logreduce_model::tags::parse_tag,
+            help = "Tag the model with a platform attribute, e.g. os=centos-9"
+        )]
+        tags: Vec<(String, String)>,
+
+        #[clap(long, help = "Only add the new baseline lines to the existing model")]
+        update: bool,
+
+        #[clap(
+            long,
+            help = "Do not store the baselines locations, only the hashed features and the tags"
+        )]
+        private: bool,
+    },
+
+    #[clap(about = "Manage a model")]
+    Model {
+        #[clap(subcommand)]
+        command: ModelCommands,
+    },
+
+    #[clap(name = "self", about = "Check for a new version, or update the binary")]
+    Installation {
+        #[clap(subcommand)]
+        command: SelfCommands,
+    },
+
+    #[clap(about = "Inspect a target with two models and show the anomalies found by only one")]
+    CompareModels {
+        #[clap(parse(from_os_str))]
+        old: PathBuf,
+
+        #[clap(parse(from_os_str))]
+        new: PathBuf,
+
+        target: String,
+    },
+
+    #[clap(about = "Evaluate the false positives on passing runs")]
+    Eval {
+        #[clap(long, required = true, multiple_values = true)]
+        baselines: Vec<String>,
+
+        #[clap(long, required = true, multiple_values = true)]
+        holdout: Vec<String>,
+    },
+
+    #[clap(about = "Compute the precision and recall on labeled cases")]
+    Benchmark {
+        #[clap(parse(from_os_str))]
+        dataset: PathBuf,
+    },
+
+    #[clap(about = "Evaluate dataset")]
+    Test {
+        #[clap(required = true)]
+        datasets: Vec<String>,
+    },
+
+    #[clap(
+        about = "List the sources grouped by index name",
+        alias = "debug-groups"
+    )]
+    Groups {
+        target: String,
+
+        #[clap(long, value_enum, default_value = "text")]
+        output: OutputFormat,
+    },
+
+    #[clap(about = "Show the anomaly count trend of the previous runs")]
+    History {
+        #[clap(long, help = "Only show the runs of this job")]
+        job: Option<String>,
+
+        #[clap(long, default_value = "10", help = "The number of runs shown per job")]
+        last: usize,
+    },
+
+    #[clap(about = "Print the shell completion script")]
+    Completion {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+
+    #[clap(about = "Print the man page")]
+    Manpage,
+
+    // Secret options to debug specific part of the process
+    #[clap(
+        hide = true,
+        about = "List the baseline lines with the same hashed features but a different text"
+    )]
+    DebugCollisions {
+        target: String,
+
+        #[clap(
+            long,
+            default_value = "10",
+            help = "The number of collisions shown per index"
+        )]
+        limit: usize,
+    },
+
+    #[clap(
+        hide = true,
+        about = "Package the tokenized and redacted lines of a target with its baselines, \
+                 to attach to a bug report"
+    )]
+    DebugExportCase {
+        #[clap(parse(from_os_str))]
+        output: PathBuf,
 
-    #[clap(about = "Train a model")]
-    Train {
-        #[clap(required = true)]
+        #[clap(long = "baseline")]
         baselines: Vec<String>,
-    },
 
-    #[clap(about = "Evaluate dataset")]
-    Test {
-        #[clap(required = true)]
-        datasets: Vec<String>,
+        target: String,
     },
 
-    // Secret options to debug specific part of the process
-    #[clap(hide = true, about = "List source groups")]
-    DebugGroups { target: String },
+    // Debug generator
+    #[clap(
+        hide = true,
+        about = "Generate a synthetic baseline and target pair, in the benchmark case format"
+    )]
+    DebugGenerate {
+        #[clap(parse(from_os_str))]
+        output: PathBuf,
+
+        #[clap(long, default_value = "1000")]
+        lines: usize,
+
+        #[clap(long, default_value = "5")]
+        anomalies: usize,
+
+        #[clap(long, default_value = "42")]
+        seed: u64,
+
+        #[clap(
+            long = "kind",
+            help = "The injected anomaly kinds: new-error, value-drift or reordered-block"
+        )]
+        kinds: Vec<logreduce_generate::AnomalyKind>,
+    },
 
     // Debug tokenizer
     #[clap(hide = true, about = "Tokenize a single line")]
@@ -77,49 +933,389 @@ enum Commands {
     DebugIndexname { path: String },
 }
 
+#[derive(Subcommand)]
+enum ModelCommands {
+    #[clap(about = "Remove the stale indexes and the oldest baselines")]
+    Prune {
+        #[clap(
+            long,
+            value_name = "N",
+            help = "Keep the N most recent baseline lines of each index"
+        )]
+        keep_recent: Option<usize>,
+
+        #[clap(
+            long,
+            value_name = "PATTERN",
+            help = "Remove the indexes whose name matches this regex"
+        )]
+        drop_index: Vec<regex::Regex>,
+    },
+
+    #[clap(about = "Check that the model does not contain the lines of its baselines")]
+    Audit {
+        #[clap(required = true)]
+        baselines: Vec<String>,
+    },
+
+    #[clap(about = "Compare the indexes of two models")]
+    Diff {
+        #[clap(parse(from_os_str))]
+        old: PathBuf,
+
+        #[clap(parse(from_os_str))]
+        new: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+enum SelfCommands {
+    #[clap(about = "Print the latest version")]
+    Check,
+
+    #[clap(about = "Replace the binary with the latest release, once its signature is verified")]
+    Update {
+        #[clap(
+            long,
+            parse(from_os_str),
+            value_name = "FILE",
+            help = "The minisign public key of the releases, instead of the built-in key"
+        )]
+        public_key: Option<PathBuf>,
+
+        #[clap(long, help = "Install the latest release even when it is not newer")]
+        force: bool,
+    },
+}
+
+/// The output format of the listing commands.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+/// The index of the sources without baselines.
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum FallbackIndex {
+    /// Every baseline source, regardless of its index name.
+    Global,
+}
+
+/// The order of the anomalies.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum GroupBy {
+    /// The sources listing order.
+    Source,
+    /// The sources of each index together, sorted by index name.
+    Index,
+}
+
 impl Cli {
-    fn run(self, progress: OutputMode) -> Result<()> {
+    fn run(mut self, progress: OutputMode) -> Result<()> {
+        #[cfg(feature = "embedding")]
+        if let Some(model_dir) = &self.options.embedding_model {
+            logreduce_model::embedding_index::check(model_dir)?;
+        }
         match self.command {
             // Discovery commands
-            Commands::Path { path } => {
-                process(progress, self.report, self.model, None, Input::Path(path))
-            }
-            Commands::Url { url } => {
-                process(progress, self.report, self.model, None, Input::Url(url))
+            Commands::Path { path } => process(
+                progress,
+                self.report,
+                &self.model,
+                &self.options,
+                None,
+                Input::Path(path),
+            ),
+            Commands::Url { url } => process(
+                progress,
+                self.report,
+                &self.model,
+                &self.options,
+                None,
+                Input::Url(url),
+            ),
+            Commands::Stream { address, index } => {
+                if !logreduce_model::streaming::is_stream(&address) {
+                    return Err(anyhow::anyhow!("{}: not a stream address", address));
+                }
+                self.options.stream_index = index.map(logreduce_model::IndexName);
+                process(
+                    progress,
+                    self.report,
+                    &self.model,
+                    &self.options,
+                    None,
+                    Input::Path(address),
+                )
             }
+            Commands::Exec { index, command } => match self.model.as_slice() {
+                [model_path] => exec::run(&self.options, model_path, index.as_deref(), &command),
+                _ => Err(anyhow::anyhow!(
+                    "The exec command requires a single `--model FILE`"
+                )),
+            },
+            Commands::Fetch { url, into } => fetch::run(
+                progress,
+                Input::from_string(url),
+                &into,
+                &self.options.source_filter(),
+                self.options.cancellation(),
+            ),
+            Commands::Journald {
+                daemon: true,
+                spool,
+                index,
+                ..
+            } => match self.model.as_slice() {
+                #[cfg(all(target_os = "linux", feature = "daemon"))]
+                [model_path] => daemon::run(
+                    model_path,
+                    index.as_deref(),
+                    spool,
+                    &self.options.redactor(),
+                ),
+                #[cfg(not(all(target_os = "linux", feature = "daemon")))]
+                [_] => {
+                    let _ = (index, spool);
+                    Err(anyhow::anyhow!(
+                        "The daemon mode is only available on linux, with the `daemon` feature"
+                    ))
+                }
+                _ => Err(anyhow::anyhow!(
+                    "The daemon requires a single `--model FILE`"
+                )),
+            },
             Commands::Journald { .. } => todo!(),
-            Commands::CurrentBuild => todo!(),
+            Commands::CurrentBuild { logs, project } => {
+                let content = Content::from_input(Input::from_string(logs.clone()))?;
+                let tags = logreduce_model::tags::detect(&content)?;
+                // The pinned baselines are preferred over the discovery.
+                let baselines = pinning::lookup(&project, tags.get("job").map(String::as_str))?
+                    .map(|baselines| {
+                        tracing::info!("Using the {} pinned baselines", baselines.len());
+                        baselines.into_iter().map(Input::from_string).collect()
+                    });
+                process(
+                    progress,
+                    self.report,
+                    &self.model,
+                    &self.options,
+                    baselines,
+                    Input::from_string(logs),
+                )
+            }
 
             // Manual commands
-            Commands::Diff { src, dst } => process(
-                progress,
-                self.report,
-                self.model,
-                Some(src.into_iter().map(Input::from_string).collect()),
-                Input::from_string(dst),
-            ),
-            Commands::Train { baselines } => {
-                let model_path = self.model.ok_or_else(|| {
-                    anyhow::anyhow!(
-                        "A output file path is required, please add a `--model FILE` argument"
-                    )
-                })?;
-                let model = Model::train(
+            Commands::Diff {
+                src,
+                dst,
+                bidirectional,
+                tree,
+                only_changed,
+            } => {
+                if tree {
+                    let is_dir = |path: &str| std::path::Path::new(path).is_dir();
+                    match src.as_slice() {
+                        [old] if is_dir(old) && is_dir(&dst) => {}
+                        _ => return Err(anyhow::anyhow!("--tree needs a src and dst directory")),
+                    }
+                }
+                if only_changed {
+                    self.options.changed_since = Some(PathBuf::from(&src[0]));
+                }
+                process(
                     progress,
-                    baselines
-                        .into_iter()
-                        .map(Input::from_string)
-                        .map(Content::from_input)
-                        .collect::<Result<Vec<_>>>()?,
-                    logreduce_model::hashing_index::new,
+                    self.report.clone(),
+                    &self.model,
+                    &self.options,
+                    Some(src.iter().cloned().map(Input::from_string).collect()),
+                    Input::from_string(dst.clone()),
                 )?;
-                model.save(&model_path)
+                if bidirectional {
+                    // Inspect the src using a throwaway model of the dst.
+                    if only_changed {
+                        self.options.changed_since = Some(PathBuf::from(&dst));
+                    }
+                    for target in src {
+                        println!("Lines unique to {}:", target);
+                        process(
+                            progress,
+                            self.report.as_deref().map(reverse_report_path),
+                            &[],
+                            &self.options,
+                            Some(vec![Input::from_string(dst.clone())]),
+                            Input::from_string(target),
+                        )?;
+                    }
+                }
+                Ok(())
+            }
+            Commands::Eval { baselines, holdout } => {
+                let options = &self.options;
+                eval::eval(progress, || options.new_index(), baselines, holdout)
+            }
+            Commands::Train {
+                baselines,
+                from_tokens,
+                tags,
+                update,
+                private,
+            } => {
+                let model_path = match self.model.as_slice() {
+                    [model_path] => Ok(model_path),
+                    _ => Err(anyhow::anyhow!(
+                        "A output file path is required, please add a `--model FILE` argument"
+                    )),
+                }?;
+                let baselines = baselines
+                    .into_iter()
+                    .map(Input::from_string)
+                    .map(Content::from_input)
+                    .collect::<Result<Vec<_>>>()?;
+                let options = &self.options;
+                let mk_index = || options.new_index();
+                let filter = options.source_filter();
+                let progress = Cancellable::new(progress, options.cancellation().clone());
+                let model = if let Some(path) = from_tokens {
+                    Model::train_tokenized(&progress, &path, mk_index)?
+                } else if update && model_path.exists() {
+                    let mut model = Model::load(model_path)?;
+                    model.update(&progress, baselines, mk_index)?;
+                    model
+                } else if options.fallback_index.is_some() {
+                    let mut groups = Content::group_sources_with(&baselines, &filter)?;
+                    Content::add_global_group(&mut groups);
+                    Model::train_groups(&progress, baselines, groups, mk_index)?
+                        .with_source_filter(filter)
+                } else {
+                    Model::train_with(&progress, baselines, &filter, mk_index)?
+                };
+                // An updated model keeps its tags, unless new ones are provided.
+                let mut model = if tags.is_empty() {
+                    model
+                } else {
+                    model.with_tags(tags.into_iter().collect())
+                };
+                if private {
+                    model.anonymize()?;
+                }
+                model.save(model_path)
+            }
+            Commands::Model {
+                command:
+                    ModelCommands::Prune {
+                        keep_recent,
+                        drop_index,
+                    },
+            } => match self.model.as_slice() {
+                [model_path] => prune(model_path, keep_recent, &drop_index),
+                _ => Err(anyhow::anyhow!(
+                    "A single `--model FILE` argument is required"
+                )),
+            },
+            Commands::Model {
+                command: ModelCommands::Audit { baselines },
+            } => match self.model.as_slice() {
+                [model_path] => audit(model_path, baselines),
+                _ => Err(anyhow::anyhow!(
+                    "A single `--model FILE` argument is required"
+                )),
+            },
+            Commands::Model {
+                command: ModelCommands::Diff { old, new },
+            } => model_diff(&old, &new),
+            Commands::Installation {
+                command: SelfCommands::Check,
+            } => update::check(),
+            Commands::Installation {
+                command: SelfCommands::Update { public_key, force },
+            } => update::update(public_key.as_deref(), force),
+            Commands::CompareModels { old, new, target } => {
+                compare_models(progress, &self.options, &old, &new, target)
             }
 
             Commands::Test { datasets } => dataset::test_datasets(&datasets),
+            Commands::Benchmark { dataset } => benchmark::run(&dataset),
+            Commands::Worker {
+                amqp,
+                nats,
+                queue,
+                model_cache_size,
+            } => {
+                self.options.model_cache = Some(ModelCache::new(model_cache_size << 20));
+                // Warm up the cache with the models of the command line.
+                for path in &self.model {
+                    self.options.load_model(path)?;
+                }
+                match (amqp, nats) {
+                    #[cfg(feature = "amqp")]
+                    (Some(url), _) => worker::amqp(&self.options, &url, &queue),
+                    #[cfg(feature = "nats")]
+                    (_, Some(url)) => worker::nats(&self.options, &url, &queue),
+                    (None, None) => Err(anyhow::anyhow!("A --amqp or --nats url is required")),
+                    #[allow(unreachable_patterns)]
+                    (amqp, _) => Err(anyhow::anyhow!(
+                        "Can't consume the {} queue, the `{}` feature is not enabled",
+                        queue,
+                        if amqp.is_some() { "amqp" } else { "nats" }
+                    )),
+                }
+            }
+
+            Commands::Completion { shell } => {
+                let mut cmd = Cli::command();
+                let name = cmd.get_name().to_string();
+                clap_complete::generate(shell, &mut cmd, name, &mut std::io::stdout());
+                Ok(())
+            }
+            Commands::Manpage => clap_mangen::Man::new(Cli::command())
+                .render(&mut std::io::stdout())
+                .context("Can't render the man page"),
 
             // Debug handlers
-            Commands::DebugGroups { target } => debug_groups(Input::from_string(target)),
+            Commands::Groups { target, output } => groups(Input::from_string(target), output),
+            Commands::History { job, last } => {
+                let records = history::load(&self.options.history_path()?)?;
+                history::show(records, job.as_deref(), last);
+                Ok(())
+            }
+            Commands::DebugCollisions { target, limit } => match self.model.as_slice() {
+                [model_path] => debug_collisions(model_path, &self.options, target, limit),
+                _ => Err(anyhow::anyhow!(
+                    "A single `--model FILE` argument is required"
+                )),
+            },
+            Commands::DebugExportCase {
+                output,
+                baselines,
+                target,
+            } => export_case::export(
+                &self.options,
+                &output,
+                baselines.into_iter().map(Input::from_string).collect(),
+                Input::from_string(target),
+            ),
+            Commands::DebugGenerate {
+                output,
+                lines,
+                anomalies,
+                seed,
+                kinds,
+            } => {
+                let default = logreduce_generate::PairConfig::default();
+                let config = logreduce_generate::PairConfig {
+                    seed,
+                    line_count: lines,
+                    anomaly_count: anomalies,
+                    kinds: if kinds.is_empty() {
+                        default.kinds
+                    } else {
+                        kinds
+                    },
+                };
+                debug_generate(&output, &logreduce_generate::gen_pair(&config))
+            }
             Commands::DebugTokenizer { line } => {
                 println!("{}\n", logreduce_tokenizer::process(&line));
                 Ok(())
@@ -134,16 +1330,12 @@ impl Cli {
                 let sources = content.get_sources()?;
                 match sources.first() {
                     Some(source) => {
-                        let reader = match source {
-                            Source::Local(_, path_buf) => Source::file_open(path_buf.as_path())?,
-                            Source::Remote(prefix, url) => Source::url_open(*prefix, url)?,
-                        };
+                        let reader = source.open()?;
                         for line in logreduce_iterator::BytesLines::new(reader, source.is_json()) {
                             match line {
-                                Ok((bytes, nr)) => match std::str::from_utf8(&bytes) {
-                                    Ok(txt) => println!("{} | {}", nr, txt),
-                                    Err(e) => println!("{} | error: {}", nr, e),
-                                },
+                                Ok((bytes, nr)) => {
+                                    println!("{} | {}", nr, String::from_utf8_lossy(&bytes))
+                                }
                                 Err(e) => println!("{}", e),
                             }
                         }
@@ -160,28 +1352,61 @@ fn main() -> Result<()> {
     use std::str::FromStr;
     use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, Layer};
 
-    let logger = tracing_subscriber::Registry::default();
+    #[cfg(feature = "otlp")]
+    let otlp = match std::env::var("LOGREDUCE_OTLP_ENDPOINT") {
+        Ok(endpoint) => {
+            Some(otlp_layer(endpoint)?.with_filter(tracing_subscriber::filter::LevelFilter::DEBUG))
+        }
+        Err(_) => None,
+    };
+    #[cfg(not(feature = "otlp"))]
+    let otlp = match std::env::var("LOGREDUCE_OTLP_ENDPOINT") {
+        Ok(_) => {
+            return Err(anyhow::anyhow!(
+                "LOGREDUCE_OTLP_ENDPOINT is set, but the `otlp` feature is not enabled"
+            ))
+        }
+        Err(_) => None::<tracing_subscriber::layer::Identity>,
+    };
+    let logger = tracing_subscriber::Registry::default().with(otlp);
+
+    // The json lines are written to stderr so that they don't mix with the anomalies.
+    let json_format = std::env::var("LOGREDUCE_LOG_FORMAT").map_or(false, |fmt| fmt == "json");
+    let json_layer = || {
+        tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_writer(std::io::stderr)
+    };
 
     let (_flush, debug) = match std::env::var("LOGREDUCE_LOG") {
         Err(_) => {
             // Default INFO stdout logger
+            let layer = if json_format {
+                json_layer().boxed()
+            } else {
+                tracing_subscriber::fmt::layer()
+                    .with_target(false)
+                    .compact()
+                    .boxed()
+            };
             logger
-                .with(
-                    tracing_subscriber::fmt::layer()
-                        .with_target(false)
-                        .compact()
-                        .with_filter(tracing_subscriber::filter::LevelFilter::INFO),
-                )
+                .with(layer.with_filter(tracing_subscriber::filter::LevelFilter::INFO))
                 .init();
             (None, false)
         }
         Ok(level) => {
             // Tracing spans
-            let logger = logger.with(
+            let layer = if json_format {
+                json_layer().boxed()
+            } else {
                 tracing_tree::HierarchicalLayer::new(2)
                     .with_targets(true)
                     .with_bracketed_fields(true)
-                    .with_filter(tracing_subscriber::filter::LevelFilter::from_str(&level)?),
+                    .boxed()
+            };
+            let logger = logger.with(
+                layer.with_filter(tracing_subscriber::filter::LevelFilter::from_str(&level)?),
             );
             let flush = if let Ok(fp) = std::env::var("LOGREDUCE_TRACE") {
                 let chrome = tracing_chrome::ChromeLayerBuilder::new()
@@ -198,36 +1423,279 @@ fn main() -> Result<()> {
             (flush, true)
         }
     };
+    let mut cli = Cli::parse();
+    logreduce_model::net::configure(logreduce_model::net::Settings {
+        limit_rate: cli.options.limit_rate,
+        connections_per_host: cli.options.connections_per_host,
+        timeout: cli.options.source_timeout(),
+    })?;
+    logreduce_model::configure_limits(logreduce_model::DecompressLimits {
+        max_size: Some(cli.options.max_decompressed_size)
+            .filter(|size| *size > 0)
+            .map(|size| size << 20),
+        max_ratio: Some(cli.options.max_decompression_ratio).filter(|ratio| *ratio > 0),
+    })?;
+    logreduce_model::walk::configure(logreduce_model::walk::Settings {
+        follow_symlinks: cli.options.follow_symlinks,
+        dedup_links: cli.options.dedup_links,
+    })?;
+    logreduce_model::phases::configure(cli.options.split_job_output)?;
+    let paging = cli.options.pager && atty::is(atty::Stream::Stdout);
+    if paging {
+        // Resolve the colors before stdout becomes the pager pipe.
+        cli.options.color = if cli.options.color.enabled() {
+            color::ColorChoice::Always
+        } else {
+            color::ColorChoice::Never
+        };
+        pager::Pager::with_default_pager("less -R").setup();
+    }
     let output_mode = if debug {
         OutputMode::Debug
-    } else if atty::is(atty::Stream::Stdout) {
+    } else if atty::is(atty::Stream::Stdout) && !paging {
         OutputMode::FastTerminal
     } else {
         OutputMode::Quiet
     };
-    Cli::parse().run(output_mode).map_err(|e| {
+    let check_version =
+        cli.options.check_version && !matches!(cli.command, Commands::Installation { .. });
+    let result = cli.run(output_mode).map_err(|e| {
         // Ensure the exception happens on a new line
         if output_mode.inlined() {
             println!();
         }
         e
-    })
+    });
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+    if check_version {
+        update::notify();
+    }
+    // The exec command exits with the status of its command, without an error message.
+    if let Some(exec::Failed(status)) = result.as_ref().err().and_then(|e| e.downcast_ref()) {
+        drop(_flush);
+        std::process::exit(*status);
+    }
+    result
+}
+
+/// Export the spans to an OpenTelemetry collector, e.g. `http://localhost:4318/v1/traces`.
+#[cfg(feature = "otlp")]
+fn otlp_layer<S>(
+    endpoint: String,
+) -> Result<tracing_opentelemetry::OpenTelemetryLayer<S, opentelemetry::sdk::trace::Tracer>>
+where
+    S: tracing::Subscriber + for<'span> tracing_subscriber::registry::LookupSpan<'span>,
+{
+    use opentelemetry_otlp::WithExportConfig;
+    let resource = opentelemetry::sdk::Resource::new(vec![opentelemetry::KeyValue::new(
+        "service.name",
+        "logreduce",
+    )]);
+    let tracer = opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .http()
+                .with_endpoint(endpoint),
+        )
+        .with_trace_config(opentelemetry::sdk::trace::config().with_resource(resource))
+        .install_simple()
+        .context("Failed to setup the otlp exporter")?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+fn prune(
+    model_path: &std::path::Path,
+    keep_recent: Option<usize>,
+    drop_index: &[regex::Regex],
+) -> Result<()> {
+    let mut model = Model::load(model_path)?;
+    let dropped = model.drop_indexes(|index_name| {
+        drop_index
+            .iter()
+            .any(|pattern| pattern.is_match(index_name.as_str()))
+    });
+    for index_name in dropped {
+        println!("Removed index {}", index_name);
+    }
+    if let Some(max_lines) = keep_recent {
+        model.truncate_indexes(max_lines)?;
+    }
+    model.save(model_path)
+}
+
+fn audit(model_path: &std::path::Path, baselines: Vec<String>) -> Result<()> {
+    let model = Model::load(model_path)?;
+    let mut sources = Vec::new();
+    for baseline in baselines {
+        sources.extend(Content::from_input(Input::from_string(baseline))?.get_sources()?);
+    }
+    let leaks = logreduce_model::privacy::audit(&model, &sources)?;
+    for leak in &leaks {
+        println!("{}:{}: found in the model", leak.source, leak.pos);
+    }
+    if !model.baselines().is_empty() {
+        println!("The model contains the baselines locations, see `train --private`");
+    }
+    if leaks.is_empty() {
+        println!("{:?}: no baseline lines found in the model", model_path);
+        Ok(())
+    } else {
+        Err(anyhow::anyhow!(
+            "{} baseline lines found in the model",
+            leaks.len()
+        ))
+    }
+}
+
+fn model_diff(old: &std::path::Path, new: &std::path::Path) -> Result<()> {
+    let changes = logreduce_model::diff::diff(&Model::load(old)?, &Model::load(new)?)?;
+    for change in &changes {
+        println!("{}", change);
+    }
+    if changes.is_empty() {
+        println!("The models have the same indexes");
+    }
+    Ok(())
+}
+
+/// Inspect the target with the two models, to validate a model refresh on a real target.
+fn compare_models(
+    output_mode: OutputMode,
+    options: &Options,
+    old: &std::path::Path,
+    new: &std::path::Path,
+    target: String,
+) -> Result<()> {
+    let content = Content::from_input(Input::from_string(target))?;
+    let sources = options.source_filter().apply(content.get_sources()?);
+    if sources.iter().any(Source::is_stream) {
+        return Err(anyhow::anyhow!("A stream can only be inspected once"));
+    }
+    let report = |path: &std::path::Path| -> Result<logreduce_model::Report> {
+        options
+            .load_model(path)?
+            .report_sources(&output_mode, content.clone(), sources.clone())
+    };
+    let (old_report, new_report) = (report(old)?, report(new)?);
+    if output_mode.inlined() {
+        println!();
+    }
+    let (changes, common) = logreduce_model::diff::diff_reports(&old_report, &new_report);
+    for change in &changes {
+        println!("{}", change);
+    }
+    let removed = changes
+        .iter()
+        .filter(|change| matches!(change, logreduce_model::diff::AnomalyChange::Removed(..)))
+        .count();
+    println!(
+        "{} common anomalies, {} only found by {}, {} only found by {}",
+        common,
+        removed,
+        old.display(),
+        changes.len() - removed,
+        new.display()
+    );
+    Ok(())
 }
 
 #[tracing::instrument(level = "debug", skip(output_mode))]
 fn process(
     output_mode: OutputMode,
     report: Option<PathBuf>,
-    model_path: Option<PathBuf>,
+    model_paths: &[PathBuf],
+    options: &Options,
     baselines: Option<Vec<Input>>,
     input: Input,
 ) -> Result<()> {
+    let start_time = std::time::Instant::now();
     // Convert user Input to target Content.
     let content = Content::from_input(input)?;
+    // List all the sources before downloading anything.
+    let filter = options.source_filter();
+    let mut target_sources = filter.apply(content.get_sources()?);
+    if target_sources.is_empty() && !options.only.is_empty() {
+        return Err(anyhow::anyhow!("No source of {} matches --only", content));
+    }
+    if let Some(tree) = &options.changed_since {
+        let count = target_sources.len();
+        target_sources.retain(|source| !source.is_unchanged_in(tree));
+        logreduce_model::debug_or_progress(
+            output_mode,
+            &format!("Skipping {} unchanged files", count - target_sources.len()),
+        );
+        if target_sources.is_empty() {
+            println!("No changed files since {:?}", tree);
+            return Ok(());
+        }
+    }
+    if options.group_by == GroupBy::Index {
+        target_sources.sort_by_cached_key(logreduce_model::IndexName::from_source);
+    }
+
+    let cancel = options.cancellation();
+    let cancellable = Cancellable::new(output_mode, cancel.clone());
+    let scores_db = match &options.scores_db {
+        Some(path) => Some(ScoresDb::open(
+            path,
+            Cancellable::new(output_mode, cancel.clone()),
+        )?),
+        None => None,
+    };
+    let progress: &dyn ProgressObserver = match &scores_db {
+        Some(scores_db) => scores_db,
+        None => &cancellable,
+    };
 
+    let model_path = match model_paths {
+        [model_path] => Some(model_path),
+        _ => None,
+    };
+    // The baselines of a trained model are compared by content, the other by location only.
+    let trained = model_paths.len() <= 1 && !model_path.map_or(false, |path| path.exists());
     let model = match model_path {
-        Some(ref path) if path.exists() => match baselines {
-            None => Model::load(path),
+        _ if model_paths.len() > 1 => match baselines {
+            None => {
+                options.check_loaded_model();
+                let models = model_paths
+                    .iter()
+                    .map(|path| Model::load(path))
+                    .collect::<Result<Vec<_>>>()?;
+                let mut model = if options.select_model {
+                    let target_tags = logreduce_model::tags::detect(&content)?;
+                    let model = logreduce_model::tags::select(models, &target_tags)?;
+                    logreduce_model::debug_or_progress(
+                        output_mode,
+                        &format!("Using the model tagged {:?}", model.tags()),
+                    );
+                    model
+                } else {
+                    Model::ensemble(models, options.aggregation)?
+                };
+                options.configure_model(&mut model);
+                if options.dry_run || options.show_plan {
+                    dry_run::with_model(&model, &target_sources)?;
+                    if options.dry_run {
+                        return Ok(());
+                    }
+                }
+                Ok(Arc::new(model))
+            }
+            Some(_) => Err(anyhow::anyhow!("Ambiguous baselines and models provided")),
+        },
+        Some(path) if path.exists() => match baselines {
+            None => {
+                let model = options.load_model(path)?;
+                if options.dry_run || options.show_plan {
+                    dry_run::with_model(&model, &target_sources)?;
+                    if options.dry_run {
+                        return Ok(());
+                    }
+                }
+                Ok(model)
+            }
             Some(_) => Err(anyhow::anyhow!("Ambiguous baselines and model provided")),
         },
         _ => {
@@ -240,23 +1708,100 @@ fn process(
                     .map(Content::from_input)
                     .collect::<Result<Vec<_>>>(),
             }?;
+            let baselines = if options.select_baseline && baselines.len() > 1 {
+                vec![content.select_baseline(baselines)?.0]
+            } else {
+                baselines
+            };
+            for baseline in &baselines {
+                logreduce_model::debug_or_progress(
+                    output_mode,
+                    &format!("Using baseline {}", baseline),
+                );
+            }
+            let mut train_groups = Content::group_sources_with(&baselines, &filter)?;
+            if options.fallback_index.is_some() {
+                Content::add_global_group(&mut train_groups);
+            }
+            if options.dry_run || options.show_plan {
+                dry_run::with_baselines(&train_groups, &target_sources)?;
+                if options.dry_run {
+                    return Ok(());
+                }
+            }
 
             // Create the model. TODO: enable custom index.
             tracing::debug!("Building model");
-            Model::train(output_mode, baselines, logreduce_model::hashing_index::new)
+            Model::train_groups(progress, baselines, train_groups, || options.new_index()).map(
+                |model| {
+                    let mut model = model.with_source_filter(filter.clone());
+                    options.configure_model(&mut model);
+                    Arc::new(model)
+                },
+            )
         }
     }?;
+    if !trained && !model.source_filter().is_empty() {
+        // Inspect the sources that are selected like the baselines of the model.
+        let count = target_sources.len();
+        target_sources.retain(|source| model.source_filter().keep(source));
+        logreduce_model::debug_or_progress(
+            output_mode,
+            &format!(
+                "Skipping {} sources excluded by the model filter {}",
+                count - target_sources.len(),
+                model.source_filter()
+            ),
+        );
+    }
 
     match model_path {
-        Some(ref path) if !path.exists() => model.save(path),
+        Some(path) if !path.exists() => model.save(path),
         _ => Ok(()),
     }?;
+    let leaks = baseline_leaks(&model, &filter, trained, &content, &target_sources);
+    for leak in &leaks {
+        println!("Warning: {}, the report is likely empty", leak);
+    }
+    let rules = options.rules()?;
+    let theme = options.report_theme()?;
 
     tracing::debug!("Inspecting");
-    match report {
-        None => process_live(output_mode, &content, &model),
+    let target = content.to_string();
+    let job = match &content {
+        Content::Zuul(build) => Some(build.job_name.clone()),
+        _ if !options.record_history => None,
+        _ => logreduce_model::tags::detect_sources(target_sources.iter().cloned().map(Ok))
+            .ok()
+            .and_then(|mut tags| tags.remove("job")),
+    };
+    let (line_count, anomaly_count, total_distance, index_counts) = match report {
+        None => process_live(
+            output_mode,
+            progress,
+            options,
+            &rules,
+            &target_sources,
+            &model,
+            cancel,
+        )?,
         Some(file) => {
-            let report = model.report(output_mode, content)?;
+            let mut report = model.report_sources(progress, content, target_sources)?;
+            if cancel.is_cancelled() && !options.save_partial {
+                return Err(logreduce_model::cancel::Cancelled.into());
+            }
+            // The filtered levels are not counted by the second pass, like with process_live.
+            report.level_filter(options.level_filter());
+            if let Some(min_occurrences) = options.self_consistency {
+                report.self_consistency_filter(&model, min_occurrences);
+            }
+            if options.group_by == GroupBy::Index {
+                report.group_by_index();
+            }
+            report.warnings.extend(leaks);
+            rules.annotate_report(&mut report);
+            rules.suppress_report(&mut report);
+            options.redactor().redact_report(&mut report);
 
             // Save raw report for debug purpose
             if std::env::var("LOGREDUCE_CACHE").is_ok() {
@@ -265,33 +1810,217 @@ fn process(
                 report.save(&report_json)?;
             }
 
-            println!("{:?}: Writing report...", file);
+            if let Some(reason) = &report.incomplete {
+                println!("{:?}: Writing partial report, {}...", file, reason);
+            } else {
+                println!("{:?}: Writing report...", file);
+            }
             std::fs::write(
-                file,
-                logreduce_report::render(&report).context("Error rendering the report")?,
+                &file,
+                logreduce_report::render_with(&report, &theme)
+                    .context("Error rendering the report")?,
+            )
+            .context("Failed to write the report")?;
+            if options.zuul_artifact {
+                zuul_artifact::write(&file)?;
+            }
+            if let Some(ref path) = options.annotations {
+                annotations::save(options.annotations_format, &report, path)?;
+            }
+            if let Some(ref path) = options.csv {
+                csv::save(&report, path)?;
+            }
+            if let Some(ref path) = options.coverage_gaps {
+                save_coverage_gaps(path, &report.coverage_gaps)?;
+            }
+            let mut index_counts = budget::Counts::new();
+            for log_report in &report.log_reports {
+                *index_counts
+                    .entry(log_report.index_name.to_string())
+                    .or_default() += log_report.anomalies.len();
+            }
+            let total_distance = report
+                .log_reports
+                .iter()
+                .flat_map(|log_report| log_report.anomalies.iter())
+                .map(|anomaly| anomaly.anomaly.distance)
+                .sum();
+            (
+                report.total_line_count,
+                report.total_anomaly_count,
+                total_distance,
+                index_counts,
             )
-            .context("Failed to write the report")
         }
+    };
+    // The interrupted runs are not recorded.
+    cancel.check()?;
+
+    if options.record_history {
+        // The target url may contain a token, like the job name of a local path.
+        let redactor = options.redactor();
+        let target = redactor.redact(&target).into_owned();
+        let record = history::Record::new(
+            job.map_or_else(|| target.clone(), |job| redactor.redact(&job).into_owned()),
+            target,
+            line_count,
+            anomaly_count,
+            total_distance,
+            start_time.elapsed(),
+        );
+        // The history is a convenience, a read-only data directory must not fail the run.
+        if let Err(e) = options
+            .history_path()
+            .and_then(|path| history::append(&path, &record))
+        {
+            tracing::warn!("Can't record the run history: {:#}", e);
+        }
+    }
+
+    if let Some(ref path) = options.provenance {
+        provenance::Provenance::new(
+            &model,
+            target,
+            start_time.elapsed(),
+            line_count,
+            anomaly_count,
+        )?
+        .save(path)?;
+    }
+
+    match options.budget {
+        Some(ref path) => {
+            let regressions = budget::check(path, options.budget_margin, &index_counts)?;
+            if regressions.is_empty() {
+                Ok(())
+            } else {
+                println!("Anomaly budget exceeded:");
+                regressions.iter().for_each(|r| println!(" -> {}", r));
+                Err(anyhow::anyhow!(
+                    "Anomaly budget exceeded for {} index(es)",
+                    regressions.len()
+                ))
+            }
+        }
+        None => Ok(()),
+    }
+}
+
+/// The report path of the reverse comparison, e.g. `report.html` becomes `report-reverse.html`.
+fn reverse_report_path(path: &std::path::Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default();
+    match path.extension() {
+        Some(ext) => path.with_file_name(format!("{}-reverse.{}", stem, ext.to_string_lossy())),
+        None => path.with_file_name(format!("{}-reverse", stem)),
     }
 }
 
-fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Result<()> {
+/// Print the anomalies as they are found, and return the total line and anomaly counts.
+fn process_live(
+    output_mode: OutputMode,
+    progress: &dyn ProgressObserver,
+    options: &Options,
+    rules: &Rules,
+    sources: &[Source],
+    model: &Model,
+    cancel: &CancellationToken,
+) -> Result<(usize, usize, f32, budget::Counts)> {
+    let style = color::Style::new(options.color);
     let print_context = |pos: usize, xs: &[String]| {
         xs.iter()
             .enumerate()
-            .for_each(|(idx, line)| println!("   {} | {}", pos + idx, line))
+            .for_each(|(idx, line)| println!("   {} | {}", pos + 1 + idx, style.context(line)))
     };
 
+    let level_filter = options.level_filter();
+    let redactor = options.redactor();
     let mut progress_sep_shown = false;
     let mut total_line_count = 0;
     let mut total_anomaly_count = 0;
-    for source in content.get_sources()? {
-        let index_name = logreduce_model::IndexName::from_source(&source);
+    let mut total_distance = 0.0;
+    let mut index_counts = budget::Counts::new();
+    let mut no_baselines = Vec::new();
+    let mut timeouts = Vec::new();
+    let mut shown_index = None;
+    // The sources with more than --max-print anomalies, and their count of hidden anomalies.
+    let mut hidden_counts = Vec::new();
+    for source in sources {
+        if cancel.is_cancelled() {
+            break;
+        }
+        let index_name = logreduce_model::IndexName::from_source(source);
         match model.get_index(&index_name) {
             Some(index) => {
+                let previous_anomaly_count = total_anomaly_count;
+                // The location prefix, for the files that editors can open.
+                let location = match source {
+                    Source::Local(_, path)
+                        if options.locations
+                            && path.extension() != Some(std::ffi::OsStr::new("gz")) =>
+                    {
+                        Some(path.display().to_string())
+                    }
+                    _ => None,
+                };
                 let mut last_pos = None;
-                let mut print_anomaly = |anomaly: logreduce_model::AnomalyContext| {
+                let mut last_test = None;
+                let mut last_task = None;
+                let mut last_command = None;
+                // The tasks of the job-output.json, to attribute the job output anomalies.
+                let tasks = logreduce_model::job_output::load(source);
+                // The positions of the printed anomalies, to show their repetitions.
+                let mut shown = std::collections::HashSet::new();
+                let mut hidden = 0;
+                let mut print_anomaly = |mut anomaly: logreduce_model::AnomalyContext| {
+                    if rules.is_suppressed(&anomaly.anomaly) {
+                        return;
+                    }
                     total_anomaly_count += 1;
+                    total_distance += anomaly.anomaly.distance;
+                    if let Some(max_print) = options.max_print {
+                        if shown.len() >= max_print {
+                            if hidden == 0 {
+                                println!(" -> Printed {} anomalies, counting the rest", max_print);
+                            }
+                            hidden += 1;
+                            return;
+                        }
+                    }
+                    shown.insert(anomaly.anomaly.pos);
+                    rules.annotate(&mut anomaly.anomaly);
+                    logreduce_model::job_output::annotate(&tasks, &mut anomaly.anomaly);
+                    redactor.redact_context(&mut anomaly);
+                    let grouped = options.group_by == GroupBy::Index;
+                    if grouped && shown_index.as_ref() != Some(&index_name) {
+                        println!("{}:", index_name);
+                        shown_index = Some(index_name.clone());
+                    }
+                    if anomaly.anomaly.test.is_some() && anomaly.anomaly.test != last_test {
+                        println!(
+                            " -> During test {}",
+                            anomaly.anomaly.test.as_deref().unwrap_or_default()
+                        );
+                        last_test = anomaly.anomaly.test.clone();
+                    }
+                    if anomaly.anomaly.task.is_some() && anomaly.anomaly.task != last_task {
+                        println!(
+                            " -> During task {}",
+                            anomaly.anomaly.task.as_deref().unwrap_or_default()
+                        );
+                        last_task = anomaly.anomaly.task.clone();
+                    }
+                    if let Some(command) = &anomaly.anomaly.command {
+                        if last_command.as_ref() != Some(command) {
+                            println!(" -> {}", command);
+                            last_command = Some(command.clone());
+                        }
+                    }
+                    if let Some(last_pos) = last_pos {
+                        anomaly.trim_before(last_pos);
+                    }
                     let context_size = 1 + anomaly.before.len();
                     let starting_pos = if anomaly.anomaly.pos > context_size {
                         anomaly.anomaly.pos - context_size
@@ -305,23 +2034,64 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
                     }
 
                     print_context(starting_pos, &anomaly.before);
-                    println!(
-                        "{:02.0} {} | {}",
-                        anomaly.anomaly.distance * 99.0,
-                        anomaly.anomaly.pos,
-                        anomaly.anomaly.line
-                    );
-                    print_context(anomaly.anomaly.pos, &anomaly.after);
+                    let context = [&anomaly.before[..], &anomaly.after[..]].concat();
+                    // A chunk of lines is printed line by line, see --granularity.
+                    for (pos, line) in anomaly.anomaly.lines() {
+                        let line = style.anomaly(anomaly.anomaly.distance, line, &context);
+                        let column = if pos == anomaly.anomaly.pos {
+                            anomaly.anomaly.column
+                        } else {
+                            1
+                        };
+                        match &location {
+                            Some(path) => println!(
+                                "{}:{}:{}: {:02.0} | {}",
+                                path,
+                                pos,
+                                column,
+                                anomaly.anomaly.distance * 99.0,
+                                line
+                            ),
+                            None => println!(
+                                "{:02.0} {} | {}",
+                                anomaly.anomaly.distance * 99.0,
+                                pos,
+                                line
+                            ),
+                        }
+                    }
+                    if let Some(hint) = &anomaly.anomaly.hint {
+                        match &hint.link {
+                            Some(link) => println!(" -> Known error: {} ({})", hint.category, link),
+                            None => println!(" -> Known error: {}", hint.category),
+                        }
+                    }
+                    if anomaly.anomaly.run > 0 {
+                        println!(
+                            " -> Repeated {}× until line {}",
+                            anomaly.anomaly.run,
+                            anomaly.after_pos()
+                        );
+                    }
+                    if options.show_ids {
+                        println!(" -> Id: {}", anomaly.anomaly.id);
+                    }
+                    print_context(anomaly.after_pos(), &anomaly.after);
 
-                    last_pos = Some(anomaly.anomaly.pos + anomaly.after.len());
+                    last_pos = Some(anomaly.last_pos());
                 };
                 progress_sep_shown = false;
-                match index.get_processor(
-                    output_mode,
-                    &source,
-                    &mut std::collections::HashSet::new(),
-                ) {
-                    Ok(mut processor) => {
+                let deadline = options
+                    .source_timeout()
+                    .map(|timeout| std::time::Instant::now() + timeout);
+                match index.get_processor(progress, source, &mut std::collections::HashSet::new()) {
+                    Ok(processor) => {
+                        let mut processor = processor
+                            .with_deadline(deadline)
+                            .with_cancellation(Some(cancel.clone()))
+                            .with_index_name(index_name.clone());
+                        // The anomalies kept for the second pass.
+                        let mut pending = Vec::new();
                         for anomaly in processor.by_ref() {
                             if output_mode.inlined() && !progress_sep_shown {
                                 // Show a progress separator for the first anomaly.
@@ -329,14 +2099,59 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
                                 progress_sep_shown = true;
                             }
                             match anomaly {
-                                Ok(anomaly) => print_anomaly(anomaly),
+                                Ok(mut anomaly) => {
+                                    if !level_filter.keep(&mut anomaly.anomaly) {
+                                        continue;
+                                    }
+                                    if options.self_consistency.is_some() {
+                                        pending.push(anomaly)
+                                    } else {
+                                        print_anomaly(anomaly)
+                                    }
+                                }
                                 Err(err) => {
                                     println!("Could not read {}: {}", &source, err);
                                     break;
                                 }
                             }
                         }
+                        if let Some(min_occurrences) = options.self_consistency {
+                            let repeats = processor.repeats();
+                            let mut retries = processor.retry_loops();
+                            for anomaly in pending.iter_mut() {
+                                if let Some(count) = repeats.get(&anomaly.anomaly.pos) {
+                                    anomaly.anomaly.repeat = *count;
+                                }
+                                anomaly.anomaly.retry = retries.remove(&anomaly.anomaly.pos);
+                            }
+                            index.self_consistency_filter(source, &mut pending, min_occurrences);
+                            pending.into_iter().for_each(&mut print_anomaly);
+                        }
+                        if processor.timed_out {
+                            progress_sep_shown = true;
+                            println!(" -> Timed out after {} lines", processor.line_count);
+                            timeouts.push(format!(
+                                "{}: timed out after {} lines, the anomalies are partial",
+                                source, processor.line_count
+                            ));
+                        }
+                        if processor.cancelled {
+                            progress_sep_shown = true;
+                            println!(" -> Interrupted after {} lines", processor.line_count);
+                        }
+                        if hidden > 0 {
+                            hidden_counts.push((source, hidden));
+                        }
                         total_line_count += processor.line_count;
+                        *index_counts.entry(index_name.to_string()).or_default() +=
+                            total_anomaly_count - previous_anomaly_count;
+                        // The attempts of a retry loop are collapsed in a single entry.
+                        let retries = processor.retry_loops();
+                        for (pos, retry) in retries.iter().sorted_by_key(|(pos, _)| **pos) {
+                            if shown.contains(pos) {
+                                println!(" -> line {} {}", pos, retry);
+                            }
+                        }
                     }
                     Err(err) => {
                         println!("Could not read {}: {}", &source, err);
@@ -346,13 +2161,18 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
             }
             None => {
                 progress_sep_shown = true;
-                println!(" -> No baselines for {}", source)
+                println!(" -> No baselines for {}", source);
+                no_baselines.push(source.clone());
             }
         }
     }
     if output_mode.inlined() && !progress_sep_shown {
         // If the last source didn't had an anomaly, then erase the current progress
-        print!("\r\x1b[K");
+        if cfg!(windows) {
+            print!("\r{:80}\r", "");
+        } else {
+            print!("\r\x1b[K");
+        }
     }
     logreduce_model::debug_or_progress(
         output_mode,
@@ -361,16 +2181,153 @@ fn process_live(output_mode: OutputMode, content: &Content, model: &Model) -> Re
             content, total_line_count, total_anomaly_count
         ),
     );
+    for warning in rules.expired_warnings().into_iter().chain(timeouts) {
+        println!("Warning: {}", warning);
+    }
+    if !hidden_counts.is_empty() {
+        println!(
+            "Not printed, more than {} anomalies:",
+            options.max_print.unwrap_or_default()
+        );
+        for (source, count) in &hidden_counts {
+            println!("  {}: {} anomalies", source, count);
+        }
+    }
+    let index_errors = Source::group_by_index(no_baselines)
+        .into_values()
+        .collect::<Vec<_>>();
+    let coverage_gaps = logreduce_model::coverage::gaps(&index_errors, model.index_names());
+    if !coverage_gaps.is_empty() {
+        println!("No baselines:");
+        for gap in &coverage_gaps {
+            println!("  {}: {} source(s)", gap.index_name, gap.sources.len());
+            gap.actions
+                .iter()
+                .for_each(|action| println!("   -> {}", action));
+        }
+    }
+    if let Some(ref path) = options.coverage_gaps {
+        save_coverage_gaps(path, &coverage_gaps)?;
+    }
+    Ok((
+        total_line_count,
+        total_anomaly_count,
+        total_distance,
+        index_counts,
+    ))
+}
+
+/// The baselines that are the target itself, see [logreduce_model::leakage].
+fn baseline_leaks(
+    model: &Model,
+    filter: &SourceFilter,
+    by_content: bool,
+    target: &Content,
+    target_sources: &[Source],
+) -> Vec<String> {
+    model
+        .baselines()
+        .iter()
+        .filter_map(|baseline| {
+            let result = if by_content {
+                baseline.get_sources().and_then(|sources| {
+                    let sources = filter.apply(sources);
+                    logreduce_model::leakage::check(baseline, &sources, target, target_sources)
+                })
+            } else {
+                logreduce_model::leakage::check(baseline, &[], target, target_sources)
+            };
+            result.unwrap_or_else(|e| {
+                tracing::warn!("{}: can't compare the baseline: {}", baseline, e);
+                None
+            })
+        })
+        .collect()
+}
+
+/// Write the machine-readable list of the sources without baselines.
+fn save_coverage_gaps(
+    path: &std::path::Path,
+    gaps: &[logreduce_model::coverage::Gap],
+) -> Result<()> {
+    let file = std::fs::File::create(path).context("Can't create coverage gaps file")?;
+    serde_json::to_writer_pretty(file, gaps).context("Can't write coverage gaps")
+}
+
+fn debug_collisions(
+    model_path: &std::path::Path,
+    options: &Options,
+    target: String,
+    limit: usize,
+) -> Result<()> {
+    let model = Model::load(model_path)?;
+    let content = Content::from_input(Input::from_string(target))?;
+    let sources = options.source_filter().apply(content.get_sources()?);
+    let collisions = logreduce_model::collisions::find(&model, &sources, limit)?;
+    for collision in &collisions {
+        println!("{}:", collision.index_name);
+        for line in &collision.baselines {
+            println!("  baseline | {}", line);
+        }
+        if let Some(line) = &collision.target {
+            println!("  target   | {}", line);
+        }
+    }
+    println!("{} collisions found", collisions.len());
     Ok(())
 }
 
-fn debug_groups(input: Input) -> Result<()> {
+/// Write the pair as a benchmark case.
+fn debug_generate(output: &std::path::Path, pair: &logreduce_generate::Pair) -> Result<()> {
+    use std::fmt::Write;
+    std::fs::create_dir_all(output)?;
+    std::fs::write(
+        output.join("baseline.good"),
+        pair.baseline.join("\n") + "\n",
+    )?;
+    std::fs::write(output.join("target.fail"), pair.target.join("\n") + "\n")?;
+    let mut expected = "anomalies:\n".to_string();
+    for (start, end) in &pair.anomalies {
+        writeln!(expected, "  - start: {}\n    end: {}", start, end)?;
+    }
+    std::fs::write(output.join("expected.yaml"), expected)?;
+    println!("{:?}: {} anomalies injected", output, pair.anomalies.len());
+    Ok(())
+}
+
+fn groups(input: Input, output: OutputFormat) -> Result<()> {
     let content = Content::from_input(input)?;
-    for (index_name, sources) in Content::group_sources(&[content])?
+    let groups = Content::group_sources(&[content])?
         .drain()
-        .sorted_by(|x, y| Ord::cmp(&x.0, &y.0))
-    {
-        println!("{:?}: {:#?}", index_name, sources);
+        .sorted_by(|x, y| Ord::cmp(&x.0, &y.0));
+    match output {
+        OutputFormat::Text => {
+            for (index_name, sources) in groups {
+                println!("{}:", index_name);
+                for source in sources {
+                    let (_, rule) = logreduce_model::IndexName::explain(&source);
+                    println!("  {} ({})", source.as_str(), rule);
+                }
+            }
+        }
+        OutputFormat::Json => {
+            let groups = groups
+                .map(|(index_name, sources)| {
+                    let sources = sources
+                        .iter()
+                        .map(|source| {
+                            let (_, rule) = logreduce_model::IndexName::explain(source);
+                            serde_json::json!({
+                                "source": source.as_str(),
+                                "rule": rule.to_string(),
+                            })
+                        })
+                        .collect::<Vec<_>>();
+                    (index_name.0, sources)
+                })
+                .collect::<std::collections::BTreeMap<_, _>>();
+            println!("{}", serde_json::to_string_pretty(&groups)?);
+        }
     }
     Ok(())
 }
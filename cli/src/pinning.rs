@@ -0,0 +1,54 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the baselines pinned in the project repository.
+//!
+//! The `.logreduce.baselines` file lists the baselines of each job, so that they are reviewed
+//! like the rest of the project:
+//!
+//! ```yaml
+//! tox-py39:
+//!   - https://zuul.opendev.org/t/openstack/build/3f16b8ba2f7d4bde9c4d7dce1a2ab08a
+//! devstack:
+//!   - https://logs.example.com/42/devstack/
+//! ```
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+pub const FILE_NAME: &str = ".logreduce.baselines";
+
+fn parse(content: &str) -> Result<BTreeMap<String, Vec<String>>> {
+    serde_yaml::from_str(content).context("Invalid pinned baselines")
+}
+
+/// The baselines pinned for the job, when the project has a baselines file.
+pub fn lookup(project: &Path, job: Option<&str>) -> Result<Option<Vec<String>>> {
+    let path = project.join(FILE_NAME);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let content = std::fs::read_to_string(&path).context("Can't read pinned baselines")?;
+    let mut jobs = parse(&content).with_context(|| format!("{:?}", path))?;
+    match job {
+        Some(job) => Ok(jobs.remove(job).filter(|baselines| !baselines.is_empty())),
+        None => {
+            tracing::warn!("The job name is unknown, ignoring {:?}", path);
+            Ok(None)
+        }
+    }
+}
+
+#[test]
+fn test_parse() {
+    let jobs = parse("tox-py39:\n  - https://zuul/build/42\n  - https://zuul/build/43\n").unwrap();
+    assert_eq!(
+        jobs.get("tox-py39"),
+        Some(&vec![
+            "https://zuul/build/42".to_string(),
+            "https://zuul/build/43".to_string()
+        ])
+    );
+    assert!(parse("tox-py39: https://zuul/build/42").is_err());
+}
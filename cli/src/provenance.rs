@@ -0,0 +1,58 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the run provenance record, to reproduce and audit a result.
+
+use anyhow::{Context, Result};
+use logreduce_model::Model;
+use serde::Serialize;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+#[derive(Serialize, Debug)]
+pub struct Provenance {
+    pub version: String,
+    pub args: Vec<String>,
+    pub created_at: u64,
+    /// The digest of the model file, null when the model is not saved.
+    pub model_hash: Option<String>,
+    pub baselines: Vec<String>,
+    pub target: String,
+    pub run_time: f32,
+    pub line_count: usize,
+    pub anomaly_count: usize,
+}
+
+impl Provenance {
+    pub fn new(
+        model: &Model,
+        target: String,
+        run_time: Duration,
+        line_count: usize,
+        anomaly_count: usize,
+    ) -> Result<Provenance> {
+        Ok(Provenance {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            args: std::env::args().collect(),
+            created_at: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0),
+            model_hash: model.hash()?,
+            baselines: model
+                .baselines()
+                .iter()
+                .map(|baseline| baseline.to_string())
+                .collect(),
+            target,
+            run_time: run_time.as_secs_f32(),
+            line_count,
+            anomaly_count,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let file = std::fs::File::create(path).context("Can't create provenance file")?;
+        serde_json::to_writer_pretty(file, self).context("Can't write provenance")
+    }
+}
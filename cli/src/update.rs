@@ -0,0 +1,252 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the self update, to replace the binary of an outdated CI image:
+//!
+//! ```shell
+//! logreduce-cli self check
+//! logreduce-cli self update
+//! ```
+//!
+//! The latest version is the tag of the latest release, and the binary of the platform, e.g.
+//! `logreduce-cli-x86_64-linux`, is only installed when its minisign signature is valid. The public
+//! key is set when building the release with `LOGREDUCE_RELEASE_KEY`, otherwise it must be given
+//! with `--public-key`. The trusted comment of the signature, e.g.
+//! `logreduce-cli 0.2.0 logreduce-cli-x86_64-linux`, must match the version and the binary, so
+//! that an older or another signed binary is not installed instead, see the release workflow.
+//! The `LOGREDUCE_RELEASES_URL` environment overrides the releases location, e.g. for a mirror.
+//!
+//! With `--check-version`, the other commands print a notice when a new version is available. The
+//! latest version is checked at most once a day, and the notice never fails the command.
+
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+const RELEASES_URL: &str = "https://github.com/logreduce/logreduce-tokenizer/releases";
+const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
+const CHECK_INTERVAL: Duration = Duration::from_secs(24 * 3600);
+
+fn releases_url() -> String {
+    std::env::var("LOGREDUCE_RELEASES_URL").unwrap_or_else(|_| RELEASES_URL.to_string())
+}
+
+/// The release binary of the platform.
+fn asset_name() -> String {
+    format!(
+        "logreduce-cli-{}-{}",
+        std::env::consts::ARCH,
+        std::env::consts::OS
+    )
+}
+
+/// The numeric components of a version, the pre-release suffix is ignored.
+fn version_parts(version: &str) -> Vec<u64> {
+    let version = version.trim_start_matches('v');
+    let version = version
+        .split(['-', '+'].as_ref())
+        .next()
+        .unwrap_or_default();
+    version
+        .split('.')
+        .map(|part| part.parse().unwrap_or(0))
+        .collect()
+}
+
+fn is_newer(latest: &str, current: &str) -> bool {
+    version_parts(latest) > version_parts(current)
+}
+
+/// The version of a release tag url, e.g. `.../releases/tag/v0.2.0`.
+fn tag_version(url: &str) -> Option<&str> {
+    let (_, tag) = url.trim_end_matches('/').rsplit_once("/tag/")?;
+    Some(tag.trim_start_matches('v')).filter(|version| !version.is_empty())
+}
+
+/// Get the latest release version, which is where the latest url redirects to.
+fn latest_version() -> Result<String> {
+    let url = format!("{}/latest", releases_url());
+    let resp = logreduce_model::reader::client()
+        .get(&url)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .with_context(|| format!("Can't get {}", url))?;
+    tag_version(resp.url().as_str())
+        .map(|version| version.to_string())
+        .with_context(|| format!("{}: not a release tag", resp.url()))
+}
+
+fn download(url: &str) -> Result<Vec<u8>> {
+    let resp = logreduce_model::reader::client()
+        .get(url)
+        .send()
+        .and_then(|resp| resp.error_for_status())
+        .with_context(|| format!("Can't get {}", url))?;
+    Ok(resp.bytes().context("Can't read the download")?.to_vec())
+}
+
+/// The public key of the releases, in the minisign format.
+fn public_key(path: Option<&Path>) -> Result<minisign_verify::PublicKey> {
+    match (path, option_env!("LOGREDUCE_RELEASE_KEY")) {
+        (Some(path), _) => {
+            let content =
+                std::fs::read_to_string(path).with_context(|| format!("Can't read {:?}", path))?;
+            minisign_verify::PublicKey::decode(&content)
+                .or_else(|_| minisign_verify::PublicKey::from_base64(content.trim()))
+                .map_err(|e| anyhow::anyhow!("{:?}: invalid public key: {}", path, e))
+        }
+        (None, Some(key)) => minisign_verify::PublicKey::from_base64(key)
+            .map_err(|e| anyhow::anyhow!("Invalid release key: {}", e)),
+        (None, None) => Err(anyhow::anyhow!(
+            "This build has no release key, use --public-key to verify the download"
+        )),
+    }
+}
+
+/// The trusted comment of the signature of a release binary.
+fn trusted_comment(version: &str) -> String {
+    format!("logreduce-cli {} {}", version, asset_name())
+}
+
+fn verify(
+    key: &minisign_verify::PublicKey,
+    version: &str,
+    binary: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let signature = std::str::from_utf8(signature)
+        .map_err(anyhow::Error::from)
+        .and_then(|signature| {
+            minisign_verify::Signature::decode(signature).map_err(|e| anyhow::anyhow!("{}", e))
+        })
+        .context("Invalid signature file")?;
+    key.verify(binary, &signature, false)
+        .map_err(|e| anyhow::anyhow!("The signature does not match the download: {}", e))?;
+    // The trusted comment is covered by the signature.
+    let expected = trusted_comment(version);
+    if signature.trusted_comment() != expected {
+        return Err(anyhow::anyhow!(
+            "The signature is for `{}`, expected `{}`",
+            signature.trusted_comment(),
+            expected
+        ));
+    }
+    Ok(())
+}
+
+/// Replace the running binary, the new file is renamed over it so that it is never incomplete.
+fn install(exe: &Path, binary: &[u8]) -> Result<()> {
+    let file_name = exe.file_name().context("Invalid binary path")?;
+    let new_exe = exe.with_file_name(format!(".{}.new", file_name.to_string_lossy()));
+    std::fs::write(&new_exe, binary).with_context(|| format!("Can't write {:?}", new_exe))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(&new_exe, std::fs::Permissions::from_mode(0o755))?;
+    }
+    std::fs::rename(&new_exe, exe).map_err(|e| {
+        let _ = std::fs::remove_file(&new_exe);
+        anyhow::anyhow!("Can't replace {:?}: {}", exe, e)
+    })
+}
+
+/// Print the latest version.
+pub fn check() -> Result<()> {
+    let latest = latest_version()?;
+    if is_newer(&latest, CURRENT_VERSION) {
+        println!(
+            "{} is available, the current version is {}",
+            latest, CURRENT_VERSION
+        );
+    } else {
+        println!("{} is the latest version", CURRENT_VERSION);
+    }
+    Ok(())
+}
+
+/// Install the latest release binary.
+pub fn update(public_key_path: Option<&Path>, force: bool) -> Result<()> {
+    // The key is checked first, to not download for nothing.
+    let key = public_key(public_key_path)?;
+    let latest = latest_version()?;
+    if !force && !is_newer(&latest, CURRENT_VERSION) {
+        println!("{} is the latest version", CURRENT_VERSION);
+        return Ok(());
+    }
+    let url = format!("{}/download/v{}/{}", releases_url(), latest, asset_name());
+    let binary = download(&url)?;
+    let signature = download(&format!("{}.minisig", url))?;
+    verify(&key, &latest, &binary, &signature)?;
+    let exe = std::env::current_exe().context("Can't find the binary path")?;
+    install(&exe, &binary)?;
+    println!(
+        "Updated {} from {} to {}, the models trained with {} may need to be trained again",
+        exe.display(),
+        CURRENT_VERSION,
+        latest,
+        CURRENT_VERSION
+    );
+    Ok(())
+}
+
+fn state_path() -> Result<PathBuf> {
+    xdg::BaseDirectories::with_prefix("logreduce")
+        .context("Failed to get xdg cache directory")?
+        .place_cache_file("latest-version")
+        .context("Can't create cache directory")
+}
+
+/// The latest version, which is stored to be checked at most once a day.
+fn cached_latest_version(path: &Path) -> Result<String> {
+    let checked_at = std::fs::metadata(path).and_then(|metadata| metadata.modified());
+    let is_recent = checked_at.map_or(false, |checked_at| {
+        SystemTime::now()
+            .duration_since(checked_at)
+            .map_or(true, |elapsed| elapsed < CHECK_INTERVAL)
+    });
+    if is_recent {
+        if let Ok(version) = std::fs::read_to_string(path) {
+            return Ok(version.trim().to_string());
+        }
+    }
+    let version = latest_version()?;
+    std::fs::write(path, &version).with_context(|| format!("Can't write {:?}", path))?;
+    Ok(version)
+}
+
+/// Print a notice on stderr when a new version is available, see `--check-version`.
+pub fn notify() {
+    match state_path().and_then(|path| cached_latest_version(&path)) {
+        Ok(latest) if is_newer(&latest, CURRENT_VERSION) => eprintln!(
+            "A new version is available: {} (current {}), run `logreduce-cli self update`",
+            latest, CURRENT_VERSION
+        ),
+        Ok(_) => {}
+        Err(e) => tracing::debug!("Can't check the latest version: {:#}", e),
+    }
+}
+
+#[test]
+fn test_version() {
+    assert!(is_newer("0.2.0", "0.1.0"));
+    assert!(is_newer("v0.10.0", "0.9.3"));
+    assert!(is_newer("1.0", "0.9.9"));
+    assert!(!is_newer("0.1.0", "0.1.0"));
+    assert!(!is_newer("0.1.0-rc1", "0.1.0"));
+    assert_eq!(
+        tag_version("https://github.com/logreduce/logreduce-tokenizer/releases/tag/v0.2.0"),
+        Some("0.2.0")
+    );
+    assert_eq!(
+        tag_version("https://github.com/logreduce/logreduce-tokenizer/releases"),
+        None
+    );
+    assert_eq!(
+        trusted_comment("0.2.0"),
+        format!(
+            "logreduce-cli 0.2.0 logreduce-cli-{}-{}",
+            std::env::consts::ARCH,
+            std::env::consts::OS
+        )
+    );
+}
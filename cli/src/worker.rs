@@ -0,0 +1,122 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the worker mode, to process analysis requests from a queue.
+//!
+//! A request is a json message like this:
+//!
+//! ```json
+//! {"target": "https://zuul/t/tenant/build/uuid", "model": "/models/job.bin", "report": "/reports/uuid.html"}
+//! ```
+//!
+//! The models are kept in memory between the requests, up to the `--model-cache-size`, and the
+//! `--model` files are loaded when the worker starts.
+//!
+//! The queues are optional dependencies, enabled with the `amqp` and `nats` features.
+
+use anyhow::{Context, Result};
+use logreduce_model::{Input, OutputMode};
+use serde::Deserialize;
+use std::path::PathBuf;
+
+use crate::Options;
+
+/// An analysis request message.
+#[derive(Deserialize, Debug)]
+pub struct AnalysisRequest {
+    pub target: String,
+    pub model: Option<PathBuf>,
+    pub report: PathBuf,
+    pub baselines: Option<Vec<String>>,
+}
+
+fn handle(options: &Options, body: &[u8]) -> Result<()> {
+    let request: AnalysisRequest =
+        serde_json::from_slice(body).context("Can't decode analysis request")?;
+    tracing::info!(
+        target = request.target.as_str(),
+        "Processing analysis request"
+    );
+    crate::process(
+        OutputMode::Quiet,
+        Some(request.report),
+        &request.model.into_iter().collect::<Vec<_>>(),
+        options,
+        request
+            .baselines
+            .map(|baselines| baselines.into_iter().map(Input::from_string).collect()),
+        Input::from_string(request.target),
+    )
+}
+
+// A failed request must not stop the worker, this returns false when it failed.
+fn handle_or_log(options: &Options, body: &[u8]) -> bool {
+    match handle(options, body) {
+        Ok(()) => true,
+        Err(e) => {
+            tracing::error!("Analysis request failed: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Consume the requests from a NATS subject.
+#[cfg(feature = "nats")]
+pub fn nats(options: &Options, url: &str, subject: &str) -> Result<()> {
+    let connection = nats::connect(url).context("Can't connect to nats")?;
+    // Using a queue group so that multiple workers share the load.
+    let subscription = connection
+        .queue_subscribe(subject, "logreduce")
+        .context("Can't subscribe")?;
+    tracing::info!(subject, "Waiting for analysis requests");
+    for message in subscription.messages() {
+        handle_or_log(options, &message.data);
+        if options.cancellation().is_cancelled() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Consume the requests from an AMQP queue. A failed request is delivered once again, e.g. to
+/// another worker, and then it is rejected, to go to the dead letter exchange of the queue.
+#[cfg(feature = "amqp")]
+pub fn amqp(options: &Options, url: &str, queue: &str) -> Result<()> {
+    use amiquip::{ConsumerMessage, ConsumerOptions, QueueDeclareOptions};
+    let mut connection =
+        amiquip::Connection::insecure_open(url).context("Can't connect to amqp")?;
+    let channel = connection.open_channel(None)?;
+    let queue = channel.queue_declare(
+        queue,
+        QueueDeclareOptions {
+            durable: true,
+            ..QueueDeclareOptions::default()
+        },
+    )?;
+    // Only fetch one request at a time so that multiple workers share the load.
+    channel.qos(0, 1, false)?;
+    let consumer = queue.consume(ConsumerOptions::default())?;
+    tracing::info!("Waiting for analysis requests");
+    for message in consumer.receiver().iter() {
+        match message {
+            ConsumerMessage::Delivery(delivery) => {
+                let processed = handle_or_log(options, &delivery.body);
+                if options.cancellation().is_cancelled() {
+                    // The interrupted request is delivered again to another worker.
+                    break;
+                }
+                if processed {
+                    consumer.ack(delivery)?;
+                } else {
+                    let requeue = !delivery.redelivered;
+                    consumer.nack(delivery, requeue)?;
+                }
+            }
+            other => {
+                tracing::info!("Consumer ended: {:?}", other);
+                break;
+            }
+        }
+    }
+    connection.close().context("Can't close amqp connection")
+}
@@ -0,0 +1,92 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Zuul artifact files, so that the report appears in the build page
+//! when logreduce runs as a post-run role.
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// The file read by the post-run role to call `zuul_return`.
+pub const RETURN_FILE: &str = "zuul-return.json";
+
+/// The manifest file listing the build logs.
+pub const MANIFEST_FILE: &str = "zuul-manifest.json";
+
+/// Write the manifest entry and the return artifact json next to the report.
+pub fn write(report: &Path) -> Result<()> {
+    let dir = report
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let name = report
+        .file_name()
+        .context("The report path has no file name")?
+        .to_string_lossy()
+        .to_string();
+
+    let manifest_path = dir.join(MANIFEST_FILE);
+    let manifest = match std::fs::read(&manifest_path) {
+        Ok(buf) => serde_json::from_slice(&buf).context("Invalid zuul manifest")?,
+        Err(_) => json!({"tree": [], "index_links": false}),
+    };
+    let entry = manifest_entry(&name, std::fs::metadata(report)?.len());
+    write_json(&manifest_path, &add_entry(manifest, entry))?;
+
+    write_json(&dir.join(RETURN_FILE), &return_artifact(&name))
+}
+
+fn manifest_entry(name: &str, size: u64) -> Value {
+    let last_modified = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    json!({
+        "name": name,
+        "mimetype": "text/html",
+        "encoding": null,
+        "last_modified": last_modified,
+        "size": size,
+    })
+}
+
+/// Add the entry to the manifest tree, replacing a previous entry with the same name.
+fn add_entry(mut manifest: Value, entry: Value) -> Value {
+    if !manifest["tree"].is_array() {
+        manifest["tree"] = json!([]);
+    }
+    if let Some(tree) = manifest["tree"].as_array_mut() {
+        tree.retain(|e| e["name"] != entry["name"]);
+        tree.push(entry);
+    }
+    manifest
+}
+
+fn return_artifact(name: &str) -> Value {
+    json!({
+        "zuul": {
+            "artifacts": [{
+                "name": "LogReduce report",
+                "url": name,
+                "metadata": {"type": "logreduce_report"},
+            }]
+        }
+    })
+}
+
+fn write_json(path: &Path, value: &Value) -> Result<()> {
+    let file = std::fs::File::create(path).with_context(|| format!("Can't create {:?}", path))?;
+    serde_json::to_writer_pretty(file, value).with_context(|| format!("Can't write {:?}", path))
+}
+
+#[test]
+fn test_add_entry() {
+    let manifest = json!({"tree": [{"name": "job-output.txt"}, {"name": "report.html", "size": 1}], "index_links": false});
+    let manifest = add_entry(manifest, manifest_entry("report.html", 42));
+    let tree = manifest["tree"].as_array().unwrap();
+    assert_eq!(tree.len(), 2);
+    assert_eq!(tree[1]["size"], 42);
+    assert_eq!(manifest["index_links"], false);
+}
@@ -20,7 +20,7 @@
         logreduce = naersk-lib.buildPackage {
           pname = "logreduce-cli";
           src = self;
-          nativeBuildInputs = with pkgs; [ openssl pkg-config ];
+          nativeBuildInputs = with pkgs; [ openssl pkg-config protobuf sqlite ];
           doCheck = true;
         };
 
@@ -47,7 +47,7 @@
         defaultPackage = logreduce;
         apps.default = flake-utils.lib.mkApp { drv = logreduce; };
         devShell = pkgs.mkShell {
-          buildInputs = with pkgs; [ toolchain openssl pkg-config ];
+          buildInputs = with pkgs; [ toolchain openssl pkg-config protobuf sqlite ];
           LOGREDUCE_CACHE = "1";
         };
 
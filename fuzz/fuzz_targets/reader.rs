@@ -0,0 +1,32 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! Read the lines of a corrupted gzip file with the decompression reader of the sources, with
+//! and without the json splitter. The size limit is lowered so that the bombs are stopped early.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use std::sync::Once;
+
+static LIMITS: Once = Once::new();
+
+fuzz_target!(|data: &[u8]| {
+    LIMITS.call_once(|| {
+        logreduce_model::configure_limits(logreduce_model::DecompressLimits {
+            max_size: Some(1 << 20),
+            max_ratio: Some(100),
+        })
+        .expect("Limits");
+    });
+    let path = std::env::temp_dir().join(format!("logreduce-fuzz-{}.gz", std::process::id()));
+    std::fs::write(&path, data).expect("Fuzz file");
+    let source = logreduce_model::Source::from_pathbuf(path);
+    for split_json in [false, true] {
+        let reader = source.open().expect("Fuzz file");
+        for line in logreduce_iterator::BytesLines::new(reader, split_json) {
+            if line.is_err() {
+                break;
+            }
+        }
+    }
+});
@@ -0,0 +1,14 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! Tokenize arbitrary bytes, including lines that are split on invalid utf-8 boundaries.
+
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = logreduce_tokenizer::process(&String::from_utf8_lossy(data));
+    for (line, _) in logreduce_iterator::BytesLines::new(data, false).flatten() {
+        let _ = logreduce_tokenizer::process(&logreduce_iterator::clone_bytes_to_string(&line));
+    }
+});
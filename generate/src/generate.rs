@@ -9,6 +9,14 @@
 //! # use logreduce_generate::{gen_lines};
 //! assert_eq!(gen_lines().next(), Some("xbWovSpJUTKzox0Pi 5l9xl5uT cREJn spFXCZ wirsrgr 2OCwC pe".to_string()))
 //! ```
+//!
+//! Baseline/target pairs with injected anomalies are created with [gen_pair]:
+//!
+//! ```rust
+//! # use logreduce_generate::{gen_pair, PairConfig};
+//! let pair = gen_pair(&PairConfig::default());
+//! assert!(!pair.anomalies.is_empty());
+//! ```
 
 use rand::distributions::Alphanumeric;
 use rand::prelude::*;
@@ -52,6 +60,141 @@ pub fn gen_lines() -> impl Iterator<Item = String> {
     RandomLine { rng: fixed_rng() }
 }
 
+/// The type of anomaly injected in a target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnomalyKind {
+    /// A new error line.
+    NewError,
+    /// A known line with one of its constant word changed.
+    ValueDrift,
+    /// A block of known lines moved to another location.
+    ReorderedBlock,
+}
+
+impl std::str::FromStr for AnomalyKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "new-error" => Ok(AnomalyKind::NewError),
+            "value-drift" => Ok(AnomalyKind::ValueDrift),
+            "reordered-block" => Ok(AnomalyKind::ReorderedBlock),
+            _ => Err(format!(
+                "Unknown anomaly kind: {} (expected new-error, value-drift or reordered-block)",
+                s
+            )),
+        }
+    }
+}
+
+pub struct PairConfig {
+    pub seed: u64,
+    pub line_count: usize,
+    pub anomaly_count: usize,
+    /// The kinds of the anomalies, used in turn.
+    pub kinds: Vec<AnomalyKind>,
+}
+
+impl Default for PairConfig {
+    fn default() -> Self {
+        PairConfig {
+            seed: SEED,
+            line_count: 1000,
+            anomaly_count: 5,
+            kinds: vec![
+                AnomalyKind::NewError,
+                AnomalyKind::ValueDrift,
+                AnomalyKind::ReorderedBlock,
+            ],
+        }
+    }
+}
+
+pub struct Pair {
+    pub baseline: Vec<String>,
+    pub target: Vec<String>,
+    /// The line ranges of the injected anomalies, starting at 1 and inclusive.
+    pub anomalies: Vec<(usize, usize)>,
+}
+
+const TEMPLATE_COUNT: usize = 20;
+const BLOCK_SIZE: usize = 3;
+
+/// A line made of the constant template words and some variable values.
+fn gen_template_line(rng: &mut impl Rng, templates: &[String]) -> String {
+    format!(
+        "{} id={} took {}ms",
+        templates[rng.gen_range(0..templates.len())],
+        rng.gen_range(0..10000),
+        rng.gen_range(0..500)
+    )
+}
+
+/// Create a baseline and a target using the same templates, then inject the anomalies.
+pub fn gen_pair(config: &PairConfig) -> Pair {
+    let mut rng = ChaCha8Rng::seed_from_u64(config.seed);
+    let templates = (0..TEMPLATE_COUNT)
+        .map(|_| gen_line(&mut rng))
+        .collect::<Vec<_>>();
+    let baseline = (0..config.line_count)
+        .map(|_| gen_template_line(&mut rng, &templates))
+        .collect::<Vec<_>>();
+    // The target lines, with a flag set for the anomalies.
+    let mut target = (0..config.line_count)
+        .map(|_| (gen_template_line(&mut rng, &templates), false))
+        .collect::<Vec<_>>();
+
+    for kind in config.kinds.iter().cycle().take(config.anomaly_count) {
+        let pos = rng.gen_range(0..target.len().max(1));
+        match kind {
+            AnomalyKind::NewError => {
+                let line = format!("ERROR {}", gen_line(&mut rng));
+                target.insert(pos.min(target.len()), (line, true));
+            }
+            AnomalyKind::ValueDrift => {
+                if let Some((line, flag)) = target.get_mut(pos) {
+                    let word = gen_line(&mut rng)
+                        .split(' ')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string();
+                    *line = format!("{} {}", word, line.split_once(' ').map_or("", |x| x.1));
+                    *flag = true;
+                }
+            }
+            AnomalyKind::ReorderedBlock => {
+                if target.len() > BLOCK_SIZE {
+                    let start = pos.min(target.len() - BLOCK_SIZE);
+                    let block = target
+                        .drain(start..start + BLOCK_SIZE)
+                        .map(|(line, _)| (line, true))
+                        .collect::<Vec<_>>();
+                    let dest = rng.gen_range(0..=target.len());
+                    target.splice(dest..dest, block);
+                }
+            }
+        }
+    }
+
+    // Collect the consecutive flagged lines.
+    let mut anomalies: Vec<(usize, usize)> = Vec::new();
+    for (idx, (_, flag)) in target.iter().enumerate() {
+        let pos = idx + 1;
+        if *flag {
+            match anomalies.last_mut() {
+                Some((_, end)) if *end + 1 == pos => *end = pos,
+                _ => anomalies.push((pos, pos)),
+            }
+        }
+    }
+
+    Pair {
+        baseline,
+        target: target.into_iter().map(|(line, _)| line).collect(),
+        anomalies,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -65,6 +208,24 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_gen_pair() {
+        let config = PairConfig {
+            line_count: 100,
+            anomaly_count: 1,
+            kinds: vec![AnomalyKind::NewError],
+            ..PairConfig::default()
+        };
+        let pair = gen_pair(&config);
+        assert_eq!(pair.baseline.len(), 100);
+        assert_eq!(pair.target.len(), 101);
+        assert_eq!(pair.anomalies.len(), 1);
+        let (start, end) = pair.anomalies[0];
+        assert_eq!(start, end);
+        assert!(pair.target[start - 1].starts_with("ERROR "));
+        assert_eq!(gen_pair(&config).target, pair.target);
+    }
+
     #[test]
     fn test_gen_lines() {
         let lines = gen_lines().skip(1).take(2).collect::<Vec<String>>();
@@ -0,0 +1,123 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module protects the service from the runaway clients.
+//!
+//! The rejected requests use the RESOURCE_EXHAUSTED code, which the HTTP gateways translate
+//! to 429, and the oversized targets are stopped with the OUT_OF_RANGE code.
+
+use std::collections::HashMap;
+use std::num::{NonZeroU32, NonZeroUsize};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tonic::Status;
+
+/// A token bucket per tenant, refilled continuously.
+struct RateLimiter {
+    per_minute: NonZeroU32,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    fn check(&self, tenant: &str, now: Instant) -> Result<(), Status> {
+        let capacity = self.per_minute.get() as f64;
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(tenant.to_string()).or_insert(Bucket {
+            tokens: capacity,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * capacity / 60.0).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            let wait = (1.0 - bucket.tokens) * 60.0 / capacity;
+            Err(Status::resource_exhausted(format!(
+                "Rate limit exceeded, retry in {} seconds",
+                wait.ceil()
+            )))
+        }
+    }
+}
+
+pub struct Limits {
+    concurrency: Option<Arc<Semaphore>>,
+    /// The maximum number of lines of a score request stream.
+    pub max_lines: Option<u64>,
+    rate: Option<RateLimiter>,
+}
+
+impl Limits {
+    pub fn new(
+        max_concurrent: Option<NonZeroUsize>,
+        max_lines: Option<u64>,
+        requests_per_minute: Option<NonZeroU32>,
+    ) -> Limits {
+        Limits {
+            concurrency: max_concurrent.map(|count| Arc::new(Semaphore::new(count.get()))),
+            max_lines,
+            rate: requests_per_minute.map(|per_minute| RateLimiter {
+                per_minute,
+                buckets: Mutex::new(HashMap::new()),
+            }),
+        }
+    }
+
+    /// Count a request of the tenant.
+    pub fn check_rate(&self, tenant: &str) -> Result<(), Status> {
+        match &self.rate {
+            Some(rate) => rate.check(tenant, Instant::now()),
+            None => Ok(()),
+        }
+    }
+
+    /// Reserve an analysis slot, which is released when the permit is dropped.
+    pub fn acquire(&self) -> Result<Option<OwnedSemaphorePermit>, Status> {
+        match &self.concurrency {
+            Some(semaphore) => semaphore
+                .clone()
+                .try_acquire_owned()
+                .map(Some)
+                .map_err(|_| Status::resource_exhausted("Too many concurrent analyses")),
+            None => Ok(None),
+        }
+    }
+}
+
+#[test]
+fn test_rate_limiter() {
+    let limiter = RateLimiter {
+        per_minute: NonZeroU32::new(2).unwrap(),
+        buckets: Mutex::new(HashMap::new()),
+    };
+    let now = Instant::now();
+    assert!(limiter.check("openstack", now).is_ok());
+    assert!(limiter.check("openstack", now).is_ok());
+    let status = limiter.check("openstack", now).unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert_eq!(status.message(), "Rate limit exceeded, retry in 30 seconds");
+    // The other tenants are not affected.
+    assert!(limiter.check("ansible", now).is_ok());
+    // A token is refilled every 30 seconds.
+    let later = now + std::time::Duration::from_secs(30);
+    assert!(limiter.check("openstack", later).is_ok());
+    assert!(limiter.check("openstack", later).is_err());
+}
+
+#[test]
+fn test_concurrency() {
+    let limits = Limits::new(NonZeroUsize::new(1), None, None);
+    let permit = limits.acquire().unwrap();
+    assert!(permit.is_some());
+    assert!(limits.acquire().is_err());
+    drop(permit);
+    assert!(limits.acquire().is_ok());
+}
@@ -0,0 +1,608 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This binary provides a gRPC service to score log lines with a logreduce model.
+//!
+//! Usage: `logreduce-grpc [MODEL_PATH | --tenants FILE] [--listen ADDR] [--db FILE]`
+//!
+//! The address can also be given after the model path, like the previous versions:
+//! `logreduce-grpc MODEL_PATH ADDR`.
+//!
+//! The anomalies are recorded with their acknowledgments in the database, see [store].
+//! The secrets of the lines are masked in the responses and in the database, see [Redactor].
+//! A service with multiple tenants requires a bearer token, see [tenant].
+//! The optional request limits are described in [limits].
+
+use clap::Parser;
+use logreduce_model::process::{LineScorer, ScoredLine};
+use logreduce_model::redact::Redactor;
+use logreduce_model::{Index, IndexName, Model};
+use std::collections::{HashMap, VecDeque};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc::{Receiver, Sender};
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+mod limits;
+mod store;
+mod tenant;
+
+pub mod pb {
+    tonic::include_proto!("logreduce");
+}
+
+use limits::Limits;
+use pb::scorer_server::{Scorer, ScorerServer};
+use pb::{
+    AcknowledgeRequest, AcknowledgeResponse, ListAnomaliesRequest, ListAnomaliesResponse,
+    ScoreRequest, ScoreResponse,
+};
+use store::Store;
+use tenant::{Tenant, Tenants};
+
+/// The listen address, when it is not given.
+const DEFAULT_ADDR: &str = "127.0.0.1:50051";
+
+#[derive(Parser)]
+#[clap(about = "A gRPC service to score log lines with a logreduce model")]
+struct Cli {
+    #[clap(parse(from_os_str), required_unless_present = "tenants")]
+    model_path: Option<PathBuf>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        conflicts_with = "model-path",
+        help = "Serve the models of the tenants defined in this yaml file"
+    )]
+    tenants: Option<PathBuf>,
+
+    #[clap(help = "The listen address, like --listen")]
+    addr: Option<String>,
+
+    #[clap(
+        long,
+        value_name = "ADDR",
+        conflicts_with = "addr",
+        help = "The listen address [default: 127.0.0.1:50051]"
+    )]
+    listen: Option<String>,
+
+    #[clap(
+        long,
+        parse(from_os_str),
+        value_name = "FILE",
+        help = "Persist the anomalies and their acknowledgments in this sqlite database"
+    )]
+    db: Option<PathBuf>,
+
+    #[clap(
+        long,
+        default_value = "30",
+        value_name = "DAYS",
+        help = "Remove the anomalies and the access log entries older than DAYS"
+    )]
+    retention_days: u64,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "The maximum number of concurrent analyses"
+    )]
+    max_concurrent: Option<std::num::NonZeroUsize>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "The maximum number of lines of a score request"
+    )]
+    max_lines: Option<u64>,
+
+    #[clap(
+        long,
+        value_name = "BYTES",
+        help = "The maximum size of a request message"
+    )]
+    max_message_size: Option<usize>,
+
+    #[clap(
+        long,
+        value_name = "COUNT",
+        help = "The maximum number of requests per minute of a tenant"
+    )]
+    requests_per_minute: Option<std::num::NonZeroU32>,
+
+    #[clap(
+        long,
+        value_name = "PATTERN",
+        help = "Mask the matches of this regex in the lines, in addition to the secrets"
+    )]
+    redact: Vec<regex::Regex>,
+
+    #[clap(
+        long,
+        help = "Do not mask the built-in secret patterns, e.g. passwords and tokens"
+    )]
+    no_redact: bool,
+}
+
+struct ScorerService {
+    tenants: Tenants,
+    store: Arc<Store>,
+    limits: Limits,
+    redactor: Arc<Redactor>,
+}
+
+fn internal_error(err: anyhow::Error) -> Status {
+    Status::internal(format!("{:#}", err))
+}
+
+type ScoreStream = Pin<Box<dyn Stream<Item = Result<ScoreResponse, Status>> + Send>>;
+
+/// The lines of a request with their position, the error ends the request.
+type Batch = Result<Vec<(u64, ScoreRequest)>, Status>;
+
+/// The maximum number of lines scored together, see [LineScorer::push_all].
+const BATCH_LINES: usize = 256;
+
+/// The time to wait for the next lines of a batch.
+const BATCH_DELAY: Duration = Duration::from_millis(20);
+
+/// The line scorer of an index, with the request positions of its lines that are not scored yet.
+struct IndexScorer<'a> {
+    scorer: LineScorer<'a>,
+    positions: VecDeque<u64>,
+    /// The number of lines before the first position.
+    scored: usize,
+}
+
+impl<'a> IndexScorer<'a> {
+    fn new(index: &'a Index, index_name: &IndexName) -> IndexScorer<'a> {
+        IndexScorer {
+            scorer: index.line_scorer(Some(index_name)),
+            positions: VecDeque::new(),
+            scored: 0,
+        }
+    }
+
+    fn push_all(&mut self, lines: Vec<(u64, String)>) -> Vec<ScoreResponse> {
+        let lines = lines
+            .into_iter()
+            .map(|(pos, line)| {
+                self.positions.push_back(pos);
+                line
+            })
+            .collect::<Vec<_>>();
+        let scored = self.scorer.push_all(lines);
+        self.responses(scored)
+    }
+
+    fn finish(&mut self) -> Vec<ScoreResponse> {
+        let scored = self.scorer.finish();
+        self.responses(scored)
+    }
+
+    /// The responses of the scored lines, at the request position of their first line.
+    fn responses(&mut self, scored: Vec<ScoredLine>) -> Vec<ScoreResponse> {
+        scored
+            .into_iter()
+            .map(|scored| {
+                while self.scored + 1 < scored.pos {
+                    self.positions.pop_front();
+                    self.scored += 1;
+                }
+                ScoreResponse {
+                    pos: self.positions.front().copied().unwrap_or_default(),
+                    distance: scored.distance,
+                    anomaly: scored.distance > logreduce_model::process::THRESHOLD,
+                    line: scored.line,
+                    acknowledged: false,
+                    id: scored.id,
+                    error: String::new(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Run the store queries with `spawn_blocking`, as sqlite blocks the thread.
+async fn blocking<T: Send + 'static>(
+    store: &Arc<Store>,
+    f: impl FnOnce(&Store) -> anyhow::Result<T> + Send + 'static,
+) -> anyhow::Result<T> {
+    let store = store.clone();
+    tokio::task::spawn_blocking(move || f(&store))
+        .await
+        .map_err(|e| anyhow::anyhow!("The store query failed: {}", e))?
+}
+
+/// Record the anomalies of the responses, the other responses are dropped when only the
+/// anomalies are sent. The lines are redacted before they are recorded.
+fn record(
+    store: &Store,
+    redactor: &Redactor,
+    tenant: &str,
+    index_name: &IndexName,
+    responses: Vec<ScoreResponse>,
+    only_anomalies: bool,
+) -> Vec<ScoreResponse> {
+    let mut responses = responses
+        .into_iter()
+        .filter(|response| response.anomaly || !only_anomalies)
+        .map(|response| ScoreResponse {
+            line: redactor.redact(&response.line).into_owned(),
+            ..response
+        })
+        .collect::<Vec<_>>();
+    let occurrences = responses
+        .iter()
+        .filter(|response| response.anomaly)
+        .map(|response| {
+            (
+                response.id.clone(),
+                response.line.clone(),
+                response.distance,
+            )
+        })
+        .collect::<Vec<store::Occurrence>>();
+    if occurrences.is_empty() {
+        return responses;
+    }
+    match store.record(tenant, index_name.as_str(), &occurrences) {
+        Ok(acknowledged) => responses
+            .iter_mut()
+            .filter(|response| response.anomaly)
+            .zip(acknowledged)
+            .for_each(|(response, acknowledged)| response.acknowledged = acknowledged),
+        Err(e) => tracing::error!("Can't record anomaly: {:?}", e),
+    }
+    responses
+}
+
+/// Send the responses, this returns false when the client is gone.
+fn send_all(
+    tx: &Sender<Result<ScoreResponse, Status>>,
+    responses: impl Iterator<Item = ScoreResponse>,
+    anomaly_count: &mut u64,
+) -> bool {
+    for response in responses {
+        if response.anomaly {
+            *anomaly_count += 1;
+        }
+        if tx.blocking_send(Ok(response)).is_err() {
+            return false;
+        }
+    }
+    true
+}
+
+/// Read the request stream by batches of lines, until an error or the lines limit.
+async fn read_batches(
+    requests: Streaming<ScoreRequest>,
+    max_lines: Option<u64>,
+    tx: Sender<Batch>,
+) {
+    let mut pos = 0;
+    let requests = requests.chunks_timeout(BATCH_LINES, BATCH_DELAY);
+    tokio::pin!(requests);
+    while let Some(requests) = requests.next().await {
+        let mut batch = Vec::with_capacity(requests.len());
+        let mut error = None;
+        for request in requests {
+            match request {
+                Ok(_) if max_lines.map_or(false, |max_lines| pos >= max_lines) => {
+                    let message = format!("The request exceeds {} lines", pos);
+                    error = Some(Status::out_of_range(message));
+                    break;
+                }
+                Ok(request) => {
+                    pos += 1;
+                    batch.push((pos, request));
+                }
+                Err(status) => {
+                    error = Some(status);
+                    break;
+                }
+            }
+        }
+        if tx.send(Ok(batch)).await.is_err() {
+            // The client is gone.
+            return;
+        }
+        if let Some(status) = error {
+            let _ = tx.send(Err(status)).await;
+            return;
+        }
+    }
+}
+
+/// Score the batches, keeping only the anomalies when requested. A line of an unknown index
+/// gets an error response, and the next lines are still scored. This returns the line and the
+/// anomaly counts.
+fn score_batches(
+    tenant: &Tenant,
+    store: &Store,
+    redactor: &Redactor,
+    mut batches: Receiver<Batch>,
+    tx: &Sender<Result<ScoreResponse, Status>>,
+    only_anomalies: bool,
+) -> (u64, u64) {
+    let (mut line_count, mut anomaly_count) = (0, 0);
+    let mut scorers: HashMap<IndexName, IndexScorer> = HashMap::new();
+    while let Some(batch) = batches.blocking_recv() {
+        let batch = match batch {
+            Ok(batch) => batch,
+            Err(status) => {
+                let _ = tx.blocking_send(Err(status));
+                return (line_count, anomaly_count);
+            }
+        };
+        line_count += batch.len() as u64;
+        // The lines of each index are scored together.
+        let mut groups: HashMap<IndexName, Vec<(u64, String)>> = HashMap::new();
+        for (pos, request) in batch {
+            let index_name = IndexName(request.index_name);
+            groups
+                .entry(index_name)
+                .or_default()
+                .push((pos, request.line));
+        }
+        let mut responses = Vec::new();
+        for (index_name, lines) in groups {
+            if !scorers.contains_key(&index_name) {
+                if let Some(index) = tenant.model.get_index(&index_name) {
+                    scorers.insert(index_name.clone(), IndexScorer::new(index, &index_name));
+                }
+            }
+            match scorers.get_mut(&index_name) {
+                Some(scorer) => {
+                    let scored = scorer.push_all(lines);
+                    responses.extend(record(
+                        store,
+                        redactor,
+                        &tenant.name,
+                        &index_name,
+                        scored,
+                        only_anomalies,
+                    ));
+                }
+                None => responses.extend(lines.into_iter().map(|(pos, line)| ScoreResponse {
+                    pos,
+                    line: redactor.redact(&line).into_owned(),
+                    error: format!("No baselines for {}", index_name),
+                    ..ScoreResponse::default()
+                })),
+            }
+        }
+        responses.sort_by_key(|response| response.pos);
+        if !send_all(tx, responses.into_iter(), &mut anomaly_count) {
+            return (line_count, anomaly_count);
+        }
+    }
+    for (index_name, scorer) in scorers.iter_mut() {
+        let responses = scorer.finish();
+        let responses = record(
+            store,
+            redactor,
+            &tenant.name,
+            index_name,
+            responses,
+            only_anomalies,
+        );
+        if !send_all(tx, responses.into_iter(), &mut anomaly_count) {
+            break;
+        }
+    }
+    (line_count, anomaly_count)
+}
+
+impl ScorerService {
+    /// Score the request stream, the requests are read by an async task, and the lines are
+    /// scored on a blocking thread, as the search is CPU bound.
+    fn process(
+        &self,
+        method: &'static str,
+        request: Request<Streaming<ScoreRequest>>,
+        only_anomalies: bool,
+    ) -> Result<ScoreStream, Status> {
+        let tenant = self.tenants.authenticate(request.metadata())?;
+        self.limits.check_rate(&tenant.name)?;
+        let permit = self.limits.acquire()?;
+        let max_lines = self.limits.max_lines;
+        let peer = request.remote_addr().map(|addr| addr.to_string());
+        let requests = request.into_inner();
+        let store = self.store.clone();
+        let redactor = self.redactor.clone();
+        let (tx, rx) = tokio::sync::mpsc::channel(128);
+        let (batch_tx, batch_rx) = tokio::sync::mpsc::channel(4);
+        tokio::spawn(read_batches(requests, max_lines, batch_tx));
+        tokio::task::spawn_blocking(move || {
+            // The analysis slot is released when the stream is done.
+            let _permit = permit;
+            let (line_count, anomaly_count) =
+                score_batches(&tenant, &store, &redactor, batch_rx, &tx, only_anomalies);
+            let logged = store.log_request(&tenant.name, method, peer, line_count, anomaly_count);
+            if let Err(e) = logged {
+                tracing::error!("Can't log request: {:?}", e)
+            }
+        });
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+}
+
+#[tonic::async_trait]
+impl Scorer for ScorerService {
+    type ScoreStream = ScoreStream;
+    type AnomaliesStream = ScoreStream;
+
+    async fn score(
+        &self,
+        request: Request<Streaming<ScoreRequest>>,
+    ) -> Result<Response<Self::ScoreStream>, Status> {
+        Ok(Response::new(self.process("Score", request, false)?))
+    }
+
+    async fn anomalies(
+        &self,
+        request: Request<Streaming<ScoreRequest>>,
+    ) -> Result<Response<Self::AnomaliesStream>, Status> {
+        Ok(Response::new(self.process("Anomalies", request, true)?))
+    }
+
+    async fn acknowledge(
+        &self,
+        request: Request<AcknowledgeRequest>,
+    ) -> Result<Response<AcknowledgeResponse>, Status> {
+        let tenant = self.tenants.authenticate(request.metadata())?;
+        self.limits.check_rate(&tenant.name)?;
+        let AcknowledgeRequest {
+            index_name,
+            line,
+            author,
+            comment,
+            id,
+        } = request.into_inner();
+        let id = match (id.is_empty(), line.is_empty()) {
+            (false, _) => id,
+            (true, false) => {
+                let index_name = IndexName(index_name.clone());
+                match tenant.model.get_index(&index_name) {
+                    Some(index) => index.line_id(&index_name, &line),
+                    None => {
+                        let message = format!("No baselines for {}", index_name);
+                        return Err(Status::not_found(message));
+                    }
+                }
+            }
+            (true, true) => return Err(Status::invalid_argument("The id or the line is required")),
+        };
+        let name = tenant.name.clone();
+        blocking(&self.store, move |store| {
+            store.acknowledge(&name, &index_name, &id, &line, &author, &comment)
+        })
+        .await
+        .map_err(internal_error)?;
+        Ok(Response::new(AcknowledgeResponse {}))
+    }
+
+    async fn list_anomalies(
+        &self,
+        request: Request<ListAnomaliesRequest>,
+    ) -> Result<Response<ListAnomaliesResponse>, Status> {
+        let tenant = self.tenants.authenticate(request.metadata())?;
+        self.limits.check_rate(&tenant.name)?;
+        let ListAnomaliesRequest { index_name, limit } = request.into_inner();
+        let name = tenant.name.clone();
+        let anomalies = blocking(&self.store, move |store| {
+            store.list(&name, &index_name, limit)
+        })
+        .await
+        .map_err(internal_error)?;
+        Ok(Response::new(ListAnomaliesResponse { anomalies }))
+    }
+}
+
+/// Remove the expired rows every hour.
+fn spawn_expire(store: Arc<Store>, retention: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(3600));
+        loop {
+            interval.tick().await;
+            match blocking(&store, move |store| store.expire(retention)).await {
+                Ok(0) => {}
+                Ok(count) => tracing::info!(count, "Removed the expired rows"),
+                Err(e) => tracing::error!("Can't remove the expired rows: {:?}", e),
+            }
+        }
+    });
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let tenants = match (&cli.model_path, &cli.tenants) {
+        (_, Some(path)) => Tenants::load(path)?,
+        (Some(model_path), None) => Tenants::anonymous(Model::load(model_path)?),
+        (None, None) => unreachable!("The model path is required without tenants"),
+    };
+    // The previous databases identified the anomalies by line.
+    let line_id = |tenant: &str, index_name: &str, line: &str| {
+        let index_name = IndexName(index_name.to_string());
+        match tenants
+            .get(tenant)
+            .and_then(|tenant| tenant.model.get_index(&index_name))
+        {
+            Some(index) => index.line_id(&index_name, line),
+            None => logreduce_model::anomaly_id(Some(&index_name), line),
+        }
+    };
+    let store = Arc::new(Store::open(cli.db.as_deref(), line_id)?);
+    spawn_expire(
+        store.clone(),
+        Duration::from_secs(cli.retention_days * 24 * 3600),
+    );
+    let limits = Limits::new(cli.max_concurrent, cli.max_lines, cli.requests_per_minute);
+    let redactor = Redactor::new(cli.redact);
+    let redactor = if cli.no_redact {
+        redactor.without_builtin()
+    } else {
+        redactor
+    };
+    let service = ScorerService {
+        tenants,
+        store,
+        limits,
+        redactor: Arc::new(redactor),
+    };
+    let mut server = ScorerServer::new(service);
+    if let Some(size) = cli.max_message_size {
+        server = server.max_decoding_message_size(size);
+    }
+    let addr = cli
+        .listen
+        .or(cli.addr)
+        .unwrap_or_else(|| DEFAULT_ADDR.to_string());
+    let addr = addr.as_str();
+    tracing::info!(addr, "Serving the scorer service");
+    tonic::transport::Server::builder()
+        .add_service(server)
+        .serve(addr.parse()?)
+        .await?;
+    Ok(())
+}
+
+#[test]
+fn test_cli_addr() {
+    let addr = |args: &[&str]| {
+        let cli =
+            Cli::try_parse_from(std::iter::once("logreduce-grpc").chain(args.iter().copied()))?;
+        Ok::<_, clap::Error>(cli.listen.or(cli.addr))
+    };
+    assert_eq!(addr(&["model.bin"]).unwrap(), None);
+    let expected = Some("0.0.0.0:50051".to_string());
+    assert_eq!(addr(&["model.bin", "0.0.0.0:50051"]).unwrap(), expected);
+    assert_eq!(
+        addr(&["model.bin", "--listen", "0.0.0.0:50051"]).unwrap(),
+        expected
+    );
+    assert!(addr(&["model.bin", "0.0.0.0:1", "--listen", "0.0.0.0:2"]).is_err());
+}
+
+#[test]
+fn test_cli_limits() {
+    let parse = |args: &[&str]| {
+        Cli::try_parse_from(["logreduce-grpc", "model.bin"].iter().chain(args).copied())
+    };
+    let cli = parse(&["--max-concurrent", "2", "--requests-per-minute", "60"]).unwrap();
+    assert_eq!(cli.max_concurrent.map(|count| count.get()), Some(2));
+    assert_eq!(cli.requests_per_minute.map(|count| count.get()), Some(60));
+    // A zero limit would reject every request.
+    assert!(parse(&["--max-concurrent", "0"]).is_err());
+    assert!(parse(&["--requests-per-minute", "0"]).is_err());
+}
@@ -0,0 +1,412 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module persists the reported anomalies, their acknowledgments and the access log.
+//!
+//! An anomaly is identified by its tenant, index name and [logreduce_model::anomaly_id], so that
+//! the acknowledgment of a line applies to its next occurrences, even when their variable parts,
+//! like a timestamp, differ. The anomalies that are not seen for the retention period are removed
+//! by [Store::expire], unless they are acknowledged.
+//!
+//! The database schema is versioned with the sqlite `user_version`, and the databases of the
+//! previous versions are migrated when they are opened, see [Store::open].
+//!
+//! The queries block, so the service runs them with `spawn_blocking`.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::pb::ReportedAnomaly;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS anomalies (
+  tenant TEXT NOT NULL,
+  index_name TEXT NOT NULL,
+  id TEXT NOT NULL,
+  line TEXT NOT NULL,
+  distance REAL NOT NULL,
+  first_seen INTEGER NOT NULL,
+  last_seen INTEGER NOT NULL,
+  count INTEGER NOT NULL,
+  author TEXT,
+  comment TEXT,
+  PRIMARY KEY (tenant, index_name, id)
+);
+CREATE TABLE IF NOT EXISTS requests (
+  time INTEGER NOT NULL,
+  tenant TEXT NOT NULL,
+  method TEXT NOT NULL,
+  peer TEXT,
+  line_count INTEGER NOT NULL,
+  anomaly_count INTEGER NOT NULL
+);
+";
+
+/// The version of the [SCHEMA]. The databases without version identify the anomalies by line,
+/// and the first ones have no tenant.
+const SCHEMA_VERSION: i32 = 1;
+
+/// The default number of listed anomalies.
+const DEFAULT_LIMIT: u32 = 100;
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// An anomaly to record: its id, line and distance.
+pub type Occurrence = (String, String, f32);
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM pragma_table_info(?1) WHERE name = ?2",
+        params![table, column],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Migrate a database without version. The ids of its lines are given by the function, with
+/// the tenant, index name and line.
+fn migrate(conn: &mut Connection, line_id: impl Fn(&str, &str, &str) -> String) -> Result<()> {
+    let tx = conn.transaction()?;
+    let table: Option<String> = tx
+        .query_row(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'anomalies'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if table.is_some() {
+        let tenant = if has_column(&tx, "anomalies", "tenant")? {
+            "tenant"
+        } else {
+            "''"
+        };
+        tx.execute_batch("ALTER TABLE anomalies RENAME TO anomalies_v0")?;
+        tx.execute_batch(SCHEMA)?;
+        let rows = {
+            let mut stmt = tx.prepare(&format!(
+                "SELECT {}, index_name, line, distance, first_seen, last_seen, count, author,
+                        comment
+                   FROM anomalies_v0",
+                tenant
+            ))?;
+            let rows = stmt.query_map([], |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, f64>(3)?,
+                    row.get::<_, i64>(4)?,
+                    row.get::<_, i64>(5)?,
+                    row.get::<_, i64>(6)?,
+                    row.get::<_, Option<String>>(7)?,
+                    row.get::<_, Option<String>>(8)?,
+                ))
+            })?;
+            rows.collect::<rusqlite::Result<Vec<_>>>()?
+        };
+        for (tenant, index_name, line, distance, first_seen, last_seen, count, author, comment) in
+            rows
+        {
+            // The lines that only differ by their variable parts are merged.
+            tx.execute(
+                "INSERT INTO anomalies VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+                   ON CONFLICT (tenant, index_name, id) DO UPDATE
+                   SET first_seen = min(first_seen, excluded.first_seen),
+                       last_seen = max(last_seen, excluded.last_seen),
+                       count = count + excluded.count,
+                       author = coalesce(author, excluded.author),
+                       comment = coalesce(comment, excluded.comment)",
+                params![
+                    tenant,
+                    index_name,
+                    line_id(&tenant, &index_name, &line),
+                    line,
+                    distance,
+                    first_seen,
+                    last_seen,
+                    count,
+                    author,
+                    comment
+                ],
+            )?;
+        }
+        tx.execute_batch("DROP TABLE anomalies_v0")?;
+    }
+    let requests: Option<String> = tx
+        .query_row(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name = 'requests'",
+            [],
+            |row| row.get(0),
+        )
+        .optional()?;
+    if requests.is_some() && !has_column(&tx, "requests", "tenant")? {
+        tx.execute_batch("ALTER TABLE requests ADD COLUMN tenant TEXT NOT NULL DEFAULT ''")?;
+    }
+    tx.execute_batch(SCHEMA)?;
+    tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+    tx.commit()?;
+    Ok(())
+}
+
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open the database, or an in-memory database that is lost on restart. The function gives
+    /// the id of a line, with its tenant and index name, to migrate the previous databases.
+    pub fn open(
+        path: Option<&Path>,
+        line_id: impl Fn(&str, &str, &str) -> String,
+    ) -> Result<Store> {
+        let mut conn = match path {
+            Some(path) => Connection::open(path),
+            None => Connection::open_in_memory(),
+        }
+        .context("Can't open the database")?;
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        match version {
+            SCHEMA_VERSION => {}
+            0 => migrate(&mut conn, line_id).context("Can't migrate the database")?,
+            _ => {
+                return Err(anyhow::anyhow!(
+                    "The database version {} is not supported",
+                    version
+                ))
+            }
+        }
+        Ok(Store {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    /// Record the anomaly occurrences, and return whether they are acknowledged.
+    pub fn record(
+        &self,
+        tenant: &str,
+        index_name: &str,
+        occurrences: &[Occurrence],
+    ) -> Result<Vec<bool>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let acknowledged = occurrences
+            .iter()
+            .map(|(id, line, distance)| {
+                tx.query_row(
+                    "INSERT INTO anomalies VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?6, 1, NULL, NULL)
+                       ON CONFLICT (tenant, index_name, id) DO UPDATE
+                       SET line = excluded.line, distance = excluded.distance,
+                           last_seen = excluded.last_seen, count = count + 1
+                     RETURNING author IS NOT NULL",
+                    params![tenant, index_name, id, line, distance, now()],
+                    |row| row.get(0),
+                )
+            })
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .context("Can't record anomaly")?;
+        tx.commit()?;
+        Ok(acknowledged)
+    }
+
+    /// Acknowledge an anomaly, including the anomalies that are not yet reported.
+    pub fn acknowledge(
+        &self,
+        tenant: &str,
+        index_name: &str,
+        id: &str,
+        line: &str,
+        author: &str,
+        comment: &str,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO anomalies VALUES (?1, ?2, ?3, ?4, 0.0, ?5, ?5, 0, ?6, ?7)
+               ON CONFLICT (tenant, index_name, id) DO UPDATE
+               SET author = excluded.author, comment = excluded.comment",
+            params![tenant, index_name, id, line, now(), author, comment],
+        )
+        .context("Can't acknowledge anomaly")?;
+        Ok(())
+    }
+
+    /// List the most recent anomalies, of every index when the name is empty.
+    pub fn list(&self, tenant: &str, index_name: &str, limit: u32) -> Result<Vec<ReportedAnomaly>> {
+        let limit = if limit == 0 { DEFAULT_LIMIT } else { limit };
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT index_name, line, distance, first_seen, last_seen, count, author, comment, id
+               FROM anomalies
+              WHERE tenant = ?1 AND count > 0 AND (?2 = '' OR index_name = ?2)
+              ORDER BY last_seen DESC LIMIT ?3",
+        )?;
+        let rows = stmt.query_map(params![tenant, index_name, limit], |row| {
+            let author: Option<String> = row.get(6)?;
+            Ok(ReportedAnomaly {
+                index_name: row.get(0)?,
+                line: row.get(1)?,
+                distance: row.get(2)?,
+                first_seen: row.get(3)?,
+                last_seen: row.get(4)?,
+                count: row.get(5)?,
+                acknowledged: author.is_some(),
+                author: author.unwrap_or_default(),
+                comment: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+                id: row.get(8)?,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<Vec<_>>>()
+            .context("Can't list anomalies")
+    }
+
+    /// Add a request to the access log.
+    pub fn log_request(
+        &self,
+        tenant: &str,
+        method: &str,
+        peer: Option<String>,
+        line_count: u64,
+        anomaly_count: u64,
+    ) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO requests (time, tenant, method, peer, line_count, anomaly_count)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![now(), tenant, method, peer, line_count, anomaly_count],
+        )
+        .context("Can't log request")?;
+        Ok(())
+    }
+
+    /// Remove the anomalies and the requests older than the retention, return the count. The
+    /// acknowledged anomalies are kept, to apply to their next occurrences.
+    pub fn expire(&self, retention: Duration) -> Result<usize> {
+        let until = now() - retention.as_secs() as i64;
+        let conn = self.conn.lock().unwrap();
+        let anomalies = conn.execute(
+            "DELETE FROM anomalies WHERE last_seen < ?1 AND author IS NULL",
+            [until],
+        )?;
+        let requests = conn.execute("DELETE FROM requests WHERE time < ?1", [until])?;
+        Ok(anomalies + requests)
+    }
+
+    #[cfg(test)]
+    fn request_count(&self) -> Result<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.query_row("SELECT COUNT(*) FROM requests", [], |row| row.get(0))?)
+    }
+}
+
+#[cfg(test)]
+fn test_line_id(_tenant: &str, _index_name: &str, line: &str) -> String {
+    line.trim_start_matches(char::is_numeric).trim().to_string()
+}
+
+#[test]
+fn test_store() {
+    let store = Store::open(None, test_line_id).unwrap();
+    let (tenant, log) = ("openstack", "zuul/merger.log");
+    let occurrence =
+        |line: &str, distance| (test_line_id("", "", line), line.to_string(), distance);
+    let refused = occurrence("10 Connection refused", 0.8);
+    assert_eq!(store.record(tenant, log, &[refused]).unwrap(), vec![false]);
+    store
+        .acknowledge(
+            tenant,
+            log,
+            "Connection refused",
+            "",
+            "alice",
+            "known issue",
+        )
+        .unwrap();
+    store
+        .acknowledge(tenant, log, "Disk full", "Disk full", "bob", "")
+        .unwrap();
+    // The next occurrences are acknowledged, even when their timestamp differs.
+    let refused = occurrence("12 Connection refused", 0.9);
+    let timeout = occurrence("13 Timeout", 0.7);
+    assert_eq!(
+        store
+            .record(tenant, log, &[refused.clone(), timeout])
+            .unwrap(),
+        vec![true, false]
+    );
+    // The other tenants have their own acknowledgments.
+    assert_eq!(
+        store.record("ansible", log, &[refused]).unwrap(),
+        vec![false]
+    );
+
+    let anomalies = store.list(tenant, "", 0).unwrap();
+    assert_eq!(anomalies.len(), 2);
+    let refused = anomalies
+        .iter()
+        .find(|anomaly| anomaly.id == "Connection refused")
+        .unwrap();
+    assert_eq!(refused.count, 2);
+    assert_eq!(refused.distance, 0.9);
+    assert_eq!(refused.line, "12 Connection refused");
+    assert!(refused.acknowledged);
+    assert_eq!(refused.comment, "known issue");
+    assert!(store
+        .list(tenant, "zuul/executor.log", 0)
+        .unwrap()
+        .is_empty());
+
+    store.log_request(tenant, "Score", None, 3, 3).unwrap();
+    assert_eq!(store.request_count().unwrap(), 1);
+    assert_eq!(store.expire(Duration::from_secs(3600)).unwrap(), 0);
+    // The acknowledged anomalies are not expired.
+    let conn = store.conn.lock().unwrap();
+    conn.execute("UPDATE anomalies SET last_seen = 0", [])
+        .unwrap();
+    drop(conn);
+    assert_eq!(store.expire(Duration::from_secs(3600)).unwrap(), 2);
+    let anomalies = store.list(tenant, "", 0).unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].id, "Connection refused");
+}
+
+#[test]
+fn test_migrate() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("store.db");
+    let conn = Connection::open(&path).unwrap();
+    // The schema without tenant nor id.
+    conn.execute_batch(
+        "CREATE TABLE anomalies (
+           index_name TEXT NOT NULL, line TEXT NOT NULL, distance REAL NOT NULL,
+           first_seen INTEGER NOT NULL, last_seen INTEGER NOT NULL, count INTEGER NOT NULL,
+           author TEXT, comment TEXT, PRIMARY KEY (index_name, line));
+         CREATE TABLE requests (
+           time INTEGER NOT NULL, method TEXT NOT NULL, peer TEXT,
+           line_count INTEGER NOT NULL, anomaly_count INTEGER NOT NULL);
+         INSERT INTO anomalies VALUES ('merger.log', '10 Connection refused', 0.8, 1, 2, 2, 'alice', 'known');
+         INSERT INTO anomalies VALUES ('merger.log', '12 Connection refused', 0.9, 3, 4, 1, NULL, NULL);
+         INSERT INTO requests VALUES (1, 'Score', NULL, 3, 3);",
+    )
+    .unwrap();
+    drop(conn);
+
+    let store = Store::open(Some(&path), test_line_id).unwrap();
+    let anomalies = store.list("", "", 0).unwrap();
+    store.log_request("", "Score", None, 1, 0).unwrap();
+    assert_eq!(store.request_count().unwrap(), 2);
+    drop(store);
+    // The migrated database is opened as is.
+    Store::open(Some(&path), test_line_id).unwrap();
+
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].id, "Connection refused");
+    assert_eq!(anomalies[0].count, 3);
+    assert_eq!((anomalies[0].first_seen, anomalies[0].last_seen), (1, 4));
+    assert!(anomalies[0].acknowledged);
+}
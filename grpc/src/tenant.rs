@@ -0,0 +1,119 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the tenants of the service, each with its own token and model.
+//!
+//! The tenants are defined in a yaml file like this:
+//!
+//! ```yaml
+//! - name: openstack
+//!   token: secret-token
+//!   model: /var/lib/logreduce/openstack.bin
+//! ```
+//!
+//! The clients authenticate with the `authorization: Bearer TOKEN` metadata, and the stored
+//! anomalies are namespaced by tenant name.
+
+use anyhow::{Context, Result};
+use logreduce_model::Model;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tonic::metadata::MetadataMap;
+use tonic::Status;
+
+#[derive(Deserialize)]
+struct TenantDef {
+    name: String,
+    token: String,
+    model: PathBuf,
+}
+
+pub struct Tenant {
+    pub name: String,
+    pub model: Model,
+}
+
+pub enum Tenants {
+    /// A single model served without authentication.
+    Anonymous(Arc<Tenant>),
+    /// The tenants by token.
+    Authenticated(HashMap<String, Arc<Tenant>>),
+}
+
+impl Tenants {
+    pub fn anonymous(model: Model) -> Tenants {
+        Tenants::Anonymous(Arc::new(Tenant {
+            name: String::new(),
+            model,
+        }))
+    }
+
+    /// Load the tenants definitions and their models.
+    pub fn load(path: &Path) -> Result<Tenants> {
+        let content = std::fs::read_to_string(path).context("Can't read tenants")?;
+        let defs: Vec<TenantDef> = serde_yaml::from_str(&content).context("Invalid tenants")?;
+        let mut tenants = HashMap::new();
+        let mut names = Vec::new();
+        for def in defs {
+            if def.name.is_empty() || names.contains(&def.name) {
+                return Err(anyhow::anyhow!("Invalid tenant name: {:?}", def.name));
+            }
+            if def.token.is_empty() || tenants.contains_key(&def.token) {
+                return Err(anyhow::anyhow!("Invalid token for tenant {}", def.name));
+            }
+            let model = Model::load(&def.model)
+                .with_context(|| format!("Can't load the model of {}", def.name))?;
+            names.push(def.name.clone());
+            let tenant = Tenant {
+                name: def.name,
+                model,
+            };
+            tenants.insert(def.token, Arc::new(tenant));
+        }
+        Ok(Tenants::Authenticated(tenants))
+    }
+
+    /// Get a tenant by name.
+    pub fn get(&self, name: &str) -> Option<&Tenant> {
+        match self {
+            Tenants::Anonymous(tenant) => {
+                Some(tenant.as_ref()).filter(|tenant| tenant.name == name)
+            }
+            Tenants::Authenticated(tenants) => tenants
+                .values()
+                .map(|tenant| tenant.as_ref())
+                .find(|tenant| tenant.name == name),
+        }
+    }
+
+    /// Get the tenant of a request.
+    pub fn authenticate(&self, metadata: &MetadataMap) -> Result<Arc<Tenant>, Status> {
+        match self {
+            Tenants::Anonymous(tenant) => Ok(tenant.clone()),
+            Tenants::Authenticated(tenants) => bearer_token(metadata)
+                .and_then(|token| tenants.get(token))
+                .cloned()
+                .ok_or_else(|| Status::unauthenticated("A valid bearer token is required")),
+        }
+    }
+}
+
+fn bearer_token(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get("authorization")?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+#[test]
+fn test_bearer_token() {
+    let mut metadata = MetadataMap::new();
+    assert_eq!(bearer_token(&metadata), None);
+    metadata.insert("authorization", "Basic dXNlcg==".parse().unwrap());
+    assert_eq!(bearer_token(&metadata), None);
+    metadata.insert("authorization", "Bearer secret-token".parse().unwrap());
+    assert_eq!(bearer_token(&metadata), Some("secret-token"));
+}
@@ -85,12 +85,18 @@ fn mk_error(msg: &str) -> std::io::Error {
 impl Crawler {
     /// Initialize the Crawler state.
     pub fn new() -> Crawler {
-        let workers = ThreadPool::new(4);
-        let (tx, rx) = channel();
         let client = Client::builder()
             .danger_accept_invalid_certs(std::env::var("LOGREDUCE_SSL_NO_VERIFY").is_ok())
             .build()
             .expect("Client");
+        Crawler::with_client(client, 4)
+    }
+
+    /// Initialize the Crawler with an existing client, to reuse its connections.
+    /// The workers count is the number of listings that are fetched concurrently.
+    pub fn with_client(client: Client, workers: usize) -> Crawler {
+        let workers = ThreadPool::new(workers.max(1));
+        let (tx, rx) = channel();
         Crawler {
             workers,
             client,
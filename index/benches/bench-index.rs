@@ -1,7 +1,10 @@
 // Copyright (C) 2022 Red Hat
 // SPDX-License-Identifier: Apache-2.0
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+//! To detect a regression, save the results of the main branch with
+//! `cargo bench -- --save-baseline main`, then compare a change with `cargo bench -- --baseline main`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use logreduce_generate::gen_lines;
 use logreduce_index::*;
 
@@ -32,5 +35,52 @@ pub fn process(c: &mut Criterion) {
     });
 }
 
-criterion_group!(benches, process);
+/// Measure the index build time, the 1M lines result is the reference for regressions.
+pub fn build(c: &mut Criterion) {
+    let lines = gen_lines().take(1_000_000).collect::<Vec<String>>();
+    let mut group = c.benchmark_group("index_build");
+    group.sample_size(10);
+    for size in [10_000, 100_000, 1_000_000] {
+        group.throughput(Throughput::Elements(size as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &lines[..size],
+            |b, lines| b.iter(|| index_mat(black_box(lines))),
+        );
+    }
+    group.finish();
+}
+
+/// Measure the latency of searching a single line.
+pub fn lookup(c: &mut Criterion) {
+    let lines = gen_lines().take(4097).collect::<Vec<String>>();
+    let target = &lines[4096..];
+    let mut group = c.benchmark_group("lookup_latency");
+    for size in [512, 4096] {
+        let model = index_mat(&lines[..size]);
+        group.bench_with_input(BenchmarkId::from_parameter(size), &model, |b, model| {
+            b.iter(|| search_mat(black_box(model), black_box(target)))
+        });
+    }
+    group.finish();
+}
+
+/// Measure the distance loop of a large index, made of many chunks, to compare the lanes
+/// accumulators with the main branch baseline.
+pub fn distance(c: &mut Criterion) {
+    let lines = gen_lines().take(64 * 512 + 512).collect::<Vec<String>>();
+    let chunks = lines[..64 * 512]
+        .chunks(512)
+        .map(index_mat)
+        .collect::<Vec<_>>();
+    let targets = &lines[64 * 512..];
+    let mut group = c.benchmark_group("distance_loop");
+    group.throughput(Throughput::Elements((64 * 512 * targets.len()) as u64));
+    group.bench_function("cosine", |b| {
+        b.iter(|| search_mat_chunk(black_box(&chunks), black_box(targets)))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, process, build, lookup, distance);
 criterion_main!(benches);
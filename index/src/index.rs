@@ -9,6 +9,7 @@ use fxhash::hash32;
 // use fasthash::murmur3::hash32;
 use bincode::{deserialize, serialize};
 use itertools::Itertools;
+use serde::{Deserialize, Serialize};
 use sprs::*;
 use std::collections::HashMap;
 
@@ -53,9 +54,235 @@ pub fn load_mat(buf: &[u8]) -> FeaturesMatrix {
     deserialize(buf).unwrap()
 }
 
+/// The similarity metric used to compare two lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Metric {
+    /// The cosine distance of the normalized features.
+    #[default]
+    Cosine,
+    /// The jaccard distance of the token sets.
+    Jaccard,
+    /// The weighted hamming distance of the hashed features: the sum of the features
+    /// differences, with the rows normalized to a unit L1 norm.
+    WeightedHamming,
+}
+
+impl std::str::FromStr for Metric {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cosine" => Ok(Metric::Cosine),
+            "jaccard" => Ok(Metric::Jaccard),
+            "weighted-hamming" => Ok(Metric::WeightedHamming),
+            _ => Err(format!(
+                "Unknown metric: {} (expected cosine, jaccard or weighted-hamming)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Metric {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Metric::Cosine => write!(f, "cosine"),
+            Metric::Jaccard => write!(f, "jaccard"),
+            Metric::WeightedHamming => write!(f, "weighted-hamming"),
+        }
+    }
+}
+
 /// Another implementation for index using a matrix storage
 pub fn index_mat(lines: &[String]) -> FeaturesMatrix {
-    create_mat(&lines.iter().map(|s| vectorize(s)).collect::<Vec<_>>())
+    index_mat_with(Metric::Cosine, lines)
+}
+
+/// Index the lines for the given metric.
+pub fn index_mat_with(metric: Metric, lines: &[String]) -> FeaturesMatrix {
+    index_mat_sized(metric, DEFAULT_FEATURES, lines)
+}
+
+/// Index the lines with the given number of features, more features reduce the collisions.
+pub fn index_mat_sized(metric: Metric, features: usize, lines: &[String]) -> FeaturesMatrix {
+    index_mat_weighted(metric, features, lines, &Weights::new())
+}
+
+/// Index the lines with the features scaled by their weights, see [damping_weights].
+pub fn index_mat_weighted(
+    metric: Metric,
+    features: usize,
+    lines: &[String],
+    weights: &Weights,
+) -> FeaturesMatrix {
+    create_mat_with(
+        metric,
+        &lines
+            .iter()
+            .map(|s| weigh(vectorize_with(features, s), weights))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// The weight of the damped features, the other features have a weight of 1.
+pub type Weights = HashMap<usize, F>;
+
+/// The ratio of the lines above which a feature is damped.
+pub const COMMON_RATIO: F = 0.5;
+
+/// The lowest weight, so that a line made of common tokens is still comparable.
+const MIN_WEIGHT: F = 0.1;
+
+/// Count the lines of each feature.
+pub fn count_features(mat: &FeaturesMatrix, counts: &mut HashMap<usize, usize>) {
+    for (_, (_, col)) in mat.iter() {
+        *counts.entry(col).or_default() += 1;
+    }
+}
+
+/// Down-weight the features that are in most of the lines, like the stop words of a text.
+/// The weight is the inverse document frequency, relative to the one of the [COMMON_RATIO].
+pub fn damping_weights(counts: &HashMap<usize, usize>, line_count: usize) -> Weights {
+    let threshold = COMMON_RATIO * line_count as F;
+    counts
+        .iter()
+        .filter(|(_, count)| **count as F > threshold)
+        .map(|(feature, count)| {
+            let weight = (line_count as F / *count as F).ln() / (1.0 / COMMON_RATIO).ln();
+            (*feature, weight.max(MIN_WEIGHT))
+        })
+        .collect()
+}
+
+fn weigh(vector: SparseVec, weights: &Weights) -> SparseVec {
+    if weights.is_empty() {
+        return vector;
+    }
+    let (dim, indices, data) = (vector.dim(), vector.indices().to_vec(), vector.data());
+    let data = indices
+        .iter()
+        .zip(data)
+        .map(|(feature, value)| value * weights.get(feature).unwrap_or(&1.0))
+        .collect();
+    CsVec::new(dim, indices, data)
+}
+
+/// Change the weights of a matrix indexed for the cosine metric, the rows are normalized again.
+pub fn reweight(mat: &FeaturesMatrix, old: &Weights, new: &Weights) -> FeaturesMatrix {
+    let weight = |feature: usize, weights: &Weights| *weights.get(&feature).unwrap_or(&1.0);
+    let mut result = TriMat::new((mat.rows(), mat.cols()));
+    for (row, vector) in mat.outer_iterator().enumerate() {
+        let values = vector
+            .iter()
+            .map(|(col, value)| (col, value * weight(col, new) / weight(col, old)))
+            .collect::<Vec<_>>();
+        let norm = values
+            .iter()
+            .map(|(_, value)| value * value)
+            .sum::<F>()
+            .sqrt();
+        for (col, value) in values {
+            result.add_triplet(row, col, value / norm);
+        }
+    }
+    result.to_csr()
+}
+
+/// The baselines chunk of a search, which is read row by row.
+pub trait Chunk {
+    fn rows(&self) -> usize;
+    fn cols(&self) -> usize;
+    /// Call the function with each feature of the row and its value.
+    fn row_features(&self, row: usize, f: impl FnMut(usize, F));
+}
+
+impl Chunk for FeaturesMatrix {
+    fn rows(&self) -> usize {
+        CsMatBase::rows(self)
+    }
+
+    fn cols(&self) -> usize {
+        CsMatBase::cols(self)
+    }
+
+    fn row_features(&self, row: usize, mut f: impl FnMut(usize, F)) {
+        if let Some(features) = self.outer_view(row) {
+            features
+                .iter()
+                .for_each(|(feature, value)| f(feature, *value))
+        }
+    }
+}
+
+/// The scale of the quantized values. The features are at most 1: the rows are normalized for
+/// the cosine and weighted hamming metrics, and the jaccard metric uses unit features. So every
+/// chunk of an index has the same scale.
+const QUANTIZED_SCALE: F = 1.0 / 127.0;
+
+/// A FeaturesMatrix stored with 8-bit values, see [quantize]. It is searched directly, the
+/// values are scaled back when they are read.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QuantizedMatrix {
+    rows: usize,
+    cols: usize,
+    indptr: Vec<u32>,
+    indices: Vec<u32>,
+    data: Vec<i8>,
+}
+
+/// Reduce the matrix memory by storing its values as i8.
+pub fn quantize(mat: &FeaturesMatrix) -> QuantizedMatrix {
+    QuantizedMatrix {
+        rows: mat.rows(),
+        cols: mat.cols(),
+        indptr: mat
+            .indptr()
+            .raw_storage()
+            .iter()
+            .map(|pos| *pos as u32)
+            .collect(),
+        indices: mat.indices().iter().map(|col| *col as u32).collect(),
+        data: mat
+            .data()
+            .iter()
+            .map(|value| (value / QUANTIZED_SCALE).round().clamp(-127.0, 127.0) as i8)
+            .collect(),
+    }
+}
+
+impl QuantizedMatrix {
+    /// Restore the matrix, e.g. to change its weights.
+    pub fn dequantize(&self) -> FeaturesMatrix {
+        CsMat::new(
+            (self.rows, self.cols),
+            self.indptr.iter().map(|pos| *pos as usize).collect(),
+            self.indices.iter().map(|col| *col as usize).collect(),
+            self.data
+                .iter()
+                .map(|value| *value as F * QUANTIZED_SCALE)
+                .collect(),
+        )
+    }
+}
+
+impl Chunk for QuantizedMatrix {
+    fn rows(&self) -> usize {
+        self.rows
+    }
+
+    fn cols(&self) -> usize {
+        self.cols
+    }
+
+    fn row_features(&self, row: usize, mut f: impl FnMut(usize, F)) {
+        let (start, end) = (self.indptr[row] as usize, self.indptr[row + 1] as usize);
+        for pos in start..end {
+            f(
+                self.indices[pos] as usize,
+                self.data[pos] as F * QUANTIZED_SCALE,
+            )
+        }
+    }
 }
 
 /// Another implementation for search using a matrix product
@@ -68,33 +295,296 @@ pub fn search_mat(baselines: &FeaturesMatrix, lines: &[String]) -> Vec<F> {
 
 /// Another impementation using baselines chunk
 pub fn search_mat_chunk(baselines: &[FeaturesMatrix], lines: &[String]) -> Vec<F> {
-    let target_vectors = lines.iter().map(|s| vectorize(s)).collect::<Vec<_>>();
-    let mut targets = create_mat(&target_vectors);
+    search_mat_chunk_with(Metric::Cosine, baselines, lines)
+}
+
+/// Search the baselines chunk for the given metric, the baselines must be indexed with the same metric.
+pub fn search_mat_chunk_with<C: Chunk>(
+    metric: Metric,
+    baselines: &[C],
+    lines: &[String],
+) -> Vec<F> {
+    search_mat_chunk_nearest_with(metric, baselines, lines)
+        .into_iter()
+        .map(|(distance, _)| distance)
+        .collect()
+}
+
+/// The (chunk, row) position of a baseline line.
+pub type Nearest = Option<(usize, usize)>;
+
+/// Search the baselines chunk, and also return the position of the closest baseline line.
+pub fn search_mat_chunk_nearest_with<C: Chunk>(
+    metric: Metric,
+    baselines: &[C],
+    lines: &[String],
+) -> Vec<(F, Nearest)> {
+    search_mat_chunk_nearest_weighted(metric, baselines, lines, &Weights::new())
+}
+
+/// Search the baselines chunk indexed with the weights, see [index_mat_weighted].
+pub fn search_mat_chunk_nearest_weighted<C: Chunk>(
+    metric: Metric,
+    baselines: &[C],
+    lines: &[String],
+    weights: &Weights,
+) -> Vec<(F, Nearest)> {
+    // The targets are vectorized with the features count of the baselines.
+    let features = baselines
+        .first()
+        .map_or(DEFAULT_FEATURES, |baseline| baseline.cols());
+    let target_vectors = lines
+        .iter()
+        .map(|s| weigh(vectorize_with(features, s), weights))
+        .collect::<Vec<_>>();
+    let mut targets = create_mat_with(metric, &target_vectors);
+    targets.transpose_mut();
+    match metric {
+        Metric::Cosine => cosine_distance_chunk(baselines, &targets),
+        Metric::Jaccard | Metric::WeightedHamming => {
+            overlap_distance_chunk(metric, baselines, &targets)
+        }
+    }
+}
+
+/// Index the features of the targets, which are transposed: the outer iterator gives the lines.
+fn postings(targets: &FeaturesMatrix) -> fxhash::FxHashMap<usize, Vec<(usize, F)>> {
+    let mut postings: fxhash::FxHashMap<usize, Vec<(usize, F)>> = Default::default();
+    for (target, features) in targets.outer_iterator().enumerate() {
+        for (feature, value) in features.iter() {
+            postings.entry(feature).or_default().push((target, *value));
+        }
+    }
+    postings
+}
+
+/// Compute the jaccard or weighted hamming distance using the features mass of each line.
+fn overlap_distance_chunk<C: Chunk>(
+    metric: Metric,
+    baselines: &[C],
+    targets: &FeaturesMatrix,
+) -> Vec<(F, Nearest)> {
+    let mut result = vec![(1.0, None); targets.cols()];
+    for_each_overlap(
+        metric,
+        baselines,
+        targets,
+        |chunk, row, target, distance| {
+            if distance < result[target].0 {
+                result[target] = (distance, Some((chunk, row)))
+            }
+        },
+    );
+    result
+}
+
+/// Call the function with the distance of every baseline row and target that share a feature.
+fn for_each_overlap<C: Chunk>(
+    metric: Metric,
+    baselines: &[C],
+    targets: &FeaturesMatrix,
+    mut f: impl FnMut(usize, usize, usize, F),
+) {
+    let targets_mass = targets
+        .outer_iterator()
+        .map(|col| col.iter().map(|(_, value)| mass(metric, *value)).sum())
+        .collect::<Vec<F>>();
+    let postings = postings(targets);
+
+    // The overlaps of a baseline row, and the targets that share a feature with it.
+    let mut overlaps = vec![0.0; targets.cols()];
+    let mut seen = vec![false; targets.cols()];
+    let mut matched = Vec::new();
+    for (chunk, baseline) in baselines.iter().enumerate() {
+        for row in 0..baseline.rows() {
+            let mut baseline_mass = 0.0;
+            baseline.row_features(row, |feature, value| {
+                baseline_mass += mass(metric, value);
+                if let Some(posting) = postings.get(&feature) {
+                    for (target, target_value) in posting {
+                        if !seen[*target] {
+                            seen[*target] = true;
+                            matched.push(*target);
+                        }
+                        overlaps[*target] += overlap(metric, value, *target_value);
+                    }
+                }
+            });
+            for target in matched.drain(..) {
+                seen[target] = false;
+                let v: F = std::mem::take(&mut overlaps[target]);
+                let total = baseline_mass + targets_mass[target];
+                f(chunk, row, target, overlap_distance(metric, v, total));
+            }
+        }
+    }
+}
+
+/// The contribution of a shared feature to the overlap of two lines.
+fn overlap(metric: Metric, a: F, b: F) -> F {
+    match metric {
+        // The product of the absolute features is the intersection size.
+        Metric::Cosine | Metric::Jaccard => a * b,
+        // The part of the features that is not in their difference.
+        Metric::WeightedHamming => a.abs() + b.abs() - (a - b).abs(),
+    }
+}
+
+/// The contribution of a feature to the size of a line.
+fn mass(metric: Metric, value: F) -> F {
+    match metric {
+        Metric::Cosine | Metric::Jaccard => 1.0,
+        Metric::WeightedHamming => value.abs(),
+    }
+}
+
+/// The distance of two lines from their overlap, and from the sum of their mass for the
+/// jaccard and weighted hamming metrics.
+fn overlap_distance(metric: Metric, overlap: F, total: F) -> F {
+    match metric {
+        Metric::Cosine => 1.0 - overlap,
+        Metric::Jaccard => 1.0 - overlap / (total - overlap),
+        Metric::WeightedHamming => 1.0 - overlap / total,
+    }
+}
+
+/// Count, for each line, how many other lines are closer than the threshold.
+pub fn count_neighbors(lines: &[String], threshold: F) -> Vec<usize> {
+    count_neighbors_with(Metric::Cosine, DEFAULT_FEATURES, lines, threshold)
+}
+
+/// Count the neighbors of each line for the given metric and features count.
+pub fn count_neighbors_with(
+    metric: Metric,
+    features: usize,
+    lines: &[String],
+    threshold: F,
+) -> Vec<usize> {
+    let vectors = lines
+        .iter()
+        .map(|s| vectorize_with(features, s))
+        .collect::<Vec<_>>();
+    let mat = create_mat_with(metric, &vectors);
+    let mut targets = mat.clone();
     targets.transpose_mut();
-    cosine_distance_chunk(baselines, &targets)
+    let mut result = vec![0; lines.len()];
+    for_each_overlap(metric, &[mat], &targets, |_, row, target, distance| {
+        if row != target && distance < threshold {
+            result[target] += 1
+        }
+    });
+    result
 }
 
-fn cosine_distance_chunk(baselines: &[FeaturesMatrix], targets: &FeaturesMatrix) -> Vec<F> {
+/// The width of the fixed size loops of the accumulators. The loops are scalar code split in
+/// manual chunks, without SIMD intrinsics, so that the compiler may autovectorize them. Their
+/// speedup is measured with the `distance` benchmark.
+const LANES: usize = 8;
+
+/// Compute the cosine distances by accumulating the products of each baseline row into a
+/// dense vector of the targets similarity, which is then reduced lane by lane.
+fn cosine_distance_chunk<C: Chunk>(baselines: &[C], targets: &FeaturesMatrix) -> Vec<(F, Nearest)> {
+    let postings = postings(targets);
+
+    // The accumulators are made of full lanes.
+    let width = (targets.cols() + LANES - 1) / LANES * LANES;
+    let mut similarities = vec![0.0; width];
+    let mut positions = vec![u64::MAX; width];
+    let mut products = vec![0.0; width];
+    for (chunk, baseline) in baselines.iter().enumerate() {
+        for row in 0..baseline.rows() {
+            let mut matched = false;
+            baseline.row_features(row, |feature, value| {
+                if let Some(posting) = postings.get(&feature) {
+                    matched = true;
+                    for (target, target_value) in posting {
+                        products[*target] += value * target_value;
+                    }
+                }
+            });
+            if matched {
+                let position = ((chunk as u64) << 32) | row as u64;
+                reduce_lanes(&mut similarities, &mut positions, &mut products, position);
+            }
+        }
+    }
+
+    similarities
+        .into_iter()
+        .zip(positions)
+        .take(targets.cols())
+        .map(|(similarity, position)| match position {
+            u64::MAX => (1.0, None),
+            _ => (
+                1.0 - similarity,
+                Some(((position >> 32) as usize, (position & 0xffff_ffff) as usize)),
+            ),
+        })
+        .collect()
+}
+
+/// Keep the best similarities and their position, and reset the products.
+fn reduce_lanes(similarities: &mut [F], positions: &mut [u64], products: &mut [F], position: u64) {
+    let lanes = similarities
+        .chunks_exact_mut(LANES)
+        .zip(positions.chunks_exact_mut(LANES))
+        .zip(products.chunks_exact_mut(LANES));
+    for ((similarities, positions), products) in lanes {
+        for lane in 0..LANES {
+            let better = products[lane] > similarities[lane];
+            similarities[lane] = if better {
+                products[lane]
+            } else {
+                similarities[lane]
+            };
+            positions[lane] = if better { position } else { positions[lane] };
+            products[lane] = 0.0;
+        }
+    }
+}
+
+/// The sparse product implementation, to check the lanes one.
+#[cfg(test)]
+fn cosine_distance_chunk_sparse(
+    baselines: &[FeaturesMatrix],
+    targets: &FeaturesMatrix,
+) -> Vec<(F, Nearest)> {
     // The targets are transposed, the column is the log line number.
-    let mut result = vec![1.0; targets.cols()];
+    let mut result = vec![(1.0, None); targets.cols()];
 
-    baselines.iter().for_each(|baseline| {
+    baselines.iter().enumerate().for_each(|(chunk, baseline)| {
         let distances_mat = baseline * targets;
 
-        distances_mat
-            .iter()
-            .for_each(|(v, (_, col))| result[col] = (1.0 - v).min(result[col]));
+        distances_mat.iter().for_each(|(v, (row, col))| {
+            if 1.0 - v < result[col].0 {
+                result[col] = (1.0 - v, Some((chunk, row)))
+            }
+        });
     });
     result
 }
 
 /// Create a normalized matrix
 fn create_mat(vectors: &[SparseVec]) -> FeaturesMatrix {
-    let mut mat = TriMat::new((vectors.len(), SIZE));
+    create_mat_with(Metric::Cosine, vectors)
+}
+
+/// Create a matrix with the features scaled for the given metric
+fn create_mat_with(metric: Metric, vectors: &[SparseVec]) -> FeaturesMatrix {
+    let features = vectors
+        .first()
+        .map_or(DEFAULT_FEATURES, |vector| vector.dim());
+    let mut mat = TriMat::new((vectors.len(), features));
     for (row, vector) in vectors.iter().enumerate() {
         let l2_norm = vector.l2_norm();
+        let l1_norm: F = vector.data().iter().map(|val| val.abs()).sum();
         for (col, val) in vector.iter() {
-            mat.add_triplet(row, col, *val / l2_norm);
+            let val = match metric {
+                Metric::Cosine => *val / l2_norm,
+                Metric::Jaccard => val.abs(),
+                Metric::WeightedHamming => *val / l1_norm,
+            };
+            mat.add_triplet(row, col, val);
         }
     }
     mat.to_csr()
@@ -111,20 +601,25 @@ fn cosine_distance(baselines: &FeaturesMatrix, targets: &FeaturesMatrix) -> Vec<
     result
 }
 
-const SIZE: usize = 260000;
+/// The features count of the hashed vectors, when it is not set.
+pub const DEFAULT_FEATURES: usize = 260000;
 
 // result = vector()
 // for each word:
 //    result[hash(word)] = 1
 // TODO: vectorize directly into a FeaturesMatrix
 fn vectorize(line: &str) -> SparseVec {
+    vectorize_with(DEFAULT_FEATURES, line)
+}
+
+fn vectorize_with(features: usize, line: &str) -> SparseVec {
     let (keys, values) = line
         .split(' ')
         .map(|word| {
             let hash = hash32(word);
             // alternate sign to improve inner product preservation in the hashed space
             let sign = if hash >= 2147483648 { 1.0 } else { -1.0 };
-            ((hash as usize) % SIZE, sign)
+            ((hash as usize) % features, sign)
         })
         .sorted_by(|a, b| Ord::cmp(&a.0, &b.0))
         // Here we sum the duplicate, but turns out,
@@ -136,7 +631,18 @@ fn vectorize(line: &str) -> SparseVec {
         .map(|(value, (key, sign))| (key, sign * value as F))*/
         .dedup_by(|a, b| a.0 == b.0)
         .unzip();
-    CsVec::new(SIZE, keys, values)
+    CsVec::new(features, keys, values)
+}
+
+/// The hashed features of a line, as their position and sign, to compare the lines vectors.
+pub fn features(features: usize, line: &str) -> Vec<(usize, bool)> {
+    let vector = vectorize_with(features, line);
+    vector
+        .indices()
+        .iter()
+        .zip(vector.data())
+        .map(|(pos, value)| (*pos, *value > 0.0))
+        .collect()
 }
 
 /// Returns a number between 1.0 and 0.0, 0.0 being the closest value.
@@ -189,6 +695,140 @@ mod tests {
         assert_eq!(distances, expected);
     }
 
+    #[test]
+    fn test_quantize() {
+        let baselines = vec!["the first line".to_string(), "the second line".to_string()];
+        let targets = vec!["a new error".to_string(), "the second line".to_string()];
+        for metric in [Metric::Cosine, Metric::Jaccard, Metric::WeightedHamming] {
+            let model = index_mat_with(metric, &baselines);
+            let expected = search_mat_chunk_with(metric, std::slice::from_ref(&model), &targets);
+            // The quantized chunks are searched directly.
+            let distances = search_mat_chunk_with(metric, &[quantize(&model)], &targets);
+            for (distance, expected) in distances.iter().zip(expected) {
+                assert!((distance - expected).abs() < 0.01);
+            }
+        }
+    }
+
+    #[test]
+    fn test_cosine_distance_chunk() {
+        let lines = |xs: &[&str]| xs.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        let baselines = [
+            index_mat(&lines(&["the first line", "a warning"])),
+            index_mat(&lines(&[
+                "the second line",
+                "an error occured",
+                "the third line",
+            ])),
+        ];
+        let target_vectors = lines(&["the second line", "an error", "a new issue", "nothing"])
+            .iter()
+            .map(|s| vectorize(s))
+            .collect::<Vec<_>>();
+        let mut targets = create_mat(&target_vectors);
+        targets.transpose_mut();
+        let expected = cosine_distance_chunk_sparse(&baselines, &targets);
+        let distances = cosine_distance_chunk(&baselines, &targets);
+        assert_eq!(distances.len(), expected.len());
+        for (distance, expected) in distances.iter().zip(expected) {
+            assert!((distance.0 - expected.0).abs() < 1e-6);
+            assert_eq!(distance.1, expected.1);
+        }
+    }
+
+    #[test]
+    fn test_search_nearest() {
+        let chunks = [
+            index_mat(&["the first line".to_string()]),
+            index_mat(&["a warning".to_string(), "the second line".to_string()]),
+        ];
+        let targets = vec!["the second line".to_string(), "an error".to_string()];
+        let nearest = search_mat_chunk_nearest_with(Metric::Cosine, &chunks, &targets);
+        assert_eq!(nearest[0].1, Some((1, 1)));
+        assert_eq!(nearest[1], (1.0, None));
+    }
+
+    #[test]
+    fn test_search_metrics() {
+        let baselines = vec!["the first line".to_string(), "the second line".to_string()];
+        let targets = vec![
+            "a new error".to_string(),
+            "the second line".to_string(),
+            "the third line".to_string(),
+        ];
+        for metric in [Metric::Jaccard, Metric::WeightedHamming] {
+            let model = index_mat_with(metric, &baselines);
+            let distances = search_mat_chunk_with(metric, &[model], &targets);
+            assert_eq!(distances[0], 1.0, "{}: new line is far", metric);
+            assert!(distances[1] < 1e-6, "{}: known line is close", metric);
+            assert!(distances[2] > 0.0 && distances[2] < 1.0, "{}", metric);
+        }
+        // The third line differs by one of its three equally weighted features.
+        let model = index_mat_with(Metric::WeightedHamming, &baselines);
+        let distances = search_mat_chunk_with(Metric::WeightedHamming, &[model], &targets);
+        assert!((distances[2] - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!("jaccard".parse::<Metric>(), Ok(Metric::Jaccard));
+        assert_eq!(
+            "weighted-hamming".parse::<Metric>(),
+            Ok(Metric::WeightedHamming)
+        );
+    }
+
+    #[test]
+    fn test_features_count() {
+        let baselines = vec!["the first line".to_string(), "a warning".to_string()];
+        let model = index_mat_sized(Metric::Cosine, 1 << 10, &baselines);
+        assert_eq!(model.cols(), 1 << 10);
+        let targets = vec!["a warning".to_string(), "an error".to_string()];
+        let distances = search_mat_chunk_with(Metric::Cosine, &[model], &targets);
+        assert!(distances[0] < 0.01);
+        assert!(distances[1] >= 0.5);
+        assert!(features(1 << 10, "a warning")
+            .iter()
+            .all(|(pos, _)| *pos < 1 << 10));
+    }
+
+    #[test]
+    fn test_damping() {
+        let mut lines = (0..8)
+            .map(|pos| format!("INFO worker{} started", pos))
+            .collect::<Vec<_>>();
+        lines.push("ERROR disk full".to_string());
+        let model = index_mat_sized(Metric::Cosine, DEFAULT_FEATURES, &lines);
+        let mut counts = HashMap::new();
+        count_features(&model, &mut counts);
+        let weights = damping_weights(&counts, lines.len());
+        // The INFO and started features are damped.
+        assert_eq!(weights.len(), 2);
+        assert!(weights.values().all(|weight| *weight < 0.2));
+
+        let targets = vec!["INFO worker42 started".to_string()];
+        let distance = |model: &FeaturesMatrix, weights: &Weights| {
+            let chunks = [model.clone()];
+            search_mat_chunk_nearest_weighted(Metric::Cosine, &chunks, &targets, weights)[0].0
+        };
+        let damped = reweight(&model, &Weights::new(), &weights);
+        assert!(distance(&damped, &weights) > distance(&model, &Weights::new()));
+        let weighted = index_mat_weighted(Metric::Cosine, DEFAULT_FEATURES, &lines, &weights);
+        assert!((distance(&weighted, &weights) - distance(&damped, &weights)).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_count_neighbors() {
+        let lines = vec![
+            "the first line".to_string(),
+            "the first line again".to_string(),
+            "a new error".to_string(),
+        ];
+        assert_eq!(count_neighbors(&lines, 0.3), vec![1, 1, 0]);
+        // The first lines share 3 of their 4 tokens, which is too far for the jaccard
+        // and weighted hamming metrics.
+        let count = |metric| count_neighbors_with(metric, DEFAULT_FEATURES, &lines, 0.2);
+        assert_eq!(count(Metric::Jaccard), vec![0, 0, 0]);
+        assert_eq!(count(Metric::WeightedHamming), vec![0, 0, 0]);
+        assert_eq!(count(Metric::Cosine), vec![1, 1, 0]);
+    }
+
     // A test playground that was used for the search_mat implementation
     #[test]
     fn test_matrix() {
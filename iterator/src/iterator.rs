@@ -28,6 +28,7 @@
 //! You can zero-copy convert a [Bytes] to [&str] using: `std::str::from_utf8(&bytes[..])`.
 
 use bytes::{Buf, Bytes, BytesMut};
+use std::collections::VecDeque;
 use std::io::{Read, Result};
 
 #[derive(Clone, Copy, Debug, PartialEq)]
@@ -105,8 +106,38 @@ pub struct BytesLines<R: Read> {
     split_json: Option<JsonState>,
     prev_pos: usize,
     escaped: bool,
+    /// The number of bytes removed from the front of the buffer.
+    consumed: usize,
+    /// The offset of the last line returned.
+    line_offset: usize,
+    /// The line number and offset of the physical line being processed.
+    physical_line: (usize, usize),
+    /// Reassemble the pretty-printed json objects, see [BytesLines::with_json_blocks].
+    json_blocks: bool,
+    /// The lines of an incomplete json block, with their offset and column.
+    pending: VecDeque<(Result<LogLine>, (usize, usize))>,
+    /// The offset and column of the last block.
+    block_position: Option<(usize, usize)>,
+    /// Group the lines into chunks, see [BytesLines::with_chunks].
+    chunks: Option<Chunks>,
 }
 
+/// How the lines are grouped into a single line, see [BytesLines::with_chunks].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Chunks {
+    /// The lines between the blank lines.
+    Paragraph,
+    /// A fixed count of lines.
+    Lines(usize),
+}
+
+/// The maximum number of lines of a json block, or of a paragraph chunk.
+pub const MAX_BLOCK_LINES: usize = 256;
+
+/// The maximum length of a line that is skipped, a longer line is an error which ends the reader,
+/// e.g. a decompression bomb without new line.
+pub const MAX_DROPPED_LENGTH: usize = 16 << 20;
+
 struct JsonState {
     in_string: bool,
 }
@@ -118,10 +149,12 @@ impl<R: Read> Iterator for BytesLines<R> {
     type Item = Result<LogLine>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        match self.state {
-            State::EoF => None,
-            State::Scanning(_) if self.buf.is_empty() => self.read_slice(),
-            State::Scanning(_) => self.get_slice(),
+        if let Some(chunks) = self.chunks {
+            self.next_chunk(chunks)
+        } else if self.json_blocks {
+            self.next_block()
+        } else {
+            self.next_line()
         }
     }
 }
@@ -151,9 +184,189 @@ impl<R: Read> BytesLines<R> {
             prev_pos: 0,
             escaped: false,
             split_json,
+            consumed: 0,
+            line_offset: 0,
+            physical_line: (0, 0),
+            json_blocks: false,
+            pending: VecDeque::new(),
+            block_position: None,
+            chunks: None,
+        }
+    }
+
+    /// Reassemble the balanced braces blocks into a single line, so that a pretty-printed
+    /// json object is a single event. This is a heuristic: a block starts with a line ending
+    /// with `{` or `[`, and it is only reassembled if all its lines look like json.
+    pub fn with_json_blocks(mut self, enabled: bool) -> BytesLines<R> {
+        self.json_blocks = enabled && self.split_json.is_none();
+        self
+    }
+
+    /// Group the lines into chunks that are returned as a single line, with the number of their
+    /// first line, so that a multi-line output like a compiler error is a single event.
+    /// The lines of a chunk are separated by a new line, and the json blocks are not reassembled.
+    /// A chunk ends with the empty lines that precede the next one, so that the line `number + n`
+    /// is the n-th line of the chunk.
+    pub fn with_chunks(mut self, chunks: Option<Chunks>) -> BytesLines<R> {
+        self.chunks = chunks.filter(|chunks| !matches!(chunks, Chunks::Lines(0..=1)));
+        self
+    }
+
+    /// The byte offset of the last line, in the uncompressed stream.
+    pub fn offset(&self) -> usize {
+        match self.block_position {
+            Some((offset, _)) => offset,
+            None => self.line_offset,
+        }
+    }
+
+    /// The column of the last line, which is greater than 1 for the sub lines.
+    pub fn column(&self) -> usize {
+        match self.block_position {
+            Some((_, column)) => column,
+            None => 1 + self.line_offset - self.physical_line.1,
         }
     }
 
+    fn next_line(&mut self) -> Option<Result<LogLine>> {
+        match self.state {
+            State::EoF => None,
+            State::Scanning(_) if self.buf.is_empty() => self.read_slice(),
+            State::Scanning(_) => self.get_slice(),
+        }
+    }
+
+    fn next_block(&mut self) -> Option<Result<LogLine>> {
+        if let Some((line, position)) = self.pending.pop_front() {
+            self.block_position = Some(position);
+            return Some(line);
+        }
+        self.block_position = None;
+        let first = match self.next_line()? {
+            Ok(line) => line,
+            Err(e) => return Some(Err(e)),
+        };
+        let mut depth = json_depth(&first.0);
+        if depth <= 0 || !opens_block(&first.0) {
+            return Some(Ok(first));
+        }
+
+        let position = |iter: &Self| (iter.offset(), iter.column());
+        let mut lines = vec![(first, position(self))];
+        while depth > 0 && lines.len() < MAX_BLOCK_LINES {
+            match self.next_line() {
+                Some(Ok(line)) if looks_like_json(&line.0) => {
+                    depth += json_depth(&line.0);
+                    lines.push((line, position(self)));
+                }
+                Some(line) => {
+                    // This is not a json block, the lines are returned as they are.
+                    let line_position = position(self);
+                    self.pending
+                        .extend(lines.into_iter().map(|(line, pos)| (Ok(line), pos)));
+                    self.pending.push_back((line, line_position));
+                    return self.next_block();
+                }
+                None => break,
+            }
+        }
+        if depth != 0 {
+            self.pending
+                .extend(lines.into_iter().map(|(line, pos)| (Ok(line), pos)));
+            return self.next_block();
+        }
+
+        let (first, first_position) = &lines[0];
+        let line_number = first.1;
+        self.block_position = Some(*first_position);
+        let block = lines
+            .iter()
+            .map(|(line, _)| trim(&line.0))
+            .collect::<Vec<_>>()
+            .join(&b' ');
+        Some(Ok((block.into(), line_number)))
+    }
+
+    fn next_chunk(&mut self, chunks: Chunks) -> Option<Result<LogLine>> {
+        let max_lines = match chunks {
+            Chunks::Paragraph => MAX_BLOCK_LINES,
+            Chunks::Lines(count) => count,
+        };
+        let mut lines: Vec<(LogLine, (usize, usize))> = Vec::new();
+        // The paragraph is complete, only its trailing blank lines are added.
+        let mut ended = false;
+        loop {
+            let (line, position) = match self.pending.pop_front() {
+                Some((line, position)) => (line, position),
+                None => {
+                    self.block_position = None;
+                    match self.next_line() {
+                        Some(line) => (line, (self.offset(), self.column())),
+                        None => break,
+                    }
+                }
+            };
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => return Some(Err(e)),
+            };
+            if lines.len() >= max_lines {
+                self.pending.push_front((Ok(line), position));
+                break;
+            }
+            if chunks == Chunks::Paragraph {
+                // The empty lines are not returned, they are found with the line number gap.
+                let is_blank = trim(&line.0).is_empty();
+                let after_gap = lines
+                    .last()
+                    .map_or(false, |((_, last_number), _)| line.1 > last_number + 1);
+                if is_blank && lines.is_empty() {
+                    continue;
+                } else if (ended || after_gap) && !is_blank {
+                    self.pending.push_front((Ok(line), position));
+                    break;
+                }
+                ended |= is_blank;
+            }
+            lines.push((line, position));
+        }
+
+        let ((first, line_number), first_position) = lines.first()?;
+        self.block_position = Some(*first_position);
+        // The empty lines are restored, up to the next chunk, so that the chunks cover every
+        // physical line and the line numbers of their context are preserved.
+        let next_number = match self.pending.front() {
+            Some((Ok((_, next_number)), _)) => Some(*next_number),
+            _ => None,
+        };
+        let mut chunk = Vec::new();
+        for (idx, ((line, number), _)) in lines.iter().enumerate() {
+            if idx > 0 {
+                chunk.push(b'\n');
+            }
+            chunk.extend_from_slice(line);
+            let next_number = match lines.get(idx + 1) {
+                Some(((_, next_number), _)) => Some(*next_number),
+                None => next_number,
+            };
+            let gap = next_number.map_or(0, |next_number| next_number.saturating_sub(number + 1));
+            chunk.resize(chunk.len() + gap, b'\n');
+        }
+        if chunk.len() == first.len() {
+            return Some(Ok((first.clone(), *line_number)));
+        }
+        Some(Ok((chunk.into(), *line_number)))
+    }
+
+    // Record the offsets of a line found at the begining of the buffer.
+    fn consume_line(&mut self, size: usize) {
+        if self.physical_line.0 != self.line_count {
+            self.physical_line = (self.line_count, self.consumed);
+        }
+        self.line_offset = self.consumed;
+        self.consumed += size;
+    }
+
     // Read a new chunk and call get_slice
     fn read_slice(&mut self) -> Option<Result<LogLine>> {
         let pos = self.buf.len();
@@ -169,6 +382,7 @@ impl<R: Read> BytesLines<R> {
             // Step H: We reached the end of the reader, but we have left-overs.
             Ok(_) if pos > 0 => {
                 self.update_line_counter(State::EoF);
+                self.consume_line(pos);
                 Some(Ok((self.buf.split_to(pos).freeze(), self.line_count)))
             }
 
@@ -186,6 +400,7 @@ impl<R: Read> BytesLines<R> {
             // Step J: The current line is over the limit, and we don't know where it ends.
             None if self.buf.len() > self.max_line_length => {
                 self.prev_pos = 0;
+                self.consumed += self.buf.len();
                 self.buf.clear();
                 self.buf.reserve(self.chunk_size);
                 self.drop_until_next_line()
@@ -195,6 +410,7 @@ impl<R: Read> BytesLines<R> {
             Some((pos, sep)) if pos > self.max_line_length => {
                 self.prev_pos = 0;
                 // The next line is already in the buffer, so we can just advance.
+                self.consumed += pos + sep.len();
                 self.buf.advance(pos + sep.len());
                 self.get_slice()
             }
@@ -210,6 +426,7 @@ impl<R: Read> BytesLines<R> {
             // Step B: We found the end of the line, we can return it now.
             Some((pos, sep)) => {
                 self.prev_pos = 0;
+                self.consume_line(pos + sep.len());
                 // Step C: split_to() creates a new zero copy reference to the buffer.
                 let res = self.buf.split_to(pos).freeze();
                 // Step D: advance the starting position
@@ -269,34 +486,49 @@ impl<R: Read> BytesLines<R> {
 
     // Drop until we find the next line
     fn drop_until_next_line(&mut self) -> Option<Result<LogLine>> {
-        self.buf.resize(self.chunk_size, 0);
-        match self.reader.read(&mut self.buf) {
-            // We read some data.
-            Ok(n) if n > 0 => match self.find_next_line() {
+        let mut dropped = 0;
+        loop {
+            self.buf.resize(self.chunk_size, 0);
+            let n = match self.reader.read(&mut self.buf) {
+                // We read some data.
+                Ok(n) if n > 0 => n,
+
+                // We reached the end of the reader, this is the end.
+                Ok(_) => return None,
+
+                // There was a reading error, we return it.
+                Err(e) => return Some(Err(e)),
+            };
+            self.buf.truncate(n);
+            match self.find_next_line() {
                 // the long line terminated at the end of the buffer.
                 Some(_) if n == self.chunk_size => {
+                    self.consumed += n;
                     self.buf.clear();
-                    self.read_slice()
+                    return self.read_slice();
                 }
 
                 // the next line is already in the buffer
                 Some((pos, sep)) => {
+                    self.consumed += pos + sep.len();
                     self.buf.advance(pos + sep.len());
-                    self.get_slice()
+                    return self.get_slice();
                 }
 
-                // No line terminator found, keep on draining.
+                // No line terminator found, keep on draining, up to the limit.
                 None => {
+                    self.consumed += n;
                     self.buf.clear();
-                    self.drop_until_next_line()
+                    dropped += n;
+                    if dropped > MAX_DROPPED_LENGTH {
+                        self.state = State::EoF;
+                        return Some(Err(std::io::Error::new(
+                            std::io::ErrorKind::InvalidData,
+                            format!("A line exceeds {} bytes", MAX_DROPPED_LENGTH),
+                        )));
+                    }
                 }
-            },
-
-            // We reached the end of the reader, this is the end.
-            Ok(_) => None,
-
-            // There was a reading error, we return it.
-            Err(e) => Some(Err(e)),
+            }
         }
     }
 }
@@ -313,8 +545,68 @@ fn match_json_kv(c: char, json_state: &mut JsonState) -> Option<Sep> {
     }
 }
 
-pub fn clone_bytes_to_string(bytes: &Bytes) -> Option<String> {
-    std::str::from_utf8(&bytes[..]).ok().map(|s| s.to_string())
+fn trim(line: &[u8]) -> &[u8] {
+    let start = line.iter().position(|c| !c.is_ascii_whitespace());
+    let end = line.iter().rposition(|c| !c.is_ascii_whitespace());
+    match (start, end) {
+        (Some(start), Some(end)) => &line[start..=end],
+        _ => &[],
+    }
+}
+
+// The nesting level change of a json line, the strings are ignored.
+fn json_depth(line: &[u8]) -> isize {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in line {
+        match c {
+            _ if escaped => escaped = false,
+            b'\\' => escaped = true,
+            b'"' => in_string = !in_string,
+            b'{' | b'[' if !in_string => depth += 1,
+            b'}' | b']' if !in_string => depth -= 1,
+            _ => {}
+        }
+    }
+    depth
+}
+
+fn opens_block(line: &[u8]) -> bool {
+    matches!(trim(line).last(), Some(b'{') | Some(b'['))
+}
+
+// Check if the line could be part of a pretty-printed json.
+fn looks_like_json(line: &[u8]) -> bool {
+    let line = trim(line);
+    match line.first() {
+        Some(b'"' | b'{' | b'}' | b'[' | b']' | b'-' | b'0'..=b'9') => true,
+        _ => line.starts_with(b"true") || line.starts_with(b"false") || line.starts_with(b"null"),
+    }
+}
+
+/// The invalid UTF-8 sequences are replaced with U+FFFD.
+pub fn clone_bytes_to_string(bytes: &Bytes) -> String {
+    String::from_utf8_lossy(&bytes[..]).into_owned()
+}
+
+#[test]
+fn test_clone_bytes_to_string() {
+    let bytes = Bytes::from_static(b"caf\xc3\xa9 \xff");
+    assert_eq!(clone_bytes_to_string(&bytes), "caf\u{e9} \u{FFFD}");
+}
+
+#[test]
+fn test_offsets() {
+    let mut lines = BytesLines::new(std::io::Cursor::new("first\nsecond\\nsub\n\nlast"), false);
+    let mut next = || {
+        let (line, _) = lines.next().unwrap().unwrap();
+        (line, lines.offset(), lines.column())
+    };
+    assert_eq!(next(), ("first".into(), 0, 1));
+    assert_eq!(next(), ("second".into(), 6, 1));
+    assert_eq!(next(), ("sub".into(), 14, 9));
+    assert_eq!(next(), ("last".into(), 19, 1));
 }
 
 #[test]
@@ -341,6 +633,66 @@ fn test_iterator() {
     assert_eq!(lines, vec![("first".into(), 1)]);
 }
 
+#[test]
+fn test_json_blocks() {
+    let get_lines = |reader| -> Vec<LogLine> {
+        let lines = BytesLines::new(std::io::Cursor::new(reader), false).with_json_blocks(true);
+        lines.collect::<Result<Vec<LogLine>>>().unwrap()
+    };
+
+    let lines =
+        get_lines("start\nresponse: {\n  \"key\": \"{value\",\n  \"list\": [\n    1\n  ]\n}\nend");
+    assert_eq!(
+        lines,
+        vec![
+            ("start".into(), 1),
+            (
+                "response: { \"key\": \"{value\", \"list\": [ 1 ] }".into(),
+                2
+            ),
+            ("end".into(), 8),
+        ]
+    );
+
+    // A code block is not reassembled.
+    let lines = get_lines("fn main() {\n  run();\n}");
+    assert_eq!(lines.len(), 3);
+}
+
+#[test]
+fn test_chunks() {
+    let get_lines = |reader, chunks| -> Vec<LogLine> {
+        let lines = BytesLines::new(std::io::Cursor::new(reader), false);
+        lines
+            .with_chunks(Some(chunks))
+            .collect::<Result<Vec<LogLine>>>()
+            .unwrap()
+    };
+    let reader = "error: mismatched types\n --> main.rs:2\n\n  \nwarning: unused\nend";
+
+    assert_eq!(
+        get_lines(reader, Chunks::Paragraph),
+        vec![
+            ("error: mismatched types\n --> main.rs:2\n\n  ".into(), 1),
+            ("warning: unused\nend".into(), 5),
+        ]
+    );
+    assert_eq!(
+        get_lines(reader, Chunks::Lines(2)),
+        vec![
+            ("error: mismatched types\n --> main.rs:2\n".into(), 1),
+            ("  \nwarning: unused".into(), 4),
+            ("end".into(), 6),
+        ]
+    );
+
+    let mut lines =
+        BytesLines::new(std::io::Cursor::new(reader), false).with_chunks(Some(Chunks::Paragraph));
+    lines.next();
+    lines.next();
+    assert_eq!((lines.offset(), lines.column()), (43, 1));
+}
+
 #[test]
 fn test_json_iterator() {
     let get_lines = |reader| -> Vec<LogLine> {
@@ -363,3 +715,23 @@ fn test_json_iterator() {
         ]
     );
 }
+
+#[test]
+fn test_line_too_long() {
+    let reader = std::io::repeat(b'x')
+        .take(MAX_DROPPED_LENGTH as u64 + 8192)
+        .chain(std::io::Cursor::new("\nok"));
+    let mut lines = BytesLines::new(reader, false);
+    assert!(lines.next().unwrap().is_err());
+    assert!(lines.next().is_none());
+
+    // A long line below the limit is skipped.
+    let reader = std::io::repeat(b'x')
+        .take(1 << 20)
+        .chain(std::io::Cursor::new("\nok"));
+    let lines = BytesLines::new(reader, false);
+    assert_eq!(
+        lines.collect::<Result<Vec<_>>>().unwrap(),
+        vec![("ok".into(), 2)]
+    );
+}
@@ -0,0 +1,107 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the ARA report database source.
+//!
+//! The task results are rendered like the zuul job output, so that the anomalies are
+//! attributed with [crate::segment::step_start].
+
+use anyhow::{Context, Result};
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use crate::reader::DecompressReader;
+
+/// Check if the path is an ARA database.
+pub fn is_ara(path: &str) -> bool {
+    path.ends_with("ara-report/ansible.sqlite")
+}
+
+/// The result content is a zlib compressed json object.
+fn decode_content(content: &[u8]) -> Option<serde_json::Value> {
+    let mut buf = Vec::new();
+    flate2::read::ZlibDecoder::new(content)
+        .read_to_end(&mut buf)
+        .ok()?;
+    serde_json::from_slice(&buf).ok()
+}
+
+/// The output lines of a result.
+fn result_lines(content: &serde_json::Value) -> Vec<String> {
+    let mut lines = Vec::new();
+    for key in ["msg", "stdout", "stderr"].iter() {
+        if let Some(text) = content[*key].as_str() {
+            lines.extend(text.lines().map(|line| line.to_string()));
+        }
+    }
+    lines
+}
+
+fn render_result(
+    output: &mut String,
+    task: &str,
+    host: &str,
+    status: &str,
+    content: &serde_json::Value,
+) {
+    output.push_str(&format!("{} | TASK [{}]\n", host, task));
+    output.push_str(&format!("{} | {}\n", host, status));
+    for line in result_lines(content) {
+        output.push_str(&format!("{} | {}\n", host, line));
+    }
+}
+
+/// Render the task results of a local database.
+pub fn open(path: &Path) -> Result<DecompressReader> {
+    let conn =
+        rusqlite::Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY)
+            .context("Failed to open ara database")?;
+    let mut stmt = conn.prepare(
+        "SELECT tasks.name, hosts.name, results.status, results.content FROM results
+           JOIN tasks ON results.task_id = tasks.id
+           JOIN hosts ON results.host_id = hosts.id
+         ORDER BY results.id",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut output = String::new();
+    while let Some(row) = rows.next()? {
+        let task: String = row.get(0)?;
+        let host: String = row.get(1)?;
+        let status: String = row.get(2)?;
+        let content: Vec<u8> = row.get(3)?;
+        let content = decode_content(&content).unwrap_or(serde_json::Value::Null);
+        render_result(&mut output, &task, &host, &status, &content);
+    }
+    Ok(DecompressReader::Rendered(Cursor::new(output.into_bytes())))
+}
+
+#[test]
+fn test_render_result() {
+    use std::io::Write;
+    let content = serde_json::json!({"msg": "non-zero return code", "stderr": "oops\nfailed"});
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder.write_all(content.to_string().as_bytes()).unwrap();
+    let content = decode_content(&encoder.finish().unwrap()).unwrap();
+
+    let mut output = String::new();
+    render_result(
+        &mut output,
+        "tox : Run tox",
+        "controller",
+        "failed",
+        &content,
+    );
+    assert_eq!(
+        output,
+        [
+            "controller | TASK [tox : Run tox]",
+            "controller | failed",
+            "controller | non-zero return code",
+            "controller | oops",
+            "controller | failed",
+            ""
+        ]
+        .join("\n")
+    );
+    assert!(is_ara("logs/ara-report/ansible.sqlite"));
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module stops a long run early, e.g. on Ctrl-C, while keeping the work already done.
+//!
+//! The [CancellationToken] is given to the training and the inspection by the
+//! [ProgressObserver::cancellation], see [Cancellable]. It is checked between the sources and
+//! every few thousand lines, so a stalled read is not interrupted.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::{AnomalyContext, ProgressObserver, Source};
+
+/// A shared flag to request the cancellation of a run, from another thread or a signal handler.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed)
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// The flag set by [CancellationToken::cancel], e.g. to register a signal handler.
+    pub fn flag(&self) -> Arc<AtomicBool> {
+        Arc::clone(&self.0)
+    }
+
+    /// Return the [Cancelled] error once the run is cancelled.
+    pub fn check(&self) -> Result<(), Cancelled> {
+        if self.is_cancelled() {
+            Err(Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// The error of a cancelled run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cancelled;
+
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("The run was cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// A [ProgressObserver] that can be cancelled with the token.
+pub struct Cancellable<P> {
+    progress: P,
+    token: CancellationToken,
+}
+
+impl<P: ProgressObserver> Cancellable<P> {
+    pub fn new(progress: P, token: CancellationToken) -> Cancellable<P> {
+        Cancellable { progress, token }
+    }
+}
+
+impl<P: ProgressObserver> ProgressObserver for Cancellable<P> {
+    fn message(&self, msg: &str) {
+        self.progress.message(msg)
+    }
+
+    fn source_started(&self, source: &Source) {
+        self.progress.source_started(source)
+    }
+
+    fn lines_read(&self, source: &Source, line_count: usize, byte_count: usize) {
+        self.progress.lines_read(source, line_count, byte_count)
+    }
+
+    fn source_finished(&self, source: &Source, line_count: usize) {
+        self.progress.source_finished(source, line_count)
+    }
+
+    fn anomaly_found(&self, source: &Source, anomaly: &AnomalyContext) {
+        self.progress.anomaly_found(source, anomaly)
+    }
+
+    fn wants_scores(&self) -> bool {
+        self.progress.wants_scores()
+    }
+
+    fn lines_scored(&self, source: &Source, scores: &[(usize, f32)]) {
+        self.progress.lines_scored(source, scores)
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        Some(&self.token)
+    }
+}
+
+#[test]
+fn test_cancellable() {
+    let token = CancellationToken::new();
+    let progress = Cancellable::new(crate::OutputMode::Quiet, token.clone());
+    assert!(!progress.is_cancelled());
+    assert_eq!(token.check(), Ok(()));
+    token.cancel();
+    assert!(progress.is_cancelled());
+    assert_eq!(token.check(), Err(Cancelled));
+    assert!(!crate::OutputMode::Quiet.is_cancelled());
+}
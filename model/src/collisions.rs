@@ -0,0 +1,184 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module finds the lines that have the same hashed features but a different text.
+//!
+//! The hashing index stores each line as a fixed size vector, and different lines get the same
+//! vector when their words hash to the same features. A target line colliding with a baseline
+//! line is never reported, so the collisions help to tune the features dimension. The baselines
+//! are read again from the sources recorded in the model.
+
+use anyhow::Result;
+use itertools::Itertools;
+use std::collections::HashMap;
+
+use crate::prefix::SAMPLE_LINES;
+use crate::profile::Framing;
+use crate::{ChunkIndex, IndexName, Model, Source};
+
+/// The lines with the same features.
+#[derive(Debug)]
+pub struct Collision {
+    pub index_name: IndexName,
+    /// The raw baseline lines, with a different tokens each.
+    pub baselines: Vec<String>,
+    /// The raw target line, when it collides with the baselines.
+    pub target: Option<String>,
+}
+
+/// The unique tokenized lines with their first raw line, by features.
+struct Buckets {
+    features: usize,
+    lines: HashMap<Vec<(usize, bool)>, Vec<(String, String)>>,
+}
+
+impl Buckets {
+    fn new(features: usize) -> Buckets {
+        Buckets {
+            features,
+            lines: HashMap::new(),
+        }
+    }
+
+    fn insert(&mut self, tokens: String, raw: String) {
+        let lines = self
+            .lines
+            .entry(logreduce_index::features(self.features, &tokens))
+            .or_default();
+        if !lines.iter().any(|(known, _)| known == &tokens) {
+            lines.push((tokens, raw));
+        }
+    }
+
+    /// The baseline lines with the same features as the tokens, when the tokens are different.
+    fn colliding(&self, tokens: &str) -> Option<Vec<String>> {
+        let lines = self
+            .lines
+            .get(&logreduce_index::features(self.features, tokens))?;
+        if lines.iter().any(|(known, _)| known == tokens) {
+            None
+        } else {
+            Some(lines.iter().map(|(_, raw)| raw.clone()).collect())
+        }
+    }
+
+    /// The baseline lines sharing their features, sorted for a stable output.
+    fn collisions(&self) -> Vec<Vec<String>> {
+        self.lines
+            .values()
+            .filter(|lines| lines.len() > 1)
+            .map(|lines| lines.iter().map(|(_, raw)| raw.clone()).collect::<Vec<_>>())
+            .sorted()
+            .collect()
+    }
+}
+
+/// The tokens and the raw text of the lines, tokenized like the index does.
+fn read_lines(index: &ChunkIndex, source: &Source) -> Result<Vec<(String, String)>> {
+    let reader = source.open()?;
+    let mut lines = Vec::new();
+    for line in logreduce_iterator::BytesLines::new(reader, source.is_json())
+        .with_json_blocks(index.json_blocks())
+        .with_chunks(index.granularity().chunks())
+    {
+        let (bytes, _) = line?;
+        lines.push(String::from_utf8_lossy(&bytes).into_owned());
+    }
+    let sample = lines.iter().take(SAMPLE_LINES).map(|line| line.as_str());
+    let framing = Framing::detect(index, source.is_json(), sample);
+    Ok(lines
+        .into_iter()
+        .map(|raw| (index.tokenize(&framing.apply(&raw)), raw))
+        .collect())
+}
+
+/// Find the collisions of the baselines of each target index, and of the target lines.
+pub fn find(model: &Model, targets: &[Source], limit: usize) -> Result<Vec<Collision>> {
+    let mut result = Vec::new();
+    let groups = Source::group_by_index(targets.iter().cloned());
+    for (index_name, sources) in groups.into_iter().sorted_by(|x, y| x.0.cmp(&y.0)) {
+        let index = match model.get_index(&index_name) {
+            Some(index) => index,
+            None => {
+                tracing::warn!("{}: no baselines", index_name);
+                continue;
+            }
+        };
+        let features = match &index.index {
+            ChunkIndex::HashingTrick(hashing_index) => hashing_index.features(),
+            _ => return Err(anyhow::anyhow!("{}: not a hashing index", index_name)),
+        };
+        let mut buckets = Buckets::new(features);
+        for source in &index.sources {
+            if source.is_stream() {
+                tracing::warn!("{}: the stream baselines can't be read again", source);
+                continue;
+            }
+            match read_lines(&index.index, source) {
+                Ok(lines) => lines
+                    .into_iter()
+                    .for_each(|(tokens, raw)| buckets.insert(tokens, raw)),
+                Err(e) => tracing::error!("{}: failed to load: {}", source, e),
+            }
+        }
+
+        let mut collisions = buckets
+            .collisions()
+            .into_iter()
+            .map(|baselines| Collision {
+                index_name: index_name.clone(),
+                baselines,
+                target: None,
+            })
+            .collect::<Vec<_>>();
+        for source in &sources {
+            match read_lines(&index.index, source) {
+                Ok(lines) => {
+                    for (tokens, raw) in lines {
+                        if let Some(baselines) = buckets.colliding(&tokens) {
+                            collisions.push(Collision {
+                                index_name: index_name.clone(),
+                                baselines,
+                                target: Some(raw),
+                            })
+                        }
+                    }
+                }
+                Err(e) => tracing::error!("{}: failed to load: {}", source, e),
+            }
+        }
+        // The target collisions are the most relevant, they hide anomalies.
+        collisions.sort_by_key(|collision| collision.target.is_none());
+        result.extend(collisions.into_iter().take(limit));
+    }
+    Ok(result)
+}
+
+#[test]
+fn test_buckets() {
+    let mut buckets = Buckets::new(logreduce_index::DEFAULT_FEATURES);
+    // The features are a set of words.
+    buckets.insert(
+        "error connecting to".into(),
+        "error connecting to db".into(),
+    );
+    buckets.insert(
+        "to connecting error".into(),
+        "to connecting error db".into(),
+    );
+    buckets.insert(
+        "error connecting to".into(),
+        "error connecting to cache".into(),
+    );
+    buckets.insert("service started".into(), "service started".into());
+    assert_eq!(
+        buckets.collisions(),
+        vec![vec!["error connecting to db", "to connecting error db"]]
+    );
+    assert_eq!(buckets.colliding("service started"), None);
+    assert_eq!(
+        buckets.colliding("started service"),
+        Some(vec!["service started".into()])
+    );
+    assert_eq!(buckets.colliding("unknown"), None);
+}
@@ -0,0 +1,119 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module lists the sources that are not inspected because no baseline has their index.
+//!
+//! Each gap comes with the `--exclude` pattern of its sources and the actions to close it, so
+//! that the baseline coverage can be improved systematically.
+
+use serde::{Deserialize, Serialize};
+
+use crate::{IndexName, Source};
+
+/// The sources of an index that is missing from the baselines.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Gap {
+    pub index_name: IndexName,
+    pub sources: Vec<String>,
+    /// The regex matching the sources, for the `--include` and `--exclude` arguments.
+    pub pattern: String,
+    pub actions: Vec<String>,
+}
+
+/// The regex matching exactly the paths, so that it does not skip the sources with baselines.
+fn pattern(paths: &[&str]) -> String {
+    match paths {
+        [path] => format!("^{}$", regex::escape(path)),
+        _ => format!(
+            "^({})$",
+            paths
+                .iter()
+                .map(|path| regex::escape(path))
+                .collect::<Vec<_>>()
+                .join("|")
+        ),
+    }
+}
+
+/// The known index with the same file name, e.g. when a directory was renamed.
+fn similar<'a>(index_name: &IndexName, known: &[&'a IndexName]) -> Option<&'a IndexName> {
+    let file_name = |name: &IndexName| name.as_str().rsplit('/').next().unwrap_or("").to_string();
+    let expected = file_name(index_name);
+    known
+        .iter()
+        .find(|name| !expected.is_empty() && file_name(name) == expected)
+        .copied()
+}
+
+/// Create the gaps of the sources without baselines, given the index names of the model.
+pub fn gaps<'a>(
+    index_errors: &[Vec<Source>],
+    known: impl IntoIterator<Item = &'a IndexName>,
+) -> Vec<Gap> {
+    let mut known = known.into_iter().collect::<Vec<_>>();
+    known.sort();
+    let mut gaps = index_errors
+        .iter()
+        .filter_map(|sources| {
+            let index_name = IndexName::from_source(sources.first()?);
+            let paths = sources
+                .iter()
+                .map(|source| source.get_relative())
+                .collect::<Vec<_>>();
+            let mut paths = paths.iter().map(|path| path.as_ref()).collect::<Vec<_>>();
+            paths.sort_unstable();
+            let pattern = pattern(&paths);
+            let mut actions = Vec::new();
+            if let Some(name) = similar(&index_name, &known) {
+                actions.push(format!(
+                    "The baselines have the similar index {}, check the grouping with \
+                     `logreduce groups`",
+                    name
+                ));
+            }
+            actions.push(format!(
+                "Add a baseline that contains {}, e.g. a successful build of the same job",
+                paths[0]
+            ));
+            actions.push("Inspect with every baseline using `--fallback-index global`".into());
+            actions.push(format!(
+                "Skip these sources using `--exclude '{}'`",
+                pattern
+            ));
+            Some(Gap {
+                index_name,
+                sources: paths.into_iter().map(|path| path.to_string()).collect(),
+                pattern,
+                actions,
+            })
+        })
+        .collect::<Vec<_>>();
+    gaps.sort_by(|x, y| x.index_name.cmp(&y.index_name));
+    gaps
+}
+
+#[test]
+fn test_gaps() {
+    let source = |path: &str| Source::Local(0, std::path::PathBuf::from(path));
+    let index_errors = vec![
+        vec![
+            source("compute/logs/nova.log.1"),
+            source("compute/logs/nova.log"),
+        ],
+        vec![source("controller/etc/my.cnf")],
+    ];
+    let known = IndexName("nova/nova.log".to_string());
+    let gaps = gaps(&index_errors, vec![&known]);
+    assert_eq!(gaps.len(), 2);
+    assert_eq!(gaps[0].pattern, r"^controller/etc/my\.cnf$");
+    assert_eq!(gaps[0].actions.len(), 3);
+    assert_eq!(gaps[1].index_name, IndexName("logs/nova.log".to_string()));
+    assert_eq!(gaps[1].sources[0], "compute/logs/nova.log");
+    assert_eq!(
+        gaps[1].pattern,
+        r"^(compute/logs/nova\.log|compute/logs/nova\.log\.1)$"
+    );
+    assert!(gaps[1].actions[0].contains("similar index nova/nova.log"));
+
+    assert_eq!(pattern(&["a/x.log", "b/y.log"]), r"^(a/x\.log|b/y\.log)$");
+}
@@ -0,0 +1,185 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module compares two models, to review a model refresh before publishing it: by their
+//! indexes, see [diff], or by their reports of the same target, see [diff_reports].
+
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use crate::{IndexName, Model, Report};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum IndexChange {
+    Added(IndexName, usize),
+    Removed(IndexName, usize),
+    /// The baseline line count changed.
+    Changed(IndexName, usize, usize),
+}
+
+impl IndexChange {
+    pub fn index_name(&self) -> &IndexName {
+        match self {
+            IndexChange::Added(name, _)
+            | IndexChange::Removed(name, _)
+            | IndexChange::Changed(name, _, _) => name,
+        }
+    }
+}
+
+impl std::fmt::Display for IndexChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IndexChange::Added(name, count) => write!(f, "+ {} ({} lines)", name, count),
+            IndexChange::Removed(name, count) => write!(f, "- {} ({} lines)", name, count),
+            IndexChange::Changed(name, old, new) => {
+                let delta = *new as i64 - *old as i64;
+                write!(f, "~ {} ({} -> {} lines, {:+})", name, old, new, delta)
+            }
+        }
+    }
+}
+
+/// The index changes between the old and the new model, sorted by index name.
+pub fn diff(old: &Model, new: &Model) -> Result<Vec<IndexChange>> {
+    Ok(diff_counts(&old.line_counts()?, &new.line_counts()?))
+}
+
+fn diff_counts(
+    old: &BTreeMap<IndexName, usize>,
+    new: &BTreeMap<IndexName, usize>,
+) -> Vec<IndexChange> {
+    let mut changes = Vec::new();
+    for (name, old_count) in old {
+        match new.get(name) {
+            None => changes.push(IndexChange::Removed(name.clone(), *old_count)),
+            Some(new_count) if new_count != old_count => {
+                changes.push(IndexChange::Changed(name.clone(), *old_count, *new_count))
+            }
+            Some(_) => {}
+        }
+    }
+    for (name, new_count) in new {
+        if !old.contains_key(name) {
+            changes.push(IndexChange::Added(name.clone(), *new_count));
+        }
+    }
+    changes.sort_by(|x, y| x.index_name().cmp(y.index_name()));
+    changes
+}
+
+/// An anomaly found by only one of the two models, with its source and line number.
+#[derive(Debug, PartialEq, Eq)]
+pub enum AnomalyChange {
+    /// Only the old model found the anomaly.
+    Removed(String, usize, String),
+    /// Only the new model found the anomaly.
+    Added(String, usize, String),
+}
+
+impl AnomalyChange {
+    fn location(&self) -> (&str, usize) {
+        match self {
+            AnomalyChange::Removed(source, pos, _) | AnomalyChange::Added(source, pos, _) => {
+                (source, *pos)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for AnomalyChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (sign, (source, pos), line) = match self {
+            AnomalyChange::Removed(_, _, line) => ('-', self.location(), line),
+            AnomalyChange::Added(_, _, line) => ('+', self.location(), line),
+        };
+        write!(f, "{} {}:{} | {}", sign, source, pos, line)
+    }
+}
+
+/// The anomaly lines by source and line number.
+type Anomalies = BTreeMap<(String, usize), String>;
+
+fn anomalies(report: &Report) -> Anomalies {
+    report
+        .log_reports
+        .iter()
+        .flat_map(|log_report| {
+            let source = log_report.source.get_relative().to_string();
+            log_report.anomalies.iter().map(move |anomaly| {
+                let key = (source.clone(), anomaly.anomaly.pos);
+                (key, anomaly.anomaly.line.clone())
+            })
+        })
+        .collect()
+}
+
+/// The anomalies found by only one of the reports of the same target, sorted by location, and
+/// the count of the anomalies found by both. The anomalies are matched by their location instead
+/// of their id, so that the models can use different tokenizers or index types.
+pub fn diff_reports(old: &Report, new: &Report) -> (Vec<AnomalyChange>, usize) {
+    diff_anomalies(&anomalies(old), &anomalies(new))
+}
+
+fn diff_anomalies(old: &Anomalies, new: &Anomalies) -> (Vec<AnomalyChange>, usize) {
+    let mut changes = Vec::new();
+    let mut common = 0;
+    for ((source, pos), line) in old {
+        if new.contains_key(&(source.clone(), *pos)) {
+            common += 1;
+        } else {
+            changes.push(AnomalyChange::Removed(source.clone(), *pos, line.clone()));
+        }
+    }
+    for ((source, pos), line) in new {
+        if !old.contains_key(&(source.clone(), *pos)) {
+            changes.push(AnomalyChange::Added(source.clone(), *pos, line.clone()));
+        }
+    }
+    changes.sort_by(|x, y| x.location().cmp(&y.location()));
+    (changes, common)
+}
+
+#[test]
+fn test_diff_counts() {
+    let name = |s: &str| IndexName(s.to_string());
+    let old = [("a", 10), ("b", 20), ("c", 30)]
+        .iter()
+        .map(|(s, count)| (name(s), *count))
+        .collect();
+    let new = [("b", 25), ("c", 30), ("d", 5)]
+        .iter()
+        .map(|(s, count)| (name(s), *count))
+        .collect();
+    let changes = diff_counts(&old, &new);
+    assert_eq!(
+        changes,
+        vec![
+            IndexChange::Removed(name("a"), 10),
+            IndexChange::Changed(name("b"), 20, 25),
+            IndexChange::Added(name("d"), 5),
+        ]
+    );
+    assert_eq!(changes[1].to_string(), "~ b (20 -> 25 lines, +5)");
+}
+
+#[test]
+fn test_diff_anomalies() {
+    let anomalies = |xs: &[(&str, usize, &str)]| -> Anomalies {
+        xs.iter()
+            .map(|(source, pos, line)| ((source.to_string(), *pos), line.to_string()))
+            .collect()
+    };
+    let old = anomalies(&[("job-output.txt", 12, "Timeout"), ("syslog", 3, "Oops")]);
+    let new = anomalies(&[("job-output.txt", 5, "Traceback"), ("syslog", 3, "Oops")]);
+    let (changes, common) = diff_anomalies(&old, &new);
+    assert_eq!(common, 1);
+    assert_eq!(
+        changes,
+        vec![
+            AnomalyChange::Added("job-output.txt".to_string(), 5, "Traceback".to_string()),
+            AnomalyChange::Removed("job-output.txt".to_string(), 12, "Timeout".to_string()),
+        ]
+    );
+    assert_eq!(changes[1].to_string(), "- job-output.txt:12 | Timeout");
+}
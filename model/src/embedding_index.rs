@@ -0,0 +1,195 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a ChunkIndex implementation using a sentence-embedding model.
+//!
+//! The model directory contains a `model.onnx` file, e.g. an export of all-MiniLM-L6-v2,
+//! and its `tokenizer.json`. The lines are embedded with the mean of the token states, and
+//! the search is a brute-force cosine similarity over the baselines embeddings.
+//! This is much slower than the hashing index, but it matches the paraphrased messages.
+//! The lines are embedded as they are, without the hashing index tokenization, so that the
+//! model sees the words.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tract_onnx::prelude::*;
+
+/// The token count of the model input, longer lines are truncated.
+const MAX_TOKENS: usize = 64;
+
+struct Encoder {
+    model: TypedRunnableModel<TypedModel>,
+    tokenizer: tokenizers::Tokenizer,
+}
+
+impl std::fmt::Debug for Encoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Encoder")
+    }
+}
+
+impl Encoder {
+    fn load(model_dir: &Path) -> Result<Encoder> {
+        let tokenizer = tokenizers::Tokenizer::from_file(model_dir.join("tokenizer.json"))
+            .map_err(|e| anyhow::anyhow!("Can't load tokenizer: {}", e))?;
+        let input = || i64::fact([1, MAX_TOKENS]).into();
+        let model = tract_onnx::onnx()
+            .model_for_path(model_dir.join("model.onnx"))
+            .context("Can't load model.onnx")?
+            .with_input_fact(0, input())?
+            .with_input_fact(1, input())?
+            .with_input_fact(2, input())?
+            .into_optimized()?
+            .into_runnable()?;
+        Ok(Encoder { model, tokenizer })
+    }
+
+    /// Compute the normalized embedding of a line.
+    fn embed(&self, line: &str) -> Result<Vec<f32>> {
+        let encoding = self
+            .tokenizer
+            .encode(line, true)
+            .map_err(|e| anyhow::anyhow!("Can't tokenize: {}", e))?;
+        let mut ids = encoding
+            .get_ids()
+            .iter()
+            .map(|id| *id as i64)
+            .collect::<Vec<_>>();
+        ids.truncate(MAX_TOKENS);
+        let token_count = ids.len();
+        ids.resize(MAX_TOKENS, 0);
+        let mask = (0..MAX_TOKENS)
+            .map(|pos| (pos < token_count) as i64)
+            .collect::<Vec<_>>();
+        let tensor = |values: Vec<i64>| -> Result<TValue> {
+            Ok(
+                tract_ndarray::Array2::from_shape_vec((1, MAX_TOKENS), values)?
+                    .into_tensor()
+                    .into(),
+            )
+        };
+        let outputs = self.model.run(tvec!(
+            tensor(ids)?,
+            tensor(mask)?,
+            tensor(vec![0; MAX_TOKENS])?
+        ))?;
+        // The token states are [1, MAX_TOKENS, dimension].
+        let states = outputs[0].to_array_view::<f32>()?;
+        let mut embedding = vec![0.0; states.shape()[2]];
+        for token in 0..token_count {
+            for (dim, value) in embedding.iter_mut().enumerate() {
+                *value += states[[0, token, dim]];
+            }
+        }
+        let norm = embedding.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if norm > 0.0 {
+            embedding.iter_mut().for_each(|v| *v /= norm);
+        }
+        Ok(embedding)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EmbeddingIndex {
+    /// The directory of the model.onnx and tokenizer.json files.
+    model_dir: PathBuf,
+    baselines: Vec<Vec<f32>>,
+    /// The loaded model, or the loading error, to not load it again for every line.
+    #[serde(skip)]
+    encoder: once_cell::sync::OnceCell<std::result::Result<Encoder, String>>,
+}
+
+/// Create an index using the embedding model of the given directory.
+pub fn new(model_dir: PathBuf) -> super::ChunkIndex {
+    super::ChunkIndex::Embedding(EmbeddingIndex {
+        model_dir,
+        baselines: Vec::new(),
+        encoder: once_cell::sync::OnceCell::new(),
+    })
+}
+
+/// Check that the directory contains a valid model, before training an index with it.
+pub fn check(model_dir: &Path) -> Result<()> {
+    Encoder::load(model_dir)
+        .map(|_| ())
+        .with_context(|| format!("Invalid embedding model {:?}", model_dir))
+}
+
+impl EmbeddingIndex {
+    fn encoder(&self) -> Result<&Encoder> {
+        self.encoder
+            .get_or_init(|| Encoder::load(&self.model_dir).map_err(|e| format!("{:#}", e)))
+            .as_ref()
+            .map_err(|e| anyhow::anyhow!("Can't load {:?}: {}", self.model_dir, e))
+    }
+
+    /// Embed the lines, the lines that can't be embedded have an empty embedding.
+    fn embed(&self, lines: &[String]) -> Vec<Vec<f32>> {
+        let encoder = match self.encoder() {
+            Ok(encoder) => encoder,
+            Err(e) => {
+                tracing::error!("{:#}", e);
+                return vec![Vec::new(); lines.len()];
+            }
+        };
+        lines
+            .iter()
+            .map(|line| {
+                encoder.embed(line).unwrap_or_else(|e| {
+                    tracing::error!("Can't embed {}: {:?}", line, e);
+                    Vec::new()
+                })
+            })
+            .collect()
+    }
+
+    pub fn add(&mut self, baselines: &[String]) {
+        let embeddings = self.embed(baselines);
+        self.baselines.extend(
+            embeddings
+                .into_iter()
+                .filter(|embedding| !embedding.is_empty()),
+        );
+    }
+
+    /// The number of baseline rows.
+    pub fn row_count(&self) -> usize {
+        self.baselines.len()
+    }
+
+    /// Remove the oldest baselines to keep at most `max_lines` lines.
+    pub fn truncate(&mut self, max_lines: usize) {
+        let len = self.baselines.len();
+        self.baselines.drain(..len.saturating_sub(max_lines));
+    }
+
+    /// The lines that can't be embedded are considered as new.
+    pub fn search(&self, targets: &[String]) -> Vec<f32> {
+        self.embed(targets)
+            .iter()
+            .map(|embedding| {
+                let similarity = self
+                    .baselines
+                    .iter()
+                    .map(|baseline| baseline.iter().zip(embedding).map(|(a, b)| a * b).sum())
+                    .fold(0.0, f32::max);
+                (1.0 - similarity).clamp(0.0, 1.0)
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_missing_model() {
+    let model_dir = std::env::temp_dir().join("logreduce-test-missing-embedding-model");
+    assert!(check(&model_dir).is_err());
+    let mut index = new(model_dir);
+    // The lines are not tokenized, and they are new without a model.
+    assert_eq!(
+        index.tokenize("Connection refused 42"),
+        "Connection refused 42"
+    );
+    index.add(&["Connection refused".to_string()]);
+    assert_eq!(index.search(&["Connection refused".to_string()]), vec![1.0]);
+}
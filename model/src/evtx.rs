@@ -0,0 +1,177 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the Windows event log (evtx) source.
+//!
+//! The binary records are rendered into text lines, one per event, so that they can be
+//! processed like any other log files. The events are grouped by provider: each provider
+//! of a file is a separate [Source::Evtx] with its own index.
+//!
+//! A file is parsed once: the lines of the other providers are kept until their source is read.
+
+use anyhow::{Context, Result};
+use itertools::Itertools;
+use once_cell::sync::OnceCell;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::reader::DecompressReader;
+use crate::{IndexName, Source};
+
+/// Check if the path is a Windows event log.
+pub fn is_evtx(path: &Path) -> bool {
+    path.extension()
+        .map_or(false, |ext| ext.eq_ignore_ascii_case("evtx"))
+}
+
+/// The rendered lines of each provider.
+type Providers = BTreeMap<String, String>;
+
+/// The rendered lines of the providers that are not read yet, by file.
+static PENDING: OnceCell<Mutex<HashMap<PathBuf, Providers>>> = OnceCell::new();
+
+fn pending() -> std::sync::MutexGuard<'static, HashMap<PathBuf, Providers>> {
+    PENDING
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Parse the file and split the rendered lines by provider.
+fn parse(path: &Path) -> Result<Providers> {
+    let mut parser = evtx::EvtxParser::from_path(path).context("Failed to open evtx file")?;
+    let mut providers = Providers::new();
+    for record in parser.records_json_value() {
+        match record {
+            Ok(record) => {
+                let (provider, line) = render(&record.data);
+                let buf = providers.entry(provider).or_default();
+                buf.push_str(&line);
+                buf.push('\n');
+            }
+            Err(e) => tracing::warn!("Skipping invalid evtx record: {}", e),
+        }
+    }
+    Ok(providers)
+}
+
+/// Create one source per provider.
+pub fn sources(base_len: usize, path: PathBuf) -> Result<Vec<Source>> {
+    let providers = parse(&path)?;
+    let sources = providers
+        .keys()
+        .map(|provider| Source::Evtx(base_len, path.clone(), provider.clone()))
+        .collect();
+    pending().insert(path, providers);
+    Ok(sources)
+}
+
+/// Read the rendered events of a single provider. The file is parsed again when the provider
+/// was already read, e.g. when a baseline is also a target.
+pub fn open(path: &Path, provider: &str) -> Result<DecompressReader> {
+    tracing::debug!(path = path.to_str(), provider, "Reading evtx file");
+    let mut pending = pending();
+    let buf = match pending
+        .get_mut(path)
+        .and_then(|providers| providers.remove(provider))
+    {
+        Some(buf) => buf,
+        None => {
+            let mut providers = parse(path)?;
+            let buf = providers.remove(provider).unwrap_or_default();
+            pending.insert(path.to_path_buf(), providers);
+            buf
+        }
+    };
+    if pending
+        .get(path)
+        .map_or(false, |providers| providers.is_empty())
+    {
+        pending.remove(path);
+    }
+    Ok(DecompressReader::Rendered(Cursor::new(buf.into_bytes())))
+}
+
+impl IndexName {
+    pub fn from_provider(provider: &str) -> IndexName {
+        IndexName(format!("evtx/{}", provider))
+    }
+}
+
+fn as_text(value: &Value) -> Option<String> {
+    match value {
+        Value::Null => None,
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        // Attributed values are encoded with a #text key
+        Value::Object(obj) => obj.get("#text").and_then(as_text),
+        Value::Array(xs) => Some(xs.iter().filter_map(as_text).join(" ")),
+    }
+}
+
+/// Render an event as: `provider event_id: key=value...`.
+fn render(event: &Value) -> (String, String) {
+    let event = event.get("Event").unwrap_or(event);
+    let system = &event["System"];
+    let provider = system["Provider"]["#attributes"]["Name"]
+        .as_str()
+        .unwrap_or("unknown")
+        .to_string();
+    let event_id = as_text(&system["EventID"]).unwrap_or_default();
+    let mut line = format!("{} {}:", provider, event_id);
+    // The message templates are not part of the file, the event data are used instead.
+    for section in ["EventData", "UserData"].iter() {
+        if let Value::Object(data) = &event[*section] {
+            for (key, value) in data {
+                if key.starts_with('#') {
+                    continue;
+                }
+                match value {
+                    Value::Object(nested) if !nested.contains_key("#text") => {
+                        for (nested_key, nested_value) in nested {
+                            if let Some(text) = as_text(nested_value) {
+                                line.push_str(&format!(" {}={}", nested_key, text));
+                            }
+                        }
+                    }
+                    value => {
+                        if let Some(text) = as_text(value) {
+                            line.push_str(&format!(" {}={}", key, text));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    (provider, line)
+}
+
+#[test]
+fn test_render() {
+    let event = serde_json::json!({
+        "Event": {
+            "System": {
+                "Provider": {"#attributes": {"Name": "Service Control Manager"}},
+                "EventID": {"#attributes": {"Qualifiers": 16384}, "#text": 7036},
+            },
+            "EventData": {
+                "param1": "Windows Update",
+                "param2": "stopped",
+                "Binary": null,
+            }
+        }
+    });
+    assert_eq!(
+        render(&event),
+        (
+            "Service Control Manager".to_string(),
+            "Service Control Manager 7036: param1=Windows Update param2=stopped".to_string()
+        )
+    );
+    assert!(is_evtx(Path::new("logs/System.evtx")));
+    assert!(!is_evtx(Path::new("logs/System.log")));
+}
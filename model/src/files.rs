@@ -6,7 +6,7 @@
 use anyhow::{Context, Result};
 use std::path::Path;
 
-use crate::{Baselines, Content, IndexName, Input, Source};
+use crate::{Baselines, Content, IndexName, Source};
 
 impl Content {
     #[tracing::instrument(level = "debug")]
@@ -25,9 +25,9 @@ impl Content {
     #[tracing::instrument(level = "debug")]
     pub fn discover_baselines_from_path(path: &Path) -> Result<Baselines> {
         // TODO: implement discovery by looking for common rotated file names.
-        let mut path_str = path.to_path_buf().into_os_string().into_string().unwrap();
-        path_str.push_str(".0");
-        let baseline = Content::from_input(Input::Path(path_str))?;
+        let mut baseline_path = path.as_os_str().to_os_string();
+        baseline_path.push(".0");
+        let baseline = Content::from_path(Path::new(&baseline_path))?;
         Ok(vec![baseline])
     }
 }
@@ -35,33 +35,91 @@ impl Content {
 impl Source {
     pub fn file_open(path: &Path) -> Result<crate::reader::DecompressReader> {
         tracing::debug!(path = path.to_str(), "Reading file");
-        crate::reader::from_path(path).context("Failed to open file")
-    }
-
-    // A file source only has one source
-    pub fn file_iter(&self) -> impl Iterator<Item = Result<Source>> {
-        std::iter::once(Ok(self.clone()))
+        let path_str = path.to_str().unwrap_or("");
+        if crate::subunit::is_subunit(path_str) {
+            crate::subunit::open(path).context("Failed to read subunit file")
+        } else if crate::ara::is_ara(path_str) {
+            crate::ara::open(path)
+        } else {
+            crate::reader::from_path(path).context("Failed to open file")
+        }
     }
 
-    fn keep_path(result: &walkdir::Result<walkdir::DirEntry>) -> bool {
-        match result {
-            Ok(entry) if !entry.path_is_symlink() && entry.file_type().is_file() => true,
-            Ok(_) => false,
-            // Keep errors for book keeping
-            Err(_) => true,
+    // A file source only has one source, except for the evtx file which has one per provider,
+    // and the subunit file which has one per failed test
+    pub fn file_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
+        let sources = match self {
+            Source::Local(base_len, path) if crate::evtx::is_evtx(path) => {
+                crate::evtx::sources(*base_len, path.clone())
+            }
+            Source::Local(base_len, path)
+                if crate::subunit::is_subunit(&path.to_string_lossy()) =>
+            {
+                crate::subunit::sources(*base_len, path.clone())
+            }
+            _ => return Box::new(std::iter::once(Ok(self.clone()))),
+        };
+        match sources {
+            Ok(sources) => Box::new(sources.into_iter().map(Ok)),
+            Err(e) => Box::new(std::iter::once(Err(e))),
         }
     }
 
+    /// List the files of a directory, see [crate::walk::files].
     pub fn dir_iter(path: &Path) -> impl Iterator<Item = Result<Source>> {
-        let base_len = path.to_str().map(|s| s.len()).unwrap_or(0);
-        walkdir::WalkDir::new(path)
+        // The length of the lossy root, see [Source::get_relative].
+        let base_len = path.to_string_lossy().len();
+        crate::walk::files(path)
             .into_iter()
-            .filter(Source::keep_path)
-            .map(move |res| match res {
-                Err(e) => Err(e.into()),
-                Ok(res) => Ok(Source::Local(base_len, res.into_path())),
+            .flat_map(move |res| match res {
+                Err(e) => vec![Err(e)],
+                Ok(path) => Source::Local(base_len, path).file_iter().collect(),
             })
     }
+
+    /// Check if the file has the same size and modification time at its relative path in the
+    /// other tree, e.g. in the previous snapshot of the artifacts.
+    pub fn is_unchanged_in(&self, tree: &Path) -> bool {
+        match self {
+            Source::Local(_, path) | Source::Evtx(_, path, _) => {
+                let other = tree.join(self.get_relative().trim_start_matches('/'));
+                match (std::fs::metadata(path), std::fs::metadata(other)) {
+                    (Ok(new), Ok(old)) => {
+                        new.len() == old.len()
+                            && matches!((new.modified(), old.modified()), (Ok(x), Ok(y)) if x == y)
+                    }
+                    _ => false,
+                }
+            }
+            Source::Remote(_, _) | Source::Stream(_) => false,
+            Source::Section(source, _) => source.is_unchanged_in(tree),
+        }
+    }
+}
+
+#[test]
+fn test_is_unchanged_in() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let (old, new) = (dir.join("old"), dir.join("new"));
+    for tree in [&old, &new] {
+        std::fs::create_dir_all(tree).unwrap();
+    }
+    std::fs::write(old.join("size.log"), "hello").unwrap();
+    std::fs::write(new.join("size.log"), "hello world").unwrap();
+    std::fs::write(new.join("added.log"), "hello").unwrap();
+    let sources = Source::dir_iter(&new)
+        .map(|source| source.unwrap())
+        .collect::<Vec<_>>();
+    let changed = |tree: &Path| {
+        sources
+            .iter()
+            .filter(|source| !source.is_unchanged_in(tree))
+            .count()
+    };
+    let (changed_old, changed_new) = (changed(&old), changed(&new));
+    assert_eq!(changed_old, 2);
+    assert_eq!(changed_new, 0);
 }
 
 fn is_small_hash(filename: &str) -> bool {
@@ -132,8 +190,46 @@ fn parent_str(path: &Path) -> Option<(&'_ Path, &'_ str)> {
     })
 }
 
+/// The rule that produced an [IndexName], to explain why a source is grouped with others.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupingRule {
+    /// The libvirt qemu instances share a single index.
+    Qemu,
+    /// The pod name without its uuid.
+    Pod,
+    /// The kubernetes service name.
+    K8sService,
+    /// The file name with its parent directory, without the numbers.
+    Normalized,
+    /// The provider of a windows event log.
+    EvtxProvider,
+    /// The playbook of a zuul job output.
+    JobSection,
+    /// The index of the sources without baselines, see [crate::Model::set_fallback_index].
+    Fallback,
+}
+
+impl std::fmt::Display for GroupingRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            GroupingRule::Qemu => "qemu instance rule",
+            GroupingRule::Pod => "pod name without its uuid",
+            GroupingRule::K8sService => "kubernetes service name",
+            GroupingRule::Normalized => "file and directory names without the numbers",
+            GroupingRule::EvtxProvider => "evtx provider",
+            GroupingRule::JobSection => "zuul job output playbook",
+            GroupingRule::Fallback => "fallback index of the sources without baselines",
+        })
+    }
+}
+
 impl IndexName {
     pub fn from_path(base: &str) -> IndexName {
+        IndexName::explain_path(base).0
+    }
+
+    /// The index name of a path, with the rule that produced it.
+    pub fn explain_path(base: &str) -> (IndexName, GroupingRule) {
         let path = Path::new(base);
         let filename: &str = path
             .file_name()
@@ -150,27 +246,47 @@ impl IndexName {
             Some((_, name)) => format!("{}/{}", name, filename),
         };
 
-        let model_name = if shortfilename.starts_with("qemu/instance-") {
-            "qemu/instance".to_string()
+        let (model_name, rule) = if shortfilename.starts_with("qemu/instance-") {
+            ("qemu/instance".to_string(), GroupingRule::Qemu)
         } else if shortfilename.starts_with("pod/") {
-            take_until_pod_uuid(&shortfilename).to_string()
+            (
+                take_until_pod_uuid(&shortfilename).to_string(),
+                GroupingRule::Pod,
+            )
         } else if let Some(service) = is_k8s_service(filename) {
-            service.to_string()
+            (service.to_string(), GroupingRule::K8sService)
         } else {
             // removes number and symbols
-            shortfilename
+            let name = shortfilename
                 .replace(
                     |c: char| !c.is_ascii_alphabetic() && !matches!(c, '/' | '.' | '_' | '-'),
                     "",
                 )
                 .trim_matches(|c| matches!(c, '/' | '.' | '_' | '-'))
                 .trim_end_matches(".gz")
-                .to_string()
+                .to_string();
+            (name, GroupingRule::Normalized)
         };
-        IndexName(model_name)
+        (IndexName(model_name), rule)
     }
 }
 
+#[test]
+fn test_explain_path() {
+    assert_eq!(
+        IndexName::explain_path("libvirt/qemu/instance-000000ec.log.txt.gz").1,
+        GroupingRule::Qemu
+    );
+    assert_eq!(
+        IndexName::explain_path("pod/zuul-7fdb57778f-qkzkc.log"),
+        (IndexName("pod/zuul".to_string()), GroupingRule::Pod)
+    );
+    assert_eq!(
+        IndexName::explain_path("zuul/merger.log.2017-11-12").1,
+        GroupingRule::Normalized
+    );
+}
+
 #[test]
 fn log_model_name() {
     IntoIterator::into_iter([
@@ -0,0 +1,150 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module reads the zuul `job-output.json`, to attribute the anomalies of the
+//! `job-output.txt` to the playbook task that produced them.
+//!
+//! The json output lists the tasks of each playbook with their start and end times, and the
+//! text output lines start with their timestamp, e.g. `2022-05-10 10:00:01.456789 | ...`.
+//! The task names are rendered like the text output, e.g. `playbooks/tox/run.yaml: tox : Run tox`.
+//! The anomalies found outside of a known task keep the task detected in the text output,
+//! see [crate::segment::step_start].
+
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use serde_json::Value;
+use std::io::Read;
+
+use crate::{Anomaly, Source};
+
+/// A task of the job output.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Task {
+    pub name: String,
+    pub start: NaiveDateTime,
+    pub end: NaiveDateTime,
+}
+
+/// Parse the tasks of a job-output.json, ordered by start time.
+pub fn parse(reader: impl Read) -> Result<Vec<Task>> {
+    let playbooks: Value = serde_json::from_reader(reader).context("Invalid job-output.json")?;
+    let mut tasks = Vec::new();
+    for playbook in playbooks.as_array().into_iter().flatten() {
+        let playbook_name = playbook["playbook"].as_str().unwrap_or("unknown");
+        let plays = playbook["plays"].as_array().into_iter().flatten();
+        for task in plays.flat_map(|play| play["tasks"].as_array().into_iter().flatten()) {
+            let duration = &task["task"]["duration"];
+            let (start, end) = match (timestamp(&duration["start"]), timestamp(&duration["end"])) {
+                (Some(start), Some(end)) => (start, end),
+                _ => continue,
+            };
+            let name = task["task"]["name"].as_str().unwrap_or_default();
+            let name = match task["role"]["name"].as_str() {
+                Some(role) => format!("{}: {} : {}", playbook_name, role, name),
+                None => format!("{}: {}", playbook_name, name),
+            };
+            tasks.push(Task { name, start, end });
+        }
+    }
+    tasks.sort_by_key(|task| task.start);
+    Ok(tasks)
+}
+
+fn timestamp(value: &Value) -> Option<NaiveDateTime> {
+    chrono::DateTime::parse_from_rfc3339(value.as_str()?)
+        .ok()
+        .map(|date| date.naive_utc())
+}
+
+/// The timestamp of a job-output.txt line.
+fn line_timestamp(line: &str) -> Option<NaiveDateTime> {
+    let (date, _) = line.split_once(" | ")?;
+    NaiveDateTime::parse_from_str(date.trim(), "%Y-%m-%d %H:%M:%S%.f").ok()
+}
+
+/// The last started task that was running when the line was written.
+pub fn task_at<'a>(tasks: &'a [Task], line: &str) -> Option<&'a Task> {
+    let timestamp = line_timestamp(line)?;
+    let started = tasks.partition_point(|task| task.start <= timestamp);
+    tasks[..started]
+        .iter()
+        .rev()
+        .find(|task| timestamp <= task.end)
+}
+
+/// Set the task of the anomaly, when its line was written by a task of the json output.
+pub fn annotate(tasks: &[Task], anomaly: &mut Anomaly) {
+    if let Some(task) = task_at(tasks, &anomaly.line) {
+        anomaly.task = Some(task.name.clone());
+    }
+}
+
+/// The job-output.json next to a job-output.txt.
+fn sibling(source: &Source) -> Option<Source> {
+    match source {
+        Source::Local(base_len, path) => ["job-output.json", "job-output.json.gz"]
+            .iter()
+            .map(|name| path.with_file_name(name))
+            .find(|path| path.exists())
+            .map(|path| Source::Local(*base_len, path)),
+        Source::Remote(base_len, url) => url
+            .join("job-output.json")
+            .ok()
+            .map(|url| Source::Remote(*base_len, url)),
+        Source::Section(source, _) => sibling(source),
+        Source::Evtx(_, _, _) | Source::Stream(_) => None,
+    }
+}
+
+/// Load the tasks of the job-output.json of a job output source. This returns no tasks when
+/// the source is not a job output, or when its json output is missing.
+pub fn load(source: &Source) -> Vec<Task> {
+    if !crate::phases::is_job_output(source) {
+        return Vec::new();
+    }
+    let tasks = sibling(source)
+        .ok_or_else(|| anyhow::anyhow!("No job-output.json"))
+        .and_then(|json| parse(json.open()?));
+    match tasks {
+        Ok(tasks) => tasks,
+        Err(e) => {
+            tracing::debug!("Can't read the job-output.json of {}: {:#}", source, e);
+            Vec::new()
+        }
+    }
+}
+
+#[test]
+fn test_job_output() {
+    let data = r#"[{
+      "phase": "run",
+      "playbook": "playbooks/tox/run.yaml",
+      "plays": [{
+        "play": {"name": "all"},
+        "tasks": [
+          {
+            "role": {"name": "tox"},
+            "task": {"name": "Run tox testing", "duration": {
+              "start": "2022-05-10T10:00:01.000000Z", "end": "2022-05-10T10:05:00.000000Z"}}
+          },
+          {
+            "task": {"name": "Collect logs", "duration": {
+              "start": "2022-05-10T10:06:00.000000Z", "end": "2022-05-10T10:07:00.000000Z"}}
+          }
+        ]
+      }]
+    }]"#;
+    let tasks = parse(data.as_bytes()).unwrap();
+    assert_eq!(tasks.len(), 2);
+    let task = |line| task_at(&tasks, line).map(|task| task.name.as_str());
+    assert_eq!(
+        task("2022-05-10 10:02:00.123456 | controller | Traceback"),
+        Some("playbooks/tox/run.yaml: tox : Run tox testing")
+    );
+    assert_eq!(
+        task("2022-05-10 10:06:30.000000 | error"),
+        Some("playbooks/tox/run.yaml: Collect logs")
+    );
+    assert_eq!(task("2022-05-10 10:05:30.000000 | between tasks"), None);
+    assert_eq!(task("no timestamp"), None);
+}
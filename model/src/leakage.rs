@@ -0,0 +1,187 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module detects the baselines that are the target itself, e.g. the same build given twice.
+//!
+//! Such a baseline contains every target line, so the report is empty and it looks like the
+//! target has no problem. The baselines are first compared by location, and then by the content
+//! hash of a few sources that have the same relative path as a target source. The sample prefers
+//! the largest sources of different directories, because the small files are often static.
+
+use anyhow::Result;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::path::Path;
+
+use crate::{Content, Source};
+
+/// The number of sources compared by content.
+pub const SAMPLE_SOURCES: usize = 3;
+
+/// The number of bytes of a source that are compared.
+const MAX_HASH_SIZE: u64 = 4 << 20;
+
+fn same_path(x: &Path, y: &Path) -> bool {
+    match (x.canonicalize(), y.canonicalize()) {
+        (Ok(x), Ok(y)) => x == y,
+        _ => x == y,
+    }
+}
+
+/// Check if the baseline is at the location of the target, e.g. the same url or build.
+pub fn same_location(baseline: &Content, target: &Content) -> bool {
+    match (baseline, target) {
+        (Content::Zuul(x), Content::Zuul(y)) => x.uuid == y.uuid,
+        (Content::File(Source::Local(_, x)), Content::File(Source::Local(_, y)))
+        | (Content::Directory(Source::Local(_, x)), Content::Directory(Source::Local(_, y))) => {
+            same_path(x, y)
+        }
+        (x, y) => x == y,
+    }
+}
+
+fn content_hash(source: &Source) -> Result<String> {
+    use sha2::Digest;
+    let mut reader = source.open()?.take(MAX_HASH_SIZE);
+    let mut hasher = sha2::Sha256::new();
+    std::io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Check if the baseline sources have the same content as the target sources. Only a sample of
+/// the sources with the same relative path are compared, and a different file list is enough to
+/// tell them apart.
+pub fn same_content(baseline_sources: &[Source], target_sources: &[Source]) -> Result<bool> {
+    if baseline_sources.len() != target_sources.len() || target_sources.is_empty() {
+        return Ok(false);
+    }
+    // The streams can only be read once, they are not compared.
+    if baseline_sources
+        .iter()
+        .chain(target_sources)
+        .any(Source::is_stream)
+    {
+        return Ok(false);
+    }
+    let baselines = baseline_sources
+        .iter()
+        .map(|source| (source.get_relative(), source))
+        .collect::<HashMap<_, _>>();
+    let mut pairs = Vec::new();
+    for target in target_sources {
+        match baselines.get(&target.get_relative()) {
+            Some(baseline) => pairs.push((*baseline, target)),
+            None => return Ok(false),
+        }
+    }
+    for (baseline, target) in sample(pairs) {
+        if content_hash(baseline)? != content_hash(target)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Select the largest sources, one per directory first. The remote sizes are not requested.
+fn sample<'a>(mut pairs: Vec<(&'a Source, &'a Source)>) -> Vec<(&'a Source, &'a Source)> {
+    pairs.sort_by_cached_key(|(_, target)| {
+        std::cmp::Reverse(match target {
+            Source::Local(_, _) => target.size(),
+            _ => None,
+        })
+    });
+    let directory = |source: &Source| {
+        let path = source.get_relative();
+        path.rsplit_once('/')
+            .map_or(String::new(), |(directory, _)| directory.to_string())
+    };
+    let mut directories = HashSet::new();
+    let (mut sample, rest): (Vec<_>, Vec<_>) = pairs
+        .into_iter()
+        .partition(|(_, target)| directories.insert(directory(target)));
+    sample.extend(rest);
+    sample.truncate(SAMPLE_SOURCES);
+    sample
+}
+
+/// The reason why the baseline is the target, when it is.
+pub fn check(
+    baseline: &Content,
+    baseline_sources: &[Source],
+    target: &Content,
+    target_sources: &[Source],
+) -> Result<Option<String>> {
+    Ok(if same_location(baseline, target) {
+        Some(format!("The baseline {} is the target", baseline))
+    } else if same_content(baseline_sources, target_sources)? {
+        Some(format!(
+            "The baseline {} has the same content as the target",
+            baseline
+        ))
+    } else {
+        None
+    })
+}
+
+#[test]
+fn test_leakage() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    for name in &["target", "copy", "other"] {
+        std::fs::create_dir_all(dir.join(name)).unwrap();
+        let content = if *name == "other" {
+            "Service started\n"
+        } else {
+            "Error\n"
+        };
+        std::fs::write(dir.join(name).join("app.log"), content).unwrap();
+    }
+    let content = |name: &str| Content::from_pathbuf(dir.join(name));
+    let sources = |name: &str| content(name).get_sources().unwrap();
+    let target = content("target");
+    let target_sources = sources("target");
+
+    let same = Content::from_pathbuf(dir.join("copy").join("..").join("target"));
+    let result = (
+        check(&same, &target_sources, &target, &target_sources).unwrap(),
+        check(&content("copy"), &sources("copy"), &target, &target_sources).unwrap(),
+        check(
+            &content("other"),
+            &sources("other"),
+            &target,
+            &target_sources,
+        )
+        .unwrap(),
+    );
+    assert!(result.0.unwrap().contains("is the target"));
+    assert!(result.1.unwrap().contains("same content"));
+    assert_eq!(result.2, None);
+}
+
+#[test]
+fn test_sample() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    for (name, size) in &[
+        ("logs/job-output.txt", 1000),
+        ("logs/services.txt", 500),
+        ("config/a.txt", 20),
+        ("config/b.txt", 10),
+        ("zuul-info/host.txt", 5),
+    ] {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, "x".repeat(*size)).unwrap();
+    }
+    let sources = Content::from_pathbuf(dir.to_path_buf())
+        .get_sources()
+        .unwrap();
+    let sample = sample(sources.iter().map(|source| (source, source)).collect())
+        .into_iter()
+        .map(|(_, target)| target.get_relative().trim_start_matches('/').to_string())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        sample,
+        vec!["logs/job-output.txt", "config/a.txt", "zuul-info/host.txt"]
+    );
+}
@@ -0,0 +1,143 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the log level of the anomalies, to filter and weight them by severity.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::Anomaly;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Level {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error,
+    Fatal,
+}
+
+impl std::str::FromStr for Level {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "trace" => Ok(Level::Trace),
+            "debug" => Ok(Level::Debug),
+            "info" => Ok(Level::Info),
+            "warn" | "warning" => Ok(Level::Warn),
+            "error" | "err" => Ok(Level::Error),
+            "fatal" | "critical" | "crit" => Ok(Level::Fatal),
+            _ => Err(format!(
+                "Unknown level: {} (expected trace, debug, info, warn, error or fatal)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Level {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Level::Trace => "trace",
+            Level::Debug => "debug",
+            Level::Info => "info",
+            Level::Warn => "warn",
+            Level::Error => "error",
+            Level::Fatal => "fatal",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref LEVEL: Regex = Regex::new(
+        r"\b(TRACE|DEBUG|INFO|WARN|WARNING|ERROR|ERR|FATAL|CRITICAL|CRIT)\b|\blevel=(\w+)"
+    ).unwrap();
+}
+
+impl Level {
+    /// Parse the first log level of a line.
+    pub fn parse(line: &str) -> Option<Level> {
+        LEVEL.captures_iter(line).find_map(|captures| {
+            captures
+                .get(1)
+                .or_else(|| captures.get(2))
+                .and_then(|level| level.as_str().parse().ok())
+        })
+    }
+
+    /// The factor applied to the distance when weighting the anomalies by level.
+    fn weight(&self) -> f32 {
+        match self {
+            Level::Trace | Level::Debug => 0.5,
+            Level::Info => 0.75,
+            Level::Warn => 0.9,
+            Level::Error | Level::Fatal => 1.0,
+        }
+    }
+}
+
+/// The severity options. The anomalies without a level are always kept.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LevelFilter {
+    pub min_level: Option<Level>,
+    /// Scale the distance by the level weight.
+    pub weighted: bool,
+}
+
+impl LevelFilter {
+    /// Apply the weight and return false when the anomaly needs to be removed.
+    pub fn keep(&self, anomaly: &mut Anomaly) -> bool {
+        match anomaly.level {
+            None => true,
+            Some(level) => {
+                if self.weighted {
+                    anomaly.distance *= level.weight();
+                }
+                self.min_level.map_or(true, |min_level| level >= min_level)
+                    && anomaly.distance > crate::process::THRESHOLD
+            }
+        }
+    }
+}
+
+#[test]
+fn test_level() {
+    assert_eq!(Level::parse("2022-01-01 ERROR oops"), Some(Level::Error));
+    assert_eq!(Level::parse("ts=1 level=warn msg=slow"), Some(Level::Warn));
+    assert_eq!(Level::parse("INFOS are not levels"), None);
+    assert!(Level::Warn > Level::Info);
+    assert_eq!(Level::Warn.to_string().parse(), Ok(Level::Warn));
+
+    let filter = LevelFilter {
+        min_level: Some(Level::Warn),
+        weighted: true,
+    };
+    let anomaly = |line: &str| Anomaly {
+        id: String::new(),
+        distance: 0.5,
+        pos: 1,
+        offset: 0,
+        column: 1,
+        line: line.to_string(),
+        level: Level::parse(line),
+        test: None,
+        task: None,
+        command: None,
+        hint: None,
+        repeat: 0,
+        run: 0,
+        retry: None,
+    };
+    assert!(!filter.keep(&mut anomaly("INFO new line")));
+    assert!(filter.keep(&mut anomaly("ERROR new line")));
+    assert!(filter.keep(&mut anomaly("new line")));
+    // 0.5 * 0.5 is below the threshold
+    let weighted = LevelFilter {
+        min_level: None,
+        weighted: true,
+    };
+    assert!(!weighted.keep(&mut anomaly("DEBUG new line")));
+}
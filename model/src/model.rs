@@ -8,17 +8,54 @@
 use anyhow::{Context, Result};
 use itertools::Itertools;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::TryFrom;
 use std::path::{Path, PathBuf};
 use std::time::{Duration, Instant, SystemTime};
 use url::Url;
 
+pub mod ara;
+pub mod cancel;
+pub mod collisions;
+pub mod coverage;
+pub mod diff;
+#[cfg(feature = "embedding")]
+pub mod embedding_index;
+pub mod evtx;
 pub mod files;
+pub mod job_output;
+pub mod leakage;
+pub mod level;
+pub mod model_cache;
+pub mod net;
+pub mod ngram;
+pub mod phases;
+pub mod prefix;
+pub mod privacy;
 pub mod process;
+pub mod profile;
+pub mod progress;
 mod reader;
+pub mod redact;
+pub mod retry;
+pub mod rules;
+pub mod scores;
+pub mod segment;
+#[cfg(feature = "async")]
+pub mod stream;
+pub mod streaming;
+pub mod subunit;
+pub mod tags;
+pub mod tokens;
 pub mod urls;
+pub mod walk;
 pub mod zuul;
 
+pub use logreduce_index::Metric;
+pub use progress::ProgressObserver;
+pub use reader::{configure_limits, DecompressLimits};
+
 #[derive(Clone, Copy)]
 pub enum OutputMode {
     // Print every steps
@@ -49,8 +86,10 @@ impl Input {
             false => Input::Path(s),
         }
     }
+    /// The invalid UTF-8 sequences of the path are replaced with U+FFFD, prefer
+    /// [Content::from_path] for such paths.
     pub fn from_pathbuf(s: PathBuf) -> Input {
-        Input::Path(s.into_os_string().into_string().unwrap())
+        Input::Path(s.to_string_lossy().into_owned())
     }
 }
 
@@ -77,6 +116,13 @@ impl std::fmt::Display for Content {
 pub enum Source {
     Local(usize, PathBuf),
     Remote(usize, url::Url),
+    /// The events of a single provider in a Windows event log.
+    Evtx(usize, PathBuf, String),
+    /// The lines of a single playbook in a zuul job output, see [phases], or of a single test
+    /// in a subunit file, see [subunit].
+    Section(Box<Source>, String),
+    /// An input that can only be read once, e.g. the stdin, see [streaming].
+    Stream(String),
 }
 
 impl std::fmt::Display for Source {
@@ -84,10 +130,62 @@ impl std::fmt::Display for Source {
         match self {
             Source::Local(_, _) => write!(f, "local: {}", self.get_relative()),
             Source::Remote(_, _) => write!(f, "remote: {}", self.get_relative()),
+            Source::Evtx(_, _, provider) => {
+                write!(f, "evtx: {} ({})", self.get_relative(), provider)
+            }
+            Source::Section(source, section) => write!(f, "{} ({})", source, section),
+            Source::Stream(address) => write!(f, "stream: {}", address),
         }
     }
 }
 
+/// Remove the root of a local path, the whole path is kept when the root length is invalid.
+fn relative_path(path: Cow<'_, str>, base_len: usize, separator: char) -> Cow<'_, str> {
+    let relative = match path {
+        Cow::Borrowed(path) => Cow::Borrowed(path.get(base_len..).unwrap_or(path)),
+        Cow::Owned(path) => match path.get(base_len..) {
+            Some(relative) => Cow::Owned(relative.to_string()),
+            None => Cow::Owned(path),
+        },
+    };
+    if separator != '/' && relative.contains(separator) {
+        Cow::Owned(relative.replace(separator, "/"))
+    } else {
+        relative
+    }
+}
+
+#[test]
+fn test_relative_path() {
+    let relative = |path: &str, base_len, separator| {
+        relative_path(Cow::Borrowed(path), base_len, separator).into_owned()
+    };
+    assert_eq!(relative("/logs/job-output.txt", 5, '/'), "/job-output.txt");
+    assert_eq!(
+        relative(r"C:\logs\controller\job-output.txt", 7, '\\'),
+        "/controller/job-output.txt"
+    );
+    // The root length is not a char boundary.
+    assert_eq!(relative("/lögs", 3, '/'), "/lögs");
+    assert_eq!(relative("/logs", 42, '/'), "/logs");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_non_utf8_relative() {
+    use std::os::unix::ffi::OsStrExt;
+    let root = Path::new(std::ffi::OsStr::from_bytes(b"/logs/\xff"));
+    let path = root.join("job-output.txt");
+    let base_len = root.to_string_lossy().len();
+    let source = Source::Local(base_len, path);
+    assert_eq!(source.get_relative(), "/job-output.txt");
+    assert_eq!(
+        Source::Local(0, root.to_path_buf()).get_relative(),
+        "/logs/\u{FFFD}"
+    );
+    assert_eq!(source.to_string(), "local: /job-output.txt");
+}
+
 impl Source {
     pub fn from_pathbuf(p: PathBuf) -> Source {
         Source::Local(0, p)
@@ -95,17 +193,68 @@ impl Source {
     pub fn is_json(&'_ self) -> bool {
         self.get_relative().ends_with(".json")
     }
-    pub fn get_relative(&'_ self) -> &'_ str {
+    /// The path from the content root, using the `/` separator on every platform.
+    /// The invalid UTF-8 sequences of a local path are replaced with U+FFFD.
+    pub fn get_relative(&'_ self) -> Cow<'_, str> {
+        match self {
+            Source::Local(base_len, path) | Source::Evtx(base_len, path, _) => {
+                relative_path(path.to_string_lossy(), *base_len, std::path::MAIN_SEPARATOR)
+            }
+            Source::Remote(base_len, url) => Cow::Borrowed(&url.as_str()[*base_len..]),
+            Source::Section(source, _) => source.get_relative(),
+            Source::Stream(address) => Cow::Borrowed(address),
+        }
+    }
+
+    /// The source without its location, only the relative path is kept.
+    pub fn anonymize(&self) -> Source {
+        let relative = PathBuf::from(self.get_relative().into_owned());
         match self {
-            Source::Local(base_len, path) => &path.to_str().unwrap_or("")[*base_len..],
-            Source::Remote(base_len, url) => &url.as_str()[*base_len..],
+            Source::Evtx(_, _, provider) => Source::Evtx(0, relative, provider.clone()),
+            Source::Section(source, section) => {
+                Source::Section(Box::new(source.anonymize()), section.clone())
+            }
+            Source::Stream(_) => self.clone(),
+            _ => Source::Local(0, relative),
         }
     }
 
     pub fn as_str(&'_ self) -> &'_ str {
         match self {
-            Source::Local(_, path) => path.to_str().unwrap_or(""),
+            Source::Local(_, path) | Source::Evtx(_, path, _) => path.to_str().unwrap_or(""),
             Source::Remote(_, url) => url.as_str(),
+            Source::Section(source, _) => source.as_str(),
+            Source::Stream(address) => address,
+        }
+    }
+
+    /// The size of the source in bytes, when it is known.
+    pub fn size(&self) -> Option<u64> {
+        match self {
+            Source::Local(_, path) => std::fs::metadata(path).ok().map(|meta| meta.len()),
+            Source::Evtx(_, _, _) | Source::Section(_, _) | Source::Stream(_) => None,
+            Source::Remote(_, url) => crate::reader::content_length(url).ok().flatten(),
+        }
+    }
+
+    /// Check if the source can only be read once, and if its size is unknown, see [streaming].
+    pub fn is_stream(&self) -> bool {
+        matches!(self, Source::Stream(_))
+    }
+
+    /// Open the source to read its lines.
+    pub fn open(&self) -> Result<crate::reader::DecompressReader> {
+        match self {
+            Source::Local(_, path_buf) => Source::file_open(path_buf.as_path()),
+            Source::Remote(prefix, url) => Source::url_open(*prefix, url),
+            Source::Evtx(_, path_buf, provider) => evtx::open(path_buf, provider),
+            Source::Section(source, section) => match source.as_ref() {
+                Source::Local(_, path) if subunit::is_subunit(&path.to_string_lossy()) => {
+                    subunit::open_section(path, section)
+                }
+                source => phases::open(source, section),
+            },
+            Source::Stream(address) => streaming::open(address),
         }
     }
 
@@ -116,7 +265,6 @@ impl Source {
                 for ext in [
                     // binary data with known extension
                     ".ico", ".png", ".clf", ".tar", ".tar.bzip2",
-                    ".subunit",
                     ".sqlite", ".db", ".bin", ".pcap.log.txt",
                     // font
                     ".eot", ".otf", ".woff", ".woff2", ".ttf",
@@ -138,6 +286,10 @@ impl Source {
             };
         }
         let s = self.as_str();
+        if ara::is_ara(s) {
+            // The database is read with sqlite, which needs a local file.
+            return matches!(self, Source::Local(_, _));
+        }
         EXTS.iter().all(|ext| !s.ends_with(ext)) && !s.contains("/etc/")
     }
 }
@@ -150,7 +302,96 @@ type Baselines = Vec<Content>;
 pub struct Model {
     created_at: SystemTime,
     baselines: Baselines,
+    /// The indexes are stored after the other fields, see [ModelFile].
+    #[serde(skip)]
     indexes: HashMap<IndexName, Index>,
+    tags: tags::Tags,
+    /// The indexes stored in separate files, see [Model::save_shards].
+    #[serde(skip)]
+    shard_names: Vec<IndexName>,
+    /// The selection of the baseline sources, see [Model::train_with].
+    source_filter: SourceFilter,
+    #[serde(skip)]
+    shards: Shards,
+    /// The index of the sources without baselines, see [Model::set_fallback_index].
+    #[serde(skip)]
+    fallback_index: Option<IndexName>,
+    /// The maximum time to inspect a source, see [Model::set_source_timeout].
+    #[serde(skip)]
+    source_timeout: Option<Duration>,
+    /// The path the model was loaded from or saved to, see [Model::hash].
+    #[serde(skip)]
+    path: std::sync::Mutex<Option<PathBuf>>,
+}
+
+/// The lazily loaded indexes of a sharded model.
+#[derive(Debug, Default)]
+struct Shards {
+    dir: PathBuf,
+    cells: HashMap<IndexName, once_cell::sync::OnceCell<Index>>,
+    context_mode: process::ContextMode,
+    frequency_weight: bool,
+}
+
+impl Shards {
+    fn path(dir: &Path, index_name: &IndexName) -> PathBuf {
+        use sha2::Digest;
+        let digest = sha2::Sha256::digest(index_name.as_str().as_bytes());
+        dir.join(format!("{:x}.bin", digest))
+    }
+
+    fn load(&self, index_name: &IndexName) -> Result<Index> {
+        let path = Shards::path(&self.dir, index_name);
+        tracing::debug!(path = path.to_str(), "Loading index shard {}", index_name);
+        let mut reader =
+            flate2::read::GzDecoder::new(std::fs::File::open(&path).context("Can't open shard")?);
+        read_header(&mut reader)?;
+        let mut index: Index = bincode::deserialize_from(reader).context("Can't load shard")?;
+        index.context_mode = self.context_mode;
+        index.set_frequency_weight(self.frequency_weight);
+        Ok(index)
+    }
+}
+
+/// The content of a model file: the model, the names of the indexes stored in separate files,
+/// and the other indexes. A sharded model file is the same without the indexes, so that every
+/// model field is saved in both layouts.
+type ModelFile = (Model, Vec<IndexName>, HashMap<IndexName, Index>);
+
+/// The model file name in a sharded model directory.
+const SHARDED_MODEL: &str = "model.bin";
+
+/// The first bytes of the model and shard files, see [write_gz].
+const FORMAT_MAGIC: &[u8; 9] = b"LOGREDUCE";
+
+/// The version of the model and shard files layout. Bump it when a serialized type changes,
+/// so that the older files are rejected with a clear error.
+pub const FORMAT_VERSION: u32 = 1;
+
+/// Check the header of a model or shard file, see [write_gz].
+fn read_header(reader: &mut impl std::io::Read) -> Result<()> {
+    let retrain = "the model needs to be retrained with this logreduce version";
+    let mut magic = [0; FORMAT_MAGIC.len()];
+    let mut version = [0; 4];
+    reader
+        .read_exact(&mut magic)
+        .and_then(|_| reader.read_exact(&mut version))
+        .with_context(|| format!("Can't read model header, {}", retrain))?;
+    if &magic != FORMAT_MAGIC {
+        return Err(anyhow::anyhow!(
+            "The model has no format version, {}",
+            retrain
+        ));
+    }
+    match u32::from_le_bytes(version) {
+        FORMAT_VERSION => Ok(()),
+        version => Err(anyhow::anyhow!(
+            "The model format version {} is not supported (expected {}), {}",
+            version,
+            FORMAT_VERSION,
+            retrain
+        )),
+    }
 }
 
 /// A LogModelName is an identifier that is used to group similar source.
@@ -165,11 +406,35 @@ impl std::fmt::Display for IndexName {
 
 impl IndexName {
     pub fn from_source(source: &Source) -> IndexName {
-        IndexName::from_path(source.get_relative())
+        IndexName::explain(source).0
+    }
+
+    /// The index name of a source, with the rule that produced it.
+    pub fn explain(source: &Source) -> (IndexName, files::GroupingRule) {
+        match source {
+            Source::Evtx(_, _, provider) => (
+                IndexName::from_provider(provider),
+                files::GroupingRule::EvtxProvider,
+            ),
+            // The tests of a subunit file share its index.
+            Source::Section(source, _) if subunit::is_subunit(source.as_str()) => {
+                IndexName::explain(source)
+            }
+            Source::Section(source, section) => (
+                IndexName::from_section(source, section),
+                files::GroupingRule::JobSection,
+            ),
+            _ => IndexName::explain_path(&source.get_relative()),
+        }
     }
     pub fn as_str(&self) -> &'_ str {
         self.0.as_str()
     }
+
+    /// The index of every baseline source, see [Content::add_global_group].
+    pub fn global() -> IndexName {
+        IndexName("(global)".to_string())
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -180,13 +445,77 @@ pub struct Index {
     index: ChunkIndex,
     line_count: usize,
     byte_count: usize,
+    #[serde(skip)]
+    context_mode: process::ContextMode,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Anomaly {
+    /// The deterministic id of the line, see [anomaly_id].
+    pub id: String,
     pub distance: f32,
     pub pos: usize,
+    /// The byte offset of the line, in the uncompressed source.
+    pub offset: usize,
+    /// The column of the line, which is greater than 1 when a long line is split.
+    pub column: usize,
     pub line: String,
+    /// The log level of the line, when present.
+    pub level: Option<level::Level>,
+    /// The test case being executed, when the source is a test runner output.
+    pub test: Option<String>,
+    /// The playbook and task being executed, when the source is a zuul job output.
+    pub task: Option<String>,
+    /// The shell command that was running, when the source is a shell trace.
+    pub command: Option<segment::Command>,
+    /// The known error matching the line, see [rules::Rules].
+    pub hint: Option<rules::Hint>,
+    /// The number of times the line was repeated after its first occurrence.
+    pub repeat: usize,
+    /// The number of consecutive repetitions that follow the line, they are collapsed in the
+    /// anomaly and the after context starts at the line `pos + run + 1`.
+    #[serde(default)]
+    pub run: usize,
+    /// The attempts of the retry loop collapsed in the anomaly, see [retry].
+    pub retry: Option<retry::RetryLoop>,
+}
+
+impl Anomaly {
+    /// The physical lines of the anomaly with their position, more than one when the index
+    /// scores the chunks of lines, see [ChunkIndex::with_granularity]. The trailing empty lines
+    /// of a chunk are not returned.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str)> {
+        let line = self.line.trim_end_matches('\n');
+        line.split('\n')
+            .enumerate()
+            .map(move |(idx, line)| (self.pos + idx, line))
+    }
+}
+
+/// The id of an anomaly, to reference it across runs, e.g. in a suppression or a report diff.
+/// It is a hash of the index name and of the tokenized line, so it does not depend on the
+/// position or the variable parts of the line.
+pub fn anomaly_id(index_name: Option<&IndexName>, tokens: &str) -> String {
+    use sha2::Digest;
+    let mut hasher = sha2::Sha256::new();
+    hasher.update(
+        index_name
+            .map_or("", |index_name| index_name.as_str())
+            .as_bytes(),
+    );
+    hasher.update(b"\0");
+    hasher.update(tokens.as_bytes());
+    format!("{:x}", hasher.finalize())[..16].to_string()
+}
+
+#[test]
+fn test_anomaly_id() {
+    let index_name = IndexName("job-output.txt".to_string());
+    let id = anomaly_id(Some(&index_name), "Connection refused");
+    assert_eq!(id.len(), 16);
+    assert_eq!(id, anomaly_id(Some(&index_name), "Connection refused"));
+    assert_ne!(id, anomaly_id(None, "Connection refused"));
+    assert_ne!(id, anomaly_id(Some(&index_name), "Connection reset"));
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -196,6 +525,32 @@ pub struct AnomalyContext {
     pub after: Vec<String>,
 }
 
+impl AnomalyContext {
+    /// The position of the line before the after context.
+    pub fn after_pos(&self) -> usize {
+        self.anomaly.pos + self.anomaly.line.matches('\n').count() + self.anomaly.run
+    }
+
+    /// The position of the first context line.
+    pub fn first_pos(&self) -> usize {
+        self.anomaly.pos.saturating_sub(self.before.len())
+    }
+
+    /// The position of the last context line.
+    pub fn last_pos(&self) -> usize {
+        self.after_pos() + self.after.len()
+    }
+
+    /// Remove the before context lines that are at or before the given line number,
+    /// so that the context of close anomalies is not repeated.
+    pub fn trim_before(&mut self, last_pos: usize) {
+        let overlap = (last_pos + 1)
+            .saturating_sub(self.first_pos())
+            .min(self.before.len());
+        self.before.drain(..overlap);
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct LogReport {
     pub test_time: Duration,
@@ -204,6 +559,20 @@ pub struct LogReport {
     pub anomalies: Vec<AnomalyContext>,
     pub source: Source,
     pub index_name: IndexName,
+    /// The source was inspected with the fallback index, see [Model::set_fallback_index].
+    #[serde(default)]
+    pub fallback: bool,
+}
+
+impl LogReport {
+    /// The rule that selected the index of the source, see [IndexName::explain].
+    pub fn grouping_rule(&self) -> files::GroupingRule {
+        if self.fallback {
+            files::GroupingRule::Fallback
+        } else {
+            IndexName::explain(&self.source).1
+        }
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -230,9 +599,15 @@ pub struct Report {
     pub log_reports: Vec<LogReport>,
     pub index_reports: HashMap<IndexName, IndexReport>,
     pub index_errors: Vec<Vec<Source>>,
+    /// The index_errors with the actions to close them.
+    pub coverage_gaps: Vec<coverage::Gap>,
     pub read_errors: Vec<(Source, String)>,
     pub total_line_count: usize,
     pub total_anomaly_count: usize,
+    /// The configuration warnings, e.g. the expired ignore rules.
+    pub warnings: Vec<String>,
+    /// Why some anomalies are missing, e.g. the run was cancelled or a source failed.
+    pub incomplete: Option<String>,
 }
 
 impl Report {
@@ -253,28 +628,120 @@ impl Report {
         ))
         .context("Can't load report")
     }
+
+    /// Remove the anomalies that are filtered by their log level.
+    pub fn level_filter(&mut self, filter: level::LevelFilter) {
+        for log_report in self.log_reports.iter_mut() {
+            log_report
+                .anomalies
+                .retain_mut(|anomaly| filter.keep(&mut anomaly.anomaly));
+        }
+        self.log_reports
+            .retain(|log_report| !log_report.anomalies.is_empty());
+        self.total_anomaly_count = self
+            .log_reports
+            .iter()
+            .map(|log_report| log_report.anomalies.len())
+            .sum();
+    }
+
+    /// Show the log reports of each index together, sorted by index name.
+    pub fn group_by_index(&mut self) {
+        self.log_reports
+            .sort_by(|x, y| x.index_name.cmp(&y.index_name));
+    }
+
+    /// Apply the second pass [process::self_consistency_filter] to each log report, with the
+    /// index of the report.
+    pub fn self_consistency_filter(&mut self, model: &Model, min_occurrences: usize) {
+        for log_report in self.log_reports.iter_mut() {
+            if let Some(index) = model.get_index(&log_report.index_name) {
+                index.self_consistency_filter(
+                    &log_report.source,
+                    &mut log_report.anomalies,
+                    min_occurrences,
+                );
+            }
+        }
+        self.log_reports
+            .retain(|log_report| !log_report.anomalies.is_empty());
+        self.total_anomaly_count = self
+            .log_reports
+            .iter()
+            .map(|log_report| log_report.anomalies.len())
+            .sum();
+    }
+}
+
+fn is_json(sources: &[Source]) -> bool {
+    sources.first().map_or(false, |source| source.is_json())
+}
+
+/// Add the sources to the trainer. The global trainer gets their tokens when it has the same
+/// granularity, so that the baselines are read once, see [Content::add_global_group].
+fn add_sources(
+    progress: &dyn ProgressObserver,
+    trainer: &mut process::ChunkTrainer,
+    sources: &[Source],
+    mut global: Option<&mut process::ChunkTrainer>,
+) -> Result<()> {
+    for source in sources {
+        progress.source_started(source);
+        let (line_count, byte_count) = (trainer.line_count, trainer.byte_count);
+        let reader = source.open()?;
+        let result = match global.as_deref_mut() {
+            Some(global) if global.granularity() == trainer.granularity() => {
+                let mut tokens = Vec::new();
+                let result = trainer.add_with(reader, |line| tokens.push(line.to_string()));
+                global.add_tokenized(tokens);
+                result
+            }
+            Some(global) => trainer
+                .add(reader)
+                .and_then(|()| global.add(source.open()?)),
+            None => trainer.add(reader),
+        };
+        if let Err(e) = result {
+            tracing::error!("{}: failed to load: {}", source, e)
+        }
+        source_read(
+            progress,
+            source,
+            trainer.line_count - line_count,
+            trainer.byte_count - byte_count,
+        );
+    }
+    Ok(())
+}
+
+/// Report the final counts of a source that is read, see [ProgressObserver::lines_read].
+fn source_read(
+    progress: &dyn ProgressObserver,
+    source: &Source,
+    line_count: usize,
+    byte_count: usize,
+) {
+    progress.lines_read(source, line_count, byte_count);
+    progress.source_finished(source, line_count);
 }
 
 impl Index {
-    #[tracing::instrument(level = "debug", name = "Index::train", skip(index))]
-    pub fn train(sources: &[Source], mut index: ChunkIndex) -> Result<Index> {
+    pub fn train(sources: &[Source], index: ChunkIndex) -> Result<Index> {
+        Index::train_with(&OutputMode::Quiet, sources, index, None)
+    }
+
+    /// Create an index, the lines of the sources are also added to the global trainer.
+    #[tracing::instrument(level = "debug", name = "Index::train", skip(progress, index, global))]
+    fn train_with(
+        progress: &dyn ProgressObserver,
+        sources: &[Source],
+        mut index: ChunkIndex,
+        global: Option<&mut process::ChunkTrainer>,
+    ) -> Result<Index> {
         let created_at = SystemTime::now();
         let start_time = Instant::now();
-        let is_json = if let Some(source) = sources.first() {
-            source.is_json()
-        } else {
-            false
-        };
-        let mut trainer = process::ChunkTrainer::new(&mut index, is_json);
-        for source in sources {
-            let reader = match source {
-                Source::Local(_, path_buf) => Source::file_open(path_buf.as_path())?,
-                Source::Remote(prefix, url) => Source::url_open(*prefix, url)?,
-            };
-            if let Err(e) = trainer.add(reader) {
-                tracing::error!("{}: failed to load: {}", source, e)
-            }
-        }
+        let mut trainer = process::ChunkTrainer::new(&mut index, is_json(sources));
+        add_sources(progress, &mut trainer, sources, global)?;
         trainer.complete();
         let train_time = start_time.elapsed();
         Ok(Index {
@@ -284,36 +751,145 @@ impl Index {
             byte_count: trainer.byte_count,
             index,
             sources: sources.to_vec(),
+            context_mode: process::ContextMode::default(),
         })
     }
 
+    /// Add the lines of new sources that are not already in the index.
+    pub fn update(&mut self, sources: &[Source]) -> Result<()> {
+        self.update_with(&OutputMode::Quiet, sources, None)
+    }
+
+    /// Update the index, the lines of the sources are also added to the global trainer.
+    #[tracing::instrument(level = "debug", name = "Index::update", skip(self, progress, global))]
+    fn update_with(
+        &mut self,
+        progress: &dyn ProgressObserver,
+        sources: &[Source],
+        global: Option<&mut process::ChunkTrainer>,
+    ) -> Result<()> {
+        let start_time = Instant::now();
+        let mut trainer = process::ChunkTrainer::new(&mut self.index, is_json(sources));
+        add_sources(progress, &mut trainer, sources, global)?;
+        trainer.complete();
+        self.line_count += trainer.line_count;
+        self.byte_count += trainer.byte_count;
+        self.sources.extend_from_slice(sources);
+        self.train_time += start_time.elapsed();
+        Ok(())
+    }
+
+    fn set_frequency_weight(&mut self, enabled: bool) {
+        self.index.set_frequency_weight(enabled);
+    }
+
+    fn anonymize(&mut self) {
+        self.sources = self.sources.iter().map(Source::anonymize).collect();
+    }
+
+    /// The number of baseline lines.
+    pub fn line_count(&self) -> usize {
+        self.line_count
+    }
+
+    /// The number of baseline sources that contained the line.
+    #[cfg(test)]
+    pub(crate) fn origin_count(&self, line: &str) -> usize {
+        self.index.origin_count(&self.index.tokenize(line))
+    }
+
+    /// Apply the second pass [process::self_consistency_filter] to the anomalies of a source.
+    pub fn self_consistency_filter(
+        &self,
+        source: &Source,
+        anomalies: &mut Vec<AnomalyContext>,
+        min_occurrences: usize,
+    ) {
+        process::self_consistency_filter(&self.index, source.is_json(), anomalies, min_occurrences)
+    }
+
+    /// Combine two indexes so that they are searched together.
+    fn merge(self, other: Index, aggregation: Aggregation) -> Index {
+        let mut sources = self.sources;
+        sources.extend(other.sources);
+        let members = match self.index {
+            ChunkIndex::Ensemble(mut members, _) => {
+                members.push(other.index);
+                members
+            }
+            index => vec![index, other.index],
+        };
+        Index {
+            created_at: self.created_at.max(other.created_at),
+            train_time: self.train_time + other.train_time,
+            line_count: self.line_count + other.line_count,
+            byte_count: self.byte_count + other.byte_count,
+            index: ChunkIndex::Ensemble(members, aggregation),
+            sources,
+            context_mode: self.context_mode,
+        }
+    }
+
+    /// Remove the oldest baselines to keep at most `max_lines` unique lines. The line and byte
+    /// counts are reduced in proportion.
+    fn truncate(&mut self, max_lines: usize) {
+        let row_count = self.index.row_count();
+        self.index.truncate(max_lines);
+        let kept = self.index.row_count();
+        if kept < row_count {
+            let ratio = kept as f64 / row_count as f64;
+            self.line_count = (self.line_count as f64 * ratio).round() as usize;
+            self.byte_count = (self.byte_count as f64 * ratio).round() as usize;
+        }
+    }
+
     pub fn get_processor<'a>(
         &'a self,
-        output_mode: OutputMode,
+        progress: &'a dyn ProgressObserver,
         source: &Source,
         skip_lines: &'a mut HashSet<String>,
     ) -> Result<process::ChunkProcessor<crate::reader::DecompressReader>> {
-        debug_or_progress(output_mode, &format!("Inspecting {}", source));
-        let fp = match source {
-            Source::Local(_, path_buf) => Source::file_open(path_buf.as_path()),
-            Source::Remote(prefix, url) => Source::url_open(*prefix, url),
-        }?;
-        Ok(process::ChunkProcessor::new(
-            fp,
-            &self.index,
-            source.is_json(),
-            skip_lines,
-        ))
+        progress.source_started(source);
+        let fp = source.open()?;
+        Ok(self
+            .get_reader_processor(fp, source.is_json(), skip_lines)
+            .with_cancellation(progress.cancellation().cloned())
+            .with_progress(progress, source.clone())
+            .with_line_scores(progress.wants_scores()))
+    }
+
+    /// Inspect the lines of a reader that is not a source, e.g. the output of a running command,
+    /// see [streaming::spawn].
+    pub fn get_reader_processor<'a, R: std::io::Read>(
+        &'a self,
+        reader: R,
+        is_json: bool,
+        skip_lines: &'a mut HashSet<String>,
+    ) -> process::ChunkProcessor<'a, R> {
+        process::ChunkProcessor::new(reader, &self.index, is_json, skip_lines)
+            .with_context_mode(self.context_mode)
+    }
+
+    /// The anomaly id of a line, see [crate::anomaly_id]. The line prefix is not removed, as the
+    /// framing of its source is not known, so the id of a scored line should be preferred.
+    pub fn line_id(&self, index_name: &IndexName, line: &str) -> String {
+        crate::anomaly_id(Some(index_name), &self.index.tokenize(line))
+    }
+
+    /// Score the lines one by one, like the lines of a source, see [process::LineScorer].
+    /// A distance of 0.0 means the line is in the baselines.
+    pub fn line_scorer(&self, index_name: Option<&IndexName>) -> process::LineScorer<'_> {
+        process::LineScorer::new(&self.index, index_name.cloned())
     }
 
-    #[tracing::instrument(level = "debug", name = "Index::inspect", skip(self, output_mode))]
+    #[tracing::instrument(level = "debug", name = "Index::inspect", skip(self, progress))]
     pub fn inspect<'a>(
         &'a self,
-        output_mode: OutputMode,
+        progress: &'a dyn ProgressObserver,
         source: &Source,
         skip_lines: &'a mut HashSet<String>,
     ) -> Box<dyn Iterator<Item = Result<AnomalyContext>> + 'a> {
-        match self.get_processor(output_mode, source, skip_lines) {
+        match self.get_processor(progress, source, skip_lines) {
             Ok(processor) => Box::new(processor),
             // If the file can't be open, the first iterator result will be the error.
             Err(e) => Box::new(std::iter::once(Err(e))),
@@ -326,7 +902,10 @@ impl Content {
     #[tracing::instrument(level = "debug")]
     pub fn from_input(input: Input) -> Result<Content> {
         match input {
-            Input::Path(path_str) => Content::from_path(Path::new(&path_str)),
+            Input::Path(path_str) => match streaming::from_input(&path_str) {
+                Some(source) => Ok(Content::File(source)),
+                None => Content::from_path(Path::new(&path_str)),
+            },
             Input::Url(url_str) => {
                 Content::from_url(Url::parse(&url_str).expect("Failed to parse url"))
             }
@@ -349,6 +928,15 @@ impl Content {
                 Source::Remote(_, _) => Err(anyhow::anyhow!(
                     "Can't find remmote baselines, they need to be provided"
                 )),
+                Source::Evtx(_, _, _) => Err(anyhow::anyhow!(
+                    "Can't discover evtx baselines, they need to be provided"
+                )),
+                Source::Section(_, _) => Err(anyhow::anyhow!(
+                    "Can't discover section baselines, they need to be provided"
+                )),
+                Source::Stream(_) => Err(anyhow::anyhow!(
+                    "Can't discover stream baselines, they need to be provided"
+                )),
             },
             Content::Directory(_) => Err(anyhow::anyhow!(
                 "Can't discover directory baselines, they need to be provided",
@@ -357,10 +945,49 @@ impl Content {
         })
         .and_then(|baselines| match baselines.len() {
             0 => Err(anyhow::anyhow!("Empty discovered baselines")),
-            _ => Ok(baselines),
+            1 => Ok(baselines),
+            _ => self
+                .select_baseline(baselines)
+                .map(|(baseline, _score)| vec![baseline]),
         })
     }
 
+    /// The set of IndexName of this Content.
+    pub fn index_names(&self) -> Result<HashSet<IndexName>> {
+        Ok(self
+            .get_sources()?
+            .iter()
+            .map(IndexName::from_source)
+            .collect())
+    }
+
+    /// Pick the baseline whose IndexName set is the most similar to this Content.
+    #[tracing::instrument(level = "debug", skip(candidates))]
+    pub fn select_baseline(&self, candidates: Baselines) -> Result<(Content, f32)> {
+        let target = self.index_names()?;
+        let (score, baseline) = candidates
+            .into_iter()
+            .filter_map(|candidate| match candidate.index_names() {
+                Ok(names) => Some((overlap_score(&target, &names), candidate)),
+                Err(e) => {
+                    tracing::warn!("{}: skipping baseline candidate: {}", candidate, e);
+                    None
+                }
+            })
+            .max_by(|(score1, _), (score2, _)| {
+                score1
+                    .partial_cmp(score2)
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .ok_or_else(|| anyhow::anyhow!("No valid baseline candidates"))?;
+        tracing::info!(
+            "Selected baseline {} (overlap score {:.2})",
+            baseline,
+            score
+        );
+        Ok((baseline, score))
+    }
+
     /// Get the sources of log lines for this Content.
     #[tracing::instrument(level = "debug")]
     pub fn get_sources(&self) -> Result<Vec<Source>> {
@@ -379,114 +1006,983 @@ impl Content {
     }
 
     pub fn get_sources_iter(&self) -> Box<dyn Iterator<Item = Result<Source>>> {
-        match self {
-            Content::File(src) => Box::new(src.file_iter()),
+        let sources: Box<dyn Iterator<Item = Result<Source>>> = match self {
+            Content::File(src) => src.file_iter(),
             Content::Directory(src) => match src {
                 Source::Local(_, pathbuf) => Box::new(Source::dir_iter(pathbuf.as_path())),
                 Source::Remote(_, url) => Box::new(Source::httpdir_iter(url)),
+                Source::Evtx(_, _, _) | Source::Section(_, _) | Source::Stream(_) => {
+                    src.file_iter()
+                }
             },
             Content::Zuul(build) => Box::new(build.sources_iter()),
-        }
+        };
+        Box::new(sources.flat_map(phases::expand))
     }
 
     pub fn group_sources(baselines: &[Content]) -> Result<HashMap<IndexName, Vec<Source>>> {
-        let mut groups = HashMap::new();
+        Content::group_sources_with(baselines, &SourceFilter::default())
+    }
+
+    /// Group the sources selected by the filter.
+    pub fn group_sources_with(
+        baselines: &[Content],
+        filter: &SourceFilter,
+    ) -> Result<HashMap<IndexName, Vec<Source>>> {
+        let mut sources = Vec::new();
         for baseline in baselines {
-            for source in baseline.get_sources()? {
-                groups
-                    .entry(IndexName::from_source(&source))
-                    .or_insert_with(Vec::new)
-                    .push(source);
+            sources.extend(filter.apply(baseline.get_sources()?));
+        }
+        Ok(Source::group_by_index(sources))
+    }
+
+    /// Add the group of every source, to inspect the sources without baselines.
+    pub fn add_global_group(groups: &mut HashMap<IndexName, Vec<Source>>) {
+        let mut sources = groups.values().flatten().cloned().collect::<Vec<_>>();
+        sources.sort_by(|x, y| x.as_str().cmp(y.as_str()));
+        groups.insert(IndexName::global(), sources);
+    }
+}
+
+impl Source {
+    /// Group the sources by their [IndexName], keeping their order.
+    pub fn group_by_index(
+        sources: impl IntoIterator<Item = Source>,
+    ) -> HashMap<IndexName, Vec<Source>> {
+        let mut groups = HashMap::new();
+        for source in sources {
+            groups
+                .entry(IndexName::from_source(&source))
+                .or_insert_with(Vec::new)
+                .push(source);
+        }
+        groups
+    }
+}
+
+/// The user selection of sources, using regexes matching their relative path.
+///
+/// Only the regexes are recorded in the model. The binary sources are skipped by
+/// [Content::get_sources] with a built-in list that is the same for the baselines and the
+/// targets, and the decompressed size limit, see [DecompressLimits], stops a read that is too
+/// large without selecting the sources, so recording them would not change the selection.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(into = "SourceFilterPatterns", try_from = "SourceFilterPatterns")]
+pub struct SourceFilter {
+    include: Vec<regex::Regex>,
+    exclude: Vec<regex::Regex>,
+    only: Vec<regex::Regex>,
+}
+
+impl SourceFilter {
+    /// An empty include list selects every sources.
+    pub fn new(include: Vec<regex::Regex>, exclude: Vec<regex::Regex>) -> SourceFilter {
+        SourceFilter {
+            include,
+            exclude,
+            only: Vec::new(),
+        }
+    }
+
+    /// Also require the sources to match one of the [glob_regex], to scope a run to a few files.
+    pub fn with_only(self, only: Vec<regex::Regex>) -> SourceFilter {
+        SourceFilter { only, ..self }
+    }
+
+    pub fn keep(&self, source: &Source) -> bool {
+        let path = source.get_relative();
+        (self.include.is_empty() || self.include.iter().any(|re| re.is_match(&path)))
+            && !self.exclude.iter().any(|re| re.is_match(&path))
+            && (self.only.is_empty() || self.only.iter().any(|re| re.is_match(&path)))
+    }
+
+    pub fn apply(&self, sources: Vec<Source>) -> Vec<Source> {
+        sources
+            .into_iter()
+            .filter(|source| self.keep(source))
+            .collect()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty() && self.only.is_empty()
+    }
+}
+
+impl PartialEq for SourceFilter {
+    fn eq(&self, other: &SourceFilter) -> bool {
+        SourceFilterPatterns::from(self.clone()) == SourceFilterPatterns::from(other.clone())
+    }
+}
+
+impl std::fmt::Display for SourceFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let args = [
+            ("include", &self.include),
+            ("exclude", &self.exclude),
+            ("only", &self.only),
+        ];
+        let args = args.iter().flat_map(|(name, regexes)| {
+            regexes
+                .iter()
+                .map(move |re| format!("--{} {:?}", name, re.as_str()))
+        });
+        write!(f, "{}", args.format(" "))
+    }
+}
+
+/// The serialized form of a [SourceFilter].
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SourceFilterPatterns {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    only: Vec<String>,
+}
+
+impl From<SourceFilter> for SourceFilterPatterns {
+    fn from(filter: SourceFilter) -> SourceFilterPatterns {
+        let patterns =
+            |regexes: Vec<regex::Regex>| regexes.iter().map(|re| re.as_str().to_string()).collect();
+        SourceFilterPatterns {
+            include: patterns(filter.include),
+            exclude: patterns(filter.exclude),
+            only: patterns(filter.only),
+        }
+    }
+}
+
+impl TryFrom<SourceFilterPatterns> for SourceFilter {
+    type Error = regex::Error;
+
+    fn try_from(patterns: SourceFilterPatterns) -> Result<SourceFilter, regex::Error> {
+        let regexes = |patterns: Vec<String>| {
+            patterns
+                .iter()
+                .map(|pattern| regex::Regex::new(pattern))
+                .collect::<Result<Vec<_>, _>>()
+        };
+        Ok(SourceFilter {
+            include: regexes(patterns.include)?,
+            exclude: regexes(patterns.exclude)?,
+            only: regexes(patterns.only)?,
+        })
+    }
+}
+
+/// Convert a path glob to a regex: `*` and `?` do not match a `/`, `**` matches any path, and a
+/// glob without a `/` matches the file name in any directory.
+/// The glob is relative to the source root, and the relative paths of the local files start with
+/// a `/`, so the leading `/` is optional.
+pub fn glob_regex(glob: &str) -> regex::Regex {
+    let mut re = String::from(if glob.contains('/') { "^/?" } else { "^(.*/)?" });
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '*' if chars.peek() == Some(&'*') => {
+                chars.next();
+                if chars.peek() == Some(&'/') {
+                    // `**/` also matches no directory.
+                    chars.next();
+                    re.push_str("(.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
             }
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            c => re.push_str(&regex::escape(&c.to_string())),
         }
-        Ok(groups)
     }
+    re.push('$');
+    regex::Regex::new(&re).expect("The glob characters are escaped")
+}
+
+#[test]
+fn test_glob_regex() {
+    let re = glob_regex("controller/logs/screen-n-*.txt.gz");
+    assert!(re.is_match("controller/logs/screen-n-cpu.txt.gz"));
+    assert!(!re.is_match("controller/logs/screen-n-cpu.txt"));
+    assert!(!re.is_match("controller/logs/sub/screen-n-cpu.txt.gz"));
+    assert!(glob_regex("**/job-output.txt").is_match("controller/logs/job-output.txt"));
+    assert!(glob_regex("**/job-output.txt").is_match("job-output.txt"));
+    assert!(!glob_regex("**/job-output.txt").is_match("controller/my-job-output.txt"));
+    assert!(glob_regex("controller/*.txt").is_match("/controller/job-output.txt"));
+    assert!(glob_regex("/controller/*.txt").is_match("controller/job-output.txt"));
+    assert!(glob_regex("job-output.txt").is_match("job-output.txt"));
+    assert!(glob_regex("job-output.txt").is_match("controller/job-output.txt"));
+    assert!(!glob_regex("job-output.txt").is_match("controller/job-output.txt.gz"));
+    assert!(!glob_regex("job-output?txt").is_match("job-output/txt"));
+}
+
+#[test]
+fn test_source_filter() {
+    let source = |path: &str| Source::Local(0, PathBuf::from(path));
+    let re = |s: &str| regex::Regex::new(s).unwrap();
+    let filter = SourceFilter::new(vec![re("^controller/")], vec![re(r"\.json$")]);
+    assert!(filter.keep(&source("controller/job-output.txt")));
+    assert!(!filter.keep(&source("controller/job-output.json")));
+    assert!(!filter.keep(&source("compute/job-output.txt")));
+    assert!(SourceFilter::default().keep(&source("compute/job-output.txt")));
+    let only = filter.with_only(vec![glob_regex("controller/*.txt")]);
+    assert!(only.keep(&source("controller/job-output.txt")));
+    assert!(only.keep(&source("/controller/job-output.txt")));
+    assert!(!only.keep(&source("controller/logs/screen-n-cpu.txt")));
+
+    let bytes = bincode::serialize(&only).unwrap();
+    let loaded: SourceFilter = bincode::deserialize(&bytes).unwrap();
+    assert_eq!(loaded, only);
+    assert!(loaded.keep(&source("controller/job-output.txt")));
+    assert_ne!(loaded, SourceFilter::default());
+    assert!(SourceFilter::default().is_empty());
+    assert_eq!(
+        only.to_string(),
+        r#"--include "^controller/" --exclude "\.json$" --only "^controller/[^/]*\.txt$""#
+    );
+}
+
+#[test]
+fn test_anonymize() {
+    let url = Url::parse("https://zuul.example.com/logs/42/controller/job-output.txt").unwrap();
+    let source = Source::Remote("https://zuul.example.com/logs/42/".len(), url);
+    assert_eq!(source.anonymize().as_str(), "controller/job-output.txt");
+    assert_eq!(
+        IndexName::from_source(&source.anonymize()),
+        IndexName::from_source(&source)
+    );
+}
+
+#[test]
+fn test_add_global_group() {
+    let source = |path: &str| Source::Local(0, PathBuf::from(path));
+    let mut groups = Source::group_by_index(vec![
+        source("controller/job-output.txt"),
+        source("compute/syslog.txt"),
+    ]);
+    Content::add_global_group(&mut groups);
+    assert_eq!(groups.len(), 3);
+    assert_eq!(
+        groups[&IndexName::global()],
+        vec![
+            source("compute/syslog.txt"),
+            source("controller/job-output.txt")
+        ]
+    );
 }
 
 impl Model {
     /// Create a Model from baselines.
-    #[tracing::instrument(level = "debug", skip(mk_index, output_mode))]
+    #[tracing::instrument(level = "debug", skip(mk_index, progress))]
     pub fn train(
-        output_mode: OutputMode,
+        progress: &dyn ProgressObserver,
+        baselines: Baselines,
+        mk_index: impl Fn() -> ChunkIndex,
+    ) -> Result<Model> {
+        Model::train_with(progress, baselines, &SourceFilter::default(), mk_index)
+    }
+
+    /// Create a Model from the baseline sources selected by the filter. The filter is recorded
+    /// in the model, so that the updates and the inspections select the same sources.
+    pub fn train_with(
+        progress: &dyn ProgressObserver,
+        baselines: Baselines,
+        filter: &SourceFilter,
+        mk_index: impl Fn() -> ChunkIndex,
+    ) -> Result<Model> {
+        let groups = Content::group_sources_with(&baselines, filter)?;
+        Model::train_groups(progress, baselines, groups, mk_index)
+            .map(|model| model.with_source_filter(filter.clone()))
+    }
+
+    /// Create a Model from the sources already grouped, see [Content::group_sources_with].
+    #[tracing::instrument(level = "debug", skip(mk_index, progress, groups))]
+    pub fn train_groups(
+        progress: &dyn ProgressObserver,
         baselines: Baselines,
-        mk_index: fn() -> ChunkIndex,
+        mut groups: HashMap<IndexName, Vec<Source>>,
+        mk_index: impl Fn() -> ChunkIndex,
     ) -> Result<Model> {
         let created_at = SystemTime::now();
+        let start_time = Instant::now();
+        // The global index is trained with the lines of the other indexes.
+        let global_sources = groups.remove(&IndexName::global());
+        let mut global_index = mk_index().for_index(&IndexName::global());
+        let mut global_trainer = match &global_sources {
+            Some(sources) => Some(process::ChunkTrainer::new(
+                &mut global_index,
+                is_json(sources),
+            )),
+            None => None,
+        };
         let mut indexes = HashMap::new();
-        for (index_name, sources) in Content::group_sources(&baselines)?.drain() {
-            debug_or_progress(
-                output_mode,
-                &format!(
-                    "Loading index {} with {}",
-                    index_name,
-                    sources.iter().format(", ")
-                ),
-            );
-            let index = Index::train(&sources, mk_index())?;
+        for (index_name, sources) in groups.drain() {
+            if progress.is_cancelled() {
+                return Err(cancel::Cancelled.into());
+            }
+            progress.message(&format!(
+                "Loading index {} with {}",
+                index_name,
+                sources.iter().format(", ")
+            ));
+            let index = Index::train_with(
+                progress,
+                &sources,
+                mk_index().for_index(&index_name),
+                global_trainer.as_mut(),
+            )?;
             indexes.insert(index_name, index);
         }
+        if let Some(mut trainer) = global_trainer {
+            trainer.complete();
+        }
+        if let Some(sources) = global_sources {
+            let global = Index {
+                created_at,
+                train_time: start_time.elapsed(),
+                line_count: indexes.values().map(|index| index.line_count).sum(),
+                byte_count: indexes.values().map(|index| index.byte_count).sum(),
+                index: global_index,
+                sources,
+                context_mode: process::ContextMode::default(),
+            };
+            indexes.insert(IndexName::global(), global);
+        }
         Ok(Model {
             created_at,
             baselines,
             indexes,
+            tags: tags::Tags::new(),
+            shard_names: Vec::new(),
+            source_filter: SourceFilter::default(),
+            shards: Shards::default(),
+            fallback_index: None,
+            source_timeout: None,
+            path: Default::default(),
         })
     }
 
-    pub fn load(path: &Path) -> Result<Model> {
-        tracing::info!(path = path.to_str(), "Loading provided model");
-        bincode::deserialize_from(flate2::read::GzDecoder::new(
-            std::fs::File::open(path).context("Can't open file")?,
-        ))
-        .context("Can't load model")
+    /// Create a Model from a pre-tokenized corpus, see [tokens]. The corpus is read once to
+    /// list the sources of each index, and once to train all the indexes.
+    pub fn train_tokenized(
+        progress: &dyn ProgressObserver,
+        path: &Path,
+        mk_index: impl Fn() -> ChunkIndex,
+    ) -> Result<Model> {
+        let created_at = SystemTime::now();
+        let start_time = Instant::now();
+        let groups: Vec<(IndexName, Vec<Source>)> = tokens::sources(path)?.into_iter().collect();
+        progress.message(&format!(
+            "Loading {} indexes from {}",
+            groups.len(),
+            path.display()
+        ));
+        let mut chunk_indexes: Vec<ChunkIndex> = groups.iter().map(|_| mk_index()).collect();
+        let mut trainers: Vec<process::ChunkTrainer> = chunk_indexes
+            .iter_mut()
+            .map(|index| process::ChunkTrainer::new(index, false))
+            .collect();
+        // The trainer of each source, with the hashes of the lines already seen in the source.
+        let mut source_trainers: HashMap<String, (usize, HashSet<u64>, &Source)> = HashMap::new();
+        for (pos, (_, sources)) in groups.iter().enumerate() {
+            for source in sources {
+                let name = source.get_relative().to_string();
+                source_trainers.insert(name, (pos, HashSet::new(), source));
+            }
+        }
+        // The lines of a source are contiguous, see [tokens::Writer::write_source].
+        let mut current: Option<(&Source, usize, usize)> = None;
+        tokens::for_each(path, |name, line| {
+            if progress.is_cancelled() {
+                return Err(cancel::Cancelled.into());
+            }
+            let (pos, reader_lines, source) = source_trainers
+                .get_mut(&name)
+                .ok_or_else(|| anyhow::anyhow!("{}: unknown source", name))?;
+            let source: &Source = *source;
+            match &mut current {
+                Some((started, line_count, byte_count)) if std::ptr::eq(*started, source) => {
+                    *line_count += 1;
+                    *byte_count += line.len();
+                }
+                _ => {
+                    if let Some((previous, line_count, byte_count)) = current {
+                        source_read(progress, previous, line_count, byte_count);
+                    }
+                    progress.source_started(source);
+                    current = Some((source, 1, line.len()));
+                }
+            }
+            trainers[*pos].add_token_line(line, reader_lines);
+            Ok(())
+        })?;
+        if let Some((source, line_count, byte_count)) = current {
+            source_read(progress, source, line_count, byte_count);
+        }
+        let stats: Vec<_> = trainers
+            .into_iter()
+            .map(|mut trainer| {
+                trainer.complete();
+                (trainer.line_count, trainer.byte_count)
+            })
+            .collect();
+        let train_time = start_time.elapsed();
+        let indexes = groups
+            .into_iter()
+            .zip(chunk_indexes)
+            .zip(stats)
+            .map(
+                |(((index_name, sources), index), (line_count, byte_count))| {
+                    let index = Index {
+                        created_at,
+                        train_time,
+                        line_count,
+                        byte_count,
+                        index,
+                        sources,
+                        context_mode: process::ContextMode::default(),
+                    };
+                    (index_name, index)
+                },
+            )
+            .collect();
+        Ok(Model {
+            created_at,
+            baselines: vec![Content::File(Source::from_pathbuf(path.to_path_buf()))],
+            indexes,
+            tags: tags::Tags::new(),
+            shard_names: Vec::new(),
+            source_filter: SourceFilter::default(),
+            shards: Shards::default(),
+            fallback_index: None,
+            source_timeout: None,
+            path: Default::default(),
+        })
     }
 
-    pub fn save(&self, path: &Path) -> Result<()> {
-        tracing::info!(path = path.to_str(), "Saving model");
-        bincode::serialize_into(
-            flate2::write::GzEncoder::new(
-                std::fs::File::create(path).context("Can't create file")?,
-                flate2::Compression::fast(),
-            ),
-            self,
-        )
-        .context("Can't save model")
+    /// Add new baselines to the model, only the lines that are not already known are indexed.
+    #[tracing::instrument(level = "debug", skip(self, mk_index, progress))]
+    pub fn update(
+        &mut self,
+        progress: &dyn ProgressObserver,
+        baselines: Baselines,
+        mk_index: impl Fn() -> ChunkIndex,
+    ) -> Result<()> {
+        let mut groups = Content::group_sources_with(&baselines, &self.source_filter)?;
+        // The global index is updated with the lines of the other indexes, so that the sources
+        // without baselines are inspected with the new baselines too.
+        let start_time = Instant::now();
+        let mut global = self.take_index(&IndexName::global())?;
+        let mut global_sources = Vec::new();
+        let mut global_trainer = global.as_mut().map(|global| {
+            let is_json = is_json(&global.sources);
+            process::ChunkTrainer::new(&mut global.index, is_json)
+        });
+        for (index_name, sources) in groups.drain() {
+            if progress.is_cancelled() {
+                return Err(cancel::Cancelled.into());
+            }
+            progress.message(&format!(
+                "Updating index {} with {}",
+                index_name,
+                sources.iter().format(", ")
+            ));
+            if global_trainer.is_some() {
+                global_sources.extend(sources.iter().cloned());
+            }
+            match self.index_mut(&index_name)? {
+                Some(index) => index.update_with(progress, &sources, global_trainer.as_mut())?,
+                None => {
+                    let index = Index::train_with(
+                        progress,
+                        &sources,
+                        mk_index().for_index(&index_name),
+                        global_trainer.as_mut(),
+                    )?;
+                    self.indexes.insert(index_name, index);
+                }
+            }
+        }
+        if let Some(mut trainer) = global_trainer {
+            trainer.complete();
+        }
+        if let Some(mut global) = global {
+            global_sources.sort_by(|x, y| x.as_str().cmp(y.as_str()));
+            global.sources.extend(global_sources);
+            global.train_time += start_time.elapsed();
+            global.line_count = self.global_counts(|index| index.line_count)?;
+            global.byte_count = self.global_counts(|index| index.byte_count)?;
+            self.indexes.insert(IndexName::global(), global);
+        }
+        self.baselines.extend(baselines);
+        Ok(())
+    }
+
+    /// Remove an index to update it with the other ones, see [Model::update].
+    fn take_index(&mut self, index_name: &IndexName) -> Result<Option<Index>> {
+        if let Some(index) = self.indexes.remove(index_name) {
+            return Ok(Some(index));
+        }
+        match self.shards.cells.remove(index_name) {
+            Some(cell) => match cell.into_inner() {
+                Some(index) => Ok(Some(index)),
+                None => self.shards.load(index_name).map(Some),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// The sum of a count of the indexes, without the global index.
+    fn global_counts(&self, count: impl Fn(&Index) -> usize) -> Result<usize> {
+        let mut total = 0;
+        for index_name in self.index_names() {
+            if index_name != &IndexName::global() {
+                total += self.load_index(index_name)?.map_or(0, &count);
+            }
+        }
+        Ok(total)
+    }
+
+    /// Inspect the sources without baselines with this index, e.g. [IndexName::global].
+    pub fn set_fallback_index(&mut self, fallback_index: Option<IndexName>) {
+        self.fallback_index = fallback_index;
+    }
+
+    fn index_mut(&mut self, index_name: &IndexName) -> Result<Option<&mut Index>> {
+        if let Some(index) = self.indexes.get_mut(index_name) {
+            return Ok(Some(index));
+        }
+        match self.shards.cells.get(index_name) {
+            Some(cell) => {
+                if cell.get().is_none() {
+                    let _ = cell.set(self.shards.load(index_name)?);
+                }
+                Ok(self
+                    .shards
+                    .cells
+                    .get_mut(index_name)
+                    .and_then(|cell| cell.get_mut()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Set the platform attributes of the model, see [tags::select].
+    pub fn with_tags(self, tags: tags::Tags) -> Model {
+        Model { tags, ..self }
+    }
+
+    pub fn tags(&self) -> &tags::Tags {
+        &self.tags
+    }
+
+    /// Set the selection of the baseline sources, when they are grouped by the caller.
+    pub fn with_source_filter(self, source_filter: SourceFilter) -> Model {
+        Model {
+            source_filter,
+            ..self
+        }
+    }
+
+    pub fn source_filter(&self) -> &SourceFilter {
+        &self.source_filter
+    }
+
+    /// Set how the anomaly context is collected when inspecting.
+    pub fn set_context_mode(&mut self, context_mode: process::ContextMode) {
+        self.indexes
+            .values_mut()
+            .for_each(|index| index.context_mode = context_mode);
+        self.shards.context_mode = context_mode;
+        self.shards
+            .cells
+            .values_mut()
+            .filter_map(|cell| cell.get_mut())
+            .for_each(|index| index.context_mode = context_mode);
+    }
+
+    /// Lower the distance of the lines close to a baseline line seen in many sources.
+    pub fn set_frequency_weight(&mut self, enabled: bool) {
+        self.indexes
+            .values_mut()
+            .for_each(|index| index.set_frequency_weight(enabled));
+        self.shards.frequency_weight = enabled;
+        self.shards
+            .cells
+            .values_mut()
+            .filter_map(|cell| cell.get_mut())
+            .for_each(|index| index.set_frequency_weight(enabled));
+    }
+
+    /// Combine several models, the indexes that have the same name are searched together.
+    pub fn ensemble(models: Vec<Model>, aggregation: Aggregation) -> Result<Model> {
+        let mut created_at = SystemTime::UNIX_EPOCH;
+        let mut baselines = Vec::new();
+        let mut indexes: HashMap<IndexName, Index> = HashMap::new();
+        for model in models {
+            created_at = created_at.max(model.created_at);
+            let (model_baselines, model_indexes) = model.into_parts()?;
+            baselines.extend(model_baselines);
+            for (index_name, index) in model_indexes {
+                let index = match indexes.remove(&index_name) {
+                    Some(previous) => previous.merge(index, aggregation),
+                    None => index,
+                };
+                indexes.insert(index_name, index);
+            }
+        }
+        Ok(Model {
+            created_at,
+            baselines,
+            indexes,
+            tags: tags::Tags::new(),
+            shard_names: Vec::new(),
+            source_filter: SourceFilter::default(),
+            shards: Shards::default(),
+            fallback_index: None,
+            source_timeout: None,
+            path: Default::default(),
+        })
+    }
+
+    /// Get the baselines and every indexes, including the shards that are not yet loaded.
+    fn into_parts(self) -> Result<(Baselines, HashMap<IndexName, Index>)> {
+        let mut indexes = self.indexes;
+        let mut shards = self.shards;
+        for (index_name, cell) in std::mem::take(&mut shards.cells) {
+            let index = match cell.into_inner() {
+                Some(index) => index,
+                None => shards.load(&index_name)?,
+            };
+            indexes.insert(index_name, index);
+        }
+        Ok((self.baselines, indexes))
+    }
+
+    /// Load a model file, or a sharded model directory whose indexes are loaded on demand.
+    pub fn load(path: &Path) -> Result<Model> {
+        tracing::info!(path = path.to_str(), "Loading provided model");
+        let model_path = if path.is_dir() {
+            path.join(SHARDED_MODEL)
+        } else {
+            path.to_path_buf()
+        };
+        let mut model = Model::from_reader(flate2::read::GzDecoder::new(
+            std::fs::File::open(model_path).context("Can't open file")?,
+        ))?;
+        model.path = std::sync::Mutex::new(Some(path.to_path_buf()));
+        if !model.shard_names.is_empty() {
+            model.shards = Shards {
+                dir: path.to_path_buf(),
+                cells: model
+                    .shard_names
+                    .iter()
+                    .map(|index_name| (index_name.clone(), once_cell::sync::OnceCell::new()))
+                    .collect(),
+                context_mode: process::ContextMode::default(),
+                frequency_weight: false,
+            };
+        }
+        Ok(model)
+    }
+
+    /// Deserialize a model from an uncompressed reader.
+    pub fn from_reader(mut reader: impl std::io::Read) -> Result<Model> {
+        read_header(&mut reader)?;
+        let (mut model, shard_names, indexes): ModelFile =
+            bincode::deserialize_from(reader).context("Can't load model")?;
+        model.shard_names = shard_names;
+        model.indexes = indexes;
+        Ok(model)
+    }
+
+    /// The baselines used to train the model.
+    pub fn baselines(&self) -> &[Content] {
+        &self.baselines
+    }
+
+    /// A sha256 digest of the model file and of its shards, as they are stored, to identify it in
+    /// provenance records. This is None when the model is not loaded from or saved to a file.
+    pub fn hash(&self) -> Result<Option<String>> {
+        use sha2::Digest;
+        let path = match self.path.lock().expect("Model path lock").clone() {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        let mut paths = vec![];
+        if path.is_dir() {
+            // Every index is stored in a shard, see [Model::save_shards].
+            paths.push(path.join(SHARDED_MODEL));
+            paths.extend(
+                self.index_names()
+                    .sorted()
+                    .map(|index_name| Shards::path(&path, index_name)),
+            );
+        } else {
+            paths.push(path);
+        }
+        let mut hasher = sha2::Sha256::new();
+        for path in paths {
+            std::io::copy(
+                &mut std::fs::File::open(&path)
+                    .with_context(|| format!("Can't open {:?}", path))?,
+                &mut hasher,
+            )
+            .context("Can't hash model")?;
+        }
+        Ok(Some(format!("{:x}", hasher.finalize())))
+    }
+
+    /// Save the model to a file, or to a sharded directory when the path is a directory.
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if path.is_dir() || path.to_string_lossy().ends_with(std::path::MAIN_SEPARATOR) {
+            return self.save_shards(path);
+        }
+        tracing::info!(path = path.to_str(), "Saving model");
+        write_gz(path, &(self, &self.shard_names, &self.indexes)).context("Can't save model")?;
+        *self.path.lock().expect("Model path lock") = Some(path.to_path_buf());
+        Ok(())
+    }
+
+    /// Save the model with one file per index, so that the inspection only loads the
+    /// indexes it needs.
+    pub fn save_shards(&self, dir: &Path) -> Result<()> {
+        tracing::info!(path = dir.to_str(), "Saving sharded model");
+        std::fs::create_dir_all(dir).context("Can't create model directory")?;
+        // Only the shards of the previous model are removed, the other files are kept.
+        let previous_names = match std::fs::File::open(dir.join(SHARDED_MODEL)) {
+            Ok(file) => Model::from_reader(flate2::read::GzDecoder::new(file))
+                .map(|previous| previous.shard_names)
+                .unwrap_or_default(),
+            Err(_) => Vec::new(),
+        };
+        let mut shard_names = Vec::new();
+        for index_name in self.index_names() {
+            let index = self
+                .load_index(index_name)?
+                .ok_or_else(|| anyhow::anyhow!("Missing index {}", index_name))?;
+            write_gz(&Shards::path(dir, index_name), index).context("Can't save shard")?;
+            shard_names.push(index_name);
+        }
+        let indexes: HashMap<IndexName, Index> = HashMap::new();
+        write_gz(&dir.join(SHARDED_MODEL), &(self, &shard_names, &indexes))
+            .context("Can't save model")?;
+
+        *self.path.lock().expect("Model path lock") = Some(dir.to_path_buf());
+
+        // Remove the shards of the dropped indexes.
+        for index_name in previous_names {
+            if !shard_names.contains(&&index_name) {
+                match std::fs::remove_file(Shards::path(dir, &index_name)) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                        return Err(e).context("Can't remove shard")
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove the indexes whose name matches, and return their names.
+    pub fn drop_indexes(&mut self, matches: impl Fn(&IndexName) -> bool) -> Vec<IndexName> {
+        let dropped = self
+            .index_names()
+            .filter(|index_name| matches(index_name))
+            .cloned()
+            .collect::<Vec<_>>();
+        for index_name in &dropped {
+            self.indexes.remove(index_name);
+            self.shards.cells.remove(index_name);
+        }
+        self.shard_names
+            .retain(|index_name| !dropped.contains(index_name));
+        dropped
+    }
+
+    /// Remove the oldest baselines of every index to keep at most `max_lines` lines.
+    pub fn truncate_indexes(&mut self, max_lines: usize) -> Result<()> {
+        for (index_name, cell) in self.shards.cells.iter() {
+            cell.get_or_try_init(|| self.shards.load(index_name))?;
+        }
+        self.indexes
+            .values_mut()
+            .chain(
+                self.shards
+                    .cells
+                    .values_mut()
+                    .filter_map(|cell| cell.get_mut()),
+            )
+            .for_each(|index| index.truncate(max_lines));
+        Ok(())
+    }
+
+    /// Remove the baselines locations, so that the model only stores the hashed features, the
+    /// index names and the tags, see [privacy::audit].
+    pub fn anonymize(&mut self) -> Result<()> {
+        for (index_name, cell) in self.shards.cells.iter() {
+            cell.get_or_try_init(|| self.shards.load(index_name))?;
+        }
+        self.baselines.clear();
+        self.indexes
+            .values_mut()
+            .chain(
+                self.shards
+                    .cells
+                    .values_mut()
+                    .filter_map(|cell| cell.get_mut()),
+            )
+            .for_each(|index| index.anonymize());
+        Ok(())
+    }
+
+    pub fn index_names(&self) -> impl Iterator<Item = &IndexName> {
+        self.indexes.keys().chain(self.shards.cells.keys())
+    }
+
+    /// Stop inspecting a source after the timeout, the anomalies already found are reported.
+    pub fn set_source_timeout(&mut self, timeout: Option<Duration>) {
+        self.source_timeout = timeout;
     }
 
     /// Get the matching index for a given Source.
     pub fn get_index<'a>(&'a self, index_name: &IndexName) -> Option<&'a Index> {
-        lookup_or_single(&self.indexes, index_name)
+        let index = match (self.load_index(index_name), &self.fallback_index) {
+            (Ok(None), Some(fallback_index)) => self.load_index(fallback_index),
+            (index, _) => index,
+        };
+        match index {
+            Ok(index) => index,
+            Err(err) => {
+                tracing::error!("Can't load index {}: {:?}", index_name, err);
+                None
+            }
+        }
+    }
+
+    /// The baseline line count of each index, loading the shards.
+    pub fn line_counts(&self) -> Result<BTreeMap<IndexName, usize>> {
+        let mut counts = BTreeMap::new();
+        for index_name in self.index_names() {
+            if let Some(index) = self.load_index(index_name)? {
+                counts.insert(index_name.clone(), index.line_count());
+            }
+        }
+        Ok(counts)
+    }
+
+    /// Load all the shards, so that the first inspection of a cached model is not slower.
+    pub fn warm_up(&self) -> Result<()> {
+        for (index_name, cell) in self.shards.cells.iter() {
+            cell.get_or_try_init(|| self.shards.load(index_name))?;
+        }
+        Ok(())
+    }
+
+    /// An estimate of the memory used by the model and its loaded shards, in bytes.
+    pub fn memory_size(&self) -> Result<u64> {
+        let mut size = bincode::serialized_size(&(self, &self.shard_names, &self.indexes))
+            .context("Can't size model")?;
+        for cell in self.shards.cells.values() {
+            if let Some(index) = cell.get() {
+                size += bincode::serialized_size(index).context("Can't size index")?;
+            }
+        }
+        Ok(size)
+    }
+
+    /// Get the matching index, loading its shard on the first use.
+    fn load_index<'a>(&'a self, index_name: &IndexName) -> Result<Option<&'a Index>> {
+        if self.shards.cells.is_empty() {
+            return Ok(lookup_or_single(&self.indexes, index_name));
+        }
+        let cells = &self.shards.cells;
+        let index_name = match cells.get_key_value(index_name) {
+            Some((index_name, _)) => index_name,
+            None if cells.len() == 1 => cells.keys().next().expect("A single shard"),
+            None => return Ok(None),
+        };
+        cells[index_name]
+            .get_or_try_init(|| self.shards.load(index_name))
+            .map(Some)
     }
 
     /// Create the final report.
-    #[tracing::instrument(level = "debug", skip(output_mode, self))]
-    pub fn report(&self, output_mode: OutputMode, target: Content) -> Result<Report> {
+    #[tracing::instrument(level = "debug", skip(progress, self))]
+    pub fn report(&self, progress: &dyn ProgressObserver, target: Content) -> Result<Report> {
+        let sources = target.get_sources()?;
+        self.report_sources(progress, target, sources)
+    }
+
+    /// Create the final report for the selected sources of the target.
+    #[tracing::instrument(level = "debug", skip(progress, self, sources))]
+    pub fn report_sources(
+        &self,
+        progress: &dyn ProgressObserver,
+        target: Content,
+        sources: Vec<Source>,
+    ) -> Result<Report> {
         let start_time = Instant::now();
         let created_at = SystemTime::now();
         let mut index_reports = HashMap::new();
         let mut log_reports = Vec::new();
         let mut index_errors = Vec::new();
         let mut read_errors = Vec::new();
+        let mut warnings = Vec::new();
         let mut total_line_count = 0;
         let mut total_anomaly_count = 0;
-        for (index_name, sources) in Content::group_sources(&[target.clone()])?.drain() {
-            let mut skip_lines = HashSet::new();
+        'groups: for (index_name, sources) in Source::group_by_index(sources).drain() {
+            let fallback =
+                self.fallback_index.is_some() && matches!(self.load_index(&index_name), Ok(None));
             match self.get_index(&index_name) {
                 Some(index) => {
                     for source in sources {
+                        if progress.is_cancelled() {
+                            break 'groups;
+                        }
                         let start_time = Instant::now();
                         let mut anomalies = Vec::new();
-                        match index.get_processor(output_mode, &source, &mut skip_lines) {
-                            Ok(mut processor) => {
+                        let deadline = self.source_timeout.map(|timeout| start_time + timeout);
+                        // The repeated lines are only skipped in their own source.
+                        let mut skip_lines = HashSet::new();
+                        match index.get_processor(progress, &source, &mut skip_lines) {
+                            Ok(processor) => {
+                                let mut processor = processor
+                                    .with_deadline(deadline)
+                                    .with_index_name(index_name.clone());
                                 for anomaly in processor.by_ref() {
                                     match anomaly {
-                                        Ok(anomaly) => anomalies.push(anomaly),
+                                        Ok(anomaly) => {
+                                            progress.anomaly_found(&source, &anomaly);
+                                            anomalies.push(anomaly)
+                                        }
                                         Err(err) => {
                                             read_errors.push((source.clone(), format!("{}", err)));
                                             break;
                                         }
                                     }
                                 }
+                                process::merge_contexts(&mut anomalies);
+                                if processor.timed_out {
+                                    warnings.push(format!(
+                                        "{}: timed out after {} lines, the anomalies are partial",
+                                        source, processor.line_count
+                                    ));
+                                }
                                 total_line_count += processor.line_count;
+                                progress.lines_read(
+                                    &source,
+                                    processor.line_count,
+                                    processor.byte_count,
+                                );
+                                progress.source_finished(&source, processor.line_count);
+                                let repeats = processor.repeats();
+                                let mut retries = processor.retry_loops();
+                                let tasks = job_output::load(&source);
+                                for anomaly in anomalies.iter_mut() {
+                                    if let Some(count) = repeats.get(&anomaly.anomaly.pos) {
+                                        anomaly.anomaly.repeat = *count;
+                                    }
+                                    anomaly.anomaly.retry = retries.remove(&anomaly.anomaly.pos);
+                                    job_output::annotate(&tasks, &mut anomaly.anomaly);
+                                }
                                 if !anomalies.is_empty() {
                                     total_anomaly_count += anomalies.len();
                                     if !index_reports.contains_key(&index_name) {
@@ -500,21 +1996,33 @@ impl Model {
                                         anomalies,
                                         source,
                                         index_name: index_name.clone(),
+                                        fallback,
                                         line_count: processor.line_count,
                                         byte_count: processor.byte_count,
                                     });
                                 }
                             }
-                            Err(err) => {
-                                read_errors.push((source.clone(), format!("{}", err)));
-                                break;
-                            }
+                            // The next sources are still inspected.
+                            Err(err) => read_errors.push((source.clone(), format!("{}", err))),
                         }
                     }
                 }
                 None => index_errors.push(sources.clone()),
             }
         }
+        let mut reasons = Vec::new();
+        if progress.is_cancelled() {
+            reasons.push("the run was cancelled".to_string());
+        }
+        if !read_errors.is_empty() {
+            reasons.push(format!("{} source(s) could not be read", read_errors.len()));
+        }
+        let incomplete = if reasons.is_empty() {
+            None
+        } else {
+            Some(reasons.join(", "))
+        };
+        let coverage_gaps = coverage::gaps(&index_errors, self.index_names());
         Ok(Report {
             created_at,
             run_time: start_time.elapsed(),
@@ -523,22 +2031,132 @@ impl Model {
             log_reports,
             index_reports,
             index_errors,
+            coverage_gaps,
             read_errors,
             total_line_count,
             total_anomaly_count,
+            warnings,
+            incomplete,
         })
     }
 }
 
+#[test]
+fn test_incomplete_report() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("app.log");
+    std::fs::write(&path, "Starting service\nService started\n").unwrap();
+    let content = || Content::from_pathbuf(path.clone());
+    let model = Model::train(&OutputMode::Quiet, vec![content()], hashing_index::new).unwrap();
+    let report = model.report(&OutputMode::Quiet, content()).unwrap();
+    assert_eq!(report.incomplete, None);
+
+    let token = cancel::CancellationToken::new();
+    token.cancel();
+    let progress = cancel::Cancellable::new(OutputMode::Quiet, token);
+    let report = model.report(&progress, content()).unwrap();
+    assert_eq!(report.incomplete, Some("the run was cancelled".to_string()));
+    let source = Source::from_pathbuf(path.clone());
+    let report = model
+        .report_sources(&OutputMode::Quiet, content(), vec![source.clone(), source])
+        .unwrap();
+    assert_eq!(report.read_errors.len(), 2);
+    assert_eq!(
+        report.incomplete,
+        Some("2 source(s) could not be read".to_string())
+    );
+}
+
+#[test]
+fn test_report_sources_skip_lines() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    // The sources have the same index, see [IndexName::explain_path].
+    let baseline = dir.join("app.log.0");
+    std::fs::write(&baseline, "Starting service\nService started\n").unwrap();
+    let content = Content::from_pathbuf(baseline);
+    let model = Model::train(
+        &OutputMode::Quiet,
+        vec![content.clone()],
+        hashing_index::new,
+    )
+    .unwrap();
+    let targets = ["app.log", "app.log.1"]
+        .iter()
+        .map(|name| {
+            let path = dir.join(name);
+            std::fs::write(&path, "Starting service\nkernel panic\n").unwrap();
+            Source::from_pathbuf(path)
+        })
+        .collect::<Vec<_>>();
+    let report = model
+        .report_sources(&OutputMode::Quiet, content, targets)
+        .unwrap();
+    // The anomaly of the first source is also reported in the second one.
+    assert_eq!(report.log_reports.len(), 2);
+    assert!(report
+        .log_reports
+        .iter()
+        .all(|log_report| log_report.anomalies.len() == 1));
+}
+
+/// The length of the last progress message, to clear it without the escape sequences.
+static PROGRESS_LEN: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
 /// Helper function to debug
 pub fn debug_or_progress(output_mode: OutputMode, msg: &str) {
     match output_mode {
-        OutputMode::FastTerminal => print!("\r\x1b[1;33m[+]\x1b[0m {}", msg),
+        // The legacy Windows console does not support the escape sequences, the rest of the
+        // previous message is overwritten with spaces.
+        OutputMode::FastTerminal if cfg!(windows) => {
+            let len = msg.chars().count();
+            let previous = PROGRESS_LEN.swap(len, std::sync::atomic::Ordering::Relaxed);
+            print!("\r[+] {}{}", msg, " ".repeat(previous.saturating_sub(len)))
+        }
+        OutputMode::FastTerminal => print!("\r\x1b[1;33m[+]\x1b[0m {}\x1b[K", msg),
         OutputMode::Debug => tracing::debug!("{}", msg),
         OutputMode::Quiet => {}
     }
 }
 
+/// The jaccard index of two IndexName sets, 1.0 means the sets are identical.
+fn overlap_score(a: &HashSet<IndexName>, b: &HashSet<IndexName>) -> f32 {
+    let union = a.union(b).count();
+    if union == 0 {
+        0.0
+    } else {
+        a.intersection(b).count() as f32 / union as f32
+    }
+}
+
+#[test]
+fn test_overlap_score() {
+    let names = |xs: &[&str]| {
+        xs.iter()
+            .map(|x| IndexName(x.to_string()))
+            .collect::<HashSet<_>>()
+    };
+    let target = names(&["job-output.txt", "zuul/merger.log"]);
+    assert_eq!(overlap_score(&target, &target), 1.0);
+    assert_eq!(overlap_score(&target, &names(&["job-output.txt"])), 0.5);
+    assert_eq!(overlap_score(&target, &names(&[])), 0.0);
+}
+
+/// Write a value with bincode in a gzip file, after the format header, see [Model::save].
+fn write_gz<T: Serialize + ?Sized>(path: &Path, value: &T) -> Result<()> {
+    use std::io::Write;
+    let mut writer = flate2::write::GzEncoder::new(
+        std::fs::File::create(path).context("Can't create file")?,
+        flate2::Compression::fast(),
+    );
+    writer.write_all(FORMAT_MAGIC)?;
+    writer.write_all(&FORMAT_VERSION.to_le_bytes())?;
+    bincode::serialize_into(&mut writer, value)?;
+    writer.finish()?;
+    Ok(())
+}
+
 /// Helper function to make a single value hash map always match the key.
 /// This is useful when logreduce is used to compare two files which may have different index name.
 fn lookup_or_single<'a, K: Eq + std::hash::Hash, V>(hm: &'a HashMap<K, V>, k: &K) -> Option<&'a V> {
@@ -559,55 +2177,755 @@ fn lookup_or_single<'a, K: Eq + std::hash::Hash, V>(hm: &'a HashMap<K, V>, k: &K
 pub enum ChunkIndex {
     HashingTrick(hashing_index::HashingIndex),
     Noop,
+    /// The indexes of multiple models, see [Model::ensemble].
+    Ensemble(Vec<ChunkIndex>, Aggregation),
+    /// A sentence-embedding model index, see the `embedding` feature. This is the last variant,
+    /// so that the stored indexes are the same with or without the feature.
+    #[cfg(feature = "embedding")]
+    Embedding(embedding_index::EmbeddingIndex),
+}
+
+/// How the index features are stored.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Precision {
+    #[default]
+    F32,
+    /// The 8-bit features, which are searched directly, the index is several times smaller.
+    Int8,
+}
+
+impl std::str::FromStr for Precision {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "f32" => Ok(Precision::F32),
+            "int8" => Ok(Precision::Int8),
+            _ => Err(format!("Unknown precision: {} (expected f32 or int8)", s)),
+        }
+    }
+}
+
+/// How the distances of an ensemble are combined.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aggregation {
+    /// The closest model wins.
+    #[default]
+    Min,
+    Mean,
+    Max,
+}
+
+impl std::str::FromStr for Aggregation {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "min" => Ok(Aggregation::Min),
+            "mean" => Ok(Aggregation::Mean),
+            "max" => Ok(Aggregation::Max),
+            _ => Err(format!(
+                "Unknown aggregation: {} (expected min, mean or max)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Display for Aggregation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Aggregation::Min => write!(f, "min"),
+            Aggregation::Mean => write!(f, "mean"),
+            Aggregation::Max => write!(f, "max"),
+        }
+    }
+}
+
+impl Aggregation {
+    /// Combine the distances of each member, per target.
+    fn combine(&self, distances: Vec<Vec<f32>>) -> Vec<f32> {
+        let count = distances.first().map_or(0, |xs| xs.len());
+        (0..count)
+            .map(|pos| {
+                let xs = distances.iter().map(|member| member[pos]);
+                match self {
+                    Aggregation::Min => xs.fold(f32::MAX, f32::min),
+                    Aggregation::Mean => xs.sum::<f32>() / distances.len() as f32,
+                    Aggregation::Max => xs.fold(0.0, f32::max),
+                }
+            })
+            .collect()
+    }
+}
+
+#[test]
+fn test_aggregation() {
+    let distances = || vec![vec![0.2, 0.0, 0.6], vec![0.4, 0.5, 0.6]];
+    assert_eq!(Aggregation::Min.combine(distances()), vec![0.2, 0.0, 0.6]);
+    assert_eq!(Aggregation::Mean.combine(distances()), vec![0.3, 0.25, 0.6]);
+    assert_eq!(Aggregation::Max.combine(distances()), vec![0.4, 0.5, 0.6]);
+}
+
+#[test]
+fn test_ensemble_tokenize() {
+    let line = "Connection refused to the database host";
+    let train = |mut index: ChunkIndex| {
+        let tokens = index.tokenize(line);
+        index.add(&[tokens]);
+        index
+    };
+    let members = vec![
+        train(hashing_index::new()),
+        train(hashing_index::new_with_vectorizer(
+            Metric::default(),
+            crate::ngram::Vectorizer::Trigrams,
+        )),
+    ];
+    let ensemble = ChunkIndex::Ensemble(members, Aggregation::Max);
+    // Each member searches its own tokens, so that the line is known by every member.
+    let distances = ensemble.search(&[ensemble.tokenize(line)]);
+    assert!(distances[0] < 0.01, "{:?}", distances);
 }
 
 /// An API to work with chunks of logs instead of individual line.
 impl ChunkIndex {
-    fn tokenize(&self, line: &str) -> String {
+    /// Read the pretty-printed json objects as single lines, see [BytesLines::with_json_blocks].
+    ///
+    /// [BytesLines::with_json_blocks]: logreduce_iterator::BytesLines::with_json_blocks
+    pub fn with_json_blocks(self, enabled: bool) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                i.json_blocks = enabled;
+                ChunkIndex::HashingTrick(i)
+            }
+            ChunkIndex::Ensemble(members, aggregation) => ChunkIndex::Ensemble(
+                members
+                    .into_iter()
+                    .map(|member| member.with_json_blocks(enabled))
+                    .collect(),
+                aggregation,
+            ),
+            index => index,
+        }
+    }
+
+    /// Down-weight the common tokens of each index, see [hashing_index::HashingIndex::damp].
+    pub fn with_damping(self, enabled: bool) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                i.damping = enabled;
+                ChunkIndex::HashingTrick(i)
+            }
+            index => index,
+        }
+    }
+
+    pub(crate) fn json_blocks(&self) -> bool {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.json_blocks,
+            ChunkIndex::Ensemble(members, _) => members.iter().any(|member| member.json_blocks()),
+            _ => false,
+        }
+    }
+
+    /// Strip the constant prefix of the lines of each source before the vectorization.
+    pub fn with_strip_prefix(self, enabled: bool) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                i.strip_prefix = enabled;
+                ChunkIndex::HashingTrick(i)
+            }
+            index => index,
+        }
+    }
+
+    pub(crate) fn strip_prefix(&self) -> bool {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.strip_prefix,
+            ChunkIndex::Ensemble(members, _) => members.iter().any(|member| member.strip_prefix()),
+            _ => false,
+        }
+    }
+
+    /// Classify each source to read it with a fitting profile, see [profile].
+    pub fn with_source_profiles(self, enabled: bool) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                i.source_profiles = enabled;
+                ChunkIndex::HashingTrick(i)
+            }
+            index => index,
+        }
+    }
+
+    pub(crate) fn source_profiles(&self) -> bool {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.source_profiles,
+            ChunkIndex::Ensemble(members, _) => {
+                members.iter().any(|member| member.source_profiles())
+            }
+            _ => false,
+        }
+    }
+
+    /// Score the chunks of lines instead of each line, e.g. the paragraphs of a verbose output.
+    /// The rules are selected by index name when the index is trained, see
+    /// [process::GranularityRule].
+    pub fn with_granularity(self, rules: Vec<process::GranularityRule>) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                i.granularity = process::GranularityRule::select(&rules, None).unwrap_or_default();
+                i.granularity_rules = rules;
+                ChunkIndex::HashingTrick(i)
+            }
+            ChunkIndex::Ensemble(members, aggregation) => ChunkIndex::Ensemble(
+                members
+                    .into_iter()
+                    .map(|member| member.with_granularity(rules.clone()))
+                    .collect(),
+                aggregation,
+            ),
+            index => index,
+        }
+    }
+
+    /// Select the granularity rule of the index name, before the index is trained.
+    pub(crate) fn for_index(self, index_name: &IndexName) -> ChunkIndex {
+        match self {
+            ChunkIndex::HashingTrick(mut i) => {
+                if let Some(granularity) =
+                    process::GranularityRule::select(&i.granularity_rules, Some(index_name))
+                {
+                    i.granularity = granularity;
+                }
+                ChunkIndex::HashingTrick(i)
+            }
+            ChunkIndex::Ensemble(members, aggregation) => ChunkIndex::Ensemble(
+                members
+                    .into_iter()
+                    .map(|member| member.for_index(index_name))
+                    .collect(),
+                aggregation,
+            ),
+            index => index,
+        }
+    }
+
+    pub(crate) fn granularity(&self) -> process::Granularity {
         match self {
-            ChunkIndex::HashingTrick(_) => hashing_index::tokenize(line),
+            ChunkIndex::HashingTrick(i) => i.granularity,
+            ChunkIndex::Ensemble(members, _) => members
+                .iter()
+                .map(|member| member.granularity())
+                .find(|granularity| *granularity != process::Granularity::Line)
+                .unwrap_or_default(),
+            _ => process::Granularity::Line,
+        }
+    }
+
+    /// Convert a raw line to the tokens that are indexed. The tokens of the ensemble members
+    /// are joined with [MEMBER_SEPARATOR], as the members may tokenize differently.
+    pub fn tokenize(&self, line: &str) -> String {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.tokenize(line),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => noop_index::tokenize(line),
             ChunkIndex::Noop => noop_index::tokenize(line),
+            ChunkIndex::Ensemble(members, _) if members.is_empty() => noop_index::tokenize(line),
+            ChunkIndex::Ensemble(_, _) => {
+                self.member_tokens(line).join(&MEMBER_SEPARATOR.to_string())
+            }
+        }
+    }
+
+    /// The tokens of each ensemble member, or the tokens of the index.
+    fn member_tokens(&self, line: &str) -> Vec<String> {
+        match self {
+            ChunkIndex::Ensemble(members, _) => members
+                .iter()
+                .map(|member| member.tokenize(line).replace(MEMBER_SEPARATOR, " "))
+                .collect(),
+            index => vec![index.tokenize(line)],
+        }
+    }
+
+    fn is_known(&self, tokens: &str) -> bool {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.is_known(tokens),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => false,
+            ChunkIndex::Noop => false,
+            ChunkIndex::Ensemble(_, _) => false,
         }
     }
 
     fn add(&mut self, baselines: &[String]) {
         match self {
             ChunkIndex::HashingTrick(i) => i.add(baselines),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.add(baselines),
+            ChunkIndex::Noop => {}
+            // An ensemble is only made of already trained indexes.
+            ChunkIndex::Ensemble(_, _) => {}
+        }
+    }
+
+    fn damp(&mut self) {
+        if let ChunkIndex::HashingTrick(i) = self {
+            i.damp()
+        }
+    }
+
+    fn set_frequency_weight(&mut self, enabled: bool) {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.frequency_weight = enabled,
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(_) => {}
+            ChunkIndex::Noop => {}
+            ChunkIndex::Ensemble(members, _) => members
+                .iter_mut()
+                .for_each(|member| member.set_frequency_weight(enabled)),
+        }
+    }
+
+    /// Count the readers of the baseline lines, by [process::line_hash].
+    pub(crate) fn add_origins(&mut self, origins: &HashMap<u64, usize>) {
+        if let ChunkIndex::HashingTrick(i) = self {
+            i.add_origins(origins)
+        }
+    }
+
+    /// The number of baseline sources that contained the tokens, the ensemble members count
+    /// their own sources, see [ChunkIndex::tokenize].
+    #[cfg(test)]
+    pub(crate) fn origin_count(&self, tokens: &str) -> usize {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.origin_count(process::line_hash(tokens)),
+            ChunkIndex::Ensemble(members, _) => members
+                .iter()
+                .zip(tokens.split(MEMBER_SEPARATOR))
+                .map(|(member, tokens)| member.origin_count(tokens))
+                .sum(),
+            _ => 0,
+        }
+    }
+
+    /// Count, for each line tokens, how many other lines are closer than the threshold with
+    /// the metric of the index. The ensemble members must all agree on a neighbor.
+    pub(crate) fn count_neighbors(&self, tokens: &[String], threshold: f32) -> Vec<usize> {
+        match self {
+            ChunkIndex::HashingTrick(i) => {
+                logreduce_index::count_neighbors_with(i.metric(), i.features(), tokens, threshold)
+            }
+            ChunkIndex::Ensemble(members, _) if !members.is_empty() => members
+                .iter()
+                .enumerate()
+                .map(|(pos, member)| {
+                    let tokens = tokens
+                        .iter()
+                        .map(|line| {
+                            line.split(MEMBER_SEPARATOR)
+                                .nth(pos)
+                                .unwrap_or(line)
+                                .to_string()
+                        })
+                        .collect::<Vec<_>>();
+                    member.count_neighbors(&tokens, threshold)
+                })
+                .reduce(|counts, member_counts| {
+                    counts
+                        .into_iter()
+                        .zip(member_counts)
+                        .map(|(count, member_count)| count.min(member_count))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            _ => logreduce_index::count_neighbors(tokens, threshold),
+        }
+    }
+
+    /// The number of baseline rows.
+    fn row_count(&self) -> usize {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.row_count(),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.row_count(),
+            ChunkIndex::Noop => 0,
+            ChunkIndex::Ensemble(members, _) => members.iter().map(ChunkIndex::row_count).sum(),
+        }
+    }
+
+    fn truncate(&mut self, max_lines: usize) {
+        match self {
+            ChunkIndex::HashingTrick(i) => i.truncate(max_lines),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.truncate(max_lines),
             ChunkIndex::Noop => {}
+            ChunkIndex::Ensemble(members, _) => members
+                .iter_mut()
+                .for_each(|member| member.truncate(max_lines)),
         }
     }
 
     fn search(&self, targets: &[String]) -> Vec<f32> {
         match self {
             ChunkIndex::HashingTrick(i) => i.search(targets),
+            #[cfg(feature = "embedding")]
+            ChunkIndex::Embedding(i) => i.search(targets),
             ChunkIndex::Noop => noop_index::search(targets),
+            ChunkIndex::Ensemble(members, aggregation) => aggregation.combine(
+                members
+                    .iter()
+                    .enumerate()
+                    .map(|(pos, member)| {
+                        // Each member searches its own tokens, see [ChunkIndex::tokenize].
+                        let targets = targets
+                            .iter()
+                            .map(|target| {
+                                target
+                                    .split(MEMBER_SEPARATOR)
+                                    .nth(pos)
+                                    .unwrap_or(target)
+                                    .to_string()
+                            })
+                            .collect::<Vec<_>>();
+                        member.search(&targets)
+                    })
+                    .collect(),
+            ),
         }
     }
 }
 
+/// The separator of the ensemble members tokens, which is not produced by the tokenizers.
+const MEMBER_SEPARATOR: char = '\u{1f}';
+
 pub mod hashing_index {
+    use crate::ngram::Vectorizer;
+    use crate::process::line_hash;
+    use crate::Precision;
+    use logreduce_index::Metric;
     use serde::{Deserialize, Serialize};
+    use std::collections::{HashMap, HashSet};
+    use std::convert::TryFrom;
     /// A ChunkIndex implementation.
     #[derive(Debug, Serialize, Deserialize)]
     pub struct HashingIndex {
+        metric: Metric,
+        vectorizer: Vectorizer,
+        precision: Precision,
+        /// The size of the hashed features vectors, see [parse_features].
+        features: usize,
+        /// Down-weight the common tokens of the index, see [HashingIndex::damp].
+        pub(crate) damping: bool,
+        /// The weights of the damped features.
+        weights: logreduce_index::Weights,
+        /// Reassemble the pretty-printed json objects, see [super::ChunkIndex::with_json_blocks].
+        pub(crate) json_blocks: bool,
+        /// Strip the constant prefix of the lines, see [crate::prefix].
+        pub(crate) strip_prefix: bool,
+        /// Read each source with the profile of its kind, see [crate::profile].
+        pub(crate) source_profiles: bool,
+        /// The unit of text that is scored, see [super::ChunkIndex::with_granularity].
+        pub(crate) granularity: crate::process::Granularity,
+        #[serde(skip)]
+        pub(crate) granularity_rules: Vec<crate::process::GranularityRule>,
         baselines: Vec<logreduce_index::FeaturesMatrix>,
+        /// The baselines chunks when the precision is [Precision::Int8].
+        quantized: Vec<logreduce_index::QuantizedMatrix>,
+        /// The [crate::process::line_hash] of every baselines line, to skip the distance
+        /// computation of the known lines. It is built from the row hashes when it is first
+        /// used, so that the hashes are not stored twice.
+        #[serde(skip)]
+        known: once_cell::sync::OnceCell<HashSet<u64>>,
+        /// The [crate::process::line_hash] of each baselines chunk row.
+        row_hashes: Vec<Vec<u64>>,
+        /// The number of baseline sources that contained each row, see [HashingIndex::add_origins].
+        row_origins: Vec<Vec<u32>>,
+        /// Weigh the distances with the origin counts, see [frequency_weight].
+        #[serde(skip)]
+        pub(crate) frequency_weight: bool,
     }
 
     pub fn new() -> super::ChunkIndex {
+        new_with(Metric::default())
+    }
+
+    /// Create an index using a custom distance metric.
+    pub fn new_with(metric: Metric) -> super::ChunkIndex {
+        new_with_vectorizer(metric, Vectorizer::default())
+    }
+
+    /// Create an index using a custom distance metric and feature extractor.
+    pub fn new_with_vectorizer(metric: Metric, vectorizer: Vectorizer) -> super::ChunkIndex {
+        new_with_precision(metric, vectorizer, Precision::default())
+    }
+
+    /// Create an index with a custom features storage.
+    pub fn new_with_precision(
+        metric: Metric,
+        vectorizer: Vectorizer,
+        precision: Precision,
+    ) -> super::ChunkIndex {
+        new_with_features(
+            metric,
+            vectorizer,
+            precision,
+            logreduce_index::DEFAULT_FEATURES,
+        )
+    }
+
+    /// Create an index with a custom features vectors size.
+    pub fn new_with_features(
+        metric: Metric,
+        vectorizer: Vectorizer,
+        precision: Precision,
+        features: usize,
+    ) -> super::ChunkIndex {
         super::ChunkIndex::HashingTrick(HashingIndex {
+            metric,
+            vectorizer,
+            precision,
+            features,
+            damping: false,
+            weights: logreduce_index::Weights::new(),
+            json_blocks: false,
+            strip_prefix: false,
+            source_profiles: false,
+            granularity: crate::process::Granularity::Line,
+            granularity_rules: Vec::new(),
             baselines: Vec::new(),
+            quantized: Vec::new(),
+            known: once_cell::sync::OnceCell::new(),
+            row_hashes: Vec::new(),
+            row_origins: Vec::new(),
+            frequency_weight: false,
         })
     }
 
     pub fn tokenize(line: &str) -> String {
         logreduce_tokenizer::process(line)
     }
+
+    /// The minimum number of unique lines to detect the common tokens of an index.
+    const DAMPING_MIN_LINES: usize = 100;
+
+    /// Parse a features vectors size, either as a power of two like `2^20`, or as a number.
+    pub fn parse_features(s: &str) -> Result<usize, String> {
+        let features = match s.strip_prefix("2^") {
+            Some(exponent) => exponent
+                .parse::<u32>()
+                .ok()
+                .and_then(|n| 2usize.checked_pow(n)),
+            None => s.parse::<usize>().ok(),
+        };
+        match features {
+            Some(features)
+                if features.is_power_of_two() && (1 << 10..=1 << 28).contains(&features) =>
+            {
+                Ok(features)
+            }
+            _ => Err(format!(
+                "Invalid features count: {} (expected a power of two between 2^10 and 2^28)",
+                s
+            )),
+        }
+    }
+
+    /// Lower the distance to a common baseline line, a line seen once is not changed.
+    pub fn frequency_weight(distance: f32, count: usize) -> f32 {
+        distance / (1.0 + (count.max(1) as f32).ln())
+    }
+
     impl HashingIndex {
+        pub fn tokenize(&self, line: &str) -> String {
+            match self.granularity {
+                crate::process::Granularity::Line => self.vectorizer.tokenize(line),
+                // The lines of a chunk are separated by a new line, see with_granularity.
+                _ => self
+                    .vectorizer
+                    .tokenize(&line.trim_end_matches('\n').replace('\n', " ")),
+            }
+        }
+
         pub fn add(&mut self, baselines: &[String]) {
-            self.baselines.push(logreduce_index::index_mat(baselines))
+            let hashes = baselines
+                .iter()
+                .map(|line| line_hash(line))
+                .collect::<Vec<_>>();
+            if let Some(known) = self.known.get_mut() {
+                known.extend(hashes.iter().copied());
+            }
+            self.row_origins.push(vec![0; hashes.len()]);
+            self.row_hashes.push(hashes);
+            let chunk = logreduce_index::index_mat_weighted(
+                self.metric,
+                self.features,
+                baselines,
+                &self.weights,
+            );
+            match self.precision {
+                Precision::F32 => self.baselines.push(chunk),
+                Precision::Int8 => self.quantized.push(logreduce_index::quantize(&chunk)),
+            }
+        }
+
+        /// Remove the oldest chunks to keep at most `max_lines` lines.
+        pub fn truncate(&mut self, max_lines: usize) {
+            let mut line_count = 0;
+            let keep_from = self
+                .row_hashes
+                .iter()
+                .rposition(|chunk| {
+                    line_count += chunk.len();
+                    line_count > max_lines
+                })
+                .map_or(0, |pos| pos + 1);
+            match self.precision {
+                Precision::F32 => {
+                    self.baselines.drain(..keep_from);
+                }
+                Precision::Int8 => {
+                    self.quantized.drain(..keep_from);
+                }
+            }
+            self.row_hashes.drain(..keep_from);
+            self.row_origins.drain(..keep_from);
+            self.known = once_cell::sync::OnceCell::new();
+            self.damp();
+        }
+
+        /// Update the weights of the tokens that are in most of the lines, e.g. a constant
+        /// prefix, so that the distances are driven by the informative tokens. This only
+        /// applies to the cosine metric, the other metrics count the features.
+        pub fn damp(&mut self) {
+            if !self.damping || self.metric != Metric::Cosine {
+                return;
+            }
+            let dequantized;
+            let chunks = match self.precision {
+                Precision::F32 => &self.baselines,
+                Precision::Int8 => {
+                    dequantized = self
+                        .quantized
+                        .iter()
+                        .map(|chunk| chunk.dequantize())
+                        .collect::<Vec<_>>();
+                    &dequantized
+                }
+            };
+            let line_count: usize = chunks.iter().map(|chunk| chunk.rows()).sum();
+            if line_count < DAMPING_MIN_LINES {
+                return;
+            }
+            let mut counts = HashMap::new();
+            chunks
+                .iter()
+                .for_each(|chunk| logreduce_index::count_features(chunk, &mut counts));
+            let weights = logreduce_index::damping_weights(&counts, line_count);
+            let reweighted = chunks
+                .iter()
+                .map(|chunk| logreduce_index::reweight(chunk, &self.weights, &weights))
+                .collect::<Vec<_>>();
+            match self.precision {
+                Precision::F32 => self.baselines = reweighted,
+                Precision::Int8 => {
+                    self.quantized = reweighted.iter().map(logreduce_index::quantize).collect()
+                }
+            }
+            self.weights = weights;
+        }
+
+        pub fn is_known(&self, target: &str) -> bool {
+            self.contains_hash(line_hash(target))
+        }
+
+        pub fn contains_hash(&self, hash: u64) -> bool {
+            self.known
+                .get_or_init(|| self.row_hashes.iter().flatten().copied().collect())
+                .contains(&hash)
+        }
+
+        /// The number of baseline rows.
+        pub fn row_count(&self) -> usize {
+            self.row_hashes.iter().map(Vec::len).sum()
+        }
+
+        /// Add the number of readers that contained each row, the counts are stored with the
+        /// row hashes so that a line hash is not stored twice.
+        pub fn add_origins(&mut self, origins: &HashMap<u64, usize>) {
+            let rows = self.row_hashes.iter().zip(self.row_origins.iter_mut());
+            for (hash, count) in rows.flat_map(|(hashes, counts)| hashes.iter().zip(counts)) {
+                if let Some(origin) = origins.get(hash) {
+                    let origin = u32::try_from(*origin).unwrap_or(u32::MAX);
+                    *count = count.saturating_add(origin);
+                }
+            }
+        }
+
+        /// The number of baseline sources that contained the line. This scans the rows, as
+        /// only the search needs the counts, so it is only used to check them.
+        #[cfg(test)]
+        pub(crate) fn origin_count(&self, hash: u64) -> usize {
+            self.row_hashes
+                .iter()
+                .zip(&self.row_origins)
+                .flat_map(|(hashes, counts)| hashes.iter().zip(counts))
+                .find(|(row_hash, _)| **row_hash == hash)
+                .map_or(0, |(_, count)| *count as usize)
         }
+
+        pub fn metric(&self) -> Metric {
+            self.metric
+        }
+
+        pub fn features(&self) -> usize {
+            self.features
+        }
+
+        /// Search the baselines chunks, the int8 chunks are searched without restoring them.
+        fn nearest(&self, targets: &[String]) -> Vec<(f32, logreduce_index::Nearest)> {
+            match self.precision {
+                Precision::F32 => logreduce_index::search_mat_chunk_nearest_weighted(
+                    self.metric,
+                    &self.baselines,
+                    targets,
+                    &self.weights,
+                ),
+                Precision::Int8 => logreduce_index::search_mat_chunk_nearest_weighted(
+                    self.metric,
+                    &self.quantized,
+                    targets,
+                    &self.weights,
+                ),
+            }
+        }
+
         pub fn search(&self, targets: &[String]) -> Vec<f32> {
-            logreduce_index::search_mat_chunk(&self.baselines, targets)
+            // Exactly seen lines are dismissed without computing their distances.
+            let (unknown_pos, unknown): (Vec<usize>, Vec<String>) = targets
+                .iter()
+                .enumerate()
+                .filter(|(_, target)| !self.is_known(target))
+                .map(|(pos, target)| (pos, target.clone()))
+                .unzip();
+            let mut distances = vec![0.0; targets.len()];
+            if unknown.is_empty() {
+                return distances;
+            }
+            let nearests = self.nearest(&unknown);
+            for (pos, (distance, nearest)) in unknown_pos.into_iter().zip(nearests) {
+                distances[pos] = if self.frequency_weight {
+                    let count = nearest
+                        .and_then(|(chunk, row)| self.row_origins.get(chunk)?.get(row))
+                        .map_or(1, |count| (*count).max(1) as usize);
+                    frequency_weight(distance, count)
+                } else {
+                    distance
+                };
+            }
+            distances
         }
     }
 }
@@ -628,3 +2946,207 @@ pub mod noop_index {
         distances
     }
 }
+
+#[test]
+fn test_shards() {
+    let mk_index = || Index {
+        created_at: SystemTime::UNIX_EPOCH,
+        train_time: Duration::ZERO,
+        sources: Vec::new(),
+        index: ChunkIndex::Noop,
+        line_count: 42,
+        byte_count: 0,
+        context_mode: process::ContextMode::default(),
+    };
+    let first = IndexName("job-output.txt".to_string());
+    let second = IndexName("syslog".to_string());
+    let second_path = |dir: &Path| Shards::path(dir, &second);
+    let source_filter = SourceFilter::new(vec![glob_regex("*.txt")], Vec::new());
+    let model = Model {
+        created_at: SystemTime::UNIX_EPOCH,
+        baselines: Vec::new(),
+        indexes: HashMap::from([(first.clone(), mk_index()), (second.clone(), mk_index())]),
+        tags: tags::Tags::new(),
+        shard_names: Vec::new(),
+        source_filter: source_filter.clone(),
+        shards: Shards::default(),
+        fallback_index: None,
+        source_timeout: None,
+        path: Default::default(),
+    };
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    assert_eq!(model.hash().unwrap(), None);
+    model.save_shards(dir).unwrap();
+    let hash = model.hash().unwrap();
+    assert!(hash.is_some());
+
+    let model = Model::load(dir).unwrap();
+    assert_eq!(model.hash().unwrap(), hash);
+    assert_eq!(model.index_names().count(), 2);
+    assert_eq!(model.source_filter(), &source_filter);
+    assert_eq!(
+        model.get_index(&first).map(|index| index.line_count),
+        Some(42)
+    );
+    // Only the requested shard is loaded.
+    let loaded = model
+        .shards
+        .cells
+        .values()
+        .filter(|cell| cell.get().is_some());
+    assert_eq!(loaded.count(), 1);
+
+    // Only the shards of the dropped indexes are removed.
+    let other = dir.join("other.bin");
+    std::fs::write(&other, "").unwrap();
+    let mut model = model;
+    model.drop_indexes(|index_name| index_name == &second);
+    model.save_shards(dir).unwrap();
+    assert!(!second_path(dir).exists());
+    assert!(other.exists());
+
+    // The files without the format header are rejected with a clear error.
+    let headerless = |path: &Path| {
+        let file = std::fs::File::create(path).unwrap();
+        let mut writer = flate2::write::GzEncoder::new(file, flate2::Compression::fast());
+        bincode::serialize_into(&mut writer, &mk_index()).unwrap();
+        writer.finish().unwrap();
+    };
+    headerless(&Shards::path(dir, &first));
+    let error = Model::load(dir).unwrap().load_index(&first).unwrap_err();
+    assert!(format!("{:#}", error).contains("retrained"));
+    headerless(&dir.join(SHARDED_MODEL));
+    let error = Model::load(dir).unwrap_err();
+    assert!(format!("{:#}", error).contains("retrained"));
+}
+
+#[test]
+fn test_global_index() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let write = |name: &str, content: &str| {
+        let path = dir.join(name);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    };
+    write("first/app.log", "Starting service\nService started\n");
+    write("first/db.log", "Database ready\n");
+    write("second/app.log", "Service restarted\n");
+    write("target/syslog", "Kernel panic\n");
+    let content = |name: &str| Content::from_pathbuf(dir.join(name));
+    let mut groups = Content::group_sources(&[content("first")]).unwrap();
+    Content::add_global_group(&mut groups);
+    let mut model = Model::train_groups(
+        &OutputMode::Quiet,
+        vec![content("first")],
+        groups,
+        hashing_index::new,
+    )
+    .unwrap();
+
+    // The sources without baselines are only inspected with the fallback index.
+    let unknown = IndexName("syslog".to_string());
+    assert!(model.get_index(&unknown).is_none());
+    model.set_fallback_index(Some(IndexName::global()));
+    let fallback = |model: &Model| {
+        model
+            .get_index(&unknown)
+            .map(|index| (index.line_count, index.sources.len()))
+    };
+    assert_eq!(fallback(&model), Some((3, 2)));
+    let report = model.report(&OutputMode::Quiet, content("target"));
+
+    // The global index is updated with the new baselines.
+    let update = model.update(
+        &OutputMode::Quiet,
+        vec![content("second")],
+        hashing_index::new,
+    );
+    let log_report = &report.unwrap().log_reports[0];
+    assert_eq!(log_report.grouping_rule(), files::GroupingRule::Fallback);
+    update.unwrap();
+    assert_eq!(fallback(&model), Some((4, 3)));
+}
+
+#[test]
+fn test_chunk_index_variants() {
+    // The variant indexes are the same with or without the embedding feature.
+    assert_eq!(
+        bincode::serialize(&ChunkIndex::Noop).unwrap(),
+        vec![1, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_truncate() {
+    let tokenize = |lines: &[&str]| {
+        lines
+            .iter()
+            .map(|line| hashing_index::tokenize(line))
+            .collect::<Vec<_>>()
+    };
+    let mut index = hashing_index::new();
+    index.add(&tokenize(&["kernel panic", "segfault at address"]));
+    index.add(&tokenize(&["starting the service", "service started"]));
+    index.add(&tokenize(&["listing packages", "installing packages"]));
+    let panic = hashing_index::tokenize("kernel panic");
+    assert!(index.is_known(&panic));
+    assert!(!index.is_known(&hashing_index::tokenize("kernel oops")));
+    assert_eq!(index.row_count(), 6);
+    index.truncate(4);
+    assert_eq!(index.row_count(), 4);
+    assert!(!index.is_known(&panic));
+    let distances = index.search(&tokenize(&["kernel panic", "service started"]));
+    assert!(distances[0] > 0.0);
+    assert_eq!(distances[1], 0.0);
+
+    // The known lines are rebuilt from the row hashes when the index is loaded.
+    let index: ChunkIndex = bincode::deserialize(&bincode::serialize(&index).unwrap()).unwrap();
+    assert!(index.is_known(&hashing_index::tokenize("service started")));
+    assert!(!index.is_known(&panic));
+}
+
+#[test]
+fn test_frequency_weight() {
+    let baselines = vec![hashing_index::tokenize("service started on port 8080")];
+    let targets = vec![hashing_index::tokenize(
+        "service started on port 8080 again",
+    )];
+    let mut index = hashing_index::new();
+    index.add(&baselines);
+    let distance = index.search(&targets)[0];
+
+    let origins = HashMap::from([(process::line_hash(&baselines[0]), 10)]);
+    index.add_origins(&origins);
+    assert_eq!(index.origin_count(&baselines[0]), 10);
+    index.set_frequency_weight(true);
+    let weighted = index.search(&targets)[0];
+    assert!(weighted < distance);
+    assert_eq!(weighted, hashing_index::frequency_weight(distance, 10));
+}
+
+#[test]
+fn test_parse_features() {
+    assert_eq!(hashing_index::parse_features("2^20"), Ok(1 << 20));
+    assert_eq!(hashing_index::parse_features("4096"), Ok(4096));
+    assert!(hashing_index::parse_features("2^4").is_err());
+    assert!(hashing_index::parse_features("5000").is_err());
+    assert!(hashing_index::parse_features("2^x").is_err());
+}
+
+#[test]
+fn test_damping() {
+    let baselines = (0..120)
+        .map(|pos| format!("INFO job{} started", pos))
+        .collect::<Vec<_>>();
+    let targets = vec!["INFO job-new started".to_string()];
+    let distance = |damping| {
+        let mut index = hashing_index::new().with_damping(damping);
+        index.add(&baselines);
+        index.damp();
+        index.search(&targets)[0]
+    };
+    // The target only shares the common tokens with the baselines.
+    assert!(distance(true) > distance(false));
+}
@@ -0,0 +1,146 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module keeps the recently used models in memory, for the worker and server modes.
+//!
+//! The models are identified by their path, and they are loaded again when their file changes.
+//! The shards are loaded when the model is added, so that the cache memory estimate is accurate.
+//! The least recently used models are dropped when the budget is exceeded, but the last model is
+//! always kept.
+
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::Model;
+
+struct Entry {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    size: u64,
+    model: Arc<Model>,
+}
+
+/// A LRU cache of the loaded models, with a memory budget.
+pub struct ModelCache {
+    budget: u64,
+    /// The entries, the most recently used is the last.
+    entries: Mutex<Vec<Entry>>,
+}
+
+impl std::fmt::Debug for ModelCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ModelCache")
+            .field("budget", &self.budget)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// The modification time of the file read by [Model::load].
+fn modified(path: &Path) -> Option<SystemTime> {
+    let path = if path.is_dir() {
+        path.join(crate::SHARDED_MODEL)
+    } else {
+        path.to_path_buf()
+    };
+    std::fs::metadata(path)
+        .and_then(|meta| meta.modified())
+        .ok()
+}
+
+impl ModelCache {
+    /// Create a cache that holds models up to the budget, in bytes.
+    pub fn new(budget: u64) -> ModelCache {
+        ModelCache {
+            budget,
+            entries: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Get the model, loading it on the first use or when its file changed. The configure
+    /// function sets the runtime settings of the model before it is shared.
+    pub fn get(&self, path: &Path, configure: impl FnOnce(&mut Model)) -> Result<Arc<Model>> {
+        let modified = modified(path);
+        let mut entries = self.entries.lock().expect("The cache lock is poisoned");
+        if let Some(pos) = entries.iter().position(|entry| entry.path == path) {
+            let entry = entries.remove(pos);
+            if entry.modified == modified {
+                let model = Arc::clone(&entry.model);
+                entries.push(entry);
+                return Ok(model);
+            }
+            tracing::info!(path = path.to_str(), "Reloading the updated model");
+        }
+
+        let mut model = Model::load(path)?;
+        model.warm_up()?;
+        configure(&mut model);
+        let model = Arc::new(model);
+        entries.push(Entry {
+            path: path.to_path_buf(),
+            modified,
+            size: model.memory_size()?,
+            model: Arc::clone(&model),
+        });
+
+        let mut total: u64 = entries.iter().map(|entry| entry.size).sum();
+        while total > self.budget && entries.len() > 1 {
+            let entry = entries.remove(0);
+            tracing::info!(
+                path = entry.path.to_str(),
+                "Evicting the model from the cache"
+            );
+            total -= entry.size;
+        }
+        Ok(model)
+    }
+
+    /// The number of cached models.
+    pub fn len(&self) -> usize {
+        self.entries
+            .lock()
+            .expect("The cache lock is poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[test]
+fn test_model_cache() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let mut paths = Vec::new();
+    for name in &["a", "b"] {
+        let log = dir.join(format!("{}.log", name));
+        std::fs::write(&log, "Starting service\nService started\n").unwrap();
+        let model = Model::train(
+            &crate::OutputMode::Quiet,
+            vec![crate::Content::from_pathbuf(log)],
+            crate::hashing_index::new,
+        )
+        .unwrap();
+        let path = dir.join(format!("{}.bin", name));
+        model.save(&path).unwrap();
+        paths.push(path);
+    }
+
+    let size = Model::load(&paths[0]).unwrap().memory_size().unwrap();
+    let cache = ModelCache::new(size);
+    let first = cache.get(&paths[0], |_| ()).unwrap();
+    let again = cache
+        .get(&paths[0], |_| panic!("The model is cached"))
+        .unwrap();
+    assert!(Arc::ptr_eq(&first, &again));
+    // The second model exceeds the budget, the first one is evicted.
+    cache.get(&paths[1], |_| ()).unwrap();
+    assert_eq!(cache.len(), 1);
+    let mut loaded = false;
+    cache.get(&paths[0], |_| loaded = true).unwrap();
+    assert!(loaded);
+    assert_eq!(cache.len(), 1);
+}
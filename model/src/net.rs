@@ -0,0 +1,123 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the network settings of the remote sources.
+//!
+//! The settings are global because the http client is shared by all the downloads, they
+//! must be set with [configure] before the first request.
+
+use once_cell::sync::OnceCell;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The network settings.
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// The maximum download rate in bytes per second, shared by all the downloads.
+    pub limit_rate: Option<u64>,
+    /// The number of connections kept open per host, which also bounds the concurrent
+    /// directory listings. The http/2 connections multiplex their requests.
+    pub connections_per_host: Option<usize>,
+    /// The maximum time of a request, including the download of its content, so that a stalled
+    /// transfer fails instead of blocking the source.
+    pub timeout: Option<Duration>,
+}
+
+/// The default number of concurrent directory listings.
+const DEFAULT_CONNECTIONS: usize = 4;
+
+impl Settings {
+    pub(crate) fn connections(&self) -> usize {
+        self.connections_per_host.unwrap_or(DEFAULT_CONNECTIONS)
+    }
+}
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+static RATE_LIMIT: OnceCell<Option<RateLimit>> = OnceCell::new();
+
+/// Set the network settings, this returns an error when they are already set.
+pub fn configure(settings: Settings) -> anyhow::Result<()> {
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("The network settings are already set"))
+}
+
+pub(crate) fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+/// Parse a rate like curl, e.g. `200K` or `1M` bytes per second.
+pub fn parse_rate(s: &str) -> Result<u64, String> {
+    let (digits, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(pos) => s.split_at(pos),
+        None => (s, ""),
+    };
+    let factor = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return Err(format!("Unknown rate: {} (expected e.g. 200K or 1M)", s)),
+    };
+    match digits.parse::<u64>() {
+        Ok(value) if value > 0 => Ok(value * factor),
+        _ => Err(format!("Unknown rate: {} (expected e.g. 200K or 1M)", s)),
+    }
+}
+
+/// A bandwidth limiter: each read reserves its transfer time after the previous ones.
+struct RateLimit {
+    bytes_per_sec: u64,
+    next: Mutex<Instant>,
+}
+
+impl RateLimit {
+    fn consume(&self, count: usize) {
+        let now = Instant::now();
+        let wait = {
+            let mut next = self.next.lock().unwrap();
+            let start = (*next).max(now);
+            *next = start + Duration::from_secs_f64(count as f64 / self.bytes_per_sec as f64);
+            next.saturating_duration_since(now)
+        };
+        if !wait.is_zero() {
+            std::thread::sleep(wait)
+        }
+    }
+}
+
+/// Wait until the bandwidth is available for the bytes that were just received.
+pub(crate) fn throttled(count: usize) -> usize {
+    let limit = RATE_LIMIT.get_or_init(|| {
+        settings().limit_rate.map(|bytes_per_sec| RateLimit {
+            bytes_per_sec,
+            next: Mutex::new(Instant::now()),
+        })
+    });
+    if let Some(limit) = limit {
+        limit.consume(count)
+    }
+    count
+}
+
+#[test]
+fn test_parse_rate() {
+    assert_eq!(parse_rate("42"), Ok(42));
+    assert_eq!(parse_rate("200K"), Ok(200 * 1024));
+    assert_eq!(parse_rate("1m"), Ok(1024 * 1024));
+    assert!(parse_rate("1T").is_err());
+    assert!(parse_rate("0").is_err());
+}
+
+#[test]
+fn test_rate_limit() {
+    let limit = RateLimit {
+        bytes_per_sec: 1000,
+        next: Mutex::new(Instant::now()),
+    };
+    let start = Instant::now();
+    limit.consume(50);
+    limit.consume(50);
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
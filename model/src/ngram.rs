@@ -0,0 +1,102 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides a character n-gram feature extractor, for the lines that the word
+//! tokenizer handles poorly, such as CJK text or dense key-value blobs.
+
+use serde::{Deserialize, Serialize};
+
+/// How the lines are converted to the words that are hashed by the index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Vectorizer {
+    /// The logreduce tokenizer.
+    #[default]
+    Words,
+    /// The character 3-grams.
+    Trigrams,
+    /// The words, or the 3-grams when the tokenizer output is poor.
+    Auto,
+}
+
+impl std::str::FromStr for Vectorizer {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "words" => Ok(Vectorizer::Words),
+            "trigrams" => Ok(Vectorizer::Trigrams),
+            "auto" => Ok(Vectorizer::Auto),
+            _ => Err(format!(
+                "Unknown vectorizer: {} (expected words, trigrams or auto)",
+                s
+            )),
+        }
+    }
+}
+
+impl Vectorizer {
+    pub fn tokenize(&self, line: &str) -> String {
+        match self {
+            Vectorizer::Words => logreduce_tokenizer::process(line),
+            Vectorizer::Trigrams => trigrams(line),
+            Vectorizer::Auto => {
+                let tokens = logreduce_tokenizer::process(line);
+                if needs_fallback(line, &tokens) {
+                    trigrams(line)
+                } else {
+                    tokens
+                }
+            }
+        }
+    }
+}
+
+/// Convert a line into its character 3-grams, separated by spaces so that the index hashes
+/// each of them. The digits are normalized and the whitespaces are replaced with `_`.
+pub fn trigrams(line: &str) -> String {
+    let chars = line
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '0'..='9' => '0',
+            c if c.is_whitespace() => '_',
+            c => c,
+        })
+        .collect::<Vec<_>>();
+    if chars.len() < 3 {
+        return chars.into_iter().collect();
+    }
+    chars
+        .windows(3)
+        .map(|gram| gram.iter().collect::<String>())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}' // Hiragana and Katakana
+        | '\u{3400}'..='\u{4dbf}' // CJK extension A
+        | '\u{4e00}'..='\u{9fff}' // CJK unified ideographs
+        | '\u{ac00}'..='\u{d7af}' // Hangul syllables
+    )
+}
+
+/// Check if the tokenizer output is poor: CJK text, or a long line with few words.
+pub fn needs_fallback(line: &str, tokens: &str) -> bool {
+    let words = tokens.split(' ').filter(|word| !word.is_empty()).count();
+    line.chars().any(is_cjk) || (line.len() > 64 && words < 3)
+}
+
+#[test]
+fn test_trigrams() {
+    assert_eq!(trigrams("abcd"), "abc bcd");
+    assert_eq!(trigrams("a 42"), "a_0 _00");
+    assert_eq!(trigrams("ab"), "ab");
+    assert!(needs_fallback("接続に失敗しました", ""));
+    assert!(!needs_fallback("the service failed", "the service failed"));
+    assert_eq!(
+        Vectorizer::Auto.tokenize("接続失敗"),
+        "接続失 続失敗".to_string()
+    );
+}
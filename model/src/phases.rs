@@ -0,0 +1,327 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module splits the zuul job output in phases, one [Source::Section] per playbook.
+//!
+//! The post-run playbooks collect the logs and their output is noisy, so a separate index per
+//! playbook keeps these lines from hiding the run phase anomalies. The lines before the first
+//! playbook are the `setup` section. The splitting is only enabled with [configure].
+//!
+//! The job output is read once to list its sections, which records the byte ranges of each
+//! section. A section is then streamed without being buffered: a local file is read at these
+//! ranges, otherwise the job output is read until the end of the section. The lines of the other
+//! sections are replaced with empty lines, which are skipped by the iterator, so that the anomalies
+//! keep their line numbers in the job output.
+
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use regex::Regex;
+use std::collections::{HashMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+use crate::reader::DecompressReader;
+use crate::{IndexName, Source};
+
+lazy_static::lazy_static! {
+    // zuul job-output: `| POST-RUN START: [trusted : opendev.org/base/post.yaml@master]`
+    static ref PHASE_START: Regex =
+        Regex::new(r"\| (PRE-RUN|RUN|POST-RUN|CLEANUP-RUN) START: \[\w+ : ([^@\]]+)").unwrap();
+}
+
+/// The section of the lines before the first playbook.
+pub const SETUP: &str = "setup";
+
+static ENABLED: OnceCell<bool> = OnceCell::new();
+
+/// Enable the splitting of the job outputs, this returns an error when it is already set.
+pub fn configure(enabled: bool) -> Result<()> {
+    ENABLED
+        .set(enabled)
+        .map_err(|_| anyhow::anyhow!("The job output splitting is already set"))
+}
+
+fn enabled() -> bool {
+    *ENABLED.get_or_init(|| false)
+}
+
+/// Check if the source is a zuul job output.
+pub fn is_job_output(source: &Source) -> bool {
+    let relative = source.get_relative();
+    relative.ends_with("job-output.txt") || relative.ends_with("job-output.txt.gz")
+}
+
+/// Return the section name when the line starts a new playbook, e.g. `run playbooks/tox.yaml`.
+pub fn section_start(line: &str) -> Option<String> {
+    if !line.contains(" START: [") {
+        return None;
+    }
+    PHASE_START
+        .captures(line)
+        .map(|captures| format!("{} {}", captures[1].to_lowercase(), &captures[2]))
+}
+
+/// Consecutive lines of a section.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Run {
+    /// The number of lines before the run.
+    line: usize,
+    line_count: usize,
+    /// The byte range of the run, including the line separators.
+    offset: u64,
+    len: u64,
+}
+
+/// The runs of each section, by job output.
+type Layout = HashMap<String, Vec<Run>>;
+
+lazy_static::lazy_static! {
+    /// The layouts recorded when the sections are listed, so that they are not read again. The
+    /// key is the full location, as the baselines and the target have the same relative path.
+    static ref LAYOUTS: Mutex<HashMap<String, Layout>> = Mutex::new(HashMap::new());
+}
+
+fn layout_key(source: &Source) -> String {
+    format!("{:?}", source)
+}
+
+/// Read the job output to find the runs of each section, and the sections in order.
+fn read_layout(reader: impl Read) -> Result<(Vec<String>, Layout)> {
+    let mut names: Vec<String> = Vec::new();
+    let mut layout: Layout = HashMap::new();
+    let mut section = SETUP.to_string();
+    let mut offset = 0;
+    for (line_number, line) in BufReader::new(reader).split(b'\n').enumerate() {
+        let line = line?;
+        if let Some(name) = section_start(&String::from_utf8_lossy(&line)) {
+            section = name;
+        }
+        let len = line.len() as u64 + 1;
+        let runs = match layout.get_mut(&section) {
+            Some(runs) => runs,
+            None => {
+                names.push(section.clone());
+                layout.entry(section.clone()).or_default()
+            }
+        };
+        match runs.last_mut() {
+            // The line continues the run.
+            Some(run) if run.line + run.line_count == line_number => {
+                run.line_count += 1;
+                run.len += len;
+            }
+            // A playbook may run more than once, its runs are read together.
+            _ => runs.push(Run {
+                line: line_number,
+                line_count: 1,
+                offset,
+                len,
+            }),
+        }
+        offset += len;
+    }
+    Ok((names, layout))
+}
+
+fn open_job_output(source: &Source) -> Result<DecompressReader> {
+    match source {
+        Source::Local(_, path_buf) => Source::file_open(path_buf.as_path()),
+        Source::Remote(prefix, url) => Source::url_open(*prefix, url),
+        _ => Err(anyhow::anyhow!("{}: not a job output file", source)),
+    }
+}
+
+/// The sections of the job output, in order.
+fn sections(source: &Source) -> Result<Vec<Source>> {
+    let (names, layout) = read_layout(open_job_output(source)?)?;
+    LAYOUTS.lock().unwrap().insert(layout_key(source), layout);
+    Ok(names
+        .into_iter()
+        .map(|name| Source::Section(Box::new(source.clone()), name))
+        .collect())
+}
+
+impl IndexName {
+    /// The index of a job output section, e.g. `job-output.txt[run playbooks/tox.yaml]`.
+    pub fn from_section(source: &Source, section: &str) -> IndexName {
+        IndexName(format!("{}[{}]", IndexName::from_source(source), section))
+    }
+}
+
+/// Replace the job outputs with their sections, when the splitting is enabled.
+pub fn expand(source: Result<Source>) -> Vec<Result<Source>> {
+    match source {
+        Ok(source) if enabled() && is_job_output(&source) => match sections(&source) {
+            Ok(sections) => sections.into_iter().map(Ok).collect(),
+            Err(e) => vec![Err(e)],
+        },
+        source => vec![source],
+    }
+}
+
+/// The lines of a section, the lines of the other sections are replaced with empty lines.
+struct SectionReader {
+    reader: DecompressReader,
+    /// The position in the job output.
+    offset: u64,
+    /// The number of lines before the current run.
+    line: usize,
+    runs: VecDeque<Run>,
+    /// The empty lines to write before the current run.
+    gap: usize,
+    /// The bytes left to read in the current run.
+    remaining: u64,
+}
+
+impl SectionReader {
+    fn new(reader: DecompressReader, runs: Vec<Run>) -> SectionReader {
+        SectionReader {
+            reader,
+            offset: 0,
+            line: 0,
+            runs: runs.into(),
+            gap: 0,
+            remaining: 0,
+        }
+    }
+
+    /// Move to the next run, returns false when the section is over.
+    fn next_run(&mut self) -> std::io::Result<bool> {
+        let run = match self.runs.pop_front() {
+            Some(run) => run,
+            None => return Ok(false),
+        };
+        match &mut self.reader {
+            // A local file is read at the run position.
+            DecompressReader::Flat(file) => {
+                file.seek(SeekFrom::Start(run.offset))?;
+            }
+            // Otherwise the lines of the other sections are discarded.
+            reader => {
+                let skip = run.offset - self.offset;
+                std::io::copy(&mut reader.take(skip), &mut std::io::sink())?;
+            }
+        }
+        self.gap = run.line - self.line;
+        self.line = run.line + run.line_count;
+        self.offset = run.offset + run.len;
+        self.remaining = run.len;
+        Ok(true)
+    }
+}
+
+impl Read for SectionReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if self.gap > 0 {
+                let size = self.gap.min(buf.len());
+                buf[..size].fill(b'\n');
+                self.gap -= size;
+                return Ok(size);
+            }
+            if self.remaining > 0 {
+                let size = (buf.len() as u64).min(self.remaining) as usize;
+                let size = self.reader.read(&mut buf[..size])?;
+                if size > 0 {
+                    self.remaining -= size as u64;
+                    return Ok(size);
+                }
+                // The last line has no separator.
+                self.remaining = 0;
+            }
+            if !self.next_run()? {
+                return Ok(0);
+            }
+        }
+    }
+}
+
+/// Read the lines of a single section.
+pub fn open(source: &Source, section: &str) -> Result<DecompressReader> {
+    tracing::debug!(section, "Reading job output section");
+    let layout = LAYOUTS.lock().unwrap().get(&layout_key(source)).cloned();
+    let layout = match layout {
+        Some(layout) => layout,
+        // The sections were not listed by this process, e.g. with a loaded model.
+        None => read_layout(open_job_output(source)?)?.1,
+    };
+    let runs = layout.get(section).cloned().unwrap_or_default();
+    Ok(DecompressReader::Stream(Box::new(SectionReader::new(
+        open_job_output(source)?,
+        runs,
+    ))))
+}
+
+#[test]
+fn test_section_start() {
+    assert_eq!(
+        section_start(
+            "2022-05-10 10:00:00.123 | PRE-RUN START: [trusted : opendev.org/base/pre.yaml@master]"
+        ),
+        Some("pre-run opendev.org/base/pre.yaml".to_string())
+    );
+    assert_eq!(
+        section_start("2022-05-10 10:01:00.123 | RUN START: [untrusted : playbooks/tox.yaml@main]"),
+        Some("run playbooks/tox.yaml".to_string())
+    );
+    assert_eq!(
+        section_start("2022-05-10 10:01:00.123 | RUN END RESULT_NORMAL"),
+        None
+    );
+}
+
+#[test]
+fn test_sections() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("job-output.txt");
+    let lines = [
+        "Job console starting",
+        "| PRE-RUN START: [trusted : opendev.org/base/pre.yaml@master]",
+        "| TASK [prepare-workspace]",
+        "| RUN START: [untrusted : playbooks/tox.yaml@main]",
+        "| tox: error",
+        "| POST-RUN START: [trusted : opendev.org/base/post.yaml@master]",
+        "| rsync: collecting logs",
+        "| RUN START: [untrusted : playbooks/tox.yaml@main]",
+        "| tox: retry",
+    ];
+    std::fs::write(&path, lines.join("\n")).unwrap();
+    let source = Source::from_pathbuf(path);
+    let sections = sections(&source).unwrap();
+    let mut run = String::new();
+    open(&source, "run playbooks/tox.yaml")
+        .unwrap()
+        .read_to_string(&mut run)
+        .unwrap();
+    // The layout is read again when the sections were not listed.
+    LAYOUTS.lock().unwrap().clear();
+    let mut post = String::new();
+    open(&source, "post-run opendev.org/base/post.yaml")
+        .unwrap()
+        .read_to_string(&mut post)
+        .unwrap();
+    assert_eq!(sections.len(), 4);
+    assert_eq!(
+        sections[0],
+        Source::Section(Box::new(source.clone()), SETUP.to_string())
+    );
+    assert_eq!(
+        IndexName::from_source(&sections[2]).0,
+        format!(
+            "{}[run playbooks/tox.yaml]",
+            IndexName::from_source(&source)
+        )
+    );
+    // The other sections are empty lines, to keep the line numbers.
+    assert_eq!(
+        run,
+        "\n\n\n| RUN START: [untrusted : playbooks/tox.yaml@main]\n| tox: error\n\n\n\
+         | RUN START: [untrusted : playbooks/tox.yaml@main]\n| tox: retry"
+    );
+    assert_eq!(
+        post,
+        "\n\n\n\n\n| POST-RUN START: [trusted : opendev.org/base/post.yaml@master]\n\
+         | rsync: collecting logs\n"
+    );
+}
@@ -0,0 +1,130 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module detects the constant prefix of the lines of a source, like the
+//! `2024-01-01 12:00:00 host app[123]:` of a syslog file.
+//!
+//! The prefix is made of the first fields whose shape, where the numbers are ignored, is the
+//! same in most of the first lines of the source. It ends with a delimiter like `:`, `]` or `|`,
+//! or with the last field without letters, e.g. a timestamp, so that a message that is often
+//! repeated is not stripped. Stripping the prefix before the vectorization lets the distances be
+//! driven by the message content.
+
+use std::collections::HashMap;
+
+/// The number of lines read to detect the prefix.
+pub const SAMPLE_LINES: usize = 64;
+
+/// The ratio of the sample lines that must start with the prefix.
+const MIN_RATIO: f32 = 0.9;
+
+/// The minimum number of sample lines, to not strip the message of a small source.
+const MIN_LINES: usize = 8;
+
+/// The shapes of the prefix fields.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Prefix {
+    shapes: Vec<String>,
+}
+
+/// The last characters of a prefix field.
+const DELIMITERS: &[char] = &[':', ']', '|'];
+
+/// The field with each number replaced by a `0`.
+fn shape(field: &str) -> String {
+    let mut shape = String::with_capacity(field.len());
+    for c in field.chars() {
+        if !c.is_ascii_digit() {
+            shape.push(c);
+        } else if !shape.ends_with('0') {
+            shape.push('0');
+        }
+    }
+    shape
+}
+
+impl Prefix {
+    pub fn detect<'a>(lines: impl IntoIterator<Item = &'a str>) -> Prefix {
+        let samples = lines
+            .into_iter()
+            .map(|line| line.split_whitespace().map(shape).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let mut shapes: Vec<String> = Vec::new();
+        if samples.len() < MIN_LINES {
+            return Prefix { shapes };
+        }
+        let min_count = (samples.len() as f32 * MIN_RATIO).ceil() as usize;
+        loop {
+            let pos = shapes.len();
+            // The lines having the prefix so far, and a message after the next field.
+            let mut counts: HashMap<&str, usize> = HashMap::new();
+            samples
+                .iter()
+                .filter(|fields| fields.len() > pos + 1 && fields[..pos] == shapes[..])
+                .for_each(|fields| *counts.entry(fields[pos].as_str()).or_default() += 1);
+            match counts.into_iter().max_by_key(|(_, count)| *count) {
+                Some((shape, count)) if count >= min_count => shapes.push(shape.to_string()),
+                _ => break,
+            }
+        }
+        let end = match shapes.iter().rposition(|shape| shape.ends_with(DELIMITERS)) {
+            Some(pos) => pos + 1,
+            None => shapes
+                .iter()
+                .take_while(|shape| !shape.chars().any(char::is_alphabetic))
+                .count(),
+        };
+        shapes.truncate(end);
+        Prefix { shapes }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    /// Remove the prefix, the lines without the prefix are not changed.
+    pub fn strip<'a>(&self, line: &'a str) -> &'a str {
+        let mut fields = line.split_whitespace();
+        let mut end = 0;
+        for expected in &self.shapes {
+            match fields.next() {
+                Some(field) if &shape(field) == expected => {
+                    end = field.as_ptr() as usize - line.as_ptr() as usize + field.len()
+                }
+                _ => return line,
+            }
+        }
+        match line[end..].trim_start() {
+            "" => line,
+            message => message,
+        }
+    }
+}
+
+#[test]
+fn test_prefix() {
+    let lines = (0..20)
+        .map(|pos| {
+            format!(
+                "2024-01-01 12:00:{:02} host app[{}]: request {} done",
+                pos,
+                100 + pos,
+                pos
+            )
+        })
+        .chain(std::iter::once(
+            "Traceback (most recent call last):".to_string(),
+        ))
+        .collect::<Vec<_>>();
+    let prefix = Prefix::detect(lines.iter().map(|line| line.as_str()));
+    // The repeated message is not part of the prefix.
+    assert_eq!(prefix.shapes, vec!["0-0-0", "0:0:0", "host", "app[0]:"]);
+    assert_eq!(
+        prefix.strip("2024-01-02 08:10:00 host app[42]: request 42 failed"),
+        "request 42 failed"
+    );
+    assert_eq!(prefix.strip(&lines[20]), lines[20]);
+    let empty = "2024-01-02 08:10:00 host app[42]:";
+    assert_eq!(prefix.strip(empty), empty);
+    assert!(Prefix::detect(lines[..4].iter().map(|line| line.as_str())).is_empty());
+}
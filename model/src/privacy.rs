@@ -0,0 +1,141 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module verifies that a model does not store the content of its baselines.
+//!
+//! The indexes only store the hashed features of the lines, and a model anonymized with
+//! [Model::anonymize] no longer contains the baselines locations. The audit searches the
+//! serialized model for every baseline line, and for its tokens as they are given to the index
+//! of the line, so that a model can be shared with confidence.
+
+use anyhow::{Context, Result};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryInto;
+use std::hash::Hasher;
+
+use crate::{IndexName, Model, Source};
+
+/// The shorter lines are too common to be considered as leaked, e.g. a lone bracket.
+const MIN_LINE_LEN: usize = 16;
+
+/// A baseline line found in the model.
+#[derive(Debug)]
+pub struct Leak {
+    pub source: Source,
+    pub pos: usize,
+}
+
+/// A baseline line, identified by its hash to keep the memory usage low.
+struct Needle {
+    len: usize,
+    hash: u64,
+    source: usize,
+    pos: usize,
+}
+
+fn hash_bytes(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(bytes);
+    hasher.finish()
+}
+
+fn prefix(bytes: &[u8]) -> u128 {
+    u128::from_le_bytes(bytes[..MIN_LINE_LEN].try_into().expect("A line prefix"))
+}
+
+/// The baselines lines, by their first bytes.
+#[derive(Default)]
+struct Needles(HashMap<u128, Vec<Needle>>);
+
+impl Needles {
+    fn insert(&mut self, line: &[u8], source: usize, pos: usize) {
+        if line.len() >= MIN_LINE_LEN {
+            self.0.entry(prefix(line)).or_default().push(Needle {
+                len: line.len(),
+                hash: hash_bytes(line),
+                source,
+                pos,
+            })
+        }
+    }
+
+    /// Add the (source, pos) of the lines that are in the haystack.
+    fn find(&self, haystack: &[u8], found: &mut HashSet<(usize, usize)>) {
+        for (offset, window) in haystack.windows(MIN_LINE_LEN).enumerate() {
+            if let Some(needles) = self.0.get(&prefix(window)) {
+                for needle in needles {
+                    if let Some(bytes) = haystack.get(offset..offset + needle.len) {
+                        if hash_bytes(bytes) == needle.hash {
+                            found.insert((needle.source, needle.pos));
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Search the lines of the sources in the serialized model and its shards.
+pub fn audit(model: &Model, sources: &[Source]) -> Result<Vec<Leak>> {
+    let mut needles = Needles::default();
+    for (source_idx, source) in sources.iter().enumerate() {
+        let reader = source.open()?;
+        for line in logreduce_iterator::BytesLines::new(reader, source.is_json()) {
+            let (bytes, pos) = line.with_context(|| format!("Failed to read {}", source))?;
+            needles.insert(&bytes, source_idx, pos);
+        }
+        // The stored rows are the tokens, which may differ from the raw line.
+        if let Some(index) = model.load_index(&IndexName::from_source(source))? {
+            crate::process::tokenize_reader(
+                &index.index,
+                source.is_json(),
+                source.open()?,
+                |pos, tokens| needles.insert(tokens.as_bytes(), source_idx, pos),
+            )
+            .with_context(|| format!("Failed to tokenize {}", source))?;
+        }
+    }
+
+    let mut found = HashSet::new();
+    // The model includes its indexes, unless they are stored in shards.
+    needles.find(
+        &bincode::serialize(&(model, &model.indexes)).context("Can't serialize model")?,
+        &mut found,
+    );
+    for index_name in model.shards.cells.keys() {
+        if let Some(index) = model.load_index(index_name)? {
+            needles.find(
+                &bincode::serialize(index).context("Can't serialize index")?,
+                &mut found,
+            );
+        }
+    }
+
+    let mut leaks = found
+        .into_iter()
+        .map(|(source_idx, pos)| Leak {
+            source: sources[source_idx].clone(),
+            pos,
+        })
+        .collect::<Vec<_>>();
+    leaks.sort_by(|x, y| (x.source.as_str(), x.pos).cmp(&(y.source.as_str(), y.pos)));
+    Ok(leaks)
+}
+
+#[test]
+fn test_needles() {
+    let mut needles = Needles::default();
+    needles.insert(b"Starting the controller service", 0, 1);
+    needles.insert(b"short line", 0, 2);
+    needles.insert(b"Connection refused to 10.0.0.1", 1, 42);
+
+    let mut found = HashSet::new();
+    let haystack = bincode::serialize(&vec!["short line", "Connection refused to 10.0.0.1"]);
+    needles.find(&haystack.unwrap(), &mut found);
+    assert_eq!(found.into_iter().collect::<Vec<_>>(), vec![(1, 42)]);
+
+    let mut found = HashSet::new();
+    needles.find(b"Connection refused to 10.0.0", &mut found);
+    assert!(found.is_empty());
+}
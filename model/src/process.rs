@@ -4,15 +4,130 @@
 //! This module provides the core utilities to use logreduce-index with Read objects.
 
 use anyhow::Result;
-use std::collections::{HashSet, VecDeque};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::io::Read;
+use std::time::Instant;
 
-use crate::{Anomaly, AnomalyContext, ChunkIndex};
+use crate::cancel::CancellationToken;
+use crate::profile::Framing;
+use crate::progress::ProgressObserver;
+use crate::{Anomaly, AnomalyContext, ChunkIndex, IndexName};
 use logreduce_iterator::LogLine;
 
-const THRESHOLD: logreduce_index::F = 0.3;
+pub const THRESHOLD: logreduce_index::F = 0.3;
 const CTX_DISTANCE: usize = 3;
 const CHUNK_SIZE: usize = 512;
+/// The maximum distance of a block start from the anomaly.
+const BLOCK_DISTANCE: usize = 50;
+/// The number of lines read between the deadline, the cancellation and the progress checks.
+const DEADLINE_LINES: usize = 1024;
+
+/// How the before context of an anomaly is collected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ContextMode {
+    /// A fixed count of lines.
+    #[default]
+    Lines,
+    /// Expand up to the start of the enclosing block, e.g. a traceback or an ansible task.
+    Block,
+}
+
+impl std::str::FromStr for ContextMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "lines" => Ok(ContextMode::Lines),
+            "block" => Ok(ContextMode::Block),
+            _ => Err(format!(
+                "Unknown context mode: {} (expected lines or block)",
+                s
+            )),
+        }
+    }
+}
+
+/// The unit of text that is scored, set when the index is trained.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Granularity {
+    /// Each line.
+    #[default]
+    Line,
+    /// The lines between the blank lines, e.g. a compiler error or an ansible task result.
+    Paragraph,
+    /// A fixed count of lines.
+    Lines(usize),
+}
+
+impl Granularity {
+    pub(crate) fn chunks(&self) -> Option<logreduce_iterator::Chunks> {
+        match self {
+            Granularity::Line => None,
+            Granularity::Paragraph => Some(logreduce_iterator::Chunks::Paragraph),
+            Granularity::Lines(count) => Some(logreduce_iterator::Chunks::Lines(*count)),
+        }
+    }
+}
+
+impl std::str::FromStr for Granularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "line" => Ok(Granularity::Line),
+            "paragraph" => Ok(Granularity::Paragraph),
+            _ => match s.parse::<usize>() {
+                Ok(count) if count > 0 => Ok(Granularity::Lines(count)),
+                _ => Err(format!(
+                    "Unknown granularity: {} (expected line, paragraph or a line count)",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// The granularity of the indexes matching the glob, or of every index, e.g.
+/// `job-output.txt=paragraph` or `5`.
+#[derive(Debug, Clone)]
+pub struct GranularityRule {
+    index: Option<regex::Regex>,
+    granularity: Granularity,
+}
+
+impl GranularityRule {
+    /// The granularity of the index, the first matching rule is used. Without the index name,
+    /// only the rules of every index match.
+    pub fn select(
+        rules: &[GranularityRule],
+        index_name: Option<&IndexName>,
+    ) -> Option<Granularity> {
+        rules
+            .iter()
+            .find(|rule| match (&rule.index, index_name) {
+                (None, _) => true,
+                (Some(re), Some(index_name)) => re.is_match(index_name.as_str()),
+                (Some(_), None) => false,
+            })
+            .map(|rule| rule.granularity)
+    }
+}
+
+impl std::str::FromStr for GranularityRule {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let (index, granularity) = match s.rsplit_once('=') {
+            Some((glob, granularity)) => (Some(crate::glob_regex(glob)), granularity),
+            None => (None, s),
+        };
+        Ok(GranularityRule {
+            index,
+            granularity: granularity.parse()?,
+        })
+    }
+}
 
 /// Helper struct to manage indexing multiples readers.
 pub struct ChunkTrainer<'a> {
@@ -20,10 +135,82 @@ pub struct ChunkTrainer<'a> {
     is_json: bool,
     skip_lines: HashSet<String>,
     baselines: Vec<String>,
+    /// The number of readers that contained each line, by [line_hash], they are added to the
+    /// index on completion.
+    origins: HashMap<u64, usize>,
     pub line_count: usize,
     pub byte_count: usize,
 }
 
+/// A line read ahead, with its byte offset and column.
+type SampleLine = (LogLine, (usize, usize));
+
+/// The score of every line of a source, see [ChunkProcessor::with_line_scores].
+#[derive(Debug, Default)]
+struct LineScores {
+    /// The line number and the [line_hash] of each line.
+    lines: Vec<(usize, u64)>,
+    /// The score of each line hash.
+    scores: HashMap<u64, f32>,
+    /// The lines that were scored with a previous source, they are scored again at the end.
+    unscored: HashMap<u64, String>,
+}
+
+/// Read the first lines of a source to detect their framing, when the index strips the prefix
+/// or uses the source profiles.
+fn sample_framing<R: Read>(
+    lines: &mut logreduce_iterator::BytesLines<R>,
+    index: &ChunkIndex,
+    is_json: bool,
+) -> Result<(Framing, Vec<SampleLine>)> {
+    let mut sample = Vec::new();
+    if index.strip_prefix() || index.source_profiles() {
+        while sample.len() < crate::prefix::SAMPLE_LINES {
+            match lines.next() {
+                Some(line) => sample.push((line?, (lines.offset(), lines.column()))),
+                None => break,
+            }
+        }
+    }
+    let lines = sample
+        .iter()
+        .map(|((bytes, _), _)| String::from_utf8_lossy(&bytes[..]))
+        .collect::<Vec<_>>();
+    let framing = Framing::detect(index, is_json, lines.iter().map(|line| line.as_ref()));
+    if let Some(kind) = framing.kind {
+        tracing::debug!(%kind, "Detected the source kind");
+    }
+    Ok((framing, sample))
+}
+
+/// Tokenize the lines of a reader like a [ChunkTrainer], without indexing them, e.g. to audit
+/// what an index stores. The callback gets the line number and the tokens.
+pub fn tokenize_reader<R: Read>(
+    index: &ChunkIndex,
+    is_json: bool,
+    read: R,
+    mut on_tokens: impl FnMut(usize, String),
+) -> Result<()> {
+    let mut lines = logreduce_iterator::BytesLines::new(read, is_json)
+        .with_json_blocks(index.json_blocks())
+        .with_chunks(index.granularity().chunks());
+    let (framing, sample) = sample_framing(&mut lines, index, is_json)?;
+    let sample = sample.into_iter().map(|(line, _)| Ok(line));
+    for line in sample.chain(lines) {
+        let line = line?;
+        let raw_str = String::from_utf8_lossy(&line.0[..]);
+        on_tokens(line.1, index.tokenize(&framing.apply(&raw_str)));
+    }
+    Ok(())
+}
+
+/// A stable hash of a tokenized line (FNV-1a), to count the line origins.
+pub fn line_hash(tokens: &str) -> u64 {
+    tokens.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
 impl<'a> ChunkTrainer<'a> {
     pub fn new(index: &'a mut ChunkIndex, is_json: bool) -> ChunkTrainer<'a> {
         ChunkTrainer {
@@ -31,6 +218,7 @@ impl<'a> ChunkTrainer<'a> {
             is_json,
             skip_lines: HashSet::new(),
             baselines: Vec::new(),
+            origins: HashMap::new(),
             line_count: 0,
             byte_count: 0,
         }
@@ -44,35 +232,255 @@ impl<'a> ChunkTrainer<'a> {
         Ok(())
     }
 
+    /// Index the lines of a reader, the lines already in the index are skipped.
     pub fn add<R: Read>(&mut self, read: R) -> Result<()> {
-        for line in logreduce_iterator::BytesLines::new(read, self.is_json) {
+        self.add_with(read, |_| {})
+    }
+
+    /// Index the lines of a reader, and give their tokens to the callback, e.g. to train
+    /// another index without tokenizing the lines again.
+    pub fn add_with<R: Read>(&mut self, read: R, mut on_tokens: impl FnMut(&str)) -> Result<()> {
+        let mut reader_lines = HashSet::new();
+        let mut lines = logreduce_iterator::BytesLines::new(read, self.is_json)
+            .with_json_blocks(self.index.json_blocks())
+            .with_chunks(self.index.granularity().chunks());
+        let (framing, sample) = sample_framing(&mut lines, self.index, self.is_json)?;
+        let sample = sample.into_iter().map(|(line, _)| Ok(line));
+        for line in sample.chain(lines) {
             let line = line?;
-            let raw_str = std::str::from_utf8(&line.0[..])
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            self.line_count += 1;
+            // The invalid UTF-8 sequences are replaced, like the anomaly lines.
+            let raw_str = String::from_utf8_lossy(&line.0[..]);
+            self.line_count += physical_lines(&line.0);
             self.byte_count += line.0.len();
-            let tokens = self.index.tokenize(raw_str);
+            let tokens = self.index.tokenize(&framing.apply(&raw_str));
+            on_tokens(&tokens);
+            self.add_tokens(tokens, &mut reader_lines);
+        }
+        Ok(())
+    }
 
-            if !self.skip_lines.contains(&tokens) {
-                self.skip_lines.insert(tokens.clone());
-                self.baselines.push(tokens);
+    /// The granularity of the index, the trainers only share their tokens when it is the same.
+    pub fn granularity(&self) -> Granularity {
+        self.index.granularity()
+    }
 
-                if self.baselines.len() == CHUNK_SIZE {
-                    self.index.add(&self.baselines);
-                    self.baselines.clear();
-                }
+    /// Index the lines of a source that are already tokenized, e.g. by another system.
+    pub fn add_tokenized(&mut self, lines: impl IntoIterator<Item = String>) {
+        let mut reader_lines = HashSet::new();
+        for tokens in lines {
+            self.add_token_line(tokens, &mut reader_lines);
+        }
+    }
+
+    /// Index a line that is already tokenized, the reader lines are the hashes of the lines
+    /// already seen in its source, so that a source is counted once per origin.
+    pub fn add_token_line(&mut self, tokens: String, reader_lines: &mut HashSet<u64>) {
+        self.line_count += 1;
+        self.byte_count += tokens.len();
+        self.add_tokens(tokens, reader_lines);
+    }
+
+    fn add_tokens(&mut self, tokens: String, reader_lines: &mut HashSet<u64>) {
+        let hash = line_hash(&tokens);
+        if reader_lines.insert(hash) {
+            *self.origins.entry(hash).or_default() += 1;
+        }
+
+        if !self.skip_lines.contains(&tokens) && !self.index.is_known(&tokens) {
+            self.skip_lines.insert(tokens.clone());
+            self.baselines.push(tokens);
+
+            if self.baselines.len() == CHUNK_SIZE {
+                self.index.add(&self.baselines);
+                self.baselines.clear();
             }
         }
-        Ok(())
     }
 
     pub fn complete(&mut self) {
         if !self.baselines.is_empty() {
             self.index.add(&self.baselines);
         }
+        self.index.add_origins(&std::mem::take(&mut self.origins));
+        self.index.damp();
+    }
+}
+
+/// A line scored by a [LineScorer], which is a chunk of lines with the index granularity.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScoredLine {
+    /// The line, or the lines of the chunk joined with a new line.
+    pub line: String,
+    /// The number of the first line.
+    pub pos: usize,
+    pub distance: f32,
+    /// The deterministic id of the line, see [crate::anomaly_id].
+    pub id: String,
+}
+
+/// Helper struct to score the lines one by one, e.g. the lines of a followed journal.
+/// The lines are framed and grouped like the lines of a [ChunkTrainer], so that their tokens
+/// and their anomaly ids are the same as with a [ChunkProcessor]. The lines are scored once the
+/// framing is detected and their chunk is complete, see [LineScorer::finish] for the last ones.
+pub struct LineScorer<'a> {
+    index: &'a ChunkIndex,
+    index_name: Option<IndexName>,
+    chunks: Option<logreduce_iterator::Chunks>,
+    /// The lines of the current chunk, with the number of the first line.
+    chunk: Vec<String>,
+    chunk_pos: usize,
+    line_count: usize,
+    /// The framing of the lines, once the first chunks are read.
+    framing: Option<Framing>,
+    /// The chunks read to detect the framing.
+    sample: Vec<(String, usize)>,
+}
+
+impl<'a> LineScorer<'a> {
+    pub fn new(index: &'a ChunkIndex, index_name: Option<IndexName>) -> LineScorer<'a> {
+        let chunks = index
+            .granularity()
+            .chunks()
+            .filter(|chunks| !matches!(chunks, logreduce_iterator::Chunks::Lines(0..=1)));
+        let framing = if index.strip_prefix() || index.source_profiles() {
+            None
+        } else {
+            Some(Framing::default())
+        };
+        LineScorer {
+            index,
+            index_name,
+            chunks,
+            chunk: Vec::new(),
+            chunk_pos: 0,
+            line_count: 0,
+            framing,
+            sample: Vec::new(),
+        }
+    }
+
+    /// Add the next line, this returns the lines that are ready to be scored.
+    pub fn push(&mut self, line: String) -> Vec<ScoredLine> {
+        self.push_all(std::iter::once(line))
+    }
+
+    /// Add the next lines, the lines that are ready are scored together with a single search.
+    pub fn push_all(&mut self, lines: impl IntoIterator<Item = String>) -> Vec<ScoredLine> {
+        let mut ready = Vec::new();
+        for line in lines {
+            self.line_count += 1;
+            let chunk = match self.chunks {
+                None => Some((line, self.line_count)),
+                Some(chunks) => self.push_chunk(chunks, line),
+            };
+            if let Some((chunk, pos)) = chunk {
+                ready.extend(self.push_sample(chunk, pos));
+            }
+        }
+        self.score(ready)
+    }
+
+    /// Group the lines like [logreduce_iterator::BytesLines::with_chunks].
+    fn push_chunk(
+        &mut self,
+        chunks: logreduce_iterator::Chunks,
+        line: String,
+    ) -> Option<(String, usize)> {
+        let max_lines = match chunks {
+            logreduce_iterator::Chunks::Paragraph => {
+                // The empty lines are not scored, they end the paragraph.
+                if line.trim().is_empty() {
+                    return self.take_chunk();
+                }
+                logreduce_iterator::MAX_BLOCK_LINES
+            }
+            logreduce_iterator::Chunks::Lines(count) => count,
+        };
+        if self.chunk.is_empty() {
+            self.chunk_pos = self.line_count;
+        }
+        self.chunk.push(line);
+        if self.chunk.len() == max_lines {
+            self.take_chunk()
+        } else {
+            None
+        }
+    }
+
+    fn take_chunk(&mut self) -> Option<(String, usize)> {
+        if self.chunk.is_empty() {
+            None
+        } else {
+            Some((std::mem::take(&mut self.chunk).join("\n"), self.chunk_pos))
+        }
+    }
+
+    /// The chunks that are ready to be scored, once the framing is detected.
+    fn push_sample(&mut self, chunk: String, pos: usize) -> Vec<(String, usize)> {
+        if self.framing.is_some() {
+            return vec![(chunk, pos)];
+        }
+        self.sample.push((chunk, pos));
+        if self.sample.len() < crate::prefix::SAMPLE_LINES {
+            Vec::new()
+        } else {
+            self.detect_framing()
+        }
+    }
+
+    /// Detect the framing with the chunks read, and return them.
+    fn detect_framing(&mut self) -> Vec<(String, usize)> {
+        let lines = self.sample.iter().map(|(line, _)| line.as_str());
+        self.framing = Some(Framing::detect(self.index, false, lines));
+        std::mem::take(&mut self.sample)
+    }
+
+    fn score(&self, chunks: Vec<(String, usize)>) -> Vec<ScoredLine> {
+        if chunks.is_empty() {
+            return Vec::new();
+        }
+        let tokens = chunks
+            .iter()
+            .map(|(line, _)| match &self.framing {
+                Some(framing) => self.index.tokenize(&framing.apply(line)),
+                None => self.index.tokenize(line),
+            })
+            .collect::<Vec<_>>();
+        let distances = self.index.search(&tokens);
+        chunks
+            .into_iter()
+            .zip(tokens.iter().zip(distances))
+            .map(|((line, pos), (tokens, distance))| ScoredLine {
+                id: crate::anomaly_id(self.index_name.as_ref(), tokens),
+                line,
+                pos,
+                distance,
+            })
+            .collect()
+    }
+
+    /// Score the lines that are left at the end of the input.
+    pub fn finish(&mut self) -> Vec<ScoredLine> {
+        let mut ready = Vec::new();
+        if let Some((chunk, pos)) = self.take_chunk() {
+            ready.extend(self.push_sample(chunk, pos));
+        }
+        if self.framing.is_none() {
+            ready.extend(self.detect_framing());
+        }
+        self.score(ready)
     }
 }
 
+/// A line of a retry loop, see [crate::retry].
+struct RetryLine {
+    coord: usize,
+    pos: usize,
+    /// The coordinate of the first attempt of the loop.
+    retry_loop: usize,
+    attempt: crate::retry::Attempt,
+}
+
 /// Helper struct to manage the log lines and the unique tokenized lines.
 /// The goal is to perform the index search on unique lines, while keeping a
 /// buffer of the raw line to manage the surrounding context.
@@ -81,6 +489,8 @@ pub struct ChunkProcessor<'a, R: Read> {
     index: &'a ChunkIndex,
     /// The raw log line with their global position
     buffer: Vec<(logreduce_iterator::LogLine, usize)>,
+    /// The byte offset and column of each buffer line.
+    buffer_offsets: Vec<(usize, usize)>,
     /// The target tokenized lines
     targets: Vec<String>,
     /// The target positions
@@ -93,27 +503,81 @@ pub struct ChunkProcessor<'a, R: Read> {
     anomalies: VecDeque<AnomalyContext>,
     /// The list of unique log lines, to avoid searching a line twice.
     skip_lines: &'a mut HashSet<String>,
+    /// The number of times a skipped line was seen again.
+    duplicates: HashMap<String, usize>,
+    /// The hash and position of the first and last lines of the current run of identical lines.
+    run: Option<(u64, usize, usize)>,
+    /// The number of consecutive repetitions of each line, indexed by its position.
+    runs: HashMap<usize, usize>,
+    /// The tokenized line of each anomaly position.
+    anomaly_tokens: Vec<(usize, String)>,
     /// The current line coordinate.
     coord: usize,
+    context_mode: ContextMode,
+    /// The coordinate and name of the test cases found in the source.
+    test_cases: Vec<(usize, String)>,
+    /// The current playbook and the coordinate and name of the tasks found in the source.
+    playbook: Option<String>,
+    tasks: Vec<(usize, String)>,
+    /// The coordinate of the shell commands and of the exit statuses found in the source.
+    commands: Vec<(usize, String)>,
+    statuses: Vec<(usize, i32)>,
+    /// The attempt lines, and the loop of each key since the last line that is not an attempt.
+    retry_lines: Vec<RetryLine>,
+    open_retries: HashMap<String, usize>,
+    /// The position and coordinate of the anomaly of each retry loop, the next attempts are
+    /// collapsed in it.
+    retry_anomalies: HashMap<usize, (usize, usize)>,
+    /// The coordinate of the anomalies that are not returned yet, by position.
+    anomaly_coords: HashMap<usize, usize>,
     /// Total lines count
     pub line_count: usize,
     /// Total bytes count
     pub byte_count: usize,
+    /// The time after which the rest of the source is not read.
+    deadline: Option<Instant>,
+    /// The source was not completely read because of the deadline.
+    pub timed_out: bool,
+    /// The token to stop reading the source, see [crate::cancel].
+    cancellation: Option<CancellationToken>,
+    /// The source was not completely read because the run was cancelled.
+    pub cancelled: bool,
+    /// The reading is done, the anomalies left are complete.
+    finished: bool,
+    /// The source is read as json, see [crate::Source::is_json].
+    is_json: bool,
+    /// The framing of the source lines, once the first lines are read.
+    framing: Option<Framing>,
+    /// The lines read to detect the framing.
+    pending: VecDeque<SampleLine>,
+    /// The index name of the source, to compute the anomaly ids.
+    index_name: Option<IndexName>,
+    /// The observer of the lines read, with the source being read.
+    progress: Option<(&'a dyn ProgressObserver, crate::Source)>,
+    /// The score of every line, until they are given to the observer.
+    line_scores: Option<LineScores>,
 }
 
 impl<'a, R: Read> Iterator for ChunkProcessor<'a, R> {
     type Item = Result<AnomalyContext>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.anomalies
-            .pop_front()
-            .map(Ok)
-            .or_else(|| match self.read_anomalies() {
-                // When read_anomalies doesn't push new anomalies, that means we reach the end.
-                Ok(()) if self.anomalies.is_empty() => None,
-                Ok(()) => self.next(),
-                Err(e) => Some(Err(e)),
-            })
+        loop {
+            if let Some(mut anomaly) = self.anomalies.pop_front() {
+                if self.update_command(&mut anomaly) {
+                    self.anomaly_coords.remove(&anomaly.anomaly.pos);
+                    return Some(Ok(anomaly));
+                }
+                // The anomaly is kept until the status of its command is found.
+                self.anomalies.push_front(anomaly);
+            }
+            if self.finished {
+                return None;
+            }
+            if let Err(e) = self.read_anomalies() {
+                return Some(Err(e));
+            }
+        }
     }
 }
 
@@ -125,27 +589,162 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         skip_lines: &'a mut HashSet<String>,
     ) -> ChunkProcessor<'a, R> {
         ChunkProcessor {
-            reader: logreduce_iterator::BytesLines::new(read, is_json),
+            reader: logreduce_iterator::BytesLines::new(read, is_json)
+                .with_json_blocks(index.json_blocks())
+                .with_chunks(index.granularity().chunks()),
             index,
             buffer: Vec::new(),
+            buffer_offsets: Vec::new(),
             left_overs: Vec::new(),
             targets: Vec::with_capacity(CHUNK_SIZE),
             targets_coord: Vec::with_capacity(CHUNK_SIZE),
             current_anomaly: None,
             anomalies: VecDeque::new(),
             skip_lines,
+            duplicates: HashMap::new(),
+            run: None,
+            runs: HashMap::new(),
+            anomaly_tokens: Vec::new(),
             coord: 0,
+            context_mode: ContextMode::default(),
+            test_cases: Vec::new(),
+            playbook: None,
+            tasks: Vec::new(),
+            commands: Vec::new(),
+            statuses: Vec::new(),
+            retry_lines: Vec::new(),
+            open_retries: HashMap::new(),
+            retry_anomalies: HashMap::new(),
+            anomaly_coords: HashMap::new(),
             line_count: 0,
             byte_count: 0,
+            deadline: None,
+            timed_out: false,
+            cancellation: None,
+            cancelled: false,
+            finished: false,
+            is_json,
+            framing: None,
+            pending: VecDeque::new(),
+            index_name: None,
+            progress: None,
+            line_scores: None,
+        }
+    }
+
+    pub fn with_context_mode(self, context_mode: ContextMode) -> ChunkProcessor<'a, R> {
+        ChunkProcessor {
+            context_mode,
+            ..self
+        }
+    }
+
+    /// Stop reading the source at the deadline, the anomalies already found are kept.
+    /// The deadline is checked between the lines: a stalled download is stopped by the client
+    /// timeout, see [crate::net::Settings], and a line longer than
+    /// [logreduce_iterator::MAX_DROPPED_LENGTH] ends the source.
+    pub fn with_deadline(self, deadline: Option<Instant>) -> ChunkProcessor<'a, R> {
+        ChunkProcessor { deadline, ..self }
+    }
+
+    /// Stop reading the source when the token is cancelled, like [ChunkProcessor::with_deadline].
+    pub fn with_cancellation(
+        self,
+        cancellation: Option<CancellationToken>,
+    ) -> ChunkProcessor<'a, R> {
+        ChunkProcessor {
+            cancellation,
+            ..self
+        }
+    }
+
+    /// Set the index name of the source, see [crate::anomaly_id].
+    pub fn with_index_name(self, index_name: IndexName) -> ChunkProcessor<'a, R> {
+        ChunkProcessor {
+            index_name: Some(index_name),
+            ..self
+        }
+    }
+
+    /// Report the lines read to the observer, see [ProgressObserver::lines_read].
+    pub fn with_progress(
+        self,
+        progress: &'a dyn ProgressObserver,
+        source: crate::Source,
+    ) -> ChunkProcessor<'a, R> {
+        ChunkProcessor {
+            progress: Some((progress, source)),
+            ..self
+        }
+    }
+
+    /// Keep the score of every line, to give them to [ProgressObserver::lines_scored] once the
+    /// source is read.
+    pub fn with_line_scores(self, enabled: bool) -> ChunkProcessor<'a, R> {
+        ChunkProcessor {
+            line_scores: if enabled {
+                Some(LineScores::default())
+            } else {
+                None
+            },
+            ..self
+        }
+    }
+
+    /// Give the score of every line to the observer, the lines that were skipped because of a
+    /// previous source are scored here.
+    fn report_line_scores(&mut self) {
+        let (mut line_scores, (progress, source)) = match (self.line_scores.take(), &self.progress)
+        {
+            (Some(line_scores), Some(progress)) => (line_scores, progress),
+            _ => return,
+        };
+        let (hashes, tokens): (Vec<_>, Vec<_>) = std::mem::take(&mut line_scores.unscored)
+            .into_iter()
+            .filter(|(hash, _)| !line_scores.scores.contains_key(hash))
+            .unzip();
+        if !tokens.is_empty() {
+            let distances = self.index.search(&tokens);
+            line_scores.scores.extend(hashes.into_iter().zip(distances));
+        }
+        let scores = line_scores
+            .lines
+            .iter()
+            .map(|(pos, hash)| {
+                (
+                    *pos,
+                    line_scores.scores.get(hash).copied().unwrap_or_default(),
+                )
+            })
+            .collect::<Vec<_>>();
+        progress.lines_scored(source, &scores);
+    }
+
+    fn next_line(&mut self) -> Option<Result<SampleLine>> {
+        match self.pending.pop_front() {
+            Some(line) => Some(Ok(line)),
+            None => self.reader.next().map(|line| {
+                let position = (self.reader.offset(), self.reader.column());
+                Ok((line?, position))
+            }),
         }
     }
 
     fn read_anomalies(&mut self) -> Result<()> {
-        while let Some(line) = self.reader.next() {
-            let line = line?;
-            let raw_str = std::str::from_utf8(&line.0[..])
-                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
-            self.line_count += 1;
+        if self.timed_out || self.cancelled {
+            self.finished = true;
+            return Ok(());
+        }
+        if self.framing.is_none() {
+            let (framing, sample) = sample_framing(&mut self.reader, self.index, self.is_json)?;
+            self.framing = Some(framing);
+            self.pending = sample.into();
+        }
+        while let Some(line) = self.next_line() {
+            let (line, position) = line?;
+            let raw_str = String::from_utf8_lossy(&line.0[..]);
+            let raw_str = raw_str.as_ref();
+            self.line_count += physical_lines(&line.0);
             self.byte_count += line.0.len();
             self.coord += 1;
 
@@ -154,11 +753,78 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
                 break;
             }
 
+            if let Some(name) = crate::segment::test_case_start(raw_str) {
+                self.test_cases.push((self.coord, name.to_string()));
+            }
+            match crate::segment::step_start(raw_str) {
+                Some(crate::segment::Step::Playbook(name)) => {
+                    self.playbook = Some(name.to_string());
+                    self.tasks.push((self.coord, name.to_string()));
+                }
+                Some(crate::segment::Step::Task(name)) => {
+                    let task = match &self.playbook {
+                        Some(playbook) => format!("{}: {}", playbook, name),
+                        None => name.to_string(),
+                    };
+                    self.tasks.push((self.coord, task));
+                }
+                None => {}
+            }
+            if let Some(command) = crate::segment::command_start(raw_str) {
+                self.commands.push((self.coord, command.to_string()));
+            } else if let Some(status) = crate::segment::exit_status(raw_str) {
+                self.statuses.push((self.coord, status));
+            }
+            match crate::retry::attempt(raw_str) {
+                Some(attempt) => {
+                    // A gap in the line numbers ends the loops, like a line that is not an attempt.
+                    if matches!(self.retry_lines.last(), Some(last) if last.pos + 1 != line.1) {
+                        self.open_retries.clear();
+                    }
+                    let key = crate::retry::retry_key(raw_str);
+                    let retry_loop = *self.open_retries.entry(key).or_insert(self.coord);
+                    self.retry_lines.push(RetryLine {
+                        coord: self.coord,
+                        pos: line.1,
+                        retry_loop,
+                        attempt,
+                    });
+                }
+                None => self.open_retries.clear(),
+            }
+
             // Call the static method of the ChunkIndex trait
-            let tokens = self.index.tokenize(raw_str);
+            let tokens = match &self.framing {
+                Some(framing) => self.index.tokenize(&framing.apply(raw_str)),
+                None => self.index.tokenize(raw_str),
+            };
+
+            if let Some(line_scores) = &mut self.line_scores {
+                let hash = line_hash(&tokens);
+                // Each line of a chunk gets the chunk score.
+                for idx in 0..physical_lines(&line.0) {
+                    line_scores.lines.push((line.1 + idx, hash));
+                }
+                if self.skip_lines.contains(&tokens) && !line_scores.scores.contains_key(&hash) {
+                    line_scores
+                        .unscored
+                        .entry(hash)
+                        .or_insert_with(|| tokens.clone());
+                }
+            }
+
+            let hash = line_hash(&tokens);
+            match &mut self.run {
+                Some((run_hash, first, last)) if *run_hash == hash && *last + 1 == line.1 => {
+                    *last = line.1;
+                    *self.runs.entry(*first).or_insert(0) += 1;
+                }
+                _ => self.run = Some((hash, line.1, line.1)),
+            }
 
             // Keep in the buffer all the lines until we get CHUNK_SIZE unique lines
             self.buffer.push((line, self.coord));
+            self.buffer_offsets.push(position);
 
             if !self.skip_lines.contains(&tokens) {
                 self.skip_lines.insert(tokens.clone());
@@ -172,11 +838,29 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
                         return Ok(());
                     }
                 }
-            } else if self.buffer.len() > CHUNK_SIZE * 10 {
-                // the source contains mostly duplicate line.
-                self.do_search_anomalies();
-                if !self.anomalies.is_empty() {
-                    return Ok(());
+            } else {
+                // The line is only counted, it is not scored again.
+                *self.duplicates.entry(tokens).or_insert(0) += 1;
+                if self.buffer.len() > CHUNK_SIZE * 10 {
+                    // the source contains mostly duplicate line.
+                    self.do_search_anomalies();
+                    if !self.anomalies.is_empty() {
+                        return Ok(());
+                    }
+                }
+            }
+
+            if self.coord % DEADLINE_LINES == 0 {
+                if let Some((progress, source)) = &self.progress {
+                    progress.lines_read(source, self.line_count, self.byte_count);
+                }
+                if matches!(self.deadline, Some(deadline) if Instant::now() >= deadline) {
+                    self.timed_out = true;
+                    break;
+                }
+                if matches!(&self.cancellation, Some(token) if token.is_cancelled()) {
+                    self.cancelled = true;
+                    break;
                 }
             }
         }
@@ -190,18 +874,27 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
             self.anomalies.push_back(anomaly.clone());
             self.current_anomaly = None;
         }
+        self.report_line_scores();
+        self.finished = true;
         Ok(())
     }
 
     /// Helper function for the anomalies_from_reader implementation.
     fn do_search_anomalies(&mut self) {
         let distances = self.index.search(&self.targets);
+        if let Some(line_scores) = &mut self.line_scores {
+            for (tokens, distance) in self.targets.iter().zip(distances.iter()) {
+                line_scores.scores.insert(line_hash(tokens), *distance);
+            }
+        }
 
         let mut buffer_pos = 0;
         let mut last_context_pos = 0;
 
-        for (distance, coord) in distances.iter().zip(self.targets_coord.iter()) {
-            let is_anomaly = distance > &THRESHOLD;
+        for (target_pos, (distance, coord)) in
+            distances.iter().zip(self.targets_coord.iter()).enumerate()
+        {
+            let is_anomaly = distance > &THRESHOLD && !self.is_next_attempt(*coord);
 
             // The distances and coords are out of sync with the buffer, because they only contains unique line.
             // Thus for each distance, we need to find the matching raw lines in the buffer.
@@ -213,13 +906,17 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
 
                 if distance_found_in_buffer && is_anomaly {
                     // We found the target in the buffer, and it is an anomaly
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
+                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes);
                     target_str = Some((raw_str, line_number));
                 } else if let Some(anomaly) = &mut self.current_anomaly {
                     // The buffer head is not anomaly, and we are still processing the last anomaly found.
-                    // In that case, we add the log line to the after context.
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
-                    anomaly.after.push(raw_str);
+                    // In that case, we add the log line to the after context, unless it repeats the anomaly.
+                    if is_run(&self.runs, anomaly, *line_number) {
+                        anomaly.anomaly.run += 1;
+                    } else {
+                        let raw_str = logreduce_iterator::clone_bytes_to_string(bytes);
+                        push_after(&mut anomaly.after, raw_str);
+                    }
                     if anomaly.after.len() >= CTX_DISTANCE {
                         // The current anomaly is completed. TODO: try using std::mem::replace
                         self.anomalies.push_back(anomaly.clone());
@@ -234,6 +931,7 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
             }
 
             if let Some((log_line, log_pos)) = target_str {
+                let (offset, column) = self.buffer_offsets[buffer_pos - 1];
                 if let Some(anomaly) = &self.current_anomaly {
                     // We can push the current anomaly because any needed after context would overlap with the current anomaly.
                     self.anomalies.push_back(anomaly.clone());
@@ -241,22 +939,51 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
                 }
 
                 // Grab before context
-                let before = collect_before(
-                    buffer_pos - 1,
-                    last_context_pos,
-                    &self.buffer,
-                    &self.left_overs,
-                );
+                let block_before = match self.context_mode {
+                    ContextMode::Lines => None,
+                    ContextMode::Block => {
+                        collect_block_before(buffer_pos - 1, last_context_pos, &self.buffer)
+                            .map(split_chunks)
+                    }
+                };
+                let before = block_before.unwrap_or_else(|| {
+                    let mut before = split_chunks(collect_before(
+                        buffer_pos - 1,
+                        last_context_pos,
+                        &self.buffer,
+                        &self.left_overs,
+                    ));
+                    before.drain(..before.len().saturating_sub(CTX_DISTANCE));
+                    before
+                });
 
                 last_context_pos = buffer_pos;
 
+                if let Some(retry_loop) = self.retry_loop(*coord) {
+                    self.retry_anomalies.insert(retry_loop, (*log_pos, *coord));
+                }
+                let tokens = &self.targets[target_pos];
+                let id = crate::anomaly_id(self.index_name.as_ref(), tokens);
+                self.anomaly_coords.insert(*log_pos, *coord);
+                self.anomaly_tokens.push((*log_pos, tokens.clone()));
                 self.current_anomaly = Some(AnomalyContext {
                     before,
                     after: Vec::new(),
                     anomaly: Anomaly {
+                        id,
                         distance: *distance,
                         pos: *log_pos,
+                        offset,
+                        column,
+                        level: crate::level::Level::parse(&log_line),
                         line: log_line,
+                        test: self.test_case(*coord),
+                        task: self.task(*coord),
+                        command: self.command(*coord),
+                        hint: None,
+                        repeat: 0,
+                        run: 0,
+                        retry: None,
                     },
                 });
             } else if is_anomaly {
@@ -270,9 +997,13 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         // Handle the last anomaly after context
         if let Some(anomaly) = &mut self.current_anomaly {
             if last_context_pos < self.buffer.len() {
-                for ((bytes, _), _) in &self.buffer[last_context_pos..] {
-                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes).unwrap();
-                    anomaly.after.push(raw_str);
+                for ((bytes, line_number), _) in &self.buffer[last_context_pos..] {
+                    if is_run(&self.runs, anomaly, *line_number) {
+                        anomaly.anomaly.run += 1;
+                        continue;
+                    }
+                    let raw_str = logreduce_iterator::clone_bytes_to_string(bytes);
+                    push_after(&mut anomaly.after, raw_str);
                     if anomaly.after.len() >= CTX_DISTANCE {
                         // The current anomaly is completed. TODO: try using std::mem::replace
                         self.anomalies.push_back(anomaly.clone());
@@ -285,6 +1016,110 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         self.reset(last_context_pos)
     }
 
+    /// The name of the test case that was running at the given coordinate.
+    fn test_case(&self, coord: usize) -> Option<String> {
+        let idx = self
+            .test_cases
+            .partition_point(|(start, _)| *start <= coord);
+        idx.checked_sub(1).map(|idx| self.test_cases[idx].1.clone())
+    }
+
+    /// The playbook and task that was running at the given coordinate.
+    fn task(&self, coord: usize) -> Option<String> {
+        let idx = self.tasks.partition_point(|(start, _)| *start <= coord);
+        idx.checked_sub(1).map(|idx| self.tasks[idx].1.clone())
+    }
+
+    /// The command that was running at the given coordinate, with the first exit status found
+    /// before the next command, when it is already read.
+    fn command(&self, coord: usize) -> Option<crate::segment::Command> {
+        let idx = self.commands.partition_point(|(start, _)| *start <= coord);
+        let idx = idx.checked_sub(1)?;
+        let end = self
+            .commands
+            .get(idx + 1)
+            .map_or(usize::MAX, |(start, _)| *start);
+        let status = self.statuses[self.statuses.partition_point(|(pos, _)| *pos < coord)..]
+            .iter()
+            .take_while(|(pos, _)| *pos < end)
+            .map(|(_, status)| *status)
+            .next();
+        Some(crate::segment::Command {
+            line: self.commands[idx].1.clone(),
+            status,
+        })
+    }
+
+    /// Update the command status of the anomaly, this returns false when the status may still be
+    /// found in the next lines, before the next command or the end of the source.
+    fn update_command(&self, anomaly: &mut AnomalyContext) -> bool {
+        let coord = match &anomaly.anomaly.command {
+            Some(command) if command.status.is_none() => {
+                match self.anomaly_coords.get(&anomaly.anomaly.pos) {
+                    Some(coord) => *coord,
+                    None => return true,
+                }
+            }
+            _ => return true,
+        };
+        anomaly.anomaly.command = self.command(coord);
+        self.finished
+            || matches!(&anomaly.anomaly.command, Some(command) if command.status.is_some())
+            || matches!(self.commands.last(), Some((start, _)) if *start > coord)
+    }
+
+    /// The retry loop of the line at the given coordinate, identified by its first coordinate.
+    fn retry_loop(&self, coord: usize) -> Option<usize> {
+        let idx = self.retry_lines.partition_point(|line| line.coord < coord);
+        match self.retry_lines.get(idx) {
+            Some(line) if line.coord == coord => Some(line.retry_loop),
+            _ => None,
+        }
+    }
+
+    /// Check if the line is an attempt of a retry loop that already has an anomaly.
+    fn is_next_attempt(&self, coord: usize) -> bool {
+        matches!(self.retry_loop(coord), Some(retry_loop) if self.retry_anomalies.contains_key(&retry_loop))
+    }
+
+    /// The retry loop of each anomaly that has more than one attempt, indexed by the anomaly
+    /// position. The attempts before the anomaly are not counted.
+    /// This is only complete once the processor reached the end of the source.
+    pub fn retry_loops(&self) -> HashMap<usize, crate::retry::RetryLoop> {
+        self.retry_anomalies
+            .iter()
+            .filter_map(|(retry_loop_id, (pos, coord))| {
+                let idx = self.retry_lines.partition_point(|line| line.coord < *coord);
+                // The attempts of a loop are consecutive lines.
+                let mut next_coord = *coord;
+                let mut retry_loop = crate::retry::RetryLoop::default();
+                for line in self.retry_lines[idx..].iter().take_while(|line| {
+                    let consecutive = line.coord == next_coord;
+                    next_coord += 1;
+                    consecutive
+                }) {
+                    if line.retry_loop == *retry_loop_id {
+                        retry_loop.add(&line.attempt, line.pos);
+                    }
+                }
+                if retry_loop.count > 1 {
+                    Some((*pos, retry_loop))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// The number of times each anomaly was repeated, indexed by the anomaly position.
+    /// This is only complete once the processor reached the end of the source.
+    pub fn repeats(&self) -> HashMap<usize, usize> {
+        self.anomaly_tokens
+            .iter()
+            .filter_map(|(pos, tokens)| self.duplicates.get(tokens).map(|count| (*pos, *count)))
+            .collect()
+    }
+
     fn reset(&mut self, left_overs_pos: usize) {
         self.targets.clear();
         self.targets_coord.clear();
@@ -299,12 +1134,110 @@ impl<'a, R: Read> ChunkProcessor<'a, R> {
         self.left_overs = self.buffer[max_left_overs_pos..]
             .iter()
             // TODO: use direct bytes -> str conversion.
-            .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes).unwrap())
+            .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes))
             .collect();
         self.buffer.clear();
+        self.buffer_offsets.clear();
+    }
+}
+
+/// Demote the anomalies that are similar to many other anomalies, such as a repeated warning.
+/// The distance is scaled down when the anomaly occurs more than `min_occurrences` times,
+/// and the anomalies that are no longer over the threshold are removed.
+///
+/// The anomalies are tokenized like the lines of their source by the index, with the prefix
+/// detected from the anomalies and their contexts, and they are compared with its metric.
+pub fn self_consistency_filter(
+    index: &ChunkIndex,
+    is_json: bool,
+    anomalies: &mut Vec<AnomalyContext>,
+    min_occurrences: usize,
+) {
+    let lines = anomalies.iter().flat_map(|anomaly| {
+        anomaly
+            .before
+            .iter()
+            .chain(std::iter::once(&anomaly.anomaly.line))
+            .chain(anomaly.after.iter())
+            .map(String::as_str)
+    });
+    let framing = Framing::detect(index, is_json, lines);
+    let tokens = anomalies
+        .iter()
+        .map(|anomaly| index.tokenize(&framing.apply(&anomaly.anomaly.line)))
+        .collect::<Vec<_>>();
+    let neighbors = index.count_neighbors(&tokens, THRESHOLD);
+    for (anomaly, neighbor_count) in anomalies.iter_mut().zip(neighbors) {
+        let occurrences = 1 + neighbor_count + anomaly.anomaly.repeat;
+        if occurrences > min_occurrences {
+            anomaly.anomaly.distance *= min_occurrences as f32 / occurrences as f32;
+        }
+    }
+    anomalies.retain(|anomaly| anomaly.anomaly.distance > THRESHOLD);
+}
+
+/// The number of physical lines of a chunk, see [Granularity].
+fn physical_lines(line: &[u8]) -> usize {
+    1 + line.iter().filter(|c| **c == b'\n').count()
+}
+
+/// Split the chunks of a context into their physical lines, see [Granularity].
+fn split_chunks(context: Vec<String>) -> Vec<String> {
+    if context.iter().any(|line| line.contains('\n')) {
+        context
+            .iter()
+            .flat_map(|chunk| chunk.split('\n').map(str::to_string))
+            .collect()
+    } else {
+        context
     }
 }
 
+/// Add the physical lines of a chunk to the after context, until it is complete.
+fn push_after(after: &mut Vec<String>, raw_str: String) {
+    if raw_str.contains('\n') {
+        let missing = CTX_DISTANCE.saturating_sub(after.len());
+        after.extend(raw_str.split('\n').take(missing).map(str::to_string));
+    } else {
+        after.push(raw_str);
+    }
+}
+
+/// Check if the line is a consecutive repetition of the anomaly, before its after context.
+fn is_run(runs: &HashMap<usize, usize>, anomaly: &AnomalyContext, pos: usize) -> bool {
+    anomaly.after.is_empty()
+        && matches!(runs.get(&anomaly.anomaly.pos), Some(count) if pos <= anomaly.anomaly.pos + count)
+}
+
+/// Merge the overlapping context windows of consecutive anomalies, so that each line is shown once.
+pub fn merge_contexts(anomalies: &mut [AnomalyContext]) {
+    for idx in 1..anomalies.len() {
+        let (prevs, nexts) = anomalies.split_at_mut(idx);
+        let (prev, next) = (&mut prevs[idx - 1], &mut nexts[0]);
+        if next.anomaly.pos > prev.after_pos() {
+            prev.after.truncate(next.anomaly.pos - prev.after_pos() - 1);
+        }
+        next.trim_before(prev.last_pos());
+    }
+}
+
+/// Split the merged anomalies into the blocks of contiguous lines, so that close anomalies are
+/// shown together with their lines highlighted, see [merge_contexts].
+pub fn context_blocks(anomalies: &[AnomalyContext]) -> Vec<&[AnomalyContext]> {
+    let mut blocks = Vec::new();
+    let mut start = 0;
+    for idx in 1..anomalies.len() {
+        if anomalies[idx].first_pos() > anomalies[idx - 1].last_pos() + 1 {
+            blocks.push(&anomalies[start..idx]);
+            start = idx;
+        }
+    }
+    if start < anomalies.len() {
+        blocks.push(&anomalies[start..]);
+    }
+    blocks
+}
+
 /// Build the before context from the buffer and the left_overs
 ///
 /// * `buffer_pos` - the current position in the buffer.
@@ -325,7 +1258,7 @@ fn collect_before(
     let mut before = buffer[before_context_pos..buffer_pos]
         .iter()
         // TODO: use direct bytes -> str conversion.
-        .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes).unwrap())
+        .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes))
         .collect::<Vec<String>>();
     if before_context_pos == 0 && before.len() < CTX_DISTANCE {
         // The anomaly happens at the begining of the buffer
@@ -343,6 +1276,68 @@ fn collect_before(
     before
 }
 
+/// Check if a line starts a block, such as a traceback, an ansible task or a test case banner.
+fn is_block_start(line: &str) -> bool {
+    const MARKERS: [&str; 6] = [
+        "Traceback (most recent call last)",
+        "TASK [",
+        "PLAY [",
+        "=== RUN ",
+        "[ RUN      ]",
+        "Start testing:",
+    ];
+    let trimmed = line.trim();
+    MARKERS.iter().any(|marker| line.contains(marker))
+        // pytest test case banner, e.g. `____ test_foo ____`
+        || (trimmed.starts_with("___") && trimmed.ends_with("___") && trimmed.contains(" test"))
+}
+
+/// Build the before context from the start of the enclosing block.
+/// This returns None when no block start is found in the buffer.
+fn collect_block_before(
+    buffer_pos: usize,
+    last_context_pos: usize,
+    buffer: &[(LogLine, usize)],
+) -> Option<Vec<String>> {
+    let min_pos = last_context_pos.max(buffer_pos.saturating_sub(BLOCK_DISTANCE));
+    let lines = buffer[min_pos..buffer_pos]
+        .iter()
+        .map(|((bytes, _), _)| logreduce_iterator::clone_bytes_to_string(bytes))
+        .collect::<Vec<String>>();
+    lines
+        .iter()
+        .rposition(|line| is_block_start(line))
+        .map(|start| lines[start..].to_vec())
+}
+
+#[test]
+fn test_block_context() {
+    let buffer = [
+        "TASK [run tests]",
+        "running",
+        "Traceback (most recent call last):",
+        "  File \"test.py\", line 42",
+        "  File \"lib.py\", line 1",
+        "  File \"lib.py\", line 2",
+        "Exception: oops",
+    ]
+    .iter()
+    .enumerate()
+    .map(|(pos, line)| ((line.to_string().into(), pos), pos))
+    .collect::<Vec<_>>();
+    assert_eq!(
+        collect_block_before(6, 0, &buffer).map(|before| before.len()),
+        Some(4),
+        "The context starts at the traceback"
+    );
+    assert_eq!(
+        collect_block_before(2, 0, &buffer).map(|before| before.len()),
+        Some(2)
+    );
+    assert_eq!(collect_block_before(6, 3, &buffer), None);
+    assert!(is_block_start("____ test_foo ____"));
+}
+
 #[test]
 fn test_leftovers() {
     let index = crate::hashing_index::new();
@@ -400,6 +1395,59 @@ fn test_leftovers() {
     );
 }
 
+#[test]
+fn test_chunk_trainer_update() {
+    let mut index = crate::hashing_index::new();
+    let mut trainer = ChunkTrainer::new(&mut index, false);
+    for baseline in [
+        "service started\nservice ready",
+        "service started\nservice stopped",
+    ] {
+        trainer.add(std::io::Cursor::new(baseline)).unwrap();
+    }
+    trainer.complete();
+    let origin_count =
+        |index: &ChunkIndex, line| index.origin_count(&crate::hashing_index::tokenize(line));
+    assert_eq!(origin_count(&index, "service started"), 2);
+    assert_eq!(origin_count(&index, "service ready"), 1);
+
+    // Only the new lines are indexed when updating.
+    let mut trainer = ChunkTrainer::new(&mut index, false);
+    let update = "service started\nservice ready\nkernel panic";
+    trainer.add(std::io::Cursor::new(update)).unwrap();
+    assert_eq!(trainer.baselines.len(), 1);
+    trainer.complete();
+    assert_eq!(origin_count(&index, "service started"), 3);
+    assert_eq!(origin_count(&index, "kernel panic"), 1);
+}
+
+#[test]
+fn test_tokenize_reader() {
+    let data = "2022-05-10 10:00:01 service started\n2022-05-10 10:00:02 service ready\n";
+    let mut index = crate::hashing_index::new().with_strip_prefix(true);
+    let mut tokens = Vec::new();
+    tokenize_reader(&index, false, std::io::Cursor::new(data), |pos, line| {
+        tokens.push((pos, line))
+    })
+    .unwrap();
+    // The tokens are the ones given to the index by the trainer.
+    let mut trainer = ChunkTrainer::new(&mut index, false);
+    let mut trained = Vec::new();
+    trainer
+        .add_with(std::io::Cursor::new(data), |line| {
+            trained.push(line.to_string())
+        })
+        .unwrap();
+    assert_eq!(
+        tokens.iter().map(|(pos, _)| *pos).collect::<Vec<_>>(),
+        [1, 2]
+    );
+    assert_eq!(
+        tokens.into_iter().map(|(_, line)| line).collect::<Vec<_>>(),
+        trained
+    );
+}
+
 #[test]
 fn test_chunk_processor() {
     let mut index = crate::hashing_index::new();
@@ -436,18 +1484,40 @@ fn test_chunk_processor() {
             ],
             after: vec!["in-between line".to_string()],
             anomaly: Anomaly {
+                id: String::new(),
                 distance: 1.0,
                 pos: 3,
+                offset: 0,
+                column: 1,
                 line: "Traceback oops".to_string(),
+                level: None,
+                test: None,
+                task: None,
+                command: None,
+                hint: None,
+                repeat: 0,
+                run: 0,
+                retry: None,
             },
         },
         AnomalyContext {
             before: Vec::new(),
             after: vec!["003: regular log line".to_string()],
             anomaly: Anomaly {
+                id: String::new(),
                 distance: 1.0,
                 pos: 5,
+                offset: 0,
+                column: 1,
                 line: "another Traceback".to_string(),
+                level: None,
+                test: None,
+                task: None,
+                command: None,
+                hint: None,
+                repeat: 0,
+                run: 0,
+                retry: None,
             },
         },
     ];
@@ -463,3 +1533,431 @@ fn test_chunk_processor() {
             assert_eq!(got.after, expected.after);
         });
 }
+
+#[test]
+fn test_chunk_processor_repeats() {
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("001: regular log line");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "001: regular log line",
+            "Traceback oops",
+            "Traceback oops",
+            "Traceback oops",
+            "002: regular log line",
+            "Traceback oops",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let mut processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomalies = processor.by_ref().collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(processor.repeats().get(&2), Some(&3));
+    // Only the consecutive repetitions are collapsed in the anomaly.
+    assert_eq!(anomalies[0].anomaly.run, 2);
+    assert_eq!(anomalies[0].after_pos(), 4);
+    assert_eq!(
+        anomalies[0].after,
+        vec!["002: regular log line", "Traceback oops"]
+    );
+}
+
+#[test]
+fn test_chunk_processor_test_case() {
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("001: regular log line");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "Traceback before any test",
+            "=== RUN   TestFoo",
+            "001: regular log line",
+            "panic: oops",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let tests = processor
+        .map(|anomaly| anomaly.unwrap().anomaly.test)
+        .collect::<Vec<_>>();
+    assert_eq!(tests.first(), Some(&None));
+    assert_eq!(tests.last(), Some(&Some("TestFoo".to_string())));
+}
+
+#[test]
+fn test_chunk_processor_command() {
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("001: regular log line");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "+ make check",
+            "001: regular log line",
+            "test_foo: assertion failed",
+            "make: *** [Makefile:12: check] Error 2",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomaly = processor
+        .map(|anomaly| anomaly.unwrap().anomaly)
+        .find(|anomaly| anomaly.line.starts_with("test_foo"))
+        .unwrap();
+    assert_eq!(
+        anomaly.command,
+        Some(crate::segment::Command {
+            line: "make check".to_string(),
+            status: Some(2)
+        })
+    );
+
+    // The status is attached when it is found after the chunk of the anomaly.
+    let mut lines = vec!["+ make check", "test_foo: assertion failed"];
+    lines.extend(vec!["001: regular log line"; CHUNK_SIZE * 11]);
+    lines.push("make: *** [Makefile:12: check] Error 2");
+    let data = std::io::Cursor::new(lines.join("\n"));
+    let mut skip_lines = HashSet::new();
+    let processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomaly = processor
+        .map(|anomaly| anomaly.unwrap().anomaly)
+        .find(|anomaly| anomaly.line.starts_with("test_foo"))
+        .unwrap();
+    assert_eq!(anomaly.command.unwrap().status, Some(2));
+}
+
+#[test]
+fn test_chunk_processor_retries() {
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("001: regular log line");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "Connection refused, retrying in 500ms (attempt 1/5)",
+            "Connection refused, retrying in 1s (attempt 2/5)",
+            "Connection refused, retrying in 1m0s (attempt 3/5)",
+            "001: regular log line",
+            "Connection refused, retrying in 2m0s (attempt 4/5)",
+            "Giving up",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let mut processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomalies = processor
+        .by_ref()
+        .map(|anomaly| anomaly.unwrap().anomaly.pos)
+        .collect::<Vec<_>>();
+    // The escalating attempts are collapsed in the first one.
+    assert_eq!(anomalies.first(), Some(&1));
+    assert!(!anomalies.contains(&2) && !anomalies.contains(&3));
+    assert_eq!(anomalies.last(), Some(&6));
+    // The regular line ends the loop, the last attempt is not part of it.
+    let retry_loop = processor.retry_loops().remove(&1).unwrap();
+    assert_eq!(retry_loop.count, 3);
+    assert_eq!(retry_loop.attempts, Some((1, 3)));
+    assert_eq!(retry_loop.last_pos, 3);
+
+    // The known attempts before the anomaly are not counted.
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("Connection refused, retrying in 5s (attempt 1/5)");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+    let data = std::io::Cursor::new(
+        [
+            "Connection refused, retrying in 5s (attempt 1/5)",
+            "Connection refused, retrying in 10 seconds (attempt 2/5)",
+            "Connection refused, retrying in 20 seconds (attempt 3/5)",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let mut processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomalies = processor
+        .by_ref()
+        .map(|anomaly| anomaly.unwrap().anomaly.pos)
+        .collect::<Vec<_>>();
+    assert_eq!(anomalies, vec![2]);
+    let retry_loop = processor.retry_loops().remove(&2).unwrap();
+    assert_eq!(retry_loop.count, 2);
+    assert_eq!(retry_loop.attempts, Some((2, 3)));
+}
+
+#[test]
+fn test_chunk_processor_task() {
+    let mut index = crate::hashing_index::new();
+    let baseline = std::io::Cursor::new("001: regular log line");
+    ChunkTrainer::single(&mut index, false, baseline).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "2022-05-10 10:00:00.123 | RUN START: [untrusted : zuul/playbooks/tox/run.yaml@master]",
+            "2022-05-10 10:00:01.456 | TASK [tox : Run tox testing]",
+            "001: regular log line",
+            "Traceback oops",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let tasks = processor
+        .map(|anomaly| anomaly.unwrap().anomaly.task)
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tasks.last(),
+        Some(&Some(
+            "zuul/playbooks/tox/run.yaml: tox : Run tox testing".to_string()
+        ))
+    );
+}
+
+#[test]
+fn test_granularity_rule() {
+    let rules = ["job-output.txt=paragraph", "*.log=5", "line"]
+        .iter()
+        .map(|rule| rule.parse().unwrap())
+        .collect::<Vec<GranularityRule>>();
+    let select = |name: &str| GranularityRule::select(&rules, Some(&IndexName(name.to_string())));
+    assert_eq!(select("job-output.txt"), Some(Granularity::Paragraph));
+    assert_eq!(select("logs/nova.log"), Some(Granularity::Lines(5)));
+    assert_eq!(select("syslog.txt"), Some(Granularity::Line));
+    assert_eq!(
+        GranularityRule::select(&rules, None),
+        Some(Granularity::Line)
+    );
+    assert_eq!(GranularityRule::select(&rules[..1], None), None);
+    assert!("0".parse::<GranularityRule>().is_err());
+    assert!("job-output.txt=words".parse::<GranularityRule>().is_err());
+}
+
+#[test]
+fn test_chunk_processor_paragraph() {
+    let rules = vec!["paragraph".parse().unwrap()];
+    let mut index = crate::hashing_index::new().with_granularity(rules);
+    let baseline = [
+        "error: unused variable",
+        " --> main.rs:2",
+        "",
+        "Compiling app",
+    ]
+    .join("\n");
+    ChunkTrainer::single(&mut index, false, std::io::Cursor::new(baseline)).unwrap();
+
+    let data = std::io::Cursor::new(
+        [
+            "error: mismatched types",
+            " --> main.rs:4",
+            "",
+            "Compiling app",
+            "",
+            "error: unused variable",
+            " --> main.rs:2",
+        ]
+        .join("\n"),
+    );
+    let mut skip_lines = HashSet::new();
+    let mut processor = ChunkProcessor::new(data, &index, false, &mut skip_lines);
+    let anomalies = processor.by_ref().collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(anomalies.len(), 1);
+    // The chunk ends with the empty line, and its context is numbered by physical line.
+    let anomaly = &anomalies[0];
+    assert_eq!(
+        anomaly.anomaly.lines().collect::<Vec<_>>(),
+        vec![(1, "error: mismatched types"), (2, " --> main.rs:4")]
+    );
+    assert_eq!(anomaly.after_pos(), 3);
+    assert_eq!(
+        anomaly.after,
+        vec!["Compiling app", "", "error: unused variable"]
+    );
+    assert_eq!(processor.line_count, 7);
+}
+
+#[test]
+fn test_merge_contexts() {
+    let mk_anomaly = |pos: usize, before: &[&str], after: &[&str]| AnomalyContext {
+        before: before.iter().map(|s| s.to_string()).collect(),
+        after: after.iter().map(|s| s.to_string()).collect(),
+        anomaly: Anomaly {
+            id: String::new(),
+            distance: 1.0,
+            pos,
+            offset: 0,
+            column: 1,
+            line: format!("line {}", pos),
+            level: None,
+            test: None,
+            task: None,
+            command: None,
+            hint: None,
+            repeat: 0,
+            run: 0,
+            retry: None,
+        },
+    };
+    let mut anomalies = vec![
+        mk_anomaly(3, &["line 1", "line 2"], &["line 4", "line 5", "line 6"]),
+        mk_anomaly(6, &["line 4", "line 5"], &["line 7"]),
+        mk_anomaly(10, &["line 7", "line 8", "line 9"], &[]),
+        mk_anomaly(20, &["line 19"], &[]),
+    ];
+    merge_contexts(&mut anomalies);
+    assert_eq!(anomalies[0].after, vec!["line 4", "line 5"]);
+    assert!(anomalies[1].before.is_empty());
+    assert_eq!(anomalies[2].before, vec!["line 8", "line 9"]);
+    let blocks = context_blocks(&anomalies);
+    assert_eq!(
+        blocks.iter().map(|block| block.len()).collect::<Vec<_>>(),
+        vec![3, 1]
+    );
+}
+
+#[test]
+fn test_self_consistency_filter() {
+    let mk_anomaly = |pos: usize, line: &str| AnomalyContext {
+        before: Vec::new(),
+        after: Vec::new(),
+        anomaly: Anomaly {
+            id: String::new(),
+            distance: 1.0,
+            pos,
+            offset: 0,
+            column: 1,
+            line: line.to_string(),
+            level: None,
+            test: None,
+            task: None,
+            command: None,
+            hint: None,
+            repeat: 0,
+            run: 0,
+            retry: None,
+        },
+    };
+    let mut anomalies = vec![
+        mk_anomaly(1, "Traceback oops"),
+        mk_anomaly(2, "WARNING deprecated option called"),
+        mk_anomaly(3, "WARNING deprecated option called"),
+        mk_anomaly(4, "WARNING deprecated option called"),
+        mk_anomaly(5, "WARNING deprecated option called"),
+    ];
+    let index = crate::hashing_index::new().with_strip_prefix(true);
+    self_consistency_filter(&index, false, &mut anomalies, 1);
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.pos, 1);
+}
+
+#[test]
+fn test_deadline() {
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(&mut index, false, std::io::Cursor::new("service started")).unwrap();
+    let target = vec!["service started"; DEADLINE_LINES * 3].join("\n");
+    let mut skip_lines = HashSet::new();
+    let mut cp = ChunkProcessor::new(std::io::Cursor::new(target), &index, false, &mut skip_lines)
+        .with_deadline(Some(Instant::now()));
+    assert!(cp.next().is_none());
+    assert!(cp.timed_out);
+    assert_eq!(cp.line_count, DEADLINE_LINES);
+}
+
+#[test]
+fn test_non_utf8() {
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(
+        &mut index,
+        false,
+        std::io::Cursor::new(b"service \xff started"),
+    )
+    .unwrap();
+    let target = b"service \xff started\nkernel \xfe panic\n".to_vec();
+    let mut skip_lines = HashSet::new();
+    let cp = ChunkProcessor::new(std::io::Cursor::new(target), &index, false, &mut skip_lines);
+    let anomalies = cp.collect::<Result<Vec<_>>>().unwrap();
+    assert_eq!(anomalies.len(), 1);
+    assert_eq!(anomalies[0].anomaly.line, "kernel \u{FFFD} panic");
+}
+
+#[test]
+fn test_cancellation() {
+    let mut index = crate::hashing_index::new();
+    ChunkTrainer::single(&mut index, false, std::io::Cursor::new("service started")).unwrap();
+    let target = vec!["service started"; DEADLINE_LINES * 3].join("\n");
+    let mut skip_lines = HashSet::new();
+    let token = CancellationToken::new();
+    token.cancel();
+    let mut cp = ChunkProcessor::new(std::io::Cursor::new(target), &index, false, &mut skip_lines)
+        .with_cancellation(Some(token));
+    assert!(cp.next().is_none());
+    assert!(cp.cancelled);
+    assert!(!cp.timed_out);
+    assert_eq!(cp.line_count, DEADLINE_LINES);
+}
+
+#[test]
+fn test_line_scorer() {
+    let mut index = crate::hashing_index::new().with_strip_prefix(true);
+    let prefixed = |count: usize, message: &str| {
+        (0..count)
+            .map(|idx| format!("2024-01-01 12:00:{:02} host app[{}]: {}", idx, idx, message))
+            .collect::<Vec<_>>()
+    };
+    let baseline = prefixed(20, "Service started").join("\n");
+    ChunkTrainer::single(&mut index, false, std::io::Cursor::new(baseline)).unwrap();
+
+    let mut lines = prefixed(20, "Service started");
+    lines.push("2024-01-01 12:00:59 host app[42]: Connection refused".to_string());
+    let index_name = IndexName("app.log".to_string());
+    let mut skip_lines = HashSet::new();
+    let data = std::io::Cursor::new(lines.join("\n"));
+    let anomalies = ChunkProcessor::new(data, &index, false, &mut skip_lines)
+        .with_index_name(index_name.clone())
+        .map(|anomaly| anomaly.unwrap().anomaly)
+        .map(|anomaly| (anomaly.pos, anomaly.id))
+        .collect::<Vec<_>>();
+
+    // The lines are scored once the framing is detected, with the same ids.
+    let mut scorer = LineScorer::new(&index, Some(index_name.clone()));
+    let mut scored = Vec::new();
+    for line in lines.iter().cloned() {
+        scored.extend(scorer.push(line));
+    }
+    assert!(scored.is_empty());
+    scored.extend(scorer.finish());
+    assert_eq!(scored.len(), 21);
+    let scored = scored
+        .into_iter()
+        .filter(|scored| scored.distance > THRESHOLD)
+        .map(|scored| (scored.pos, scored.id))
+        .collect::<Vec<_>>();
+    assert_eq!(scored, anomalies);
+    assert_eq!(scored.len(), 1);
+
+    // The lines of a batch are scored together, with the same distances.
+    let mut scorer = LineScorer::new(&index, Some(index_name));
+    let mut batched = scorer.push_all(lines);
+    batched.extend(scorer.finish());
+    let batched = batched
+        .into_iter()
+        .filter(|scored| scored.distance > THRESHOLD)
+        .map(|scored| (scored.pos, scored.id))
+        .collect::<Vec<_>>();
+    assert_eq!(batched, anomalies);
+
+    let rules = vec!["paragraph".parse().unwrap()];
+    let index = crate::hashing_index::new().with_granularity(rules);
+    let mut scorer = LineScorer::new(&index, None);
+    assert_eq!(scorer.push("error: mismatched types".to_string()), vec![]);
+    assert_eq!(scorer.push(" --> main.rs:4".to_string()), vec![]);
+    let scored = scorer.push("".to_string());
+    assert_eq!(scored.len(), 1);
+    assert_eq!(scored[0].line, "error: mismatched types\n --> main.rs:4");
+    assert_eq!(scored[0].pos, 1);
+    assert_eq!(scorer.push("Compiling app".to_string()), vec![]);
+    assert_eq!(scorer.finish()[0].pos, 4);
+}
@@ -0,0 +1,205 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module classifies each source from its first lines, to read it with a fitting profile.
+//!
+//! - The console outputs, like a job output or a build log, contain ANSI escape codes and shell
+//!   traces. The codes and the trace markers are removed, so that a colored line or a nested
+//!   command matches the baselines.
+//! - The service logs are only stripped of their constant prefix, see [crate::prefix].
+//! - The structured data, like the json lines, are tokenized as they are.
+//!
+//! The profiles are stored in the hashing index, so that the baselines and the targets are read
+//! the same way, see [crate::ChunkIndex::with_source_profiles].
+
+use regex::Regex;
+use std::borrow::Cow;
+
+use crate::prefix::Prefix;
+use crate::ChunkIndex;
+
+lazy_static::lazy_static! {
+    // `ESC[1;31m`, `ESC[0K`
+    static ref ANSI_ESCAPE: Regex = Regex::new(r"\x1b\[[0-9;?]*[A-Za-z]").unwrap();
+    // shell trace: `+ make check`, `++ git rev-parse HEAD`
+    static ref SHELL_TRACE: Regex = Regex::new(r"^\++ ").unwrap();
+}
+
+/// The ratio of the sample lines with a console marker to classify a console.
+const CONSOLE_RATIO: f32 = 0.1;
+
+/// The ratio of the sample lines that are json objects to classify structured data.
+const STRUCTURED_RATIO: f32 = 0.5;
+
+/// The kind of content of a source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SourceKind {
+    /// An interactive console output, e.g. a job output or a build log.
+    Console,
+    /// The log of a service, e.g. a syslog file.
+    Service,
+    /// Structured data, e.g. json lines.
+    Structured,
+}
+
+impl std::fmt::Display for SourceKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            SourceKind::Console => "console",
+            SourceKind::Service => "service",
+            SourceKind::Structured => "structured",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+fn is_console_line(line: &str) -> bool {
+    line.contains('\x1b')
+        || line.trim_end_matches('\r').contains('\r')
+        || line.starts_with("$ ")
+        || crate::segment::command_start(line).is_some()
+        || crate::segment::step_start(line).is_some()
+}
+
+fn is_structured_line(line: &str) -> bool {
+    let line = line.trim();
+    (line.starts_with('{') && line.ends_with('}')) || (line.starts_with('[') && line.ends_with(']'))
+}
+
+/// Classify a source from its first lines.
+pub fn classify<'a>(is_json: bool, lines: impl IntoIterator<Item = &'a str>) -> SourceKind {
+    let lines = lines
+        .into_iter()
+        .filter(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>();
+    if is_json {
+        return SourceKind::Structured;
+    }
+    let ratio = |f: fn(&str) -> bool| -> f32 {
+        let count = lines.iter().filter(|line| f(line)).count();
+        count as f32 / lines.len().max(1) as f32
+    };
+    if !lines.is_empty() && ratio(is_structured_line) >= STRUCTURED_RATIO {
+        SourceKind::Structured
+    } else if !lines.is_empty() && ratio(is_console_line) >= CONSOLE_RATIO {
+        SourceKind::Console
+    } else {
+        SourceKind::Service
+    }
+}
+
+/// Remove the ANSI escape codes, and keep the last refresh of a line that uses carriage returns.
+pub fn strip_ansi(line: &str) -> Cow<'_, str> {
+    let line = line
+        .trim_end_matches('\r')
+        .rsplit('\r')
+        .next()
+        .unwrap_or(line);
+    ANSI_ESCAPE.replace_all(line, "")
+}
+
+/// Remove the shell trace marker, the `+` repeated for each nested level.
+pub fn strip_trace(line: &str) -> &str {
+    match SHELL_TRACE.find(line) {
+        Some(marker) => &line[marker.end()..],
+        None => line,
+    }
+}
+
+/// How the lines of a source are read before the tokenization.
+#[derive(Debug, Default)]
+pub struct Framing {
+    /// The kind of the source, when the profiles are enabled.
+    pub kind: Option<SourceKind>,
+    prefix: Prefix,
+}
+
+impl Framing {
+    /// Detect the framing of a source from its first lines, according to the index settings.
+    pub fn detect<'a>(
+        index: &ChunkIndex,
+        is_json: bool,
+        lines: impl IntoIterator<Item = &'a str>,
+    ) -> Framing {
+        let lines = lines.into_iter().collect::<Vec<_>>();
+        let kind = if index.source_profiles() {
+            Some(classify(is_json, lines.iter().copied()))
+        } else {
+            None
+        };
+        let prefix = match kind {
+            Some(SourceKind::Structured) => Prefix::default(),
+            _ if !index.strip_prefix() => Prefix::default(),
+            Some(SourceKind::Console) => {
+                let lines = lines
+                    .iter()
+                    .map(|line| strip_ansi(line))
+                    .collect::<Vec<_>>();
+                Prefix::detect(lines.iter().map(|line| line.as_ref()))
+            }
+            _ => Prefix::detect(lines),
+        };
+        Framing { kind, prefix }
+    }
+
+    /// The part of the line that is tokenized.
+    pub fn apply<'a>(&self, line: &'a str) -> Cow<'a, str> {
+        if self.kind != Some(SourceKind::Console) {
+            return Cow::Borrowed(self.prefix.strip(line));
+        }
+        match strip_ansi(line) {
+            Cow::Borrowed(line) => Cow::Borrowed(strip_trace(self.prefix.strip(line))),
+            Cow::Owned(line) => Cow::Owned(strip_trace(self.prefix.strip(&line)).to_string()),
+        }
+    }
+}
+
+#[test]
+fn test_classify() {
+    let console = [
+        "2022-05-10 10:00:00.123 | TASK [tox : Run tox testing]",
+        "2022-05-10 10:00:01.123 | + tox -e py3",
+        "2022-05-10 10:00:02.123 | py3 create: /home/zuul/.tox/py3",
+    ];
+    assert_eq!(classify(false, console), SourceKind::Console);
+    let service = [
+        "2024-01-01 12:00:00 host app[123]: Starting service",
+        "2024-01-01 12:00:01 host app[123]: Service started",
+    ];
+    assert_eq!(classify(false, service), SourceKind::Service);
+    let structured = [
+        r#"{"level": "info", "msg": "started"}"#,
+        r#"{"level": "error"}"#,
+    ];
+    assert_eq!(classify(false, structured), SourceKind::Structured);
+    assert_eq!(classify(true, service), SourceKind::Structured);
+    assert_eq!(classify(false, []), SourceKind::Service);
+}
+
+#[test]
+fn test_framing() {
+    let index = crate::hashing_index::new().with_source_profiles(true);
+    let console = [
+        "\x1b[1;31mERROR\x1b[0m: build failed",
+        "++ git rev-parse HEAD",
+    ];
+    let framing = Framing::detect(&index, false, console);
+    assert_eq!(framing.kind, Some(SourceKind::Console));
+    assert_eq!(framing.apply(console[0]), "ERROR: build failed");
+    assert_eq!(framing.apply(console[1]), "git rev-parse HEAD");
+    assert_eq!(
+        framing.apply("Downloading 10%\rDownloading 100%\r"),
+        "Downloading 100%"
+    );
+
+    // The traces are kept in the service logs.
+    let mut service = vec!["Service started"; 12];
+    service.push("+ not a trace");
+    let framing = Framing::detect(&index, false, service);
+    assert_eq!(framing.kind, Some(SourceKind::Service));
+    assert_eq!(framing.apply("+ not a trace"), "+ not a trace");
+
+    let framing = Framing::detect(&crate::hashing_index::new(), false, console);
+    assert_eq!(framing.kind, None);
+    assert_eq!(framing.apply(console[0]), console[0]);
+}
@@ -0,0 +1,115 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module lets the library users follow a run, e.g. to update a GUI or a server status.
+//!
+//! The training and the inspection report their progress to a [ProgressObserver]. Every callback
+//! does nothing by default, and the [OutputMode] implementation prints the messages like the cli
+//! does. The observer also gives the token to cancel the run, see [crate::cancel].
+
+use crate::cancel::CancellationToken;
+use crate::{debug_or_progress, AnomalyContext, OutputMode, Source};
+
+/// The callbacks of a run, they are called from the thread doing the work. The observer can be
+/// shared with the other threads, e.g. to serve the status of the run.
+pub trait ProgressObserver: Send + Sync {
+    /// A step of the run, e.g. the index being trained.
+    fn message(&self, _msg: &str) {}
+
+    /// A source is being read, to train an index or to inspect it.
+    fn source_started(&self, _source: &Source) {}
+
+    /// The number of lines and bytes read so far, it is called periodically while the source is
+    /// inspected, and with the final counts when it is finished. This is the only progress of a
+    /// stream, whose size is unknown.
+    fn lines_read(&self, _source: &Source, _line_count: usize, _byte_count: usize) {}
+
+    /// A source is read.
+    fn source_finished(&self, _source: &Source, _line_count: usize) {}
+
+    /// An anomaly is found, before it is added to the report.
+    fn anomaly_found(&self, _source: &Source, _anomaly: &AnomalyContext) {}
+
+    /// Whether the observer needs the score of every line, see [ProgressObserver::lines_scored].
+    fn wants_scores(&self) -> bool {
+        false
+    }
+
+    /// The score of every line of a source, by line number, it is called when the source is
+    /// finished and only if [ProgressObserver::wants_scores].
+    fn lines_scored(&self, _source: &Source, _scores: &[(usize, f32)]) {}
+
+    /// The token checked by the long running loops.
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        None
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancellation()
+            .map_or(false, |token| token.is_cancelled())
+    }
+}
+
+impl ProgressObserver for OutputMode {
+    fn message(&self, msg: &str) {
+        debug_or_progress(*self, msg)
+    }
+
+    fn source_started(&self, source: &Source) {
+        debug_or_progress(*self, &format!("Reading {}", source))
+    }
+
+    fn lines_read(&self, source: &Source, line_count: usize, _byte_count: usize) {
+        if source.is_stream() {
+            debug_or_progress(*self, &format!("Reading {}: {} lines", source, line_count))
+        }
+    }
+}
+
+#[test]
+fn test_observer() {
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Counts {
+        sources: Mutex<Vec<String>>,
+        bytes: Mutex<usize>,
+        finished: Mutex<Vec<usize>>,
+    }
+
+    impl ProgressObserver for Counts {
+        fn source_started(&self, source: &Source) {
+            self.sources.lock().unwrap().push(source.to_string())
+        }
+        fn lines_read(&self, _source: &Source, _line_count: usize, byte_count: usize) {
+            // The counts are cumulative, the last one is the total.
+            *self.bytes.lock().unwrap() = byte_count
+        }
+        fn source_finished(&self, _source: &Source, line_count: usize) {
+            self.finished.lock().unwrap().push(line_count)
+        }
+    }
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("app.log");
+    std::fs::write(&path, "Starting service\nService started\n").unwrap();
+    let counts = Counts::default();
+    let model = crate::Model::train(
+        &counts,
+        vec![crate::Content::from_pathbuf(path.clone())],
+        crate::hashing_index::new,
+    )
+    .unwrap();
+    // The training reports the baseline sources like the inspection.
+    assert_eq!(counts.sources.lock().unwrap().len(), 1);
+    assert_eq!(*counts.finished.lock().unwrap(), vec![2]);
+    assert_eq!(*counts.bytes.lock().unwrap(), 31);
+
+    *counts.bytes.lock().unwrap() = 0;
+    let report = model.report(&counts, crate::Content::from_pathbuf(path));
+    assert_eq!(report.unwrap().total_line_count, 2);
+    assert_eq!(counts.sources.lock().unwrap().len(), 2);
+    assert_eq!(*counts.finished.lock().unwrap(), vec![2, 2]);
+    assert_eq!(*counts.bytes.lock().unwrap(), 31);
+}
@@ -11,14 +11,23 @@ use url::Url;
 use std::fs::File;
 
 use flate2::read::GzDecoder;
+use once_cell::sync::OnceCell;
 
 // TODO: use a struct to pass these references.
 lazy_static::lazy_static! {
     static ref CACHE: logreduce_cache::Cache = logreduce_cache::Cache::new().expect("Cache");
-    static ref CLIENT: reqwest::blocking::Client = reqwest::blocking::Client::builder()
-        .danger_accept_invalid_certs(std::env::var("LOGREDUCE_SSL_NO_VERIFY").is_ok())
-        .build()
-        .expect("Client");
+    // The client is shared so that the connections are reused, and it negotiates http/2.
+    static ref CLIENT: reqwest::blocking::Client = {
+        let mut builder = reqwest::blocking::Client::builder()
+            .danger_accept_invalid_certs(std::env::var("LOGREDUCE_SSL_NO_VERIFY").is_ok());
+        if let Some(count) = crate::net::settings().connections_per_host {
+            builder = builder.pool_max_idle_per_host(count);
+        }
+        if let Some(timeout) = crate::net::settings().timeout {
+            builder = builder.timeout(timeout);
+        }
+        builder.build().expect("Client")
+    };
 
     static ref USE_CACHE: bool = std::env::var("LOGREDUCE_CACHE").is_ok();
 }
@@ -36,29 +45,230 @@ mod remote {
         let resp = CLIENT.head(url.clone()).send().context("Can't head url")?;
         Ok(resp.status().is_success())
     }
+
+    pub fn content_length(url: &Url) -> Result<Option<u64>> {
+        let resp = CLIENT.head(url.clone()).send().context("Can't head url")?;
+        Ok(resp
+            .headers()
+            .get(reqwest::header::CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse().ok()))
+    }
 }
 
 // allow large enum for gzdecoder, which are the most used
 #[allow(clippy::large_enum_variant)]
 pub enum DecompressReader {
     Flat(File),
-    Gz(GzDecoder<File>),
+    Gz(GzDecoder<File>, Budget),
     // TODO: support BZIP2 compression
-    Remote(Response),
-    Cached(logreduce_cache::CacheReader<Response>),
+    Remote(Response, Budget),
+    Cached(logreduce_cache::CacheReader<Response>, Budget),
+    // The text rendering of a binary format, e.g. evtx
+    Rendered(std::io::Cursor<Vec<u8>>),
+    // A stream that can only be read once, e.g. the stdin, see [crate::streaming]
+    Stream(Box<dyn Read + Send>),
+    #[cfg(target_os = "linux")]
+    Sparse(local::SparseFile),
 }
 use DecompressReader::*;
 
+/// The limits of the decompressed content, to stop the decompression bombs.
+#[derive(Debug)]
+pub struct DecompressLimits {
+    /// The maximum size of a decompressed source, local or remote.
+    pub max_size: Option<u64>,
+    /// The maximum ratio between the decompressed and the compressed size of a source, when the
+    /// compressed size is known.
+    pub max_ratio: Option<u64>,
+}
+
+/// The compressed files are not checked for their ratio until they reach this size.
+const MIN_RATIO_SIZE: u64 = 64 << 20;
+
+/// The default maximum size of a decompressed source.
+pub const DEFAULT_MAX_SIZE: u64 = 8 << 30;
+
+impl Default for DecompressLimits {
+    fn default() -> DecompressLimits {
+        DecompressLimits {
+            max_size: Some(DEFAULT_MAX_SIZE),
+            max_ratio: Some(500),
+        }
+    }
+}
+
+static LIMITS: OnceCell<DecompressLimits> = OnceCell::new();
+
+/// Set the decompression limits, this returns an error when they are already set.
+pub fn configure_limits(limits: DecompressLimits) -> Result<()> {
+    LIMITS
+        .set(limits)
+        .map_err(|_| anyhow::anyhow!("The decompression limits are already set"))
+}
+
+fn limits() -> &'static DecompressLimits {
+    LIMITS.get_or_init(DecompressLimits::default)
+}
+
+/// The remaining decompressed bytes of a source.
+pub struct Budget(Option<u64>);
+
+impl Budget {
+    /// The budget of a compressed file.
+    fn compressed(limits: &DecompressLimits, compressed_size: u64) -> Budget {
+        let ratio_size = limits
+            .max_ratio
+            .map(|ratio| compressed_size.saturating_mul(ratio).max(MIN_RATIO_SIZE));
+        Budget(match (limits.max_size, ratio_size) {
+            (Some(x), Some(y)) => Some(x.min(y)),
+            (x, y) => x.or(y),
+        })
+    }
+
+    /// The budget of a download. The client decodes the compressed responses and then their
+    /// compressed size is unknown, so only the size limit applies.
+    fn remote(response: &Response) -> Budget {
+        match response.content_length() {
+            Some(size) => Budget::compressed(limits(), size),
+            None => Budget(limits().max_size),
+        }
+    }
+
+    fn consume(&mut self, count: usize) -> std::io::Result<usize> {
+        match &mut self.0 {
+            Some(remaining) if *remaining < count as u64 => Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                "The decompressed size exceeds the limit",
+            )),
+            Some(remaining) => {
+                *remaining -= count as u64;
+                Ok(count)
+            }
+            None => Ok(count),
+        }
+    }
+}
+
 pub fn from_path(path: &Path) -> Result<DecompressReader> {
-    let fp = File::open(path)?;
+    let fp = local::open(path)?;
     let extension = path.extension().unwrap_or_else(|| std::ffi::OsStr::new(""));
-    Ok(if extension == ".gz" {
-        Gz(GzDecoder::new(fp))
+    Ok(if extension == "gz" {
+        let budget = Budget::compressed(limits(), fp.metadata()?.len());
+        Gz(GzDecoder::new(fp), budget)
     } else {
+        #[cfg(target_os = "linux")]
+        if let Some(hole) = local::first_hole(&fp)? {
+            return Ok(Sparse(local::SparseFile::new(fp, hole)));
+        }
         Flat(fp)
     })
 }
 
+/// The kind of a special file, which is never read because it may block forever.
+#[cfg(unix)]
+pub(crate) fn special_kind(file_type: &std::fs::FileType) -> Option<&'static str> {
+    use std::os::unix::fs::FileTypeExt;
+    if file_type.is_fifo() {
+        Some("fifo")
+    } else if file_type.is_socket() {
+        Some("socket")
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        Some("device")
+    } else {
+        None
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) fn special_kind(_file_type: &std::fs::FileType) -> Option<&'static str> {
+    None
+}
+
+/// Handle local file.
+mod local {
+    use super::*;
+    #[cfg(target_os = "linux")]
+    use std::convert::TryFrom;
+
+    /// Open a file, the special files are refused instead of blocking, e.g. a fifo without writer.
+    pub fn open(path: &Path) -> Result<File> {
+        let mut options = std::fs::OpenOptions::new();
+        options.read(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            // The flag does not change the reads of a regular file.
+            options.custom_flags(libc::O_NONBLOCK);
+        }
+        let fp = options.open(path)?;
+        match special_kind(&fp.metadata()?.file_type()) {
+            Some(kind) => Err(anyhow::anyhow!("Refusing to read the {} {:?}", kind, path)),
+            None => Ok(fp),
+        }
+    }
+
+    /// Move the offset of the file to the next data or hole, this returns None when there is
+    /// no more data after the position, or when the filesystem does not report the holes.
+    #[cfg(target_os = "linux")]
+    fn seek(file: &File, pos: u64, whence: libc::c_int) -> Option<u64> {
+        use std::os::unix::io::AsRawFd;
+        // SAFETY: the file descriptor is owned by the file, this only moves its offset.
+        let offset = unsafe { libc::lseek(file.as_raw_fd(), pos as libc::off_t, whence) };
+        u64::try_from(offset).ok()
+    }
+
+    /// The offset of the first hole of a file that has holes. The length is checked first, as
+    /// a file with fewer blocks than its length may only be compressed by the filesystem.
+    #[cfg(target_os = "linux")]
+    pub fn first_hole(file: &File) -> Result<Option<u64>> {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = file.metadata()?;
+        if metadata.blocks() * 512 >= metadata.len() {
+            return Ok(None);
+        }
+        let hole = seek(file, 0, libc::SEEK_HOLE);
+        seek(file, 0, libc::SEEK_SET).context("Can't rewind file")?;
+        Ok(hole.filter(|hole| *hole < metadata.len()))
+    }
+
+    /// A file whose holes are skipped instead of being read as zeros. The file is read as usual
+    /// until its next hole, so that it is only seeked once per hole.
+    #[cfg(target_os = "linux")]
+    pub struct SparseFile {
+        file: File,
+        pos: u64,
+        hole: u64,
+    }
+
+    #[cfg(target_os = "linux")]
+    impl SparseFile {
+        pub fn new(file: File, hole: u64) -> SparseFile {
+            SparseFile { file, pos: 0, hole }
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    impl Read for SparseFile {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.pos >= self.hole {
+                let data = match seek(&self.file, self.pos, libc::SEEK_DATA) {
+                    Some(data) => data,
+                    // The rest of the file is a hole.
+                    None => return Ok(0),
+                };
+                self.hole = seek(&self.file, data, libc::SEEK_HOLE).unwrap_or(u64::MAX);
+                self.pos = seek(&self.file, data, libc::SEEK_SET)
+                    .ok_or_else(std::io::Error::last_os_error)?;
+            }
+            let max = usize::try_from(self.hole - self.pos).unwrap_or(usize::MAX);
+            let count = self.file.read(&mut buf[..buf.len().min(max)])?;
+            self.pos += count as u64;
+            Ok(count)
+        }
+    }
+}
+
 pub fn head_url(base: &Url, url: &Url) -> Result<bool> {
     if *USE_CACHE {
         match CACHE.head(base, url) {
@@ -81,20 +291,36 @@ pub fn from_url(base: &Url, url: &Url) -> Result<DecompressReader> {
         match CACHE.remote_get(base, url) {
             Some(cache) => {
                 tracing::debug!("Cache hit for {}", url);
-                cache.map(Gz)
+                let cache = cache?;
+                // The cached content is compressed, like a local file.
+                let size = cache.get_ref().metadata()?.len();
+                Ok(Gz(cache, Budget::compressed(limits(), size)))
             }
             None => {
                 tracing::debug!("Cache miss for {}", url);
                 let resp = remote::get_url(url)?;
+                let budget = Budget::remote(&resp);
                 let cache = CACHE.remote_add(base, url, resp)?;
-                Ok(Cached(cache))
+                Ok(Cached(cache, budget))
             }
         }
     } else {
-        Ok(Remote(remote::get_url(url)?))
+        let resp = remote::get_url(url)?;
+        let budget = Budget::remote(&resp);
+        Ok(Remote(resp, budget))
     }
 }
 
+/// The shared http client.
+pub fn client() -> &'static reqwest::blocking::Client {
+    &CLIENT
+}
+
+/// Get the remote size without downloading the content.
+pub fn content_length(url: &Url) -> Result<Option<u64>> {
+    remote::content_length(url)
+}
+
 pub fn drop_url(base: &Url, url: &Url) -> Result<()> {
     if *USE_CACHE {
         CACHE.remote_drop(base, url)
@@ -108,9 +334,13 @@ impl Read for DecompressReader {
         // TODO: refactor using the enum_dispatch crate.
         match self {
             Flat(r) => r.read(buf),
-            Gz(r) => r.read(buf),
-            Remote(r) => r.read(buf),
-            Cached(r) => r.read(buf),
+            Gz(r, budget) => budget.consume(r.read(buf)?),
+            Remote(r, budget) => budget.consume(r.read(buf).map(crate::net::throttled)?),
+            Cached(r, budget) => budget.consume(r.read(buf).map(crate::net::throttled)?),
+            Rendered(r) => r.read(buf),
+            Stream(r) => r.read(buf),
+            #[cfg(target_os = "linux")]
+            Sparse(r) => r.read(buf),
         }
     }
 }
@@ -132,3 +362,59 @@ pub fn auto<R: Read + 'static>(mut reader: R) -> Result<Box<dyn Read>> {
     })
 }
 */
+
+#[cfg(unix)]
+#[test]
+fn test_special_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let fifo = dir.join("fifo");
+    let status = std::process::Command::new("mkfifo")
+        .arg(&fifo)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let result = from_path(&fifo);
+
+    // A file with large holes is read quickly.
+    let sparse = dir.join("sparse.log");
+    let mut fp = File::create(&sparse).unwrap();
+    std::io::Write::write_all(&mut fp, b"Starting\n").unwrap();
+    fp.set_len(64 << 20).unwrap();
+    std::io::Seek::seek(&mut fp, std::io::SeekFrom::End(0)).unwrap();
+    std::io::Write::write_all(&mut fp, b"Disk full\n").unwrap();
+    fp.set_len(128 << 20).unwrap();
+    let mut content = Vec::new();
+    from_path(&sparse)
+        .unwrap()
+        .read_to_end(&mut content)
+        .unwrap();
+
+    assert!(result
+        .err()
+        .unwrap()
+        .to_string()
+        .starts_with("Refusing to read the fifo"));
+    assert!(content.starts_with(b"Starting\n"));
+    // The holes are skipped on the filesystems that report them.
+    assert!(content.windows(10).any(|window| window == b"Disk full\n"));
+}
+
+#[test]
+fn test_budget() {
+    let limits = DecompressLimits {
+        max_size: None,
+        max_ratio: Some(100),
+    };
+    // The small files are not checked for their ratio.
+    assert_eq!(Budget::compressed(&limits, 1024).0, Some(MIN_RATIO_SIZE));
+    assert_eq!(Budget::compressed(&limits, 1 << 20).0, Some(100 << 20));
+    let limits = DecompressLimits {
+        max_size: Some(1000),
+        max_ratio: None,
+    };
+    let mut budget = Budget::compressed(&limits, 1 << 20);
+    assert_eq!(budget.consume(600).unwrap(), 600);
+    assert!(budget.consume(600).is_err());
+    assert_eq!(DecompressLimits::default().max_size, Some(DEFAULT_MAX_SIZE));
+}
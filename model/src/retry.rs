@@ -0,0 +1,258 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module detects the retry loops, e.g. `Connection failed, retrying in 5s (attempt 3/10)`.
+//!
+//! The attempts of a loop only differ by their counter and by their delay, which often escalates,
+//! e.g. `500ms`, `1s`, then `1m0s`. The counter and the delay are removed to get the key of the
+//! loop, so that its attempts are collapsed in a single anomaly with the attempt range, instead
+//! of being reported each time the delay changes the tokens. A loop ends at the first line that
+//! is not an attempt, so that a later outage is reported again.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use regex::Regex;
+
+lazy_static::lazy_static! {
+    static ref RETRY_WORD: Regex =
+        Regex::new(r"(?i)\b(?:retry|retrying|retries|retried|attempt|backing off)\b").unwrap();
+    // `attempt 3/10`, `retry #3 of 10`, `try 3 out of 10`
+    static ref ATTEMPT: Regex = Regex::new(
+        r"(?i)\b(?:attempt|try|retry)\s*#?\s*(\d+)(?:\s*(?:/|of|out of)\s*(\d+))?"
+    ).unwrap();
+    // `retrying in 5s`, `sleeping for 1m30s`, `next attempt after 00:00:05`
+    static ref DELAY: Regex = Regex::new(concat!(
+        r"(?i)\b(?:retrying|retry|trying again|sleeping|waiting|wait|backing off|next attempt)",
+        r"\s+(?:in|for|after)?\s*",
+        r"(\d[\d.:a-zµ]*(?:\s(?:ms|milliseconds?|secs?|seconds?|mins?|minutes?|hrs?|hours?)\b)?)",
+    )).unwrap();
+    // `(10 retries left)`
+    static ref REMAINING: Regex =
+        Regex::new(r"(?i)\b\d+\s+(?:retries|attempts|tries)\s+(?:left|remaining)\b").unwrap();
+    // `2023-01-01`, `12:00:01.123`
+    static ref TIMESTAMP: Regex =
+        Regex::new(r"\d{4}-\d{2}-\d{2}|\d{1,2}:\d{2}(?::\d{2})?(?:[.,]\d+)?").unwrap();
+    // `1m30s`, `500 ms`, `5 seconds`
+    static ref DURATION_PART: Regex = Regex::new(r"(\d+(?:\.\d+)?)\s?([a-zµ]*)").unwrap();
+}
+
+/// The substrings of the retry lines, to avoid running the regexes on every lines.
+const HINTS: [&str; 8] = [
+    "etry",
+    "etri",
+    "ttempt",
+    "acking off",
+    "ETRY",
+    "ETRI",
+    "TTEMPT",
+    "ACKING OFF",
+];
+
+fn seconds(secs: f64) -> Option<Duration> {
+    // The bound avoids the conversion panic of a garbled number.
+    if (0.0..1e12).contains(&secs) {
+        Some(Duration::from_secs_f64(secs))
+    } else {
+        None
+    }
+}
+
+/// Parse a duration in the usual notations: `1.5s`, `500ms`, `5 seconds`, `1m30s`, `PT1M30S`
+/// and `00:01:30`. A number without a unit is a count of seconds.
+pub fn parse_duration(s: &str) -> Option<Duration> {
+    let s = s.trim().to_lowercase();
+    if s.contains(':') {
+        let mut secs = 0.0;
+        for part in s.split(':') {
+            secs = secs * 60.0 + part.parse::<f64>().ok()?;
+        }
+        return seconds(secs);
+    }
+    let s = s.strip_prefix("pt").unwrap_or(&s);
+    let mut secs = 0.0;
+    let mut end = 0;
+    for captures in DURATION_PART.captures_iter(s) {
+        let part = captures.get(0)?;
+        if part.start() != end {
+            return None;
+        }
+        end = part.end();
+        let value: f64 = captures[1].parse().ok()?;
+        let unit = match &captures[2] {
+            "us" | "µs" => 0.000_001,
+            "ms" | "msec" | "millisecond" | "milliseconds" => 0.001,
+            "" | "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            _ => return None,
+        };
+        secs += value * unit;
+    }
+    if end == 0 || end != s.len() {
+        return None;
+    }
+    seconds(secs)
+}
+
+/// An attempt of a retry loop.
+#[derive(Debug, Default, PartialEq)]
+pub struct Attempt {
+    /// The attempt number and the maximum number of attempts, when the line has them.
+    pub number: Option<u32>,
+    pub total: Option<u32>,
+    /// The delay before the next attempt.
+    pub delay: Option<Duration>,
+}
+
+/// Return the attempt when the line is part of a retry loop.
+pub fn attempt(line: &str) -> Option<Attempt> {
+    if !HINTS.iter().any(|hint| line.contains(hint)) || !RETRY_WORD.is_match(line) {
+        return None;
+    }
+    let mut attempt = Attempt::default();
+    if let Some(captures) = ATTEMPT.captures(line) {
+        attempt.number = captures[1].parse().ok();
+        attempt.total = captures
+            .get(2)
+            .and_then(|total| total.as_str().parse().ok());
+    }
+    attempt.delay = DELAY
+        .captures(line)
+        .and_then(|captures| parse_duration(&captures[1]));
+    Some(attempt)
+}
+
+/// The key of the retry loop of the line: its words without the attempt counter, the delay and
+/// the timestamps. The other numbers are kept, so that the attempts for different addresses are
+/// not collapsed.
+pub fn retry_key(line: &str) -> String {
+    let line = ATTEMPT.replace_all(line, "");
+    let line = DELAY.replace_all(&line, "");
+    let line = REMAINING.replace_all(&line, "");
+    let line = TIMESTAMP.replace_all(&line, "");
+    line.split(|c: char| !c.is_alphanumeric() && c != '.')
+        .map(|word| word.trim_matches('.'))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// The attempts of a retry loop.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RetryLoop {
+    /// The number of attempt lines.
+    pub count: usize,
+    /// The first and last attempt numbers, when the lines have them.
+    pub attempts: Option<(u32, u32)>,
+    /// The maximum number of attempts, when the lines have it.
+    pub max_attempts: Option<u32>,
+    /// The sum of the announced delays.
+    pub delay: Duration,
+    /// The position of the last attempt line.
+    pub last_pos: usize,
+}
+
+impl RetryLoop {
+    pub fn add(&mut self, attempt: &Attempt, pos: usize) {
+        self.count += 1;
+        if let Some(number) = attempt.number {
+            self.attempts = Some(match self.attempts {
+                Some((first, last)) => (first.min(number), last.max(number)),
+                None => (number, number),
+            });
+        }
+        self.max_attempts = attempt.total.or(self.max_attempts);
+        self.delay += attempt.delay.unwrap_or_default();
+        self.last_pos = pos;
+    }
+}
+
+impl std::fmt::Display for RetryLoop {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "retried {}×", self.count)?;
+        let mut details = Vec::new();
+        if let Some((first, last)) = self.attempts {
+            details.push(match self.max_attempts {
+                Some(max) => format!("attempts {} to {} of {}", first, last, max),
+                None => format!("attempts {} to {}", first, last),
+            });
+        }
+        if !self.delay.is_zero() {
+            details.push(format!("waiting {:?} in total", self.delay));
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        write!(f, " until line {}", self.last_pos)
+    }
+}
+
+#[test]
+fn test_parse_duration() {
+    let secs = |secs: f64| Some(Duration::from_secs_f64(secs));
+    assert_eq!(parse_duration("5s"), secs(5.0));
+    assert_eq!(parse_duration("1.5 s"), secs(1.5));
+    assert_eq!(parse_duration("500ms"), secs(0.5));
+    assert_eq!(parse_duration("5 seconds"), secs(5.0));
+    assert_eq!(parse_duration("1m30s"), secs(90.0));
+    assert_eq!(parse_duration("PT1M30S"), secs(90.0));
+    assert_eq!(parse_duration("00:01:30"), secs(90.0));
+    assert_eq!(parse_duration("2"), secs(2.0));
+    assert_eq!(parse_duration("5 apples"), None);
+    assert_eq!(parse_duration(""), None);
+}
+
+#[test]
+fn test_attempt() {
+    assert_eq!(
+        attempt("Connection failed, retrying in 500ms (attempt 3/10)"),
+        Some(Attempt {
+            number: Some(3),
+            total: Some(10),
+            delay: Some(Duration::from_millis(500)),
+        })
+    );
+    assert_eq!(
+        attempt("FAILED - RETRYING: [node]: Wait for the service (10 retries left)."),
+        Some(Attempt::default())
+    );
+    assert_eq!(attempt("Service started"), None);
+    assert_eq!(attempt("Reading the retryable settings"), None);
+
+    assert_eq!(
+        retry_key("Connection failed, retrying in 500ms (attempt 3/10)"),
+        retry_key("Connection failed, retrying in 1m0s (attempt 4/10)")
+    );
+    assert_ne!(
+        retry_key("Connection failed, retrying in 5s"),
+        retry_key("Download failed, retrying in 5s")
+    );
+    assert_eq!(
+        retry_key("12:00:01.123 Wait for the service (10 retries left)."),
+        retry_key("12:00:06.456 Wait for the service (9 retries left).")
+    );
+    assert_ne!(
+        retry_key("Failed password attempt from 10.0.0.1"),
+        retry_key("Failed password attempt from 10.0.0.2")
+    );
+}
+
+#[test]
+fn test_retry_loop() {
+    let mut retry_loop = RetryLoop::default();
+    for (pos, line) in [
+        "retrying in 1s (attempt 2/5)",
+        "retrying in 2s (attempt 3/5)",
+    ]
+    .iter()
+    .enumerate()
+    {
+        retry_loop.add(&attempt(line).unwrap(), pos + 10);
+    }
+    assert_eq!(
+        retry_loop.to_string(),
+        "retried 2× (attempts 2 to 3 of 5, waiting 3s in total) until line 11"
+    );
+}
@@ -0,0 +1,295 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the known error rules, to tag the anomalies with a human readable hint.
+//!
+//! The rules file is a yaml list, see the built-in `rules.yaml`:
+//!
+//! ```yaml
+//! - category: Disk full
+//!   pattern: "No space left on device"
+//!   link: https://example.com/disk-full
+//! ```
+//!
+//! A rule can also remove the matching anomalies until its expiry date, after which the
+//! anomalies are reported again along with a warning:
+//!
+//! ```yaml
+//! - category: Flaky mirror
+//!   pattern: "Failed to fetch .* from mirror"
+//!   ignore: true
+//!   owner: infra-team
+//!   expires: 2023-06-30
+//! ```
+//!
+//! Instead of a pattern, a rule can match the id of an anomaly, which is printed with
+//! `--show-ids`, so that a single anomaly is ignored whatever its variable parts:
+//!
+//! ```yaml
+//! - category: Known race
+//!   id: 3f2a9c1b0d4e5f67
+//!   ignore: true
+//! ```
+
+use anyhow::{Context, Result};
+use chrono::NaiveDate;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{Anomaly, Report};
+
+/// The hint attached to an anomaly.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Hint {
+    pub category: String,
+    pub link: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RuleDef {
+    category: String,
+    pattern: Option<String>,
+    /// The anomaly id, see [crate::anomaly_id].
+    id: Option<String>,
+    link: Option<String>,
+    #[serde(default)]
+    ignore: bool,
+    owner: Option<String>,
+    expires: Option<NaiveDate>,
+}
+
+/// What a rule matches: the line, or the anomaly id.
+enum Matcher {
+    Pattern(Regex),
+    Id(String),
+}
+
+impl Matcher {
+    fn is_match(&self, anomaly: &Anomaly) -> bool {
+        match self {
+            Matcher::Pattern(re) => re.is_match(&anomaly.line),
+            Matcher::Id(id) => &anomaly.id == id,
+        }
+    }
+}
+
+/// An ignore rule, whose anomalies are removed until the expiry date.
+pub struct Suppression {
+    matcher: Matcher,
+    pub category: String,
+    pub owner: Option<String>,
+    pub expires: Option<NaiveDate>,
+}
+
+impl Suppression {
+    fn is_active(&self, today: NaiveDate) -> bool {
+        self.expires.map_or(true, |expires| today <= expires)
+    }
+
+    fn warning(&self) -> String {
+        format!(
+            "The ignore rule {} of {} expired on {}",
+            self.category,
+            self.owner.as_deref().unwrap_or("unknown owner"),
+            self.expires
+                .map(|date| date.to_string())
+                .unwrap_or_default()
+        )
+    }
+}
+
+pub struct Rules {
+    rules: Vec<(Matcher, Hint)>,
+    suppressions: Vec<Suppression>,
+    /// The date to check the expiry of the suppressions.
+    today: NaiveDate,
+}
+
+impl Rules {
+    /// The built-in rules.
+    pub fn builtin() -> Rules {
+        Rules::parse(include_str!("rules.yaml")).expect("Invalid built-in rules")
+    }
+
+    /// The built-in rules extended with the rules of a file.
+    pub fn load(path: &Path) -> Result<Rules> {
+        let mut rules = Rules::builtin();
+        let file_rules =
+            Rules::parse(&std::fs::read_to_string(path).context("Can't read the rules file")?)?;
+        rules.rules.extend(file_rules.rules);
+        rules.suppressions.extend(file_rules.suppressions);
+        Ok(rules)
+    }
+
+    fn parse(content: &str) -> Result<Rules> {
+        let defs: Vec<RuleDef> = serde_yaml::from_str(content).context("Invalid rules")?;
+        let mut rules = Vec::new();
+        let mut suppressions = Vec::new();
+        for def in defs {
+            let matcher = match (&def.pattern, def.id) {
+                (Some(pattern), None) => Matcher::Pattern(
+                    Regex::new(pattern)
+                        .with_context(|| format!("Invalid pattern for {}", def.category))?,
+                ),
+                (None, Some(id)) => Matcher::Id(id),
+                _ => {
+                    return Err(anyhow::anyhow!(
+                        "The rule {} requires either a pattern or an id",
+                        def.category
+                    ))
+                }
+            };
+            if def.ignore {
+                suppressions.push(Suppression {
+                    matcher,
+                    category: def.category,
+                    owner: def.owner,
+                    expires: def.expires,
+                });
+            } else {
+                let hint = Hint {
+                    category: def.category,
+                    link: def.link,
+                };
+                rules.push((matcher, hint));
+            }
+        }
+        Ok(Rules {
+            rules,
+            suppressions,
+            today: chrono::Local::now().date_naive(),
+        })
+    }
+
+    pub fn find(&self, anomaly: &Anomaly) -> Option<&Hint> {
+        self.rules
+            .iter()
+            .find(|(matcher, _)| matcher.is_match(anomaly))
+            .map(|(_, hint)| hint)
+    }
+
+    pub fn annotate(&self, anomaly: &mut Anomaly) {
+        anomaly.hint = self.find(anomaly).cloned();
+    }
+
+    pub fn annotate_report(&self, report: &mut Report) {
+        report
+            .log_reports
+            .iter_mut()
+            .flat_map(|lr| lr.anomalies.iter_mut())
+            .for_each(|anomaly| self.annotate(&mut anomaly.anomaly));
+    }
+
+    /// Check if the anomaly matches an ignore rule that is not expired.
+    pub fn is_suppressed(&self, anomaly: &Anomaly) -> bool {
+        self.suppressions.iter().any(|suppression| {
+            suppression.is_active(self.today) && suppression.matcher.is_match(anomaly)
+        })
+    }
+
+    /// The warnings of the expired ignore rules.
+    pub fn expired_warnings(&self) -> Vec<String> {
+        self.suppressions
+            .iter()
+            .filter(|suppression| !suppression.is_active(self.today))
+            .map(|suppression| suppression.warning())
+            .collect()
+    }
+
+    /// Remove the suppressed anomalies, and add the warnings of the expired ignore rules.
+    pub fn suppress_report(&self, report: &mut Report) {
+        for log_report in report.log_reports.iter_mut() {
+            log_report
+                .anomalies
+                .retain(|anomaly| !self.is_suppressed(&anomaly.anomaly));
+        }
+        report
+            .log_reports
+            .retain(|log_report| !log_report.anomalies.is_empty());
+        report.total_anomaly_count = report
+            .log_reports
+            .iter()
+            .map(|log_report| log_report.anomalies.len())
+            .sum();
+        report.warnings.extend(self.expired_warnings());
+    }
+}
+
+#[cfg(test)]
+fn anomaly(id: &str, line: &str) -> Anomaly {
+    Anomaly {
+        id: id.to_string(),
+        distance: 0.5,
+        pos: 1,
+        offset: 0,
+        column: 1,
+        line: line.to_string(),
+        level: None,
+        test: None,
+        task: None,
+        command: None,
+        hint: None,
+        repeat: 0,
+        run: 0,
+        retry: None,
+    }
+}
+
+#[test]
+fn test_builtin_rules() {
+    let rules = Rules::builtin();
+    let category = |line| {
+        rules
+            .find(&anomaly("", line))
+            .map(|hint| hint.category.clone())
+    };
+    assert_eq!(
+        category("kernel: python3 invoked oom-killer: gfp_mask=0x100cca"),
+        Some("OOM killer".to_string())
+    );
+    assert_eq!(
+        category("curl: (6) Could not resolve host: example.com"),
+        Some("DNS resolution failure".to_string())
+    );
+    assert_eq!(category("regular log line"), None);
+}
+
+#[test]
+fn test_suppressions() {
+    let mut rules = Rules::parse(
+        "
+- category: Flaky mirror
+  pattern: Failed to fetch
+  ignore: true
+  owner: infra-team
+  expires: 2023-06-30
+- category: Deprecation
+  pattern: DeprecationWarning
+  ignore: true
+- category: Known race
+  id: 3f2a9c1b0d4e5f67
+  ignore: true
+",
+    )
+    .unwrap();
+    assert!(rules.find(&anomaly("", "Failed to fetch")).is_none());
+    rules.today = NaiveDate::from_ymd_opt(2023, 6, 30).unwrap();
+    assert!(rules.is_suppressed(&anomaly("", "E: Failed to fetch http://mirror/repo")));
+    assert!(rules.expired_warnings().is_empty());
+
+    rules.today = NaiveDate::from_ymd_opt(2023, 7, 1).unwrap();
+    assert!(!rules.is_suppressed(&anomaly("", "E: Failed to fetch http://mirror/repo")));
+    assert!(rules.is_suppressed(&anomaly("", "DeprecationWarning: the module is deprecated")));
+    assert!(rules.is_suppressed(&anomaly("3f2a9c1b0d4e5f67", "race on port 4242")));
+    assert!(!rules.is_suppressed(&anomaly("0000000000000000", "race on port 4242")));
+    assert_eq!(
+        rules.expired_warnings(),
+        vec!["The ignore rule Flaky mirror of infra-team expired on 2023-06-30"]
+    );
+}
+
+#[test]
+fn test_invalid_rule() {
+    assert!(Rules::parse("- category: Empty\n  ignore: true\n").is_err());
+}
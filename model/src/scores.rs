@@ -0,0 +1,163 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module stores the score of every line in a sqlite database, not only the anomalies, to
+//! tune the threshold or to plot the scores after the run without inspecting the sources again:
+//!
+//! ```sql
+//! SELECT source, count(*) FROM scores JOIN sources ON sources.id = scores.source_id
+//!  WHERE score > 0.4 GROUP BY source;
+//! ```
+//!
+//! Each run adds its sources, with the time of the run.
+
+use anyhow::{Context, Result};
+use rusqlite::{params, Connection};
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use crate::cancel::CancellationToken;
+use crate::{AnomalyContext, IndexName, ProgressObserver, Source};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS sources (
+  id INTEGER PRIMARY KEY,
+  run_time INTEGER NOT NULL,
+  source TEXT NOT NULL,
+  index_name TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS scores (
+  source_id INTEGER NOT NULL REFERENCES sources (id),
+  line INTEGER NOT NULL,
+  score REAL NOT NULL
+);
+";
+
+/// A [ProgressObserver] that stores the line scores in the database.
+pub struct ScoresDb<P> {
+    progress: P,
+    conn: Mutex<Connection>,
+    run_time: i64,
+}
+
+impl<P: ProgressObserver> ScoresDb<P> {
+    /// Open or create the database.
+    pub fn open(path: &Path, progress: P) -> Result<ScoresDb<P>> {
+        let conn = Connection::open(path).context("Can't open the scores database")?;
+        conn.execute_batch(SCHEMA)
+            .context("Can't create the scores database")?;
+        let run_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        Ok(ScoresDb {
+            progress,
+            conn: Mutex::new(conn),
+            run_time,
+        })
+    }
+
+    fn insert(&self, source: &Source, scores: &[(usize, f32)]) -> Result<()> {
+        let conn = self.conn.lock().expect("Scores connection lock");
+        let tx = conn.unchecked_transaction()?;
+        tx.execute(
+            "INSERT INTO sources (run_time, source, index_name) VALUES (?1, ?2, ?3)",
+            params![
+                self.run_time,
+                source.get_relative().as_ref(),
+                IndexName::from_source(source).as_str()
+            ],
+        )?;
+        let source_id = tx.last_insert_rowid();
+        {
+            let mut stmt =
+                tx.prepare("INSERT INTO scores (source_id, line, score) VALUES (?1, ?2, ?3)")?;
+            for (line, score) in scores {
+                stmt.execute(params![source_id, *line as i64, score])?;
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+}
+
+impl<P: ProgressObserver> ProgressObserver for ScoresDb<P> {
+    fn message(&self, msg: &str) {
+        self.progress.message(msg)
+    }
+
+    fn source_started(&self, source: &Source) {
+        self.progress.source_started(source)
+    }
+
+    fn lines_read(&self, source: &Source, line_count: usize, byte_count: usize) {
+        self.progress.lines_read(source, line_count, byte_count)
+    }
+
+    fn source_finished(&self, source: &Source, line_count: usize) {
+        self.progress.source_finished(source, line_count)
+    }
+
+    fn anomaly_found(&self, source: &Source, anomaly: &AnomalyContext) {
+        self.progress.anomaly_found(source, anomaly)
+    }
+
+    fn wants_scores(&self) -> bool {
+        true
+    }
+
+    fn lines_scored(&self, source: &Source, scores: &[(usize, f32)]) {
+        // The inspection goes on, the database only misses this source.
+        if let Err(e) = self.insert(source, scores) {
+            tracing::warn!("Can't store the scores of {}: {:#}", source, e);
+        }
+        self.progress.lines_scored(source, scores)
+    }
+
+    fn cancellation(&self) -> Option<&CancellationToken> {
+        self.progress.cancellation()
+    }
+}
+
+#[test]
+fn test_scores_db() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let baseline = dir.join("app.log");
+    std::fs::write(&baseline, "Starting service\nService started\n").unwrap();
+    let model = crate::Model::train(
+        &crate::OutputMode::Quiet,
+        vec![crate::Content::from_pathbuf(baseline.clone())],
+        crate::hashing_index::new,
+    )
+    .unwrap();
+    let target = dir.join("target").join("app.log");
+    std::fs::create_dir_all(target.parent().unwrap()).unwrap();
+    let lines = "Starting service\nTraceback: oops\nStarting service\nService started\n";
+    std::fs::write(&target, lines).unwrap();
+
+    let db_path = dir.join("scores.sqlite");
+    let progress = ScoresDb::open(&db_path, crate::OutputMode::Quiet).unwrap();
+    let report = model
+        .report(&progress, crate::Content::from_pathbuf(target))
+        .unwrap();
+    let scores = progress
+        .conn
+        .lock()
+        .unwrap()
+        .prepare("SELECT line, score FROM scores ORDER BY line")
+        .unwrap()
+        .query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, f32>(1)?)))
+        .unwrap()
+        .collect::<rusqlite::Result<Vec<_>>>()
+        .unwrap();
+
+    assert_eq!(report.total_anomaly_count, 1);
+    assert_eq!(
+        scores.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+        vec![1, 2, 3, 4]
+    );
+    assert!(scores[1].1 > crate::process::THRESHOLD);
+    assert!(scores[2].1 < crate::process::THRESHOLD);
+    assert_eq!(scores[0].1, scores[2].1);
+}
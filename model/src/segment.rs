@@ -0,0 +1,199 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module detects the test runner outputs, to attribute the anomalies to a test case,
+//! the zuul job outputs, to attribute the anomalies to an ansible task, and the shell traces,
+//! to attribute the anomalies to a command and its exit status.
+
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+lazy_static::lazy_static! {
+    static ref TEST_STARTS: Vec<Regex> = [
+        // go test: `=== RUN   TestFoo`
+        r"=== RUN\s+(\S+)",
+        // googletest: `[ RUN      ] Suite.Name`
+        r"\[ RUN\s+\] (\S+)",
+        // ctest: `    Start 3: foo_test`
+        r"^\s*Start\s+\d+: (\S+)",
+        // pytest failure banner: `____ test_foo ____`
+        r"^_{3,} (\S+) _{3,}$",
+        // pytest verbose: `tests/test_foo.py::test_bar PASSED`
+        r"^(\S+\.py::\S+)",
+        // stestr: `{0} tempest.api.test_foo [0.42s] ... ok`, see [crate::subunit]
+        r"^\{\d+\} (\S+) ",
+    ]
+    .iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect();
+
+    // zuul job-output: `| PRE-RUN START: [trusted : opendev.org/base/pre.yaml@master]`
+    static ref PLAYBOOK_START: Regex =
+        Regex::new(r"\| (?:PRE-|POST-|CLEANUP-)?RUN START: \[\w+ : ([^@\]]+)").unwrap();
+    // zuul job-output: `| TASK [tox : Run tox testing]`
+    static ref TASK_START: Regex = Regex::new(r"\| TASK \[(.+)\]$").unwrap();
+
+    // shell trace: `+ make check`, or `| ++ git rev-parse HEAD` in a zuul job-output
+    static ref COMMAND_START: Regex = Regex::new(r"(?:^|\| )\++ (\S.*)$").unwrap();
+    static ref EXIT_STATUS: Vec<Regex> = [
+        // `exit status 2`, `Process completed with exit code 2`
+        r"(?i)exit (?:status|code):? (\d+)",
+        // make: `make: *** [Makefile:12: check] Error 2`
+        r"\*\*\* \[.+\] Error (\d+)$",
+        // ansible: `"rc": 2`
+        r#""rc": (\d+)"#,
+    ]
+    .iter()
+    .map(|re| Regex::new(re).unwrap())
+    .collect();
+}
+
+/// The substrings of the test case starts, to avoid running the regexes on every lines.
+const HINTS: [&str; 5] = ["RUN", "Start", "___", ".py::", "} "];
+
+/// Return the test case name when the line starts a new test case.
+pub fn test_case_start(line: &str) -> Option<&str> {
+    if !HINTS.iter().any(|hint| line.contains(hint)) {
+        return None;
+    }
+    TEST_STARTS
+        .iter()
+        .find_map(|re| re.captures(line.trim_end()))
+        .and_then(|captures| captures.get(1))
+        .map(|name| name.as_str())
+}
+
+/// A step of a zuul job output.
+#[derive(Debug, PartialEq)]
+pub enum Step<'a> {
+    Playbook(&'a str),
+    Task(&'a str),
+}
+
+/// Return the playbook or task when the line starts a new step of a zuul job.
+pub fn step_start(line: &str) -> Option<Step<'_>> {
+    if line.contains("TASK [") {
+        TASK_START
+            .captures(line.trim_end())
+            .and_then(|captures| captures.get(1))
+            .map(|name| Step::Task(name.as_str()))
+    } else if line.contains(" START: [") {
+        PLAYBOOK_START
+            .captures(line)
+            .and_then(|captures| captures.get(1))
+            .map(|name| Step::Playbook(name.as_str()))
+    } else {
+        None
+    }
+}
+
+/// The shell command that was running before an anomaly.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Command {
+    pub line: String,
+    /// The exit status found after the anomaly, before the next command.
+    pub status: Option<i32>,
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.status {
+            Some(status) if status != 0 => {
+                write!(f, "Command `{}` failed with status {}", self.line, status)
+            }
+            _ => write!(f, "After command `{}`", self.line),
+        }
+    }
+}
+
+/// Return the command when the line is a shell trace, e.g. from `set -x`.
+pub fn command_start(line: &str) -> Option<&str> {
+    if !line.contains("+ ") {
+        return None;
+    }
+    COMMAND_START
+        .captures(line.trim_end())
+        .and_then(|captures| captures.get(1))
+        .map(|command| command.as_str())
+}
+
+/// Return the exit status when the line reports the end of a command.
+pub fn exit_status(line: &str) -> Option<i32> {
+    if !(line.contains("xit") || line.contains("Error ") || line.contains("\"rc\"")) {
+        return None;
+    }
+    EXIT_STATUS
+        .iter()
+        .find_map(|re| re.captures(line.trim_end()))
+        .and_then(|captures| captures.get(1))
+        .and_then(|status| status.as_str().parse().ok())
+}
+
+#[test]
+fn test_commands() {
+    assert_eq!(command_start("+ make check"), Some("make check"));
+    assert_eq!(
+        command_start("2022-05-10 10:00:00.123 | ++ git rev-parse HEAD"),
+        Some("git rev-parse HEAD")
+    );
+    assert_eq!(command_start("1 + 1 = 2"), None);
+    assert_eq!(exit_status("ERROR: InvocationError: exit code 2"), Some(2));
+    assert_eq!(
+        exit_status("make: *** [Makefile:12: check] Error 2"),
+        Some(2)
+    );
+    assert_eq!(
+        exit_status(r#"fatal: [controller]: FAILED! => {"rc": 127}"#),
+        Some(127)
+    );
+    assert_eq!(exit_status("regular log line"), None);
+    let command = Command {
+        line: "make check".into(),
+        status: Some(2),
+    };
+    assert_eq!(
+        command.to_string(),
+        "Command `make check` failed with status 2"
+    );
+}
+
+#[test]
+fn test_step_start() {
+    assert_eq!(
+        step_start(
+            "2022-05-10 10:00:00.123 | PRE-RUN START: [trusted : opendev.org/base/pre.yaml@master]"
+        ),
+        Some(Step::Playbook("opendev.org/base/pre.yaml"))
+    );
+    assert_eq!(
+        step_start("2022-05-10 10:00:01.456 | TASK [tox : Run tox testing]"),
+        Some(Step::Task("tox : Run tox testing"))
+    );
+    assert_eq!(
+        step_start("2022-05-10 10:00:02.789 | controller | ok"),
+        None
+    );
+}
+
+#[test]
+fn test_test_case_start() {
+    assert_eq!(
+        test_case_start("=== RUN   TestFoo/sub"),
+        Some("TestFoo/sub")
+    );
+    assert_eq!(
+        test_case_start("[ RUN      ] Suite.Name"),
+        Some("Suite.Name")
+    );
+    assert_eq!(test_case_start("    Start 3: foo_test"), Some("foo_test"));
+    assert_eq!(test_case_start("_____ test_bar _____"), Some("test_bar"));
+    assert_eq!(
+        test_case_start("tests/test_foo.py::test_bar PASSED [ 10%]"),
+        Some("tests/test_foo.py::test_bar")
+    );
+    assert_eq!(
+        test_case_start("{0} tempest.api.test_foo ... FAILED"),
+        Some("tempest.api.test_foo")
+    );
+    assert_eq!(test_case_start("regular log line"), None);
+}
@@ -0,0 +1,98 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the async variants of the sources listing and reading, for the async
+//! consumers like a server.
+//!
+//! The blocking work runs on the tokio blocking threads, and the results are sent through a
+//! bounded channel, so that a slow consumer pauses the reading. The streams must be created
+//! within a tokio runtime, and dropping a stream stops the blocking work at the next item.
+
+use anyhow::Result;
+use logreduce_iterator::LogLine;
+use tokio::sync::mpsc::Sender;
+use tokio_stream::wrappers::ReceiverStream;
+
+use crate::{Content, Source};
+
+/// The number of items buffered by a stream.
+const CHANNEL_SIZE: usize = 128;
+
+/// Run the producer on a blocking thread, with the sender of the returned stream.
+fn spawn_stream<T: Send + 'static>(
+    producer: impl FnOnce(Sender<T>) + Send + 'static,
+) -> ReceiverStream<T> {
+    let (tx, rx) = tokio::sync::mpsc::channel(CHANNEL_SIZE);
+    tokio::task::spawn_blocking(move || producer(tx));
+    ReceiverStream::new(rx)
+}
+
+impl Content {
+    /// List the sources, like [Content::get_sources_iter].
+    pub fn get_sources_stream(&self) -> ReceiverStream<Result<Source>> {
+        let content = self.clone();
+        spawn_stream(move |tx| {
+            for source in content.get_sources_iter() {
+                if tx.blocking_send(source).is_err() {
+                    // The stream is dropped.
+                    break;
+                }
+            }
+        })
+    }
+}
+
+impl Source {
+    /// Read the lines of the source, the first item is the error when the source can't be open.
+    pub fn lines_stream(&self) -> ReceiverStream<Result<LogLine>> {
+        let source = self.clone();
+        spawn_stream(move |tx| {
+            let reader = source.open();
+            let reader = match reader {
+                Ok(reader) => reader,
+                Err(e) => {
+                    let _ = tx.blocking_send(Err(e));
+                    return;
+                }
+            };
+            for line in logreduce_iterator::BytesLines::new(reader, source.is_json()) {
+                if tx.blocking_send(line.map_err(Into::into)).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+}
+
+#[test]
+fn test_streams() {
+    use tokio_stream::StreamExt;
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::write(dir.join("app.log"), "Starting service\nService started\n").unwrap();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let (sources, lines) = runtime.block_on(async {
+        let sources = Content::from_path(dir)
+            .unwrap()
+            .get_sources_stream()
+            .collect::<Result<Vec<_>>>()
+            .await
+            .unwrap();
+        let lines = sources[0].lines_stream().collect::<Vec<_>>().await;
+        (sources, lines)
+    });
+    let missing = runtime.block_on(async {
+        Source::from_pathbuf(dir.join("missing.log"))
+            .lines_stream()
+            .collect::<Vec<_>>()
+            .await
+    });
+    assert_eq!(sources.len(), 1);
+    assert_eq!(lines.len(), 2);
+    assert_eq!(&lines[1].as_ref().unwrap().0[..], b"Service started");
+    assert_eq!(missing.len(), 1);
+    assert!(missing[0].is_err());
+}
@@ -0,0 +1,431 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module reads the streaming inputs, which have no path nor length, and which can only be
+//! read once:
+//!
+//! - `-`: the standard input.
+//! - `tcp://HOST:PORT`: the lines sent by a server.
+//! - `journald://[UNIT]`: the followed systemd journal, optionally of a single unit.
+//! - `k8s://NAMESPACE/POD[/CONTAINER]`: the followed logs of a kubernetes pod.
+//!
+//! They are [Source::Stream] sources, so that they are inspected and reported like the files.
+//! The daemon and the exec mode also read their journal and command output with this module, see
+//! [follow_journal] and [spawn].
+//! Their size is unknown, the progress is given by the lines read, see
+//! [crate::progress::ProgressObserver::lines_read].
+
+use anyhow::{Context, Result};
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdout, Command, ExitStatus, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+
+use crate::reader::DecompressReader;
+use crate::Source;
+
+/// The address of the standard input.
+pub const STDIN: &str = "-";
+
+const SCHEMES: [&str; 3] = ["tcp://", "journald://", "k8s://"];
+
+/// Check if the input is the address of a stream.
+pub fn is_stream(input: &str) -> bool {
+    input == STDIN || SCHEMES.iter().any(|scheme| input.starts_with(scheme))
+}
+
+/// The source of a stream address, see [is_stream].
+pub fn from_input(input: &str) -> Option<Source> {
+    if is_stream(input) {
+        Some(Source::Stream(input.to_string()))
+    } else {
+        None
+    }
+}
+
+/// The identifier of the journal entries written by the daemon, which are not inspected again.
+pub const ANOMALY_IDENTIFIER: &str = "logreduce-anomaly";
+
+/// The command of a followed stream, which is killed when the reader is dropped.
+type SharedChild = Arc<Mutex<Child>>;
+
+/// A followed stream, to stop it and to get the status of its command.
+#[derive(Clone)]
+pub struct Handle(Option<SharedChild>);
+
+impl Handle {
+    /// Stop the command gracefully, so that the reader reaches the end, e.g. journalctl writes its
+    /// cursor file on SIGTERM.
+    pub fn stop(&self) {
+        if let Some(child) = &self.0 {
+            let mut child = child.lock().expect("Child lock");
+            #[cfg(unix)]
+            {
+                if let Ok(None) = child.try_wait() {
+                    // Safety: the child is not waited for yet, so its pid is not reused.
+                    unsafe { libc::kill(child.id() as libc::pid_t, libc::SIGTERM) };
+                }
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = child.kill();
+            }
+        }
+    }
+
+    /// Check if the command is still running, a stream without command is always running.
+    pub fn is_running(&self) -> bool {
+        match &self.0 {
+            Some(child) => matches!(child.lock().expect("Child lock").try_wait(), Ok(None)),
+            None => true,
+        }
+    }
+
+    /// Wait for the end of the command, this returns None for a stream without command.
+    pub fn wait(&self) -> Result<Option<ExitStatus>> {
+        match &self.0 {
+            Some(child) => Ok(Some(child.lock().expect("Child lock").wait()?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// The output of a following command.
+struct ChildReader<R> {
+    child: SharedChild,
+    output: R,
+}
+
+impl<R: Read> Read for ChildReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.output.read(buf)
+    }
+}
+
+impl<R> Drop for ChildReader<R> {
+    fn drop(&mut self) {
+        let mut child = self.child.lock().expect("Child lock");
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// The lines of the stdout and stderr of a command, in the order they are read.
+struct Lines(mpsc::Receiver<Vec<u8>>, Cursor<Vec<u8>>);
+
+impl Read for Lines {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let size = self.1.read(buf)?;
+            if size > 0 {
+                return Ok(size);
+            }
+            match self.0.recv() {
+                Ok(line) => self.1 = Cursor::new(line),
+                // Both outputs are closed.
+                Err(_) => return Ok(0),
+            }
+        }
+    }
+}
+
+/// Send the lines of the output to the channel, until its end. The lines are also written to
+/// the tee output as soon as they are read.
+fn forward(
+    output: impl Read + Send + 'static,
+    lines: mpsc::Sender<Vec<u8>>,
+    mut tee: Option<Box<dyn Write + Send>>,
+) {
+    std::thread::spawn(move || {
+        for line in BufReader::new(output).split(b'\n') {
+            let mut line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            line.push(b'\n');
+            if let Some(output) = &mut tee {
+                if output
+                    .write_all(&line)
+                    .and_then(|_| output.flush())
+                    .is_err()
+                {
+                    tee = None;
+                }
+            }
+            if lines.send(line).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+/// The journal entries in the json format, which are read as their messages, see
+/// [entry_message].
+struct Journal<R> {
+    entries: std::io::Split<BufReader<R>>,
+    message: Cursor<Vec<u8>>,
+}
+
+impl<R: Read> Read for Journal<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let size = self.message.read(buf)?;
+            if size > 0 {
+                return Ok(size);
+            }
+            let entry = match self.entries.next() {
+                Some(entry) => entry?,
+                None => return Ok(0),
+            };
+            match entry_message(&entry) {
+                Ok(Some(message)) => {
+                    let mut message = message.into_bytes();
+                    message.push(b'\n');
+                    self.message = Cursor::new(message);
+                }
+                Ok(None) => {}
+                Err(e) => tracing::warn!("{:#}", e),
+            }
+        }
+    }
+}
+
+/// The message of a journal entry, None for the anomalies written by the daemon, to not
+/// inspect them again.
+fn entry_message(entry: &[u8]) -> Result<Option<String>> {
+    let entry: serde_json::Value =
+        serde_json::from_slice(entry).context("Invalid journal entry")?;
+    if entry["SYSLOG_IDENTIFIER"] == ANOMALY_IDENTIFIER {
+        return Ok(None);
+    }
+    Ok(match &entry["MESSAGE"] {
+        serde_json::Value::String(message) => Some(message.clone()),
+        // The messages that are not valid UTF-8 are given as an array of bytes.
+        serde_json::Value::Array(bytes) => {
+            let bytes = bytes
+                .iter()
+                .filter_map(|byte| byte.as_u64().map(|byte| byte as u8))
+                .collect::<Vec<_>>();
+            Some(String::from_utf8_lossy(&bytes).into_owned())
+        }
+        _ => None,
+    })
+}
+
+fn journal_command(unit: &str, cursor_file: Option<&Path>) -> Command {
+    let mut cmd = Command::new("journalctl");
+    cmd.arg("--follow")
+        .arg("--output=json")
+        .arg("--output-fields=MESSAGE,SYSLOG_IDENTIFIER");
+    if !unit.is_empty() {
+        cmd.arg(format!("--unit={}", unit));
+    }
+    if let Some(cursor_file) = cursor_file {
+        cmd.arg(format!("--cursor-file={}", cursor_file.display()));
+        if !cursor_file.exists() {
+            // Only look at the new entries on the first start.
+            cmd.arg("--lines=0");
+        }
+    }
+    cmd
+}
+
+/// The command that follows the stream.
+fn command(address: &str) -> Result<Command> {
+    if let Some(unit) = address.strip_prefix("journald://") {
+        Ok(journal_command(unit, None))
+    } else if let Some(pod) = address.strip_prefix("k8s://") {
+        let mut cmd = Command::new("kubectl");
+        cmd.arg("logs").arg("--follow");
+        match pod.split('/').collect::<Vec<_>>().as_slice() {
+            [namespace, pod] => cmd.arg(format!("--namespace={}", namespace)).arg(pod),
+            [namespace, pod, container] => cmd
+                .arg(format!("--namespace={}", namespace))
+                .arg(pod)
+                .arg(format!("--container={}", container)),
+            _ => return Err(anyhow::anyhow!("{}: expected k8s://NAMESPACE/POD", address)),
+        };
+        Ok(cmd)
+    } else {
+        Err(anyhow::anyhow!("{}: unknown stream", address))
+    }
+}
+
+/// Start the following command, with its stdout.
+fn spawn_stdout(mut cmd: Command, address: &str) -> Result<(ChildReader<ChildStdout>, Handle)> {
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Can't follow {}", address))?;
+    let output = child.stdout.take().expect("Piped stdout");
+    let child = Arc::new(Mutex::new(child));
+    let handle = Handle(Some(child.clone()));
+    Ok((ChildReader { child, output }, handle))
+}
+
+/// Follow the journal, optionally of a single unit. With a cursor file, the journal is read from
+/// the last entry read by the previous run.
+pub fn follow_journal(
+    unit: &str,
+    cursor_file: Option<&Path>,
+) -> Result<(DecompressReader, Handle)> {
+    let address = format!("journald://{}", unit);
+    let (reader, handle) = spawn_stdout(journal_command(unit, cursor_file), &address)?;
+    let reader = Journal {
+        entries: BufReader::new(reader).split(b'\n'),
+        message: Cursor::new(Vec::new()),
+    };
+    Ok((DecompressReader::Stream(Box::new(reader)), handle))
+}
+
+/// Run a command, its stdout and stderr lines are read concurrently, so their relative order is
+/// only approximate. With tee, the lines are also written to the stdout and stderr, like the
+/// command would.
+pub fn spawn(command: &[String], tee: bool) -> Result<(DecompressReader, Handle)> {
+    let (program, args) = command.split_first().context("Missing command")?;
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Can't run {}", program))?;
+    let (sender, receiver) = mpsc::channel();
+    let tee_to = |output: Box<dyn Write + Send>| if tee { Some(output) } else { None };
+    let (stdout, stderr) = (
+        tee_to(Box::new(std::io::stdout())),
+        tee_to(Box::new(std::io::stderr())),
+    );
+    forward(
+        child.stdout.take().expect("Piped stdout"),
+        sender.clone(),
+        stdout,
+    );
+    forward(child.stderr.take().expect("Piped stderr"), sender, stderr);
+    let child = Arc::new(Mutex::new(child));
+    let handle = Handle(Some(child.clone()));
+    let output = Lines(receiver, Cursor::new(Vec::new()));
+    let reader = ChildReader { child, output };
+    Ok((DecompressReader::Stream(Box::new(reader)), handle))
+}
+
+/// Open the stream, and return its handle, this starts the following command when there is one.
+pub fn follow(address: &str) -> Result<(DecompressReader, Handle)> {
+    if address == STDIN {
+        Ok((
+            DecompressReader::Stream(Box::new(std::io::stdin())),
+            Handle(None),
+        ))
+    } else if let Some(addr) = address.strip_prefix("tcp://") {
+        let stream = std::net::TcpStream::connect(addr)
+            .with_context(|| format!("Can't connect to {}", address))?;
+        Ok((DecompressReader::Stream(Box::new(stream)), Handle(None)))
+    } else if let Some(unit) = address.strip_prefix("journald://") {
+        follow_journal(unit, None)
+    } else {
+        let (reader, handle) = spawn_stdout(command(address)?, address)?;
+        Ok((DecompressReader::Stream(Box::new(reader)), handle))
+    }
+}
+
+/// Open the stream, this starts the following command when there is one.
+pub fn open(address: &str) -> Result<DecompressReader> {
+    follow(address).map(|(reader, _)| reader)
+}
+
+#[test]
+fn test_streams() {
+    assert_eq!(from_input("-"), Some(Source::Stream("-".to_string())));
+    assert!(is_stream("journald://sshd.service"));
+    assert!(!is_stream("logs/job-output.txt"));
+
+    let args = |address: &str| {
+        command(address)
+            .unwrap()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        args("journald://sshd.service"),
+        vec![
+            "--follow",
+            "--output=json",
+            "--output-fields=MESSAGE,SYSLOG_IDENTIFIER",
+            "--unit=sshd.service"
+        ]
+    );
+    assert_eq!(
+        args("k8s://default/api/server"),
+        vec![
+            "logs",
+            "--follow",
+            "--namespace=default",
+            "api",
+            "--container=server"
+        ]
+    );
+    assert!(command("k8s://api").is_err());
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = format!("tcp://{}", listener.local_addr().unwrap());
+    let server = std::thread::spawn(move || {
+        use std::io::Write;
+        let (mut socket, _) = listener.accept().unwrap();
+        socket.write_all(b"Starting service\n").unwrap();
+    });
+    let mut lines = String::new();
+    open(&address).unwrap().read_to_string(&mut lines).unwrap();
+    server.join().unwrap();
+    assert_eq!(lines, "Starting service\n");
+}
+
+#[test]
+fn test_entry_message() {
+    let message = |entry: &str| entry_message(entry.as_bytes()).unwrap();
+    assert_eq!(
+        message(r#"{"MESSAGE": "Started session", "SYSLOG_IDENTIFIER": "systemd"}"#),
+        Some("Started session".to_string())
+    );
+    assert_eq!(
+        message(r#"{"MESSAGE": "Timeout", "SYSLOG_IDENTIFIER": "logreduce-anomaly"}"#),
+        None
+    );
+    assert_eq!(
+        message(r#"{"MESSAGE": [79, 75, 255]}"#),
+        Some("OK\u{fffd}".to_string())
+    );
+    assert!(entry_message(b"not json").is_err());
+
+    let entries = concat!(
+        r#"{"MESSAGE": "Started session\nuser: root"}"#,
+        "\n",
+        r#"{"MESSAGE": "Timeout", "SYSLOG_IDENTIFIER": "logreduce-anomaly"}"#,
+        "\nnot json\n",
+        r#"{"MESSAGE": "Stopped session"}"#,
+    );
+    let mut journal = Journal {
+        entries: BufReader::new(entries.as_bytes()).split(b'\n'),
+        message: Cursor::new(Vec::new()),
+    };
+    let mut lines = String::new();
+    journal.read_to_string(&mut lines).unwrap();
+    assert_eq!(lines, "Started session\nuser: root\nStopped session\n");
+}
+
+#[cfg(unix)]
+#[test]
+fn test_spawn() {
+    let command = ["sh", "-c", "echo Running; echo Error >&2; exit 3"].map(String::from);
+    let (mut reader, handle) = spawn(&command, false).unwrap();
+    let mut lines = String::new();
+    reader.read_to_string(&mut lines).unwrap();
+    let mut lines = lines.lines().collect::<Vec<_>>();
+    lines.sort_unstable();
+    assert_eq!(lines, vec!["Error", "Running"]);
+    assert_eq!(
+        handle.wait().unwrap().and_then(|status| status.code()),
+        Some(3)
+    );
+}
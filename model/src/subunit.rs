@@ -0,0 +1,343 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the subunit (v2) test results source.
+//!
+//! The binary stream is rendered into text lines, like the stestr output: a line per test with
+//! its status, followed by its attachments (e.g. the traceback), so that a failing test is
+//! attributed with [crate::segment::test_case_start].
+//!
+//! A local file is split in one [Source::Section] per test that failed or has attachments, so
+//! that the anomalies are attributed per test, and the other tests are the [SUMMARY] section.
+//! The sections share the index of the file. The file is parsed once: the output of the other
+//! tests is kept until their section is read.
+
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::reader::DecompressReader;
+use crate::Source;
+
+/// The section of the tests without attachments that succeeded or were skipped.
+pub const SUMMARY: &str = "summary";
+
+const SIGNATURE: u8 = 0xb3;
+const FLAG_TEST_ID: u16 = 0x0800;
+const FLAG_ROUTE_CODE: u16 = 0x0400;
+const FLAG_TIMESTAMP: u16 = 0x0200;
+const FLAG_TAGS: u16 = 0x0080;
+const FLAG_FILE_CONTENT: u16 = 0x0040;
+const FLAG_MIME_TYPE: u16 = 0x0020;
+
+/// Check if the path is a subunit stream.
+pub fn is_subunit(path: &str) -> bool {
+    path.ends_with(".subunit")
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct Packet {
+    test_id: Option<String>,
+    status: u8,
+    file: Option<(String, Vec<u8>)>,
+}
+
+struct Bytes<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let end = self.pos + count;
+        if end > self.buf.len() {
+            return Err(anyhow::anyhow!("Truncated subunit packet"));
+        }
+        let res = &self.buf[self.pos..end];
+        self.pos = end;
+        Ok(res)
+    }
+
+    /// The first two bits of the first byte are the number of extra bytes.
+    fn varint(&mut self) -> Result<usize> {
+        let first = self.take(1)?[0];
+        let extra = (first >> 6) as usize;
+        Ok(self
+            .take(extra)?
+            .iter()
+            .fold((first & 0x3f) as usize, |acc, b| (acc << 8) | *b as usize))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.varint()?;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+}
+
+/// Parse the packet at the start of the buffer, returning it with its length.
+fn parse(buf: &[u8]) -> Result<(Packet, usize)> {
+    let mut bytes = Bytes { buf, pos: 0 };
+    if bytes.take(1)?[0] != SIGNATURE {
+        return Err(anyhow::anyhow!("Invalid subunit signature"));
+    }
+    let flags = bytes.take(2)?;
+    let flags = u16::from_be_bytes([flags[0], flags[1]]);
+    let length = bytes.varint()?;
+    if length > buf.len() {
+        return Err(anyhow::anyhow!("Truncated subunit packet"));
+    }
+    // The content ends before the crc32.
+    let mut bytes = Bytes {
+        buf: &buf[..length.saturating_sub(4)],
+        pos: bytes.pos,
+    };
+    let mut packet = Packet {
+        status: (flags & 0x7) as u8,
+        ..Packet::default()
+    };
+    if flags & FLAG_TIMESTAMP != 0 {
+        bytes.take(4)?;
+        bytes.varint()?;
+    }
+    if flags & FLAG_TEST_ID != 0 {
+        packet.test_id = Some(bytes.string()?);
+    }
+    if flags & FLAG_TAGS != 0 {
+        for _ in 0..bytes.varint()? {
+            bytes.string()?;
+        }
+    }
+    if flags & FLAG_MIME_TYPE != 0 {
+        bytes.string()?;
+    }
+    if flags & FLAG_FILE_CONTENT != 0 {
+        let name = bytes.string()?;
+        let len = bytes.varint()?;
+        packet.file = Some((name, bytes.take(len)?.to_vec()));
+    }
+    if flags & FLAG_ROUTE_CODE != 0 {
+        bytes.string()?;
+    }
+    Ok((packet, length))
+}
+
+fn status_name(status: u8) -> Option<&'static str> {
+    match status {
+        3 => Some("ok"),
+        4 => Some("UNEXPECTED SUCCESS"),
+        5 => Some("SKIPPED"),
+        6 => Some("FAILED"),
+        7 => Some("xfail"),
+        _ => None,
+    }
+}
+
+/// The section and the rendered output of each test, in the order of the tests results.
+type Sections = Vec<(String, String)>;
+
+/// Render the tests and their attachments, with their section.
+fn render_sections(mut reader: impl Read) -> Result<Sections> {
+    let mut buf = Vec::new();
+    reader.read_to_end(&mut buf)?;
+    // The attachments of the running tests, by test id and file name.
+    let mut attachments: HashMap<String, Vec<(String, Vec<u8>)>> = HashMap::new();
+    let mut sections = Sections::new();
+    let mut pos = 0;
+    while pos < buf.len() {
+        let (packet, length) = match parse(&buf[pos..]) {
+            Ok(res) => res,
+            Err(e) => {
+                tracing::warn!("Stopping at subunit offset {}: {}", pos, e);
+                break;
+            }
+        };
+        pos += length.max(1);
+        let test_id = match packet.test_id {
+            Some(test_id) => test_id,
+            None => continue,
+        };
+        if let Some((name, content)) = packet.file {
+            let files = attachments.entry(test_id.clone()).or_default();
+            match files.iter_mut().find(|(file_name, _)| *file_name == name) {
+                Some((_, buf)) => buf.extend(content),
+                None => files.push((name, content)),
+            }
+        }
+        if let Some(status) = status_name(packet.status) {
+            let files = attachments.remove(&test_id).unwrap_or_default();
+            let mut output = format!("{{0}} {} ... {}\n", test_id, status);
+            for (_, content) in files.iter() {
+                output.push_str(String::from_utf8_lossy(content).trim_end());
+                output.push('\n');
+            }
+            if files.is_empty() && matches!(status, "ok" | "SKIPPED" | "xfail") {
+                sections.push((SUMMARY.to_string(), output));
+            } else {
+                sections.push((test_id, output));
+            }
+        }
+    }
+    Ok(sections)
+}
+
+/// Group the output by section, returning the section names in order.
+fn group(sections: Sections) -> (Vec<String>, HashMap<String, String>) {
+    let mut names = Vec::new();
+    let mut outputs: HashMap<String, String> = HashMap::new();
+    for (section, output) in sections {
+        match outputs.get_mut(&section) {
+            Some(buf) => buf.push_str(&output),
+            None => {
+                names.push(section.clone());
+                outputs.insert(section, output);
+            }
+        }
+    }
+    (names, outputs)
+}
+
+/// Render the tests and their attachments.
+pub fn render(reader: impl Read) -> Result<DecompressReader> {
+    let output = render_sections(reader)?
+        .into_iter()
+        .map(|(_, output)| output)
+        .collect::<String>();
+    Ok(DecompressReader::Rendered(Cursor::new(output.into_bytes())))
+}
+
+/// Render a local subunit file.
+pub fn open(path: &Path) -> Result<DecompressReader> {
+    render(std::fs::File::open(path)?)
+}
+
+/// The rendered sections of the files that are not read yet.
+static PENDING: OnceCell<Mutex<HashMap<PathBuf, HashMap<String, String>>>> = OnceCell::new();
+
+fn pending() -> std::sync::MutexGuard<'static, HashMap<PathBuf, HashMap<String, String>>> {
+    PENDING
+        .get_or_init(Default::default)
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+}
+
+/// Create one source per section of a local file.
+pub fn sources(base_len: usize, path: PathBuf) -> Result<Vec<Source>> {
+    let (names, outputs) = group(render_sections(std::fs::File::open(&path)?)?);
+    let file = Source::Local(base_len, path.clone());
+    let sources = names
+        .into_iter()
+        .map(|section| Source::Section(Box::new(file.clone()), section))
+        .collect();
+    pending().insert(path, outputs);
+    Ok(sources)
+}
+
+/// Read the output of a single section. The file is parsed again when the section was already
+/// read, e.g. when a baseline is also a target.
+pub fn open_section(path: &Path, section: &str) -> Result<DecompressReader> {
+    tracing::debug!(path = path.to_str(), section, "Reading subunit section");
+    let mut pending = pending();
+    let output = match pending
+        .get_mut(path)
+        .and_then(|sections| sections.remove(section))
+    {
+        Some(output) => output,
+        None => {
+            let (_, mut sections) = group(render_sections(std::fs::File::open(path)?)?);
+            let output = sections.remove(section).unwrap_or_default();
+            pending.insert(path.to_path_buf(), sections);
+            output
+        }
+    };
+    if pending
+        .get(path)
+        .map_or(false, |sections| sections.is_empty())
+    {
+        pending.remove(path);
+    }
+    Ok(DecompressReader::Rendered(Cursor::new(output.into_bytes())))
+}
+
+#[cfg(test)]
+fn encode(test_id: &str, status: u8, file: Option<(&str, &str)>) -> Vec<u8> {
+    let string = |s: &str| {
+        let mut v = vec![s.len() as u8];
+        v.extend(s.as_bytes());
+        v
+    };
+    let mut flags = 0x2000 | FLAG_TEST_ID | status as u16;
+    let mut content = string(test_id);
+    if let Some((name, data)) = file {
+        flags |= FLAG_FILE_CONTENT;
+        content.extend(string(name));
+        content.extend(string(data));
+    }
+    // signature, flags, length and crc32
+    let length = 1 + 2 + 1 + content.len() + 4;
+    let mut packet = vec![SIGNATURE];
+    packet.extend(flags.to_be_bytes());
+    packet.push(length as u8);
+    packet.extend(content);
+    packet.extend([0; 4]);
+    packet
+}
+
+#[test]
+fn test_subunit() {
+    let mut stream = encode("test_ok", 3, None);
+    stream.extend(encode("test_ko", 2, Some(("traceback", "Traceback\n"))));
+    stream.extend(encode(
+        "test_ko",
+        2,
+        Some(("traceback", "AssertionError\n")),
+    ));
+    stream.extend(encode("test_ko", 6, None));
+    assert_eq!(
+        parse(&stream).unwrap().0,
+        Packet {
+            test_id: Some("test_ok".to_string()),
+            status: 3,
+            file: None
+        }
+    );
+    let mut output = String::new();
+    render(Cursor::new(stream))
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert_eq!(
+        output,
+        "{0} test_ok ... ok\n{0} test_ko ... FAILED\nTraceback\nAssertionError\n"
+    );
+
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let path = dir.join("testrepository.subunit");
+    std::fs::write(&path, &stream).unwrap();
+    let sources = sources(dir.to_string_lossy().len(), path.clone()).unwrap();
+    let sections = sources
+        .iter()
+        .map(|source| match source {
+            Source::Section(_, section) => section.as_str(),
+            _ => panic!("Unexpected source {}", source),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(sections, vec![SUMMARY, "test_ko"]);
+    let mut output = String::new();
+    sources[1]
+        .open()
+        .unwrap()
+        .read_to_string(&mut output)
+        .unwrap();
+    assert_eq!(
+        output,
+        "{0} test_ko ... FAILED\nTraceback\nAssertionError\n"
+    );
+    assert_eq!(
+        crate::IndexName::from_source(&sources[0]),
+        crate::IndexName::from_source(&sources[1])
+    );
+}
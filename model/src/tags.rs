@@ -0,0 +1,129 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the model tags, to select the model matching the target platform.
+//!
+//! The tags are set at train time, e.g. `os=centos-9`, and the target tags are detected
+//! from the zuul inventory found in the artifact tree.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+
+use crate::{Content, Model, Source};
+
+/// The model attributes, e.g. the OS, the arch or the job variant.
+pub type Tags = BTreeMap<String, String>;
+
+/// Parse a `key=value` tag.
+pub fn parse_tag(s: &str) -> std::result::Result<(String, String), String> {
+    match s.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid tag: {} (expected key=value)", s)),
+    }
+}
+
+/// Detect the target attributes from its `zuul-info/inventory.yaml`.
+pub fn detect(content: &Content) -> Result<Tags> {
+    detect_sources(content.get_sources_iter())
+}
+
+/// Detect the tags of sources that are already listed.
+pub fn detect_sources(sources: impl IntoIterator<Item = Result<Source>>) -> Result<Tags> {
+    for source in sources {
+        let source = source?;
+        if source.get_relative().ends_with("zuul-info/inventory.yaml") {
+            let reader = source.open()?;
+            let inventory = serde_yaml::from_reader(reader).context("Invalid inventory")?;
+            return Ok(from_inventory(&inventory));
+        }
+    }
+    Ok(Tags::new())
+}
+
+fn from_inventory(inventory: &serde_yaml::Value) -> Tags {
+    let mut tags = Tags::new();
+    let zuul = &inventory["all"]["vars"]["zuul"];
+    for (tag, value) in [("job", &zuul["job"]), ("branch", &zuul["branch"])] {
+        if let Some(value) = value.as_str() {
+            tags.insert(tag.to_string(), value.to_string());
+        }
+    }
+    if let Some(hosts) = inventory["all"]["hosts"].as_mapping() {
+        let labels = hosts
+            .values()
+            .filter_map(|host| host["nodepool"]["label"].as_str())
+            .collect::<std::collections::BTreeSet<_>>();
+        if !labels.is_empty() {
+            let labels = labels.into_iter().collect::<Vec<_>>().join(",");
+            tags.insert("label".to_string(), labels);
+        }
+        // The nodeset name is not part of the inventory, its host names are used instead.
+        let nodes = hosts
+            .keys()
+            .filter_map(|host| host.as_str())
+            .collect::<std::collections::BTreeSet<_>>();
+        if !nodes.is_empty() {
+            let nodes = nodes.into_iter().collect::<Vec<_>>().join(",");
+            tags.insert("nodeset".to_string(), nodes);
+        }
+    }
+    tags
+}
+
+/// The number of matching tags, or None when a tag conflicts with the target.
+fn score(model: &Tags, target: &Tags) -> Option<usize> {
+    model
+        .iter()
+        .try_fold(0, |acc, (key, value)| match target.get(key) {
+            Some(target_value) if target_value == value => Some(acc + 1),
+            Some(_) => None,
+            None => Some(acc),
+        })
+}
+
+/// Select the model whose tags best match the target.
+pub fn select(models: Vec<Model>, target: &Tags) -> Result<Model> {
+    models
+        .into_iter()
+        .filter_map(|model| score(model.tags(), target).map(|score| (score, model)))
+        .max_by_key(|(score, _)| *score)
+        .map(|(_, model)| model)
+        .ok_or_else(|| anyhow::anyhow!("No model matches the target tags {:?}", target))
+}
+
+#[test]
+fn test_tags() {
+    let inventory = serde_yaml::from_str(
+        r#"
+all:
+  hosts:
+    controller:
+      nodepool:
+        label: centos-9-stream
+  vars:
+    zuul:
+      job: tox-py39
+      branch: main
+"#,
+    )
+    .unwrap();
+    let target = from_inventory(&inventory);
+    assert_eq!(
+        target.get("label").map(|s| s.as_str()),
+        Some("centos-9-stream")
+    );
+    assert_eq!(
+        target.get("nodeset").map(|s| s.as_str()),
+        Some("controller")
+    );
+    assert_eq!(target.len(), 4);
+
+    let tags = |xs: &[&str]| xs.iter().map(|x| parse_tag(x).unwrap()).collect::<Tags>();
+    assert_eq!(score(&tags(&[]), &target), Some(0));
+    assert_eq!(
+        score(&tags(&["job=tox-py39", "arch=x86"]), &target),
+        Some(1)
+    );
+    assert_eq!(score(&tags(&["label=fedora"]), &target), None);
+    assert!(parse_tag("novalue").is_err());
+}
@@ -0,0 +1,176 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module reads the baselines that are already tokenized, e.g. by the wasm or ffi tokenizer
+//! of another system, so that the preprocessing can be distributed.
+//!
+//! The corpus is a json lines file, where each line has the relative path of its source:
+//!
+//! ```json
+//! {"source": "controller/logs/nova.log", "tokens": "%ID INFO nova.compute Starting instance"}
+//! ```
+//!
+//! The tokens must be produced by the same tokenizer version, otherwise the model does not
+//! recognize the target lines. The version is recorded in the first line of the corpus:
+//!
+//! ```json
+//! {"tokenizer_version": "0.1.0"}
+//! ```
+//!
+//! The corpus is streamed: it is read once to list its sources, and once to train the indexes.
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::{IndexName, Source};
+
+/// The first line of a corpus, with the version of the tokenizer that produced the tokens.
+#[derive(Serialize, Deserialize)]
+struct Header {
+    tokenizer_version: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct TokenLine {
+    source: String,
+    tokens: String,
+}
+
+/// Read the lines of a corpus, after checking the tokenizer version of its header.
+fn read(reader: impl BufRead, mut on_line: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+    let mut first = true;
+    for (pos, line) in reader.lines().enumerate() {
+        let line = line.context("Can't read tokens")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if std::mem::take(&mut first) {
+            match serde_json::from_str::<Header>(&line) {
+                Ok(header) if header.tokenizer_version == logreduce_tokenizer::VERSION => continue,
+                Ok(header) => {
+                    return Err(anyhow::anyhow!(
+                        "The tokens were produced by the tokenizer {}, the version {} is expected",
+                        header.tokenizer_version,
+                        logreduce_tokenizer::VERSION
+                    ))
+                }
+                Err(_) => tracing::warn!(
+                    "The tokens have no tokenizer version, they may not match the target lines"
+                ),
+            }
+        }
+        let line: TokenLine = serde_json::from_str(&line)
+            .with_context(|| format!("Invalid tokens at line {}", pos + 1))?;
+        on_line(line.source, line.tokens)?;
+    }
+    Ok(())
+}
+
+fn open(path: &Path) -> Result<impl BufRead> {
+    let file = std::fs::File::open(path).context("Can't open tokens")?;
+    Ok(std::io::BufReader::new(file))
+}
+
+/// Group the sources of the corpus by index, in the corpus order.
+fn group(reader: impl BufRead) -> Result<BTreeMap<IndexName, Vec<Source>>> {
+    let mut seen = std::collections::HashSet::new();
+    let mut groups: BTreeMap<IndexName, Vec<Source>> = BTreeMap::new();
+    read(reader, |name, _| {
+        if seen.insert(name.clone()) {
+            let source = Source::Local(0, PathBuf::from(name));
+            groups
+                .entry(IndexName::from_source(&source))
+                .or_default()
+                .push(source);
+        }
+        Ok(())
+    })?;
+    Ok(groups)
+}
+
+/// List the sources of the corpus, grouped by index.
+pub fn sources(path: &Path) -> Result<BTreeMap<IndexName, Vec<Source>>> {
+    group(open(path)?)
+}
+
+/// Read the tokenized lines of the corpus, with the relative path of their source.
+pub fn for_each(path: &Path, on_line: impl FnMut(String, String) -> Result<()>) -> Result<()> {
+    read(open(path)?, on_line)
+}
+
+/// Write a corpus, one source at a time.
+pub struct Writer {
+    writer: std::io::BufWriter<std::fs::File>,
+}
+
+impl Writer {
+    /// Create the corpus file with the tokenizer version.
+    pub fn create(path: &Path) -> Result<Writer> {
+        let file = std::fs::File::create(path).context("Can't create tokens")?;
+        let mut writer = Writer {
+            writer: std::io::BufWriter::new(file),
+        };
+        let header = Header {
+            tokenizer_version: logreduce_tokenizer::VERSION.to_string(),
+        };
+        writer.write_json(&header)?;
+        Ok(writer)
+    }
+
+    fn write_json(&mut self, value: &impl Serialize) -> Result<()> {
+        writeln!(self.writer, "{}", serde_json::to_string(value)?).context("Can't write tokens")
+    }
+
+    /// Write the tokenized lines of a source, by relative path.
+    pub fn write_source(&mut self, source: &str, lines: &[String]) -> Result<()> {
+        for tokens in lines {
+            let line = TokenLine {
+                source: source.to_string(),
+                tokens: tokens.clone(),
+            };
+            self.write_json(&line)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> Result<()> {
+        self.writer.flush().context("Can't write tokens")
+    }
+}
+
+#[test]
+fn test_group() {
+    let corpus = |header: &str| {
+        [
+            header,
+            r#"{"source": "controller/logs/nova.log", "tokens": "nova Starting"}"#,
+            r#"{"source": "compute/logs/nova.log.1", "tokens": "nova Stopping"}"#,
+            "",
+            r#"{"source": "controller/logs/nova.log", "tokens": "nova Started"}"#,
+        ]
+        .join("\n")
+    };
+    let header = format!(
+        r#"{{"tokenizer_version": "{}"}}"#,
+        logreduce_tokenizer::VERSION
+    );
+    let groups = group(std::io::Cursor::new(corpus(&header))).unwrap();
+    let nova = &groups[&IndexName("logs/nova.log".to_string())];
+    assert_eq!(nova.len(), 2);
+    let mut lines = Vec::new();
+    read(std::io::Cursor::new(corpus(&header)), |source, tokens| {
+        lines.push((source, tokens));
+        Ok(())
+    })
+    .unwrap();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[2].1, "nova Started");
+    // The corpus without header is accepted, but not the one of another tokenizer.
+    assert!(group(std::io::Cursor::new(corpus(""))).is_ok());
+    let other = r#"{"tokenizer_version": "0.0.0-other"}"#;
+    assert!(group(std::io::Cursor::new(corpus(other))).is_err());
+    assert!(group(std::io::Cursor::new("{\"source\": \"a\"}")).is_err());
+}
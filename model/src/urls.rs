@@ -28,6 +28,16 @@ impl Content {
 impl Source {
     #[tracing::instrument(level = "debug")]
     pub fn url_open(prefix: usize, url: &Url) -> Result<crate::reader::DecompressReader> {
+        let reader = Source::url_open_raw(prefix, url)?;
+        if crate::subunit::is_subunit(url.path()) {
+            crate::subunit::render(reader).context("Failed to read subunit url")
+        } else {
+            Ok(reader)
+        }
+    }
+
+    /// Open the url without rendering the binary formats, e.g. to save the file.
+    pub fn url_open_raw(prefix: usize, url: &Url) -> Result<crate::reader::DecompressReader> {
         tracing::debug!(url = url.as_str(), "Fetching url");
         if prefix == 0 {
             crate::reader::from_url(url, url)
@@ -42,12 +52,16 @@ impl Source {
         // TODO: fix the httpdir cache to work with iterator
         let urls = match CACHE.httpdir_get(url) {
             Some(res) => res,
-            None => httpdir::list(url.clone())
-                .context("Can't list url")
-                .and_then(|res| {
-                    CACHE.httpdir_add(url, &res)?;
-                    Ok(res)
-                }),
+            None => httpdir::Crawler::with_client(
+                crate::reader::client().clone(),
+                crate::net::settings().connections(),
+            )
+            .list(url.clone())
+            .context("Can't list url")
+            .and_then(|res| {
+                CACHE.httpdir_add(url, &res)?;
+                Ok(res)
+            }),
         };
         match urls {
             Ok(urls) => Box::new(
@@ -0,0 +1,213 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module lists the files of a directory tree.
+//!
+//! The sub directories are read in parallel, and the entries are sorted by name so that the
+//! sources are listed in the same order on every filesystem. A directory that is already being
+//! walked, e.g. through a bind mount or a symlink, is skipped to avoid an infinite loop.
+//!
+//! The settings are global like the [crate::net] settings, they must be set with [configure]
+//! before the first listing.
+
+use anyhow::{Context, Result};
+use once_cell::sync::OnceCell;
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+/// The walk settings.
+#[derive(Debug, Default)]
+pub struct Settings {
+    /// Follow the symlinks, which are otherwise ignored.
+    pub follow_symlinks: bool,
+    /// List only once the files that are reachable through multiple paths, e.g. hardlinks.
+    pub dedup_links: bool,
+}
+
+static SETTINGS: OnceCell<Settings> = OnceCell::new();
+
+/// Set the walk settings, this returns an error when they are already set.
+pub fn configure(settings: Settings) -> Result<()> {
+    SETTINGS
+        .set(settings)
+        .map_err(|_| anyhow::anyhow!("The walk settings are already set"))
+}
+
+/// The identity of a file, to detect the loops and the duplicates.
+#[cfg(unix)]
+fn file_id(metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_metadata: &std::fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// List the regular files of the directory tree, in sorted order.
+pub fn files(root: &Path) -> Vec<Result<PathBuf>> {
+    files_with(SETTINGS.get_or_init(Settings::default), root)
+}
+
+fn files_with(settings: &Settings, root: &Path) -> Vec<Result<PathBuf>> {
+    let ancestors = match std::fs::metadata(root) {
+        Ok(metadata) => file_id(&metadata).into_iter().collect(),
+        Err(err) => {
+            return vec![Err(err).with_context(|| format!("Can't read {:?}", root))];
+        }
+    };
+    let files = walk_dir(settings, root, &ancestors);
+    if settings.dedup_links {
+        dedup(files)
+    } else {
+        files
+    }
+}
+
+/// Remove the files that are already listed, keeping the first path.
+fn dedup(files: Vec<Result<PathBuf>>) -> Vec<Result<PathBuf>> {
+    let mut seen = HashSet::new();
+    files
+        .into_iter()
+        .filter(|file| match file {
+            Ok(path) => match std::fs::metadata(path).ok().and_then(|meta| file_id(&meta)) {
+                Some(id) if !seen.insert(id) => {
+                    tracing::debug!("Skipping duplicated file {:?}", path);
+                    false
+                }
+                _ => true,
+            },
+            Err(_) => true,
+        })
+        .collect()
+}
+
+fn walk_dir(settings: &Settings, dir: &Path, ancestors: &[(u64, u64)]) -> Vec<Result<PathBuf>> {
+    let mut entries: Vec<std::fs::DirEntry> =
+        match std::fs::read_dir(dir).and_then(|entries| entries.collect()) {
+            Ok(entries) => entries,
+            Err(err) => return vec![Err(err).with_context(|| format!("Can't read {:?}", dir))],
+        };
+    entries.sort_by_key(|entry| entry.file_name());
+    entries
+        .into_par_iter()
+        .map(|entry| walk_entry(settings, entry, ancestors))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+fn walk_entry(
+    settings: &Settings,
+    entry: std::fs::DirEntry,
+    ancestors: &[(u64, u64)],
+) -> Vec<Result<PathBuf>> {
+    let path = entry.path();
+    let metadata = match entry.file_type() {
+        Ok(file_type) if file_type.is_symlink() && settings.follow_symlinks => {
+            match std::fs::metadata(&path) {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    tracing::debug!("Skipping broken symlink {:?}: {}", path, err);
+                    return Vec::new();
+                }
+            }
+        }
+        Ok(file_type) if crate::reader::special_kind(&file_type).is_some() => {
+            tracing::debug!("Skipping special file {:?}", path);
+            return Vec::new();
+        }
+        // The symlinks are ignored.
+        Ok(file_type) if !file_type.is_file() && !file_type.is_dir() => return Vec::new(),
+        Ok(_) => match entry.metadata() {
+            Ok(metadata) => metadata,
+            Err(err) => return vec![Err(err).with_context(|| format!("Can't read {:?}", path))],
+        },
+        Err(err) => return vec![Err(err).with_context(|| format!("Can't read {:?}", path))],
+    };
+    if metadata.is_file() {
+        vec![Ok(path)]
+    } else if metadata.is_dir() {
+        match file_id(&metadata) {
+            Some(id) if ancestors.contains(&id) => {
+                tracing::warn!("Skipping directory loop {:?}", path);
+                Vec::new()
+            }
+            id => {
+                let ancestors = ancestors.iter().copied().chain(id).collect::<Vec<_>>();
+                walk_dir(settings, &path, &ancestors)
+            }
+        }
+    } else {
+        // A symlink to a special file.
+        tracing::debug!("Skipping special file {:?}", path);
+        Vec::new()
+    }
+}
+
+#[cfg(test)]
+fn relative_files(settings: &Settings, dir: &Path) -> Vec<PathBuf> {
+    files_with(settings, dir)
+        .into_iter()
+        .map(|path| path.unwrap().strip_prefix(dir).unwrap().to_path_buf())
+        .collect()
+}
+
+#[test]
+fn test_files() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    for sub in ["b", "a/c", "a"] {
+        std::fs::create_dir_all(dir.join(sub)).unwrap();
+        std::fs::write(dir.join(sub).join("job-output.txt"), "").unwrap();
+    }
+    std::fs::write(dir.join("a").join("0.log"), "").unwrap();
+    let files = relative_files(&Settings::default(), dir);
+    assert_eq!(
+        files,
+        [
+            "a/0.log",
+            "a/c/job-output.txt",
+            "a/job-output.txt",
+            "b/job-output.txt"
+        ]
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<_>>()
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn test_links() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    std::fs::create_dir_all(dir.join("logs")).unwrap();
+    std::fs::write(dir.join("logs").join("job-output.txt"), "").unwrap();
+    std::fs::hard_link(dir.join("logs/job-output.txt"), dir.join("logs/hard.txt")).unwrap();
+    std::os::unix::fs::symlink(dir.join("logs"), dir.join("link")).unwrap();
+    std::os::unix::fs::symlink(dir, dir.join("logs").join("loop")).unwrap();
+
+    let ignored = relative_files(&Settings::default(), dir);
+    let followed = relative_files(
+        &Settings {
+            follow_symlinks: true,
+            dedup_links: false,
+        },
+        dir,
+    );
+    let deduped = relative_files(
+        &Settings {
+            follow_symlinks: true,
+            dedup_links: true,
+        },
+        dir,
+    );
+    assert_eq!(ignored.len(), 2);
+    // The logs are listed twice, and the loop back to the root is skipped.
+    assert_eq!(followed.len(), 4);
+    assert_eq!(deduped, vec![PathBuf::from("link/hard.txt")]);
+}
@@ -105,7 +105,8 @@ impl Build {
 
     pub fn discover_baselines(&self) -> Result<Baselines> {
         let samples = self.get_success_samples()?;
-        let max_builds = 1;
+        // Keep a few candidates, the best one is selected by content similarity.
+        let max_builds = 3;
         let now = Utc::now().date_naive();
         Ok(samples
             .into_iter()
@@ -118,7 +119,7 @@ impl Build {
             // Filter stalled url
             .filter(|(_, build)| Self::logs_available(build))
             // .map(|b| dbg!(b))
-            // Keep the best
+            // Keep the best candidates
             .take(max_builds)
             // Create the content data type
             .map(|(_score, build)| new_content(self.api.clone(), build))
@@ -3,13 +3,25 @@
 
 use html_builder::*;
 use itertools::Itertools;
+use logreduce_model::process::context_blocks;
 use std::borrow::Cow;
 use std::fmt::Write;
 
+mod theme;
+pub use theme::{Link, Theme};
+
 type Result<A> = core::result::Result<A, std::fmt::Error>;
 
 pub fn render(report: &logreduce_model::Report) -> Result<String> {
-    Ok(Html::from(report)?.render())
+    render_with(report, &Theme::default())
+}
+
+/// Render the report with a theme, in its template when it has one.
+pub fn render_with(report: &logreduce_model::Report, theme: &Theme) -> Result<String> {
+    match &theme.template {
+        Some(template) => render_template(template, report, theme),
+        None => Ok(Html::from(report, theme)?.render()),
+    }
 }
 
 struct Html {
@@ -17,17 +29,66 @@ struct Html {
 }
 
 impl Html {
-    fn from(report: &logreduce_model::Report) -> Result<Html> {
+    fn from(report: &logreduce_model::Report, theme: &Theme) -> Result<Html> {
         let mut buffer = Buffer::new();
         let mut html = buffer.html().attr("lang='en'");
 
-        add_head(&mut html, &format!("Logreduce of {}", report.target))?;
-        add_body(&mut html, report)?;
+        add_head(&mut html, &title(report, theme), theme)?;
+        add_body(&mut html, report, theme)?;
 
         Ok(Html { buffer })
     }
 }
 
+fn title(report: &logreduce_model::Report, theme: &Theme) -> String {
+    match report.incomplete {
+        Some(_) => format!("{} of {} (incomplete)", escape(theme.name()), report.target),
+        None => format!("{} of {}", escape(theme.name()), report.target),
+    }
+}
+
+/// Render a part of the page, for the template variables.
+fn fragment(add: impl FnOnce(&mut Node) -> Result<()>) -> Result<String> {
+    let mut buffer = Buffer::new();
+    {
+        let mut div = buffer.div();
+        add(&mut div)?;
+    }
+    Ok(buffer.finish())
+}
+
+fn render_template(
+    template: &str,
+    report: &logreduce_model::Report,
+    theme: &Theme,
+) -> Result<String> {
+    let variables = [
+        ("title", title(report, theme)),
+        ("styles", styles(theme)?),
+        ("nav", fragment(|div| add_nav(div, theme))?),
+        ("report", fragment(|div| add_container(div, report, theme))?),
+        ("scripts", scripts()?),
+        ("version", env!("CARGO_PKG_VERSION").to_string()),
+    ];
+    let anomalies: Vec<_> = report
+        .log_reports
+        .iter()
+        .flat_map(|log_report| {
+            log_report
+                .anomalies
+                .iter()
+                .map(move |anomaly| theme::AnomalyVariables::new(log_report, anomaly, escape))
+        })
+        .collect();
+    theme::render(template, &theme::page_context(&variables, &anomalies))
+        .map_err(|_| std::fmt::Error)
+}
+
+/// Escape the text written in the page or in an attribute.
+fn escape(text: &str) -> String {
+    tera::escape_html(text)
+}
+
 impl Html {
     fn render(self) -> String {
         self.buffer.finish()
@@ -60,41 +121,51 @@ fn table(parent: &mut Node, columns: Option<&[&str]>, rows: &[&[&str]]) -> Resul
     Ok(())
 }
 
-fn add_head(parent: &mut Node, title: &str) -> Result<()> {
-    fn add_link(head: &mut Node, href: &str, integrity: &str) {
-        head.link()
-            .attr(&format!("href=\"{}\"", href))
-            .attr("rel=\"stylesheet\"")
-            .attr(&format!("integrity=\"{}\"", integrity))
-            .attr("crossorigin=\"anonymous\"");
+/// The stylesheets, followed by the one of the theme.
+fn styles(theme: &Theme) -> Result<String> {
+    let mut styles = String::new();
+    for (href, integrity) in STYLES {
+        write!(
+            styles,
+            "<link href=\"{}\" rel=\"stylesheet\" integrity=\"{}\" crossorigin=\"anonymous\">",
+            href, integrity
+        )?;
+    }
+    write!(styles, "<style>{}</style>", include_str!("style.css"))?;
+    if let Some(stylesheet) = &theme.stylesheet {
+        write!(styles, "<style>{}</style>", stylesheet)?;
+    }
+    Ok(styles)
+}
+
+fn scripts() -> Result<String> {
+    let mut scripts = String::new();
+    for (src, integrity) in SCRIPTS {
+        write!(
+            scripts,
+            "<script src=\"{}\" integrity=\"{}\" crossorigin=\"anonymous\"></script>",
+            src, integrity
+        )?;
     }
+    write!(scripts, "<script>{}</script>", JS)?;
+    Ok(scripts)
+}
+
+fn add_head(parent: &mut Node, title: &str, theme: &Theme) -> Result<()> {
     let mut head = parent.head();
     head.title().write_str(title)?;
     head.meta().attr("charset='utf-8'");
-    for (href, integrity) in STYLES {
-        add_link(&mut head, href, integrity);
-    }
-    head.style().write_str(include_str!("style.css"))?;
+    head.write_str(&styles(theme)?)?;
     Ok(())
 }
 
-fn add_body(parent: &mut Node, report: &logreduce_model::Report) -> Result<()> {
-    fn add_script(body: &mut Node, href: &str, integrity: &str) {
-        body.script()
-            .attr(&format!("src=\"{}\"", href))
-            .attr(&format!("integrity=\"{}\"", integrity))
-            .attr("crossorigin=\"anonymous\"");
-    }
-
+fn add_body(parent: &mut Node, report: &logreduce_model::Report, theme: &Theme) -> Result<()> {
     let mut body = parent.body();
 
-    add_nav(&mut body)?;
-    add_container(&mut body, report)?;
+    add_nav(&mut body, theme)?;
+    add_container(&mut body, report, theme)?;
 
-    for (src, integrity) in SCRIPTS {
-        add_script(&mut body, src, integrity)
-    }
-    body.script().write_str(JS)?;
+    body.write_str(&scripts()?)?;
     Ok(())
 }
 
@@ -105,13 +176,20 @@ fn div_<'b>(node: &'b mut Node<'b>, class: &str) -> Node<'b> {
     class_(node, std::borrow::Cow::Borrowed("div"), class)
 }
 
-fn add_nav(body: &mut Node) -> Result<()> {
+fn add_nav(body: &mut Node, theme: &Theme) -> Result<()> {
     let mut nav = body
         .nav()
         .attr("class=\"navbar navbar-default navbar-pf\"")
         .attr("role=\"navigation\"");
     let mut header = nav.div().attr("class=\"navbar-header\"");
-    header.img().attr(LOGO).attr("alt=\"LogReduce\"");
+    let logo = match &theme.logo {
+        Some(logo) => format!("src=\"{}\"", logo),
+        None => LOGO.to_string(),
+    };
+    header
+        .img()
+        .attr(&logo)
+        .attr(&format!("alt=\"{}\"", escape(theme.name())));
     let mut div = div_(&mut nav, "collapse navbar-collapse navbar-collapse-1");
     {
         // Utility
@@ -130,6 +208,13 @@ fn add_nav(body: &mut Node) -> Result<()> {
                 .attr("target=\"_blank\"")
                 .write_str("Documentation")?;
         }
+        for link in &theme.links {
+            let mut li = utils.li();
+            li.a()
+                .attr(&format!("href=\"{}\"", link.url))
+                .attr("target=\"_blank\"")
+                .write_str(&escape(&link.title))?;
+        }
         {
             let mut li = utils.li();
             let mut a = li.a().attr("href=\"#\"");
@@ -154,12 +239,21 @@ fn add_nav(body: &mut Node) -> Result<()> {
     Ok(())
 }
 
-fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()> {
+fn add_container(body: &mut Node, report: &logreduce_model::Report, theme: &Theme) -> Result<()> {
     let mut div = body
         .div()
         .attr("class=\"container\"")
         .attr("style='width: 100%'");
 
+    if let Some(reason) = &report.incomplete {
+        div.div()
+            .attr("class=\"alert alert-warning\"")
+            .write_str(&format!(
+                "Incomplete report: {}, only the anomalies already found are shown.",
+                reason
+            ))?;
+    }
+
     // Info table
     // TODO: reproducer command, baselines info, target info, anomalies count and runtime
     table(
@@ -190,6 +284,30 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
         ],
     )?;
 
+    if !report.warnings.is_empty() {
+        let rows = report
+            .warnings
+            .iter()
+            .map(|warning| [warning.as_str()])
+            .collect::<Vec<_>>();
+        let rows = rows.iter().map(|row| &row[..]).collect::<Vec<_>>();
+        table(&mut div, Some(&["Warnings"]), &rows)?;
+    }
+
+    if !report.read_errors.is_empty() {
+        let rows = report
+            .read_errors
+            .iter()
+            .map(|(source, error)| [source.to_string(), error.clone()])
+            .collect::<Vec<_>>();
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let rows = rows.iter().map(|row| &row[..]).collect::<Vec<_>>();
+        table(&mut div, Some(&["Source", "Read error"]), &rows)?;
+    }
+
     // Summary table
     // TODO: Anomaly count | Filename | Test time | Model
 
@@ -202,6 +320,7 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
                 log_report,
                 report.index_reports.get(&log_report.index_name),
                 expand,
+                theme,
             )?;
             expand = false;
         }
@@ -210,8 +329,30 @@ fn add_container(body: &mut Node, report: &logreduce_model::Report) -> Result<()
     // Model summary table
     // TODO: Model | Train time | Infos | Baseline files
 
-    // Error table
-    // TODO: Add files that were not processed dut to errors or missing model name
+    // Coverage gaps table
+    if !report.coverage_gaps.is_empty() {
+        let rows = report
+            .coverage_gaps
+            .iter()
+            .map(|gap| {
+                [
+                    gap.index_name.to_string(),
+                    gap.sources.join(", "),
+                    gap.actions.join(". "),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let rows = rows
+            .iter()
+            .map(|row| row.iter().map(|cell| cell.as_str()).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        let rows = rows.iter().map(|row| &row[..]).collect::<Vec<_>>();
+        table(
+            &mut div,
+            Some(&["No baselines", "Sources", "Actions"]),
+            &rows,
+        )?;
+    }
     Ok(())
 }
 
@@ -220,6 +361,7 @@ fn render_content_report(
     log_report: &logreduce_model::LogReport,
     index_report: Option<&logreduce_model::IndexReport>,
     expand: bool,
+    theme: &Theme,
 ) -> Result<()> {
     let mut list_group_item = list_group
         .div()
@@ -258,7 +400,7 @@ fn render_content_report(
                     let mut desc = pf_body.div().attr("class=\"list-view-pf-description\"");
                     desc.div()
                         .attr("class=\"list-group-item-heading\"")
-                        .write_str(log_report.source.get_relative())?;
+                        .write_str(&log_report.source.get_relative())?;
                 }
 
                 {
@@ -276,7 +418,8 @@ fn render_content_report(
                             model_anchor(&log_report.index_name)
                         ))
                         .write_str(&format!("{}", log_report.index_name))?;
-                    additional_item.write_str(" model")?;
+                    let rule = log_report.grouping_rule();
+                    additional_item.write_str(&format!(" model ({})", rule))?;
                 }
 
                 {
@@ -325,7 +468,7 @@ fn render_content_report(
         }
 
         let mut loglines = item_container.div().attr("class=\"loglines\"");
-        render_lines(&mut loglines, &log_report.anomalies)?;
+        render_lines(&mut loglines, log_report, theme)?;
     }
     Ok(())
 }
@@ -343,32 +486,111 @@ fn render_context(loglines: &mut Node, pos: usize, xs: &[String]) -> Result<()>
     Ok(())
 }
 
-fn render_lines(loglines: &mut Node, anomalies: &[logreduce_model::AnomalyContext]) -> Result<()> {
-    let mut last_pos = None;
-
-    for anomaly in anomalies {
-        let starting_pos = anomaly.anomaly.pos - 1 - anomaly.before.len();
-        if let Some(last_pos) = last_pos {
-            if last_pos != starting_pos {
-                loglines.hr().attr("class=\"ls\"");
-            }
+fn render_lines(
+    loglines: &mut Node,
+    log_report: &logreduce_model::LogReport,
+    theme: &Theme,
+) -> Result<()> {
+    let mut last_test = None;
+    let mut last_task = None;
+    let mut last_command = None;
+
+    // The close anomalies are shown in a single block, see [context_blocks].
+    for (idx, block) in context_blocks(&log_report.anomalies)
+        .into_iter()
+        .enumerate()
+    {
+        if idx > 0 {
+            loglines.hr().attr("class=\"ls\"");
         }
-        let dist: usize = (anomaly.anomaly.distance * 99.0) as _;
-        let color: usize = (anomaly.anomaly.distance * 255.0) as _;
-
-        render_context(loglines, starting_pos, &anomaly.before)?;
-
-        loglines
-            .pre()
-            .attr(&format!("style=\"color: #{:2X}0000\"", color))
-            .write_str(&format!(
-                "{:02} {:4} | {}",
-                dist, anomaly.anomaly.pos, anomaly.anomaly.line
-            ))?;
+        let mut block_lines = loglines.div().attr("class=\"block\"");
+        for anomaly in block {
+            let starting_pos = anomaly.first_pos() - 1;
+            let dist: usize = (anomaly.anomaly.distance * 99.0) as _;
+            let color: usize = (anomaly.anomaly.distance * 255.0) as _;
+
+            if let Some(test) = &anomaly.anomaly.test {
+                if last_test != Some(test) {
+                    block_lines
+                        .pre()
+                        .attr("style=\"font-weight: bold\"")
+                        .write_str(&format!("During test {}", test))?;
+                }
+            }
+            last_test = anomaly.anomaly.test.as_ref();
+
+            if let Some(task) = &anomaly.anomaly.task {
+                if last_task != Some(task) {
+                    block_lines
+                        .pre()
+                        .attr("style=\"font-weight: bold\"")
+                        .write_str(&format!("During task {}", task))?;
+                }
+            }
+            last_task = anomaly.anomaly.task.as_ref();
+
+            if let Some(command) = &anomaly.anomaly.command {
+                if last_command != Some(command) {
+                    block_lines
+                        .pre()
+                        .attr("style=\"font-weight: bold\"")
+                        .write_str(&command.to_string())?;
+                }
+            }
+            last_command = anomaly.anomaly.command.as_ref();
+
+            render_context(&mut block_lines, starting_pos, &anomaly.before)?;
+
+            block_lines
+                .pre()
+                .attr(&format!("id=\"anomaly-{}\"", anomaly.anomaly.id))
+                .attr(&format!("style=\"color: #{:2X}0000\"", color))
+                .write_str(&format!(
+                    "{}{}",
+                    anomaly
+                        .anomaly
+                        .lines()
+                        .map(|(pos, line)| format!("{:02} {:4} | {}", dist, pos, line))
+                        .join("\n"),
+                    match &anomaly.anomaly.retry {
+                        Some(retry) => format!(" ({})", retry),
+                        None if anomaly.anomaly.repeat > 0 => {
+                            format!(" (repeated {}×)", anomaly.anomaly.repeat)
+                        }
+                        None => "".to_string(),
+                    }
+                ))?;
+
+            if let Some(hint) = &anomaly.anomaly.hint {
+                let mut pre = block_lines.pre();
+                pre.write_str("Known error: ")?;
+                match &hint.link {
+                    Some(link) => pre
+                        .a()
+                        .attr(&format!("href=\"{}\"", link))
+                        .write_str(&hint.category)?,
+                    None => pre.write_str(&hint.category)?,
+                }
+            }
 
-        render_context(loglines, anomaly.anomaly.pos, &anomaly.after)?;
+            if !theme.actions.is_empty() {
+                let mut pre = block_lines.pre();
+                for (idx, action) in theme.actions.iter().enumerate() {
+                    if idx > 0 {
+                        pre.write_str(" | ")?;
+                    }
+                    pre.a()
+                        .attr(&format!(
+                            "href=\"{}\"",
+                            theme.action_url(action, log_report, anomaly)
+                        ))
+                        .attr("target=\"_blank\"")
+                        .write_str(&escape(&action.title))?;
+                }
+            }
 
-        last_pos = Some(anomaly.anomaly.pos + anomaly.after.len());
+            render_context(&mut block_lines, anomaly.after_pos(), &anomaly.after)?;
+        }
     }
 
     Ok(())
@@ -0,0 +1,278 @@
+// Copyright (C) 2022 Red Hat
+// SPDX-License-Identifier: Apache-2.0
+
+//! This module provides the report themes, to brand the report without changing the renderer.
+//!
+//! A theme is a directory with these optional files:
+//!
+//! - `theme.yaml`: the name, the logo, the navigation links and the anomaly actions:
+//!
+//! ```yaml
+//! name: ACME CI
+//! logo: logo.svg
+//! links:
+//!   - title: Runbooks
+//!     url: https://wiki.example.com/runbooks
+//! actions:
+//!   - title: Create ticket
+//!     url: https://issues.example.com/new?title={{ line }}&body={{ source }}:{{ pos }}
+//! ```
+//!
+//! - `style.css`: the stylesheet added after the default one.
+//! - `template.html`: the [tera](https://keats.github.io/tera/docs/) page template, with the
+//!   `{{ title }}`, `{{ styles }}`, `{{ nav }}`, `{{ report }}`, `{{ scripts }}` and
+//!   `{{ version }}` variables. The styles go in the head, and the scripts at the end of the body.
+//!   The `anomalies` variable lists the anomalies, to build custom views:
+//!
+//! ```html
+//! <table>
+//! {% for anomaly in anomalies %}
+//!   <tr><td>{{ anomaly.source }}:{{ anomaly.pos }}</td><td>{{ anomaly.line }}</td></tr>
+//! {% endfor %}
+//! </table>
+//! ```
+//!
+//! The anomaly values are already html escaped in the page, and url encoded in the action urls,
+//! so the templates are not auto escaped.
+
+use anyhow::{Context, Result};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// The variables of the page template, in addition to the anomalies.
+pub(crate) const PAGE_VARIABLES: [&str; 6] =
+    ["title", "styles", "nav", "report", "scripts", "version"];
+
+/// The variables of an anomaly, in the page template and in the action urls.
+#[derive(Debug, Default, Serialize)]
+pub(crate) struct AnomalyVariables {
+    pub id: String,
+    pub line: String,
+    pub source: String,
+    pub pos: usize,
+    pub index: String,
+    pub distance: f32,
+    pub hint: Option<String>,
+}
+
+impl AnomalyVariables {
+    /// The variables of the anomaly, with the text values encoded for their use.
+    pub(crate) fn new(
+        log_report: &logreduce_model::LogReport,
+        anomaly: &logreduce_model::AnomalyContext,
+        encode: impl Fn(&str) -> String,
+    ) -> AnomalyVariables {
+        AnomalyVariables {
+            id: encode(&anomaly.anomaly.id),
+            line: encode(&anomaly.anomaly.line),
+            source: encode(&log_report.source.get_relative()),
+            pos: anomaly.anomaly.pos,
+            index: encode(log_report.index_name.as_str()),
+            distance: anomaly.anomaly.distance,
+            hint: anomaly
+                .anomaly
+                .hint
+                .as_ref()
+                .map(|hint| encode(&hint.category)),
+        }
+    }
+}
+
+/// The context of the page template, which is checked with a placeholder anomaly.
+pub(crate) fn page_context(
+    variables: &[(&str, String)],
+    anomalies: &[AnomalyVariables],
+) -> tera::Context {
+    let mut context = tera::Context::new();
+    for (name, value) in variables {
+        context.insert(*name, value);
+    }
+    context.insert("anomalies", anomalies);
+    context
+}
+
+/// A link of the navigation bar, or an action of the anomalies.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Link {
+    pub title: String,
+    pub url: String,
+}
+
+/// The customization of the report, see [Theme::load].
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Theme {
+    /// The name in the title, instead of `Logreduce`.
+    pub name: Option<String>,
+    /// The logo url, or its path in the theme directory.
+    pub logo: Option<String>,
+    /// The links added to the navigation bar, e.g. the runbooks.
+    #[serde(default)]
+    pub links: Vec<Link>,
+    /// The links added to each anomaly, e.g. to create a ticket.
+    #[serde(default)]
+    pub actions: Vec<Link>,
+    #[serde(skip)]
+    pub stylesheet: Option<String>,
+    #[serde(skip)]
+    pub template: Option<String>,
+}
+
+fn read_optional(path: &Path) -> Result<Option<String>> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Can't read {:?}", path)),
+    }
+}
+
+impl Theme {
+    /// Load the theme directory, the logo file is embedded in the report.
+    pub fn load(dir: &Path) -> Result<Theme> {
+        if !dir.is_dir() {
+            return Err(anyhow::anyhow!("{:?}: the theme is not a directory", dir));
+        }
+        let mut theme = match read_optional(&dir.join("theme.yaml"))? {
+            Some(content) => serde_yaml::from_str(&content).context("Invalid theme.yaml")?,
+            None => Theme::default(),
+        };
+        if let Some(logo) = &theme.logo {
+            if !logo.contains("://") && !logo.starts_with("data:") {
+                theme.logo = Some(data_uri(&dir.join(logo))?);
+            }
+        }
+        let placeholder = AnomalyVariables::default();
+        for action in &theme.actions {
+            render(&action.url, &action_context(&placeholder))
+                .with_context(|| format!("Invalid url of the {} action", action.title))?;
+        }
+        theme.stylesheet = read_optional(&dir.join("style.css"))?;
+        theme.template = read_optional(&dir.join("template.html"))?;
+        if let Some(template) = &theme.template {
+            let variables = PAGE_VARIABLES.map(|name| (name, String::new()));
+            render(template, &page_context(&variables, &[placeholder]))
+                .context("Invalid template.html")?;
+        }
+        Ok(theme)
+    }
+
+    /// The name of the tool in the page title.
+    pub(crate) fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or("Logreduce")
+    }
+
+    /// The url of the action for an anomaly.
+    pub(crate) fn action_url(
+        &self,
+        action: &Link,
+        log_report: &logreduce_model::LogReport,
+        anomaly: &logreduce_model::AnomalyContext,
+    ) -> String {
+        let encode = |value: &str| -> String {
+            url::form_urlencoded::byte_serialize(value.as_bytes()).collect()
+        };
+        let variables = AnomalyVariables::new(log_report, anomaly, encode);
+        // The urls are checked when the theme is loaded.
+        render(&action.url, &action_context(&variables)).unwrap_or_else(|_| action.url.clone())
+    }
+}
+
+fn action_context(variables: &AnomalyVariables) -> tera::Context {
+    // The struct serializes to a map, so this can't fail.
+    tera::Context::from_serialize(variables).unwrap_or_default()
+}
+
+/// Render the template, the values are encoded by the caller.
+pub(crate) fn render(template: &str, context: &tera::Context) -> Result<String> {
+    tera::Tera::one_off(template, context, false).map_err(|e| {
+        // The tera errors put the cause in the source.
+        let mut message = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            message = format!("{}: {}", message, cause);
+            source = cause.source();
+        }
+        anyhow::anyhow!(message)
+    })
+}
+
+/// Embed the image file, so that the report stays a single file.
+fn data_uri(path: &Path) -> Result<String> {
+    let mime = match path.extension().and_then(|ext| ext.to_str()) {
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        _ => return Err(anyhow::anyhow!("{:?}: unknown image format", path)),
+    };
+    let bytes = std::fs::read(path).with_context(|| format!("Can't read {:?}", path))?;
+    Ok(format!(
+        "data:{};base64,{}",
+        mime,
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+#[test]
+fn test_render() {
+    let variables = [
+        ("title", "Report".to_string()),
+        ("report", "<div/>".to_string()),
+    ];
+    let anomalies = [AnomalyVariables {
+        line: "&lt;error&gt;".to_string(),
+        pos: 42,
+        ..AnomalyVariables::default()
+    }];
+    let context = page_context(&variables, &anomalies);
+    assert_eq!(
+        render("<h1>{{ title }}</h1>{{report}}", &context).unwrap(),
+        "<h1>Report</h1><div/>"
+    );
+    assert_eq!(
+        render(
+            "{% for a in anomalies %}<td>{{ a.pos }}: {{ a.line }}</td>{% endfor %}",
+            &context
+        )
+        .unwrap(),
+        "<td>42: &lt;error&gt;</td>"
+    );
+    assert!(render("{{ footer }}", &context).is_err());
+    assert!(render("{{ title", &context).is_err());
+}
+
+#[test]
+fn test_load() {
+    let tmp = tempfile::tempdir().unwrap();
+    let dir = tmp.path();
+    let theme_yaml = concat!(
+        "name: ACME CI\n",
+        "logo: logo.svg\n",
+        "actions:\n",
+        "  - title: Create ticket\n",
+        "    url: https://issues.example.com/new?title={{ line }}\n",
+    );
+    std::fs::write(dir.join("theme.yaml"), theme_yaml).unwrap();
+    std::fs::write(dir.join("logo.svg"), "<svg/>").unwrap();
+    std::fs::write(dir.join("template.html"), "<html>{{ report }}</html>").unwrap();
+    let theme = Theme::load(dir);
+    std::fs::write(dir.join("template.html"), "<html>{{ footer }}</html>").unwrap();
+    let invalid = Theme::load(dir);
+    let template = "{% for a in anomalies %}{{ a.missing }}{% endfor %}";
+    std::fs::write(dir.join("template.html"), template).unwrap();
+    let invalid_anomaly = Theme::load(dir);
+
+    let theme = theme.unwrap();
+    assert_eq!(theme.name(), "ACME CI");
+    assert_eq!(
+        theme.logo.as_deref(),
+        Some("data:image/svg+xml;base64,PHN2Zy8+")
+    );
+    assert_eq!(theme.actions.len(), 1);
+    assert_eq!(theme.stylesheet, None);
+    assert!(invalid.is_err());
+    assert!(invalid_anomaly.is_err());
+    assert_eq!(Theme::default().name(), "Logreduce");
+}
@@ -1,7 +1,7 @@
 // Copyright (C) 2022 Red Hat
 // SPDX-License-Identifier: Apache-2.0
 
-use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
 use logreduce_generate::gen_lines;
 use logreduce_tokenizer::process;
 
@@ -10,5 +10,21 @@ pub fn lexer_process(c: &mut Criterion) {
     c.bench_function("parser::process", |b| b.iter(|| process(black_box(&input))));
 }
 
-criterion_group!(benches, lexer_process);
+/// Measure the tokenizer throughput in bytes per second.
+pub fn lexer_throughput(c: &mut Criterion) {
+    let lines = gen_lines().take(10_000).collect::<Vec<String>>();
+    let bytes = lines.iter().map(|line| line.len() as u64).sum();
+    let mut group = c.benchmark_group("tokenizer");
+    group.throughput(Throughput::Bytes(bytes));
+    group.bench_function("throughput", |b| {
+        b.iter(|| {
+            lines.iter().for_each(|line| {
+                process(black_box(line));
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, lexer_process, lexer_throughput);
 criterion_main!(benches);
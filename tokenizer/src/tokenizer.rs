@@ -98,6 +98,56 @@ fn test_global_filter() {
     assert!(global_filter("                    \"|           oo... |\""));
 }
 
+/// Remove the kernel ring buffer prefix, e.g. `<6>[ 1234.567890] ` or `[   12.3][  T1] `.
+fn strip_kernel_timestamp(line: &str) -> &str {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^(<[0-9]+>)?\[ *[0-9]+\.[0-9]+\]( ?\[ *[CT][0-9]+\])? *").unwrap();
+    }
+    match RE.find(line) {
+        Some(m) => &line[m.end()..],
+        None => line,
+    }
+}
+
+/// Unwrap the rate-limited lines, e.g. `message repeated 3 times: [ msg]` is `msg`.
+fn strip_repeated(line: &str) -> std::borrow::Cow<'_, str> {
+    lazy_static! {
+        static ref RE: Regex =
+            Regex::new(r"^(.*)message repeated [0-9]+ times: \[ *(.*?) *\]$").unwrap();
+    }
+    RE.replace(line, "$1$2")
+}
+
+#[test]
+fn test_kernel_prefix() {
+    assert_eq!(strip_kernel_timestamp("[    0.000000] Linux"), "Linux");
+    assert_eq!(strip_kernel_timestamp("<6>[12345.678901] Linux"), "Linux");
+    assert_eq!(
+        strip_kernel_timestamp("[    1.234567][    T1] Linux"),
+        "Linux"
+    );
+    assert_eq!(strip_kernel_timestamp("[ok] Linux"), "[ok] Linux");
+    assert_eq!(
+        strip_repeated("sshd[42]: message repeated 2 times: [ Failed password]"),
+        "sshd[42]: Failed password"
+    );
+}
+
+/// Check if a word is a cpu or irq number, e.g. `CPU3`, `cpu#12` or `IRQ42`.
+fn is_cpu_or_irq(word: &str) -> Option<&'static str> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"(?i)^(cpu|irq)#?[0-9]+:?$").unwrap();
+    }
+    RE.captures(word).map(|caps| {
+        if caps[1].eq_ignore_ascii_case("cpu") {
+            "%CPU"
+        } else {
+            "%IRQ"
+        }
+    })
+}
+
 /// Replace numbers sequences with `N`.
 fn remove_numbers(word: &str) -> String {
     lazy_static! {
@@ -436,6 +486,9 @@ fn do_process(mut word: &str, result: &mut String) -> bool {
     if let Some(token) = parse_literal(word) {
         // e.g. `February` or `sha256:...`
         result.push_str(token)
+    } else if let Some(token) = is_cpu_or_irq(word) {
+        // e.g. `CPU3`
+        result.push_str(token)
     } else if is_error(word) {
         // e.g. `Traceback`
         push_error(word, result)
@@ -499,10 +552,15 @@ fn do_process(mut word: &str, result: &mut String) -> bool {
     added
 }
 
+/// The version of the tokenizer, the tokens of another version may differ.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
 /// The tokenizer entry point
 pub fn process(line: &str) -> String {
-    // Remove surrounding whitespaces
-    let line = line.trim();
+    // Remove surrounding whitespaces and the dmesg prefixes
+    let line = strip_kernel_timestamp(line.trim());
+    let line = strip_repeated(line);
+    let line: &str = &line;
 
     // check for global filter first
     if global_filter(line) {
@@ -702,6 +760,23 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_process_dmesg() {
+        tokens_eq!(
+            "[    2.015136] smpboot: CPU3 is now offline",
+            "[   14.512307] smpboot: CPU12 is now offline"
+        );
+        tokens_eq!(
+            "<4>[  731.000012] irq 42: nobody cared (try booting with the \"irqpoll\" option)",
+            "irq 17: nobody cared (try booting with the \"irqpoll\" option)"
+        );
+        tokens_eq!(
+            "kernel: message repeated 12 times: [ NMI watchdog: Watchdog detected hard LOCKUP on cpu#7]",
+            "kernel: NMI watchdog: Watchdog detected hard LOCKUP on cpu#1"
+        );
+        assert_eq!(process("[    0.000000] Linux version"), "Linux version");
+    }
+
     #[test]
     fn test_kv() {
         assert_eq!(